@@ -1,4 +1,4 @@
-from .floky import LshL2, LshSrp, LshSrpMem, LshL2Mem, sort_by_distances
+from .floky import LshL2, LshSrp, LshSrpMem, LshL2Mem, PyLsh, sort_by_distances
 from tqdm import tqdm
 import numpy as np
 import os
@@ -0,0 +1,37 @@
+from typing import List, Union
+import numpy as np
+
+class PyLsh:
+    """
+    In memory LSH for quick prototyping.
+
+    Parameters
+    ----------
+    n_projections
+        Hash length.
+    n_hash_tables
+        Number of hash tables.
+    dim
+        Dimension of the data points.
+    seed
+        Seed for the hash functions. If 0, randomness is seeded from the os.
+    hasher_type
+        "srp" (cosine similarity) or "l2" (euclidean distance).
+    r
+        Bucket width of the "l2" hasher.
+    """
+
+    def __init__(
+        self,
+        n_projections: int,
+        n_hash_tables: int,
+        dim: int,
+        seed: int = 0,
+        hasher_type: str = "srp",
+        r: float = 4.0,
+    ) -> None: ...
+    def store_vec(self, v: List[float]) -> int: ...
+    def store_vecs(self, vs: Union[np.ndarray, List[List[float]]]) -> List[int]: ...
+    def query_bucket_ids(self, v: List[float]) -> List[int]: ...
+    def dump(self, path: str) -> None: ...
+    def load(self, path: str) -> None: ...
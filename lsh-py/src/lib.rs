@@ -73,6 +73,7 @@ fn floky(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<LshSrp>()?;
     m.add_class::<LshL2Mem>()?;
     m.add_class::<LshSrpMem>()?;
+    m.add_class::<PyLsh>()?;
     m.add_wrapped(wrap_pyfunction!(sort_by_distances)).unwrap();
     Ok(())
 }
@@ -488,3 +489,96 @@ impl LshSrpMem {
         ))
     }
 }
+
+enum MemTypes {
+    Srp(LshMem<SignRandomProjections>),
+    L2(LshMem<L2>),
+}
+
+macro_rules! call_mem_types {
+    ($lsh:expr, $method_call:ident, $($value:expr),*) => {
+        match $lsh {
+            MemTypes::Srp(lsh) => lsh.$method_call($($value),*),
+            MemTypes::L2(lsh) => lsh.$method_call($($value),*),
+        }
+    };
+}
+
+/// In memory LSH for quick prototyping. Data points are stored, so the
+/// index can be dumped and loaded.
+#[pyclass]
+struct PyLsh {
+    lsh: MemTypes,
+    n_hash_tables: usize,
+}
+
+impl PyLsh {
+    fn _store_vecs(&mut self, vs: &PyAny) -> IntResult<Vec<u32>> {
+        let ids = match vs.extract::<&PyArray2<f32>>() {
+            Ok(arr) => {
+                let arr = arr.as_array();
+                if !arr.is_standard_layout() {
+                    return Err(PyLshErr::NonContiguous);
+                }
+                call_mem_types!(&mut self.lsh, store_array, arr)?
+            }
+            Err(_) => {
+                let vs: Vec<Vec<f32>> = vs
+                    .extract()
+                    .map_err(|_| PyLshErr::Err(LshError::Failed("expected a 2D array".into())))?;
+                call_mem_types!(&mut self.lsh, store_vecs, &vs)?
+            }
+        };
+        // store_vecs returns an id for every hash table.
+        Ok(ids.into_iter().step_by(self.n_hash_tables).collect())
+    }
+}
+
+#[pymethods]
+impl PyLsh {
+    #[new]
+    #[args(seed = "0", hasher_type = "\"srp\"", r = "4.0")]
+    fn new(
+        n_projections: usize,
+        n_hash_tables: usize,
+        dim: usize,
+        seed: u64,
+        hasher_type: &str,
+        r: f32,
+    ) -> PyResult<Self> {
+        let mut builder = LshMem::new(n_projections, n_hash_tables, dim);
+        builder.seed(seed);
+        let lsh = match hasher_type {
+            "srp" | "cosine" => builder.srp().map(MemTypes::Srp),
+            "l2" | "euclidean" => builder.l2(r).map(MemTypes::L2),
+            _ => return Err(PyErr::new::<ValueError, _>("hasher_type should be srp or l2")),
+        };
+        let lsh = lsh.map_err(PyLshErr::from)?;
+        Ok(PyLsh { lsh, n_hash_tables })
+    }
+
+    fn store_vec(&mut self, v: Vec<f32>) -> PyResult<u32> {
+        let id = call_mem_types!(&mut self.lsh, store_vec, &v).map_err(PyLshErr::from)?;
+        Ok(id)
+    }
+
+    fn store_vecs(&mut self, vs: &PyAny) -> PyResult<Vec<u32>> {
+        let ids = self._store_vecs(vs)?;
+        Ok(ids)
+    }
+
+    fn query_bucket_ids(&self, v: Vec<f32>) -> PyResult<Vec<u32>> {
+        let ids = call_mem_types!(&self.lsh, query_bucket_ids, &v).map_err(PyLshErr::from)?;
+        Ok(ids)
+    }
+
+    fn dump(&self, path: &str) -> PyResult<()> {
+        call_mem_types!(&self.lsh, dump, path).map_err(PyLshErr::from)?;
+        Ok(())
+    }
+
+    fn load(&mut self, path: &str) -> PyResult<()> {
+        call_mem_types!(&mut self.lsh, load, path).map_err(PyLshErr::from)?;
+        Ok(())
+    }
+}
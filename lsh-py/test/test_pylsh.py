@@ -0,0 +1,23 @@
+from floky import PyLsh
+import numpy as np
+
+
+def test_pylsh_round_trip(tmp_path):
+    np.random.seed(1)
+    arr = np.random.randn(100, 10).astype(np.float32)
+    for hasher_type in ["srp", "l2"]:
+        lsh = PyLsh(n_projections=5, n_hash_tables=4, dim=10, seed=1, hasher_type=hasher_type)
+        ids = lsh.store_vecs(arr)
+        assert ids == list(range(100))
+        assert lsh.store_vec(arr[0].tolist()) == 100
+        assert lsh.store_vecs(arr[:2].tolist()) == [101, 102]
+
+        query = arr[3].tolist()
+        result = lsh.query_bucket_ids(query)
+        assert 3 in result
+
+        path = str(tmp_path / "lsh.bin")
+        lsh.dump(path)
+        loaded = PyLsh(n_projections=5, n_hash_tables=4, dim=10, hasher_type=hasher_type)
+        loaded.load(path)
+        assert sorted(loaded.query_bucket_ids(query)) == sorted(result)
@@ -1,8 +1,9 @@
 #![feature(test)]
 extern crate test;
+use fnv::FnvHashSet;
 use lsh_rs::{
-    utils::rand_unit_vec, HashTables, LshSqlMem, MemoryTable, SignRandomProjections, SqlTable,
-    SqlTableMem, LSH,
+    utils::rand_unit_vec, CascadingLSH, ConcurrentInsertPool, DataId, EnsembleLSH, HashTables,
+    LSHWithHnswEntry, LshSqlMem, MemoryTable, SignRandomProjections, SqlTable, SqlTableMem, LSH,
 };
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
@@ -17,6 +18,17 @@ fn prep_vecs(n: usize, dim: usize) -> Vec<Vec<f32>> {
     v
 }
 
+/// Fraction of the candidate lists that contain their own index, e.g. the recall of the stored
+/// vectors when the lists are the candidates of their perturbed copies.
+fn recall<I: IntoIterator<Item = Vec<DataId>>>(candidates: I) -> f32 {
+    let (mut hits, mut n) = (0, 0);
+    for (i, ids) in candidates.into_iter().enumerate() {
+        hits += ids.contains(&(i as DataId)) as usize;
+        n += 1;
+    }
+    hits as f32 / n as f32
+}
+
 fn store_n(n: usize, dim: usize, index_only: bool) -> LSH<MemoryTable, SignRandomProjections> {
     let v = prep_vecs(n, dim);
     let mut lsh;
@@ -48,6 +60,39 @@ fn bench_storing_sqlite_mem(b: &mut Bencher) {
     })
 }
 
+fn bench_db_path(name: &str) -> String {
+    let mut path = std::env::temp_dir();
+    path.push(name);
+    std::fs::remove_file(&path).unwrap_or_default();
+    path.to_str().unwrap().to_string()
+}
+
+#[bench]
+fn bench_build_sqlite_direct(b: &mut Bencher) {
+    let v = prep_vecs(100, 100);
+    b.iter(|| {
+        let db = bench_db_path("lsh_bench_direct.db3");
+        let mut lsh = LSH::<SqlTable, _>::new(20, 7, 100)
+            .seed(1)
+            .set_database_file(&db)
+            .srp()
+            .unwrap();
+        // end the initial transaction, so that every row is committed on insert.
+        lsh.commit().unwrap();
+        lsh.store_vecs(&v).unwrap();
+    })
+}
+
+#[bench]
+fn bench_build_in_memory_then_flush(b: &mut Bencher) {
+    let v = prep_vecs(100, 100);
+    b.iter(|| {
+        let db = bench_db_path("lsh_bench_flush.db3");
+        let mut lsh = LshSqlMem::new(20, 7, 100).seed(1).srp().unwrap();
+        lsh.build_in_memory_then_flush(&v, &db).unwrap();
+    })
+}
+
 #[bench]
 fn bench_query(b: &mut Bencher) {
     let lsh = store_n(100, 100, false);
@@ -63,6 +108,49 @@ fn bench_query(b: &mut Bencher) {
     });
 }
 
+fn bench_insert_pool(b: &mut Bencher, n_workers: usize) {
+    let v = prep_vecs(200, 100);
+    b.iter(|| {
+        let lsh: LSH<MemoryTable, _> = LSH::new(20, 10, 100).seed(1).srp().unwrap();
+        let pool = ConcurrentInsertPool::new(lsh, n_workers).unwrap();
+        let handles: Vec<_> = v.iter().map(|v| pool.submit(v.clone()).unwrap()).collect();
+        handles.into_iter().for_each(|h| {
+            h.recv().unwrap().unwrap();
+        });
+        pool.into_inner()
+    })
+}
+
+#[bench]
+fn bench_insert_pool_1(b: &mut Bencher) {
+    bench_insert_pool(b, 1)
+}
+
+#[bench]
+fn bench_insert_pool_4(b: &mut Bencher) {
+    bench_insert_pool(b, 4)
+}
+
+#[bench]
+fn bench_insert_pool_8(b: &mut Bencher) {
+    bench_insert_pool(b, 8)
+}
+
+#[bench]
+fn bench_insert_pool_16(b: &mut Bencher) {
+    bench_insert_pool(b, 16)
+}
+
+#[bench]
+fn bench_store_vecs_200(b: &mut Bencher) {
+    let v = prep_vecs(200, 100);
+    b.iter(|| {
+        let mut lsh: LSH<MemoryTable, _> = LSH::new(20, 10, 100).seed(1).srp().unwrap();
+        lsh.store_vecs(&v).unwrap();
+        lsh
+    })
+}
+
 #[bench]
 fn bench_sqlite(b: &mut Bencher) {
     let mut sql = SqlTableMem::new(1, true, ".").unwrap();
@@ -72,3 +160,218 @@ fn bench_sqlite(b: &mut Bencher) {
         sql.put(hash.clone(), &v, 0);
     })
 }
+
+fn half_duplicate_queries() -> (LSH<MemoryTable, SignRandomProjections>, Vec<Vec<f32>>) {
+    let v = prep_vecs(1000, 100);
+    let mut lsh: LSH<MemoryTable, _> = LSH::new(12, 50, 100).seed(1).srp().unwrap();
+    lsh.store_vecs(&v);
+    // 50% of the queries are duplicates.
+    let mut qs = prep_vecs(32, 100);
+    qs.extend_from_within(..);
+    (lsh, qs)
+}
+
+#[bench]
+fn bench_query_batch(b: &mut Bencher) {
+    let (lsh, qs) = half_duplicate_queries();
+    b.iter(|| lsh.query_bucket_ids_batch(&qs));
+}
+
+#[bench]
+fn bench_query_batch_dedup(b: &mut Bencher) {
+    let (lsh, qs) = half_duplicate_queries();
+    b.iter(|| lsh.query_bucket_ids_batch_dedup(&qs));
+}
+
+fn bench_query_owned(b: &mut Bencher, quantized: bool) {
+    // stored data points: 4 bytes per dimension raw, 1 byte per dimension quantized.
+    let v = prep_vecs(1000, 100);
+    let mut lsh: LSH<MemoryTable, _> = LSH::new(8, 7, 100).seed(1).srp().unwrap();
+    if quantized {
+        lsh.scalar_quantization().unwrap();
+    }
+    lsh.store_vecs(&v);
+    let qs = prep_vecs(16, 100);
+    b.iter(|| {
+        for q in &qs {
+            lsh.query_bucket_owned(q).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_query_owned_raw(b: &mut Bencher) {
+    bench_query_owned(b, false)
+}
+
+#[bench]
+fn bench_query_owned_quantized(b: &mut Bencher) {
+    bench_query_owned(b, true)
+}
+
+#[bench]
+fn bench_query_multi_index(b: &mut Bencher) {
+    let v = prep_vecs(1000, 100);
+    let mut index_a: LSH<MemoryTable, _> = LSH::new(20, 7, 100).seed(1).only_index().srp().unwrap();
+    let mut index_b: LSH<MemoryTable, _> = LSH::new(20, 7, 100).seed(2).only_index().srp().unwrap();
+    index_a.store_vecs(&v).unwrap();
+    index_b.store_vecs(&v).unwrap();
+    let qs = prep_vecs(16, 100);
+    // report the recall of the stored vectors for perturbed queries once.
+    let noisy: Vec<Vec<f32>> = v[..200]
+        .iter()
+        .zip(&v[500..700])
+        .map(|(x, z)| x.iter().zip(z).map(|(a, b)| a + 0.6 * b).collect())
+        .collect();
+    println!(
+        "recall single: {} multi: {}",
+        recall(noisy.iter().map(|q| index_a.query_bucket_ids(q).unwrap())),
+        recall(
+            index_a
+                .query_bucket_ids_multi_index(&noisy, &index_b)
+                .unwrap()
+        )
+    );
+    b.iter(|| index_a.query_bucket_ids_multi_index(&qs, &index_b).unwrap());
+}
+
+fn store_l500() -> LSH<MemoryTable, SignRandomProjections> {
+    let v = prep_vecs(1000, 100);
+    let mut lsh = LSH::new(20, 500, 100).seed(1).only_index().srp().unwrap();
+    lsh.sketched_query();
+    lsh.store_vecs(&v).unwrap();
+    lsh
+}
+
+#[bench]
+fn bench_query_l500(b: &mut Bencher) {
+    let lsh = store_l500();
+    let qs = prep_vecs(16, 100);
+    b.iter(|| {
+        for q in &qs {
+            lsh.query_bucket_ids(q).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_query_sketched_l500(b: &mut Bencher) {
+    let lsh = store_l500();
+    let qs = prep_vecs(16, 100);
+    b.iter(|| {
+        for q in &qs {
+            lsh.query_bucket_ids_sketched(q).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_query_ids_alloc(b: &mut Bencher) {
+    let lsh = store_n(1000, 100, true);
+    let qs = prep_vecs(16, 100);
+    b.iter(|| {
+        for q in &qs {
+            lsh.query_bucket_ids(q).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_query_ids_into(b: &mut Bencher) {
+    let lsh = store_n(1000, 100, true);
+    let qs = prep_vecs(16, 100);
+    let mut out = FnvHashSet::default();
+    b.iter(|| {
+        for q in &qs {
+            lsh.query_bucket_ids_into(q, &mut out).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_query_single_l8(b: &mut Bencher) {
+    let mut lsh: LSH<MemoryTable, _> = LSH::new(8, 8, 100).seed(1).srp().unwrap();
+    lsh.store_vecs(&prep_vecs(1000, 100)).unwrap();
+    let qs = prep_vecs(16, 100);
+    b.iter(|| {
+        for q in &qs {
+            lsh.query_bucket_ids(q).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_query_ensemble_4x2(b: &mut Bencher) {
+    let mut ensemble = EnsembleLSH::<MemoryTable, _>::build(8, 2, 100, 4, 1).unwrap();
+    ensemble.store_vecs(&prep_vecs(1000, 100)).unwrap();
+    let qs = prep_vecs(16, 100);
+    b.iter(|| {
+        for q in &qs {
+            ensemble.query_bucket_ids(q).unwrap();
+        }
+    });
+}
+
+fn bench_cascade_query_until(b: &mut Bencher, min_candidates: usize) {
+    let mut cascade =
+        CascadingLSH::<MemoryTable, _>::build(&[(8, 2), (12, 4), (16, 8)], 100, 1).unwrap();
+    cascade.store_vecs(&prep_vecs(1000, 100)).unwrap();
+    let qs = prep_vecs(200, 100);
+    // report the latency distribution of single queries once.
+    let mut latencies: Vec<_> = qs
+        .iter()
+        .map(|q| {
+            let t0 = std::time::Instant::now();
+            cascade.query_until(q, min_candidates).unwrap();
+            t0.elapsed()
+        })
+        .collect();
+    latencies.sort_unstable();
+    println!(
+        "min_candidates {}: p50 {:?} p90 {:?} p99 {:?}",
+        min_candidates, latencies[100], latencies[180], latencies[198]
+    );
+    b.iter(|| {
+        for q in &qs[..16] {
+            cascade.query_until(q, min_candidates).unwrap();
+        }
+    });
+}
+
+#[bench]
+fn bench_cascade_query_until_10(b: &mut Bencher) {
+    bench_cascade_query_until(b, 10)
+}
+
+#[bench]
+fn bench_cascade_query_until_all(b: &mut Bencher) {
+    bench_cascade_query_until(b, usize::MAX)
+}
+
+#[bench]
+fn bench_query_hnsw_entry(b: &mut Bencher) {
+    let v = prep_vecs(1000, 100);
+    let mut lsh: LSH<MemoryTable, _> = LSH::new(12, 4, 100).seed(1).srp().unwrap();
+    lsh.multi_probe(8);
+    lsh.store_vecs(&v).unwrap();
+    // report the recall of the stored vectors for perturbed queries once.
+    let noisy: Vec<Vec<f32>> = v[..200]
+        .iter()
+        .zip(&v[500..700])
+        .map(|(x, z)| x.iter().zip(z).map(|(a, b)| a + 0.6 * b).collect())
+        .collect();
+    let recall_lsh = recall(noisy.iter().map(|q| lsh.query_bucket_ids(q).unwrap()));
+    // the graph is built from the data points stored in the same LSH.
+    let index = LSHWithHnswEntry::from_lsh(lsh, 16, 64).unwrap();
+    println!(
+        "recall multi-probe: {} hnsw entry multi-probe: {}",
+        recall_lsh,
+        recall(noisy.iter().map(|q| index.query_bucket_ids(q).unwrap()))
+    );
+    let qs = prep_vecs(16, 100);
+    b.iter(|| {
+        for q in &qs {
+            index.query_bucket_ids(q).unwrap();
+        }
+    });
+}
@@ -0,0 +1,267 @@
+//! Exact search for small datasets, LSH once the dataset grows.
+use crate::{
+    dist::{l2_dist, rank_candidates},
+    lsh::lsh::validate_dim,
+    DataId, DataPoint, DataPointSlice, Error, HashTables, Result, SignRandomProjections, VecHash,
+    LSH,
+};
+
+/// Default number of data points up to which [AdaptiveLSH](struct.AdaptiveLSH.html) uses a
+/// [FlatL2Index](struct.FlatL2Index.html).
+pub const DEFAULT_MIN_ITEMS: usize = 100;
+
+/// Exact index that compares a query with every stored data point. The id of a data point is
+/// its insertion order.
+pub struct FlatL2Index {
+    dim: usize,
+    points: Vec<DataPoint>,
+}
+
+impl FlatL2Index {
+    pub fn new(dim: usize) -> Self {
+        FlatL2Index {
+            dim,
+            points: vec![],
+        }
+    }
+
+    /// Number of stored data points.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Stored data points, ordered by id.
+    pub fn points(&self) -> &[DataPoint] {
+        &self.points
+    }
+
+    /// Store a single data point. Returns id.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn store_vec(&mut self, v: &DataPointSlice) -> Result<DataId> {
+        validate_dim(v, self.dim)?;
+        self.points.push(v.to_vec());
+        Ok((self.points.len() - 1) as DataId)
+    }
+
+    /// Query the `k` nearest neighbors of `v`. Returns `(id, distance)` pairs sorted by distance.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    /// * `k` - Number of neighbors.
+    pub fn query_knn(&self, v: &DataPointSlice, k: usize) -> Vec<(DataId, f32)> {
+        let dists = self
+            .points
+            .iter()
+            .enumerate()
+            .map(|(id, p)| (id as DataId, l2_dist(v, p)))
+            .collect();
+        rank_candidates(dists, k)
+    }
+}
+
+/// Nearest neighbor index that searches exactly with a [FlatL2Index](struct.FlatL2Index.html)
+/// while at most `min_items` data points are stored. The store that exceeds `min_items` moves
+/// all data points to the [LSH](../struct.LSH.html), after which queries are approximate.
+/// The LSH assigns the same ids as the flat index. Neighbors are ranked by L2 distance.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::AdaptiveLSH;
+/// let mut index = AdaptiveLSH::<lsh_rs::MemoryTable, _>::new(5, 10, 3, 1).unwrap();
+/// index.store_vecs(&[vec![0., 0., 0.], vec![1., 1., 1.]]).unwrap();
+/// assert!(index.uses_flat_index());
+/// assert_eq!(index.query_knn(&[0.9, 1., 1.], 1).unwrap()[0].0, 1);
+/// ```
+pub struct AdaptiveLSH<T: HashTables, H: VecHash> {
+    lsh: LSH<T, H>,
+    flat: Option<FlatL2Index>,
+    min_items: usize,
+    /// Number of data points of the flat index that are already stored in the LSH.
+    n_migrated: usize,
+}
+
+impl<T: HashTables> AdaptiveLSH<T, SignRandomProjections> {
+    /// Create a new adaptive index with a SignRandomProjections LSH and the default `min_items`.
+    ///
+    /// # Arguments
+    ///
+    /// * `n_projections` - Hash length.
+    /// * `n_hash_tables` - Number of hash tables.
+    /// * `dim` - Dimensions of the data points.
+    /// * `seed` - Seed of the hash functions. If 0, randomness is seeded from the os.
+    pub fn new(n_projections: usize, n_hash_tables: usize, dim: usize, seed: u64) -> Result<Self> {
+        let lsh = LSH::new(n_projections, n_hash_tables, dim)
+            .seed(seed)
+            .srp()?;
+        AdaptiveLSH::from_lsh(lsh, DEFAULT_MIN_ITEMS)
+    }
+}
+
+impl<T: HashTables, H: VecHash> AdaptiveLSH<T, H> {
+    /// Wrap an LSH. If it already stores data points, the flat index is skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `lsh` - LSH that is used once more than `min_items` data points are stored.
+    /// * `min_items` - Maximum number of data points that are searched exactly.
+    pub fn from_lsh(lsh: LSH<T, H>, min_items: usize) -> Result<Self> {
+        let is_empty = lsh
+            .hash_tables
+            .as_ref()
+            .unwrap()
+            .to_bucket_map()?
+            .iter()
+            .all(|tbl| tbl.is_empty());
+        let flat = if is_empty {
+            Some(FlatL2Index::new(lsh.dim))
+        } else {
+            None
+        };
+        Ok(AdaptiveLSH {
+            lsh,
+            flat,
+            min_items,
+            n_migrated: 0,
+        })
+    }
+
+    /// If queries are answered exactly by the flat index.
+    pub fn uses_flat_index(&self) -> bool {
+        self.flat.is_some()
+    }
+
+    /// Underlying LSH. Empty while the flat index is used.
+    pub fn lsh(&self) -> &LSH<T, H> {
+        &self.lsh
+    }
+
+    /// Move the data points of the flat index to the LSH. The flat index is kept until all data
+    /// points are stored, so a failed migration loses nothing and resumes on the next store.
+    fn migrate(&mut self) -> Result<()> {
+        let flat = match self.flat.as_ref() {
+            Some(flat) => flat,
+            None => return Ok(()),
+        };
+        for (expected, v) in flat.points.iter().enumerate().skip(self.n_migrated) {
+            if self.lsh.store_vec(v)? != expected as DataId {
+                return Err(Error::Failed(
+                    "LSH assigned other ids than the flat index".to_string(),
+                ));
+            }
+            self.n_migrated += 1;
+        }
+        self.flat = None;
+        Ok(())
+    }
+
+    /// Store a single data point. Returns id. If this exceeds `min_items`, all data points are
+    /// moved to the LSH. If moving them fails, the data point is still stored in the flat index.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn store_vec(&mut self, v: &DataPointSlice) -> Result<DataId> {
+        match self.flat.as_mut() {
+            Some(flat) => {
+                let id = flat.store_vec(v)?;
+                if flat.len() > self.min_items {
+                    self.migrate()?;
+                }
+                Ok(id)
+            }
+            None => self.lsh.store_vec(v),
+        }
+    }
+
+    /// Store multiple data points. Returns the ids.
+    ///
+    /// # Arguments
+    /// * `vs` - Data points.
+    pub fn store_vecs(&mut self, vs: &[DataPoint]) -> Result<Vec<DataId>> {
+        vs.iter().map(|v| self.store_vec(v)).collect()
+    }
+
+    /// Query the `k` nearest neighbors of `v`. Exact with the flat index, approximate over the
+    /// colliding candidates otherwise. Returns `(id, distance)` pairs sorted by distance.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    /// * `k` - Number of neighbors.
+    pub fn query_knn(&self, v: &DataPointSlice, k: usize) -> Result<Vec<(DataId, f32)>> {
+        self.lsh.validate_vec(v)?;
+        if let Some(flat) = &self.flat {
+            return Ok(flat.query_knn(v, k));
+        }
+        let ht = self.lsh.hash_tables.as_ref().unwrap();
+        let ids = self
+            .lsh
+            .query_bucket_ids(v)?
+            .into_iter()
+            .map(|id| Ok((id, l2_dist(&ht.idx_to_datapoint_owned(id)?, v))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(rank_candidates(ids, k))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{utils::create_rng, MemoryTable};
+    use rand::Rng;
+
+    #[test]
+    fn test_adaptive_migration() {
+        let mut rng = create_rng(1);
+        let vs: Vec<DataPoint> = (0..30)
+            .map(|_| (0..3).map(|_| rng.gen_range(-1., 1.)).collect())
+            .collect();
+        let lsh = LSH::new(5, 4, 3).seed(1).srp().unwrap();
+        let mut index = AdaptiveLSH::<MemoryTable, _>::from_lsh(lsh, 20).unwrap();
+        let ids = index.store_vecs(&vs[..20]).unwrap();
+        assert_eq!(ids, (0..20).collect::<Vec<DataId>>());
+        assert!(index.uses_flat_index());
+
+        // exact while flat.
+        let q = [0.1, 0.2, 0.3];
+        let mut brute: Vec<(DataId, f32)> = vs[..20]
+            .iter()
+            .enumerate()
+            .map(|(id, v)| (id as DataId, l2_dist(&q, v)))
+            .collect();
+        brute.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+        brute.truncate(3);
+        assert_eq!(index.query_knn(&q, 3).unwrap(), brute);
+        assert!(index.query_knn(&[1., 2.], 3).is_err());
+
+        // the 21st data point moves everything to the LSH, with the same ids.
+        assert_eq!(index.store_vecs(&vs[20..]).unwrap()[0], 20);
+        assert!(!index.uses_flat_index());
+        for (id, v) in vs.iter().enumerate() {
+            assert_eq!(index.query_knn(v, 1).unwrap(), vec![(id as DataId, 0.)]);
+        }
+    }
+
+    #[test]
+    fn test_failed_migration_keeps_flat_index() {
+        // the cleared LSH still counts its data point, so it assigns other ids than the flat index.
+        let mut lsh = LSH::new(5, 4, 3).seed(1).srp().unwrap();
+        lsh.store_vec(&[1., 1., 1.]).unwrap();
+        for i in 0..4 {
+            lsh.clear_table(i).unwrap();
+        }
+        let mut index = AdaptiveLSH::<MemoryTable, _>::from_lsh(lsh, 2).unwrap();
+        let vs = [vec![0., 0., 1.], vec![0., 1., 0.], vec![1., 0., 0.]];
+        index.store_vecs(&vs[..2]).unwrap();
+        assert!(index.store_vec(&vs[2]).is_err());
+        assert!(index.uses_flat_index());
+        for (id, v) in vs.iter().enumerate() {
+            assert_eq!(index.query_knn(v, 1).unwrap(), vec![(id as DataId, 0.)]);
+        }
+    }
+}
@@ -0,0 +1,446 @@
+//! Hyperparameter sensitivity analysis.
+use crate::{
+    config::HasherType,
+    dist::{cosine_sim, inner_prod, l2_dist, sample_pair_distances, DistanceMetric},
+    eval::candidate_stats,
+    utils::create_rng,
+    DataId, DataPoint, Error, LshConfig, LshMem, Result, VecHash, L2, MIPS,
+};
+use rand::seq::index::sample;
+use rand::Rng;
+
+/// Histogram of the distances of random pairs of data points, e.g. to choose the parameters of
+/// an LSH. The distances are binned in `n_bins` bins of equal width between the smallest and
+/// the largest sampled distance. Returns `(bin_upper, count)` pairs. For `Cosine` the distance
+/// is `1 - cosine similarity` and for `InnerProduct` the negative inner product.
+///
+/// # Arguments
+/// * `data` - Data points.
+/// * `n_pairs` - Number of random pairs that are sampled.
+/// * `n_bins` - Number of bins.
+/// * `seed` - Seed for the RNG. If 0, RNG is seeded randomly.
+/// * `metric` - Distance of a pair.
+///
+/// # Panics
+///
+/// Panics if `data` contains less than two data points.
+pub fn distance_histogram(
+    data: &[DataPoint],
+    n_pairs: usize,
+    n_bins: usize,
+    seed: u64,
+    metric: DistanceMetric,
+) -> Vec<(f32, usize)> {
+    assert!(data.len() > 1, "at least two data points are needed");
+    let distances = sample_pair_distances(data, n_pairs, seed, metric);
+    if n_bins == 0 || distances.is_empty() {
+        return vec![];
+    }
+    let min = distances.iter().copied().fold(f32::INFINITY, f32::min);
+    let max = distances.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let width = (max - min) / n_bins as f32;
+    let mut counts = vec![0; n_bins];
+    for d in distances {
+        let bin = if width > 0. {
+            ((d - min) / width) as usize
+        } else {
+            0
+        };
+        // the largest distance is the upper edge of the last bin.
+        counts[bin.min(n_bins - 1)] += 1;
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| {
+            let upper = if i == n_bins - 1 {
+                max
+            } else {
+                min + (i + 1) as f32 * width
+            };
+            (upper, count)
+        })
+        .collect()
+}
+
+/// Compute the recall of the L2 hasher for different values of `r`.
+///
+/// For every `r` an in memory LSH is built on `data`. Then `k_queries` random data points are
+/// queried. A query counts as a hit if its exact nearest neighbor (excluding itself) is among the
+/// candidates. Returns `(r, recall)` pairs.
+///
+/// # Arguments
+/// * `data` - Data points.
+/// * `r_values` - Values of `r` to evaluate.
+/// * `n_projections` - Number of projections of every LSH.
+/// * `n_hash_tables` - Number of hash tables of every LSH.
+/// * `k_queries` - Number of data points that are queried.
+/// * `seed` - Seed for the LSH and the query sampling. If 0, RNG's are seeded randomly.
+pub fn recall_curve(
+    data: &[DataPoint],
+    r_values: &[f32],
+    n_projections: usize,
+    n_hash_tables: usize,
+    k_queries: usize,
+    seed: u64,
+) -> Result<Vec<(f32, f32)>> {
+    if data.len() < 2 {
+        return Err(Error::Failed(
+            "at least two data points are needed".to_string(),
+        ));
+    }
+    let dim = data[0].len();
+    let mut rng = create_rng(seed);
+    let k_queries = std::cmp::min(k_queries, data.len());
+    let query_idx = sample(&mut rng, data.len(), k_queries).into_vec();
+
+    // ground truth
+    let nn: Vec<DataId> = query_idx
+        .iter()
+        .map(|&i| {
+            (0..data.len())
+                .filter(|&j| j != i)
+                .min_by(|&a, &b| {
+                    l2_dist(&data[i], &data[a]).total_cmp(&l2_dist(&data[i], &data[b]))
+                })
+                .unwrap() as DataId
+        })
+        .collect();
+
+    r_values
+        .iter()
+        .map(|&r| {
+            let mut lsh = LshMem::new(n_projections, n_hash_tables, dim)
+                .seed(seed)
+                .only_index()
+                .l2(r)?;
+            lsh.store_vecs(data)?;
+            let mut hits = 0;
+            for (&i, nn) in query_idx.iter().zip(&nn) {
+                if lsh.query_bucket_ids(&data[i])?.contains(nn) {
+                    hits += 1
+                }
+            }
+            Ok((r, hits as f32 / k_queries as f32))
+        })
+        .collect()
+}
+
+/// Store `sample` and return the recall of the nearest neighbors `nn` of the `queries` and the
+/// average number of candidates times `scale`.
+fn evaluate_recall<H: VecHash + Sync>(
+    mut lsh: LshMem<H>,
+    sample: &[DataPoint],
+    queries: &[usize],
+    nn: &[DataId],
+    scale: f32,
+) -> Result<(f32, f32)> {
+    lsh.store_vecs(sample)?;
+    let mut hits = 0;
+    for (&i, nn) in queries.iter().zip(nn) {
+        if lsh.query_bucket_ids(&sample[i])?.contains(nn) {
+            hits += 1
+        }
+    }
+    let query_points: Vec<DataPoint> = queries.iter().map(|&i| sample[i].clone()).collect();
+    let candidates = candidate_stats(&lsh, &query_points)?.mean * scale;
+    Ok((hits as f32 / queries.len() as f32, candidates))
+}
+
+/// Multiples of the median pairwise distance that are tried as `r` of the L2 and MIPS hashers.
+const R_SCALES: [f32; 4] = [0.5, 1., 2., 4.];
+
+/// Hash family and parameters recommended by [RecallOptimizer](struct.RecallOptimizer.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct HasherRecommendation {
+    pub hasher_type: HasherType,
+    /// Parameters of the recommended LSH. It can be built with
+    /// [LshConfig::build_fitted](../config/struct.LshConfig.html#method.build_fitted).
+    pub params: LshConfig,
+    /// Fraction of the sampled queries of which the exact nearest neighbor is a candidate.
+    pub estimated_recall: f32,
+    /// Average number of candidates per query, extrapolated to all data points.
+    pub estimated_candidates: f32,
+}
+
+/// Select the hash family (SRP, L2 or MIPS) with the best recall for a dataset.
+///
+/// Every family is evaluated with calibrated parameters on a subsample of the data. The sampled
+/// data points are stored and queried; a query is a hit if its exact nearest neighbor under the
+/// given similarity (excluding itself) is among the candidates.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::{analysis::RecallOptimizer, dist::DistanceMetric};
+/// let data: Vec<Vec<f32>> = (0..200)
+///     .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+///     .collect();
+/// let rec = RecallOptimizer::default()
+///     .recommend(&data, DistanceMetric::Cosine, 20)
+///     .unwrap();
+/// assert!(rec.estimated_candidates <= 20.);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RecallOptimizer {
+    pub n_projections: usize,
+    pub n_hash_tables: usize,
+    /// Fraction of the data points that are sampled.
+    pub sample_fraction: f64,
+    /// Maximum number of sampled data points that are queried.
+    pub n_queries: usize,
+    /// If 0, RNG's are seeded randomly.
+    pub seed: u64,
+}
+
+impl Default for RecallOptimizer {
+    fn default() -> Self {
+        RecallOptimizer {
+            n_projections: 8,
+            n_hash_tables: 10,
+            sample_fraction: 0.1,
+            n_queries: 100,
+            seed: 1,
+        }
+    }
+}
+
+impl RecallOptimizer {
+    fn new_lsh<H: VecHash>(&self, dim: usize) -> LshMem<H> {
+        let mut lsh = LshMem::new(self.n_projections, self.n_hash_tables, dim);
+        lsh.seed(self.seed).only_index();
+        lsh
+    }
+
+    /// Recommend the configuration with the highest recall that returns at most
+    /// `budget_candidates` candidates per query on average. If no configuration is within
+    /// budget, the one with the fewest candidates is returned.
+    ///
+    /// # Arguments
+    /// * `data` - Data points.
+    /// * `similarity` - Similarity under which the nearest neighbors are searched.
+    /// * `budget_candidates` - Maximum average number of candidates per query.
+    pub fn recommend(
+        &self,
+        data: &[DataPoint],
+        similarity: DistanceMetric,
+        budget_candidates: usize,
+    ) -> Result<HasherRecommendation> {
+        let results = self.evaluate(data, similarity)?;
+        let budget = budget_candidates as f32;
+        let best = results
+            .iter()
+            .filter(|(_, _, candidates)| *candidates <= budget)
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(b.2.total_cmp(&a.2)))
+            .or_else(|| results.iter().min_by(|a, b| a.2.total_cmp(&b.2)))
+            .unwrap()
+            .clone();
+        Ok(HasherRecommendation {
+            hasher_type: best.0.hasher,
+            params: best.0,
+            estimated_recall: best.1,
+            estimated_candidates: best.2,
+        })
+    }
+
+    /// Evaluate all configurations. Returns the configurations with their estimated recall and
+    /// number of candidates.
+    fn evaluate(
+        &self,
+        data: &[DataPoint],
+        similarity: DistanceMetric,
+    ) -> Result<Vec<(LshConfig, f32, f32)>> {
+        let n_sample = ((data.len() as f64 * self.sample_fraction).ceil() as usize)
+            .max(2)
+            .min(data.len());
+        if n_sample < 2 {
+            return Err(Error::Failed(
+                "at least two data points are needed".to_string(),
+            ));
+        }
+        let dim = data[0].len();
+        let mut rng = create_rng(self.seed);
+        let sample: Vec<DataPoint> = sample(&mut rng, data.len(), n_sample)
+            .into_iter()
+            .map(|i| data[i].clone())
+            .collect();
+        let queries: Vec<usize> = (0..std::cmp::min(self.n_queries, n_sample)).collect();
+        let score = |a: &[f32], b: &[f32]| match similarity {
+            DistanceMetric::L2 => -l2_dist(a, b),
+            // NaN of zero vectors is the lowest similarity.
+            DistanceMetric::Cosine => cosine_sim(a, b).max(-1.),
+            DistanceMetric::InnerProduct => inner_prod(a, b),
+        };
+        // the ids of the sample are its indexes.
+        let nn: Vec<DataId> = queries
+            .iter()
+            .map(|&i| {
+                (0..n_sample)
+                    .filter(|&j| j != i)
+                    .max_by(|&a, &b| {
+                        score(&sample[i], &sample[a]).total_cmp(&score(&sample[i], &sample[b]))
+                    })
+                    .unwrap() as DataId
+            })
+            .collect();
+        // the candidates of the sample are extrapolated to all data points.
+        let scale = data.len() as f32 / n_sample as f32;
+        let config = |hasher, r, U, m| LshConfig {
+            n_projections: self.n_projections,
+            n_hash_tables: self.n_hash_tables,
+            dim,
+            seed: self.seed,
+            multi_probe_budget: None,
+            hasher,
+            r,
+            U,
+            m,
+        };
+
+        let mut results = vec![];
+        let (recall, candidates) =
+            evaluate_recall(self.new_lsh(dim).srp()?, &sample, &queries, &nn, scale)?;
+        results.push((
+            config(HasherType::Srp, None, None, None),
+            recall,
+            candidates,
+        ));
+        let median = L2::calibrate_r(&sample, 0.5, 1000, self.seed)?;
+        for &r_scale in &R_SCALES {
+            let r = median * r_scale;
+            let (recall, candidates) =
+                evaluate_recall(self.new_lsh(dim).l2(r)?, &sample, &queries, &nn, scale)?;
+            results.push((
+                config(HasherType::L2, Some(r), None, None),
+                recall,
+                candidates,
+            ));
+        }
+        let (U, m) = (0.83, 3);
+        let mut fitted = MIPS::new(dim, 1., U, m, self.n_projections, self.seed);
+        let flat = sample.concat();
+        fitted.fit(&flat);
+        // queries and data points are transformed differently, so `r` is calibrated on the
+        // distances between transformed queries and transformed data points.
+        let mut distances: Vec<f32> = (0..1000)
+            .map(|_| {
+                let q = fitted.transform_query(&sample[rng.gen_range(0, n_sample)]);
+                let p = fitted.tranform_put(&sample[rng.gen_range(0, n_sample)]);
+                l2_dist(&q, &p)
+            })
+            .collect();
+        distances.sort_unstable_by(|a, b| a.total_cmp(b));
+        let median = distances[distances.len() / 2];
+        for &r_scale in &R_SCALES {
+            let r = median * r_scale;
+            let mut lsh = self.new_lsh(dim).mips(r, U, m)?;
+            for h in &mut lsh.hashers {
+                h.fit(&flat);
+            }
+            let (recall, candidates) = evaluate_recall(lsh, &sample, &queries, &nn, scale)?;
+            results.push((
+                config(HasherType::Mips, Some(r), Some(U), Some(m)),
+                recall,
+                candidates,
+            ));
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_recall_curve() {
+        let data: Vec<DataPoint> = (0..100)
+            .map(|i| {
+                vec![
+                    (i as f32 * 0.37).sin() * 5.,
+                    (i as f32 * 0.11).cos() * 5.,
+                    1.,
+                ]
+            })
+            .collect();
+        let curve = recall_curve(&data, &[0.1, 20.], 4, 4, 20, 1).unwrap();
+        assert_eq!(curve.len(), 2);
+        assert_eq!(curve[1].0, 20.);
+        // a larger bucket width leads to more collisions
+        assert!(curve[1].1 >= curve[0].1);
+        assert!(recall_curve(&data[..1], &[1.], 4, 4, 20, 1).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_candidates() {
+        let data: Vec<DataPoint> = (0..50)
+            .map(|i| vec![(i as f32).sin() * 3., (i as f32 * 0.7).cos(), 1.])
+            .collect();
+        let mut optimizer = RecallOptimizer {
+            sample_fraction: 1.,
+            ..Default::default()
+        };
+        // no more candidates than data points, also with the largest `r`.
+        let results = optimizer.evaluate(&data, DistanceMetric::L2).unwrap();
+        assert_eq!(results.len(), 1 + 2 * R_SCALES.len());
+        assert!(results.iter().all(|(_, _, candidates)| *candidates <= 50.));
+
+        // the candidates of the sample are extrapolated.
+        optimizer.sample_fraction = 0.5;
+        let results = optimizer.evaluate(&data, DistanceMetric::L2).unwrap();
+        assert!(results.iter().all(|(_, _, candidates)| *candidates <= 50.));
+        assert!(results.iter().any(|(_, _, candidates)| *candidates > 25.));
+    }
+
+    #[test]
+    fn test_distance_histogram() {
+        let data: Vec<DataPoint> = (0..50).map(|i| vec![i as f32, 0.]).collect();
+        let hist = distance_histogram(&data, 1000, 7, 1, DistanceMetric::L2);
+        assert_eq!(hist.len(), 7);
+        assert_eq!(hist.iter().map(|(_, c)| c).sum::<usize>(), 1000);
+        assert!(hist.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(hist[6].0 <= 49.);
+        // pairs on a line have more small distances than large ones.
+        assert!(hist[0].1 > hist[6].1);
+        // same points, a single distance.
+        let same = vec![vec![1., 1.]; 3];
+        let hist = distance_histogram(&same, 10, 2, 1, DistanceMetric::Cosine);
+        assert_eq!(hist.iter().map(|(_, c)| c).sum::<usize>(), 10);
+        assert!(distance_histogram(&data, 10, 0, 1, DistanceMetric::L2).is_empty());
+    }
+
+    #[test]
+    fn test_recommend() {
+        use crate::utils::rand_unit_vec;
+        let mut rng = create_rng(3);
+        // directions with very different norms
+        let data: Vec<DataPoint> = (0..2000)
+            .map(|i| {
+                let norm = 1. + (i % 10) as f32 * 10.;
+                rand_unit_vec(10, &mut rng)
+                    .iter()
+                    .map(|x| x * norm)
+                    .collect()
+            })
+            .collect();
+        let optimizer = RecallOptimizer::default();
+        for (sim, expected) in [
+            (DistanceMetric::Cosine, HasherType::Srp),
+            (DistanceMetric::L2, HasherType::L2),
+            (DistanceMetric::InnerProduct, HasherType::Mips),
+        ] {
+            let rec = optimizer.recommend(&data, sim, 700).unwrap();
+            assert_eq!(rec.hasher_type, expected);
+            assert_eq!(rec.params.hasher, expected);
+            assert!(rec.estimated_candidates <= 700.);
+            assert!(rec.params.build_fitted(&data).is_ok());
+            assert!(rec.estimated_recall > 0.4);
+        }
+        // nothing within budget, the fewest candidates.
+        let rec = optimizer.recommend(&data, DistanceMetric::L2, 0).unwrap();
+        assert!(rec.estimated_candidates < 1.);
+        assert!(optimizer
+            .recommend(&data[..1], DistanceMetric::L2, 10)
+            .is_err());
+    }
+}
@@ -0,0 +1,467 @@
+//! Audit trail of the insertions of an LSH in an on-disk ring buffer.
+use crate::{
+    DataId, DataPoint, DataPointSlice, Error, Hash, HashPrimitive, HashTables, Result, VecHash, LSH,
+};
+use fnv::{FnvHashMap, FnvHasher};
+use std::fs::{File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const AUDIT_MAGIC: &[u8; 4] = b"LSHA";
+const AUDIT_VERSION: u32 = 2;
+/// magic, version, capacity, n_hash_tables, hash length, dim, head, count
+const HEADER_BYTES: u64 = 4 + 4 + 6 * 8;
+
+/// A logged insertion or deletion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub id: DataId,
+    /// Milliseconds since the unix epoch.
+    pub timestamp_ms: u64,
+    /// `true` if the data point was deleted. Deletions have no hashes and no data point.
+    pub deleted: bool,
+    /// Hash per hash table.
+    pub hashes: Vec<Hash>,
+    pub data_point: DataPoint,
+}
+
+/// Fixed size layout of the slots of the ring buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Layout {
+    capacity: u64,
+    n_hash_tables: u64,
+    hash_len: u64,
+    dim: u64,
+}
+
+impl Layout {
+    /// checksum, id, timestamp, deleted, hashes, data point
+    fn slot_bytes(&self) -> u64 {
+        4 * 8 + self.n_hash_tables * self.hash_len + self.dim * 4
+    }
+
+    // `DataId` is `u64` with the `id64` feature.
+    #[allow(clippy::useless_conversion)]
+    fn encode(&self, e: &AuditEntry) -> Vec<u8> {
+        let mut slot = Vec::with_capacity(self.slot_bytes() as usize);
+        slot.extend_from_slice(&[0; 8]);
+        slot.extend_from_slice(&u64::from(e.id).to_le_bytes());
+        slot.extend_from_slice(&e.timestamp_ms.to_le_bytes());
+        slot.extend_from_slice(&u64::from(e.deleted).to_le_bytes());
+        for hash in &e.hashes {
+            slot.extend(hash.iter().map(|&h| h as u8));
+        }
+        for x in &e.data_point {
+            slot.extend_from_slice(&x.to_le_bytes());
+        }
+        // deletions are padded.
+        slot.resize(self.slot_bytes() as usize, 0);
+        let checksum = checksum(&slot[8..]);
+        slot[..8].copy_from_slice(&checksum.to_le_bytes());
+        slot
+    }
+
+    /// `None` for unwritten or torn slots.
+    fn decode(&self, slot: &[u8]) -> Option<AuditEntry> {
+        let u64_at = |i: usize| {
+            let mut b = [0; 8];
+            b.copy_from_slice(&slot[i..i + 8]);
+            u64::from_le_bytes(b)
+        };
+        if u64_at(0) != checksum(&slot[8..]) {
+            return None;
+        }
+        let deleted = u64_at(24) != 0;
+        if deleted {
+            return Some(AuditEntry {
+                id: u64_at(8) as DataId,
+                timestamp_ms: u64_at(16),
+                deleted,
+                hashes: vec![],
+                data_point: vec![],
+            });
+        }
+        let (hash_len, n_hash_tables) = (self.hash_len as usize, self.n_hash_tables as usize);
+        let hashes_end = 32 + n_hash_tables * hash_len;
+        let hashes = slot[32..hashes_end]
+            .chunks(hash_len.max(1))
+            .take(n_hash_tables)
+            .map(|h| h.iter().map(|&b| b as HashPrimitive).collect())
+            .collect();
+        let data_point = slot[hashes_end..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        Some(AuditEntry {
+            id: u64_at(8) as DataId,
+            timestamp_ms: u64_at(16),
+            deleted,
+            hashes,
+            data_point,
+        })
+    }
+}
+
+/// Read the `count` entries of a log that ends before slot `head`, oldest first, one slot at a
+/// time. Torn entries of an interrupted write are skipped.
+fn read_log(
+    file: &File,
+    layout: Layout,
+    head: u64,
+    count: u64,
+) -> impl Iterator<Item = Result<AuditEntry>> + '_ {
+    let mut reader = BufReader::new(file);
+    let mut slot = vec![0; layout.slot_bytes() as usize];
+    let start = (head + layout.capacity - count) % layout.capacity;
+    (0..count).filter_map(move |i| {
+        let idx = (start + i) % layout.capacity;
+        // the slots are read sequentially, except at the start and when the ring wraps around.
+        let read = if i == 0 || idx == 0 {
+            reader
+                .seek(SeekFrom::Start(HEADER_BYTES + idx * layout.slot_bytes()))
+                .and_then(|_| reader.read_exact(&mut slot))
+        } else {
+            reader.read_exact(&mut slot)
+        };
+        match read {
+            Ok(()) => layout.decode(&slot).map(Ok),
+            Err(e) => Some(Err(e.into())),
+        }
+    })
+}
+
+/// Milliseconds since the unix epoch.
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+fn checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(bytes);
+    // never 0, so that zeroed slots are invalid.
+    hasher.finish() | 1
+}
+
+/// [LSH](../struct.LSH.html) that logs every stored data point (id, hashes, timestamp and the
+/// data point) and every deleted id to an append-only ring buffer on disk.
+///
+/// The log holds the last `capacity` insertions and deletions. After a crash, restore the last
+/// snapshot of the LSH (e.g. with [load](../struct.LSH.html#method.load)) and pass it to
+/// [open](struct.AuditedLSH.html#method.open), which replays the logged operations since the
+/// snapshot.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::{AuditedLSH, LshMem};
+/// let dir = tempfile::tempdir().unwrap();
+/// let path = dir.path().join("audit.log");
+/// let lsh = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+/// let mut audited = AuditedLSH::create(lsh, &path, 100).unwrap();
+/// let id = audited.store_vec(&[2., 3., 4.]).unwrap();
+/// assert_eq!(audited.audit_log().next().unwrap().unwrap().id, id);
+/// ```
+pub struct AuditedLSH<T: HashTables, H: VecHash> {
+    lsh: LSH<T, H>,
+    file: File,
+    layout: Layout,
+    /// Next slot to write.
+    head: u64,
+    /// Number of written slots.
+    count: u64,
+}
+
+impl<T: HashTables, H: VecHash> AuditedLSH<T, H> {
+    fn layout_of(lsh: &LSH<T, H>, capacity: u64) -> Layout {
+        Layout {
+            capacity,
+            n_hash_tables: lsh.n_hash_tables as u64,
+            hash_len: lsh.hashers.first().map_or(0, |h| h.n_projections()) as u64,
+            dim: lsh.dim as u64,
+        }
+    }
+
+    /// Wrap an LSH and start a new log at `path`. An existing file is overwritten.
+    ///
+    /// # Arguments
+    /// * `lsh` - LSH that stores the data points.
+    /// * `path` - Path of the log.
+    /// * `capacity` - Maximum number of entries in the log. Older entries are overwritten.
+    pub fn create<P: AsRef<Path>>(lsh: LSH<T, H>, path: P, capacity: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(Error::Failed("capacity should be positive".to_string()));
+        }
+        let layout = AuditedLSH::layout_of(&lsh, capacity as u64);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(HEADER_BYTES + layout.capacity * layout.slot_bytes())?;
+        let mut audited = AuditedLSH {
+            lsh,
+            file,
+            layout,
+            head: 0,
+            count: 0,
+        };
+        audited.write_header()?;
+        Ok(audited)
+    }
+
+    /// Wrap an LSH and continue the existing log at `path`. The last logged operation of every
+    /// id is replayed: insertions of which the id has no data point in `lsh`, e.g. the
+    /// insertions after the last snapshot, and deletions of which the id still has a data point.
+    ///
+    /// # Arguments
+    /// * `lsh` - LSH, e.g. restored from a snapshot.
+    /// * `path` - Path of the log.
+    pub fn open<P: AsRef<Path>>(lsh: LSH<T, H>, path: P) -> Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut header = [0; HEADER_BYTES as usize];
+        file.read_exact(&mut header)?;
+        let u64_at = |i: usize| {
+            let mut b = [0; 8];
+            b.copy_from_slice(&header[i..i + 8]);
+            u64::from_le_bytes(b)
+        };
+        let version = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        if &header[..4] != AUDIT_MAGIC || version != AUDIT_VERSION {
+            return Err(Error::Failed(
+                "not an audit log of this version".to_string(),
+            ));
+        }
+        let layout = Layout {
+            capacity: u64_at(8),
+            n_hash_tables: u64_at(16),
+            hash_len: u64_at(24),
+            dim: u64_at(32),
+        };
+        if layout != AuditedLSH::layout_of(&lsh, layout.capacity) {
+            return Err(Error::Failed(
+                "the number of hash tables, hash length or dimension of the log don't match"
+                    .to_string(),
+            ));
+        }
+        let mut audited = AuditedLSH {
+            lsh,
+            file,
+            layout,
+            head: u64_at(40),
+            count: u64_at(48),
+        };
+
+        // position of the last logged operation per id.
+        let mut last = FnvHashMap::default();
+        for (i, e) in read_log(&audited.file, layout, audited.head, audited.count).enumerate() {
+            last.insert(e?.id, i);
+        }
+        let log = read_log(&audited.file, layout, audited.head, audited.count);
+        let lsh = &mut audited.lsh;
+        for (i, e) in log.enumerate() {
+            let e = e?;
+            if last[&e.id] != i {
+                continue;
+            }
+            let stored = lsh
+                .hash_tables
+                .as_ref()
+                .unwrap()
+                .idx_to_datapoint_owned(e.id)
+                .is_ok();
+            if e.deleted && stored {
+                lsh.delete_by_id(e.id)?;
+            } else if !e.deleted && !stored {
+                let ht = lsh.hash_tables.as_mut().unwrap();
+                for (i, hash) in e.hashes.into_iter().enumerate() {
+                    ht.put_with_id(e.id, hash, &e.data_point, i)?;
+                }
+            }
+        }
+        Ok(audited)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let mut header = Vec::with_capacity(HEADER_BYTES as usize);
+        header.extend_from_slice(AUDIT_MAGIC);
+        header.extend_from_slice(&AUDIT_VERSION.to_le_bytes());
+        for x in &[
+            self.layout.capacity,
+            self.layout.n_hash_tables,
+            self.layout.hash_len,
+            self.layout.dim,
+            self.head,
+            self.count,
+        ] {
+            header.extend_from_slice(&x.to_le_bytes());
+        }
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&header)?;
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &AuditEntry) -> Result<()> {
+        if entry
+            .hashes
+            .iter()
+            .any(|h| h.len() as u64 != self.layout.hash_len)
+        {
+            return Err(Error::Failed(
+                "hash length doesn't match the audit log".to_string(),
+            ));
+        }
+        let offset = HEADER_BYTES + self.head * self.layout.slot_bytes();
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&self.layout.encode(entry))?;
+        self.head = (self.head + 1) % self.layout.capacity;
+        self.count = std::cmp::min(self.count + 1, self.layout.capacity);
+        self.write_header()
+    }
+
+    /// Store a single data point and log it. Returns id.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn store_vec(&mut self, v: &DataPointSlice) -> Result<DataId> {
+        let id = self.lsh.store_vec(v)?;
+        let entry = AuditEntry {
+            id,
+            timestamp_ms: now_ms(),
+            deleted: false,
+            hashes: self.lsh.hashers.iter().map(|h| h.hash_vec_put(v)).collect(),
+            data_point: v.to_vec(),
+        };
+        self.append(&entry)?;
+        Ok(id)
+    }
+
+    /// Store multiple data points and log them. Returns the ids.
+    ///
+    /// # Arguments
+    /// * `vs` - Data points.
+    pub fn store_vecs(&mut self, vs: &[DataPoint]) -> Result<Vec<DataId>> {
+        vs.iter().map(|v| self.store_vec(v)).collect()
+    }
+
+    /// Delete a data point by id and log the deletion.
+    ///
+    /// # Arguments
+    /// * `id` - Id of the data point.
+    pub fn delete_by_id(&mut self, id: DataId) -> Result<()> {
+        self.lsh.delete_by_id(id)?;
+        let entry = AuditEntry {
+            id,
+            timestamp_ms: now_ms(),
+            deleted: true,
+            hashes: vec![],
+            data_point: vec![],
+        };
+        self.append(&entry)
+    }
+
+    /// Logged entries, oldest first. The log is read lazily and torn entries of an interrupted
+    /// write are skipped.
+    pub fn audit_log(&self) -> impl Iterator<Item = Result<AuditEntry>> + '_ {
+        read_log(&self.file, self.layout, self.head, self.count)
+    }
+
+    /// Flush the log to disk.
+    pub fn sync(&self) -> Result<()> {
+        Ok(self.file.sync_data()?)
+    }
+
+    /// Underlying LSH.
+    pub fn lsh(&self) -> &LSH<T, H> {
+        &self.lsh
+    }
+
+    /// Return the underlying LSH. The log stays on disk.
+    pub fn into_inner(self) -> LSH<T, H> {
+        self.lsh
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LshMem;
+
+    #[test]
+    fn test_audit_log_and_recovery() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let vs: Vec<DataPoint> = (0..5)
+            .map(|i| vec![i as f32, (i as f32).sin(), 1.])
+            .collect();
+        let lsh = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+        let mut audited = AuditedLSH::create(lsh, &path, 3).unwrap();
+        let ids = audited.store_vecs(&vs[..2]).unwrap();
+        let log: Vec<AuditEntry> = audited.audit_log().collect::<Result<_>>().unwrap();
+        assert_eq!(log.iter().map(|e| e.id).collect::<Vec<_>>(), ids);
+        assert_eq!(log[1].data_point, vs[1]);
+        assert_eq!(
+            log[1].hashes[1],
+            audited.lsh().hashers[1].hash_vec_put(&vs[1])
+        );
+
+        // the ring buffer keeps the last 3 entries.
+        audited.store_vecs(&vs[2..]).unwrap();
+        let log: Vec<DataId> = audited.audit_log().map(|e| e.unwrap().id).collect();
+        assert_eq!(log, vec![2, 3, 4]);
+
+        // a snapshot with the first 3 data points, the last 2 are replayed.
+        let mut snapshot = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+        snapshot.store_vecs(&vs[..3]).unwrap();
+        drop(audited);
+        let recovered = AuditedLSH::open(snapshot, &path).unwrap();
+        let ht = recovered.lsh().hash_tables.as_ref().unwrap();
+        for (id, v) in vs.iter().enumerate() {
+            assert_eq!(ht.idx_to_datapoint(id as DataId).unwrap(), v);
+            assert!(recovered
+                .lsh()
+                .query_bucket_ids(v)
+                .unwrap()
+                .contains(&(id as DataId)));
+        }
+
+        let other = LshMem::new(6, 2, 3).seed(1).srp().unwrap();
+        assert!(AuditedLSH::open(other, &path).is_err());
+        let lsh = LshMem::new(5, 2, 3).srp().unwrap();
+        assert!(AuditedLSH::create(lsh, &path, 0).is_err());
+    }
+
+    #[test]
+    fn test_replay_deletions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let vs: Vec<DataPoint> = (0..5)
+            .map(|i| vec![i as f32, (i as f32).sin(), 1.])
+            .collect();
+        let lsh = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+        let mut audited = AuditedLSH::create(lsh, &path, 10).unwrap();
+        audited.store_vecs(&vs).unwrap();
+        audited.delete_by_id(1).unwrap();
+        audited.delete_by_id(4).unwrap();
+        let log: Vec<AuditEntry> = audited.audit_log().collect::<Result<_>>().unwrap();
+        assert_eq!(log.len(), 7);
+        assert!(log[6].deleted && log[6].id == 4 && log[6].data_point.is_empty());
+        drop(audited);
+
+        // a snapshot before the deletions, with the first 3 data points.
+        let mut snapshot = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+        snapshot.store_vecs(&vs[..3]).unwrap();
+        let recovered = AuditedLSH::open(snapshot, &path).unwrap();
+        let ht = recovered.lsh().hash_tables.as_ref().unwrap();
+        for id in 0..5 {
+            let stored = ht.idx_to_datapoint(id).is_ok();
+            assert_eq!(stored, id != 1 && id != 4, "id {}", id);
+            let found = recovered.lsh().query_bucket_ids(&vs[id as usize]).unwrap();
+            assert_eq!(found.contains(&id), stored);
+        }
+    }
+}
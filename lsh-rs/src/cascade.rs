@@ -0,0 +1,197 @@
+//! Anytime retrieval over LSH's with increasing hash lengths.
+use crate::{DataId, DataPoint, DataPointSlice, Error, HashTables, Result, VecHash, LSH};
+use fnv::FnvHashSet;
+
+/// Cascade of [LSH](../struct.LSH.html) stages ordered by increasing hash length `k`. Every
+/// stage stores all data points. A query consults the stages in order and stops as soon as
+/// enough candidates are found, which trades recall for latency at query time.
+///
+/// The stages share ids like the members of an [EnsembleLSH](../struct.EnsembleLSH.html).
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::{CascadingLSH, MemoryTable};
+/// let mut cascade = CascadingLSH::<MemoryTable, _>::build(&[(4, 2), (12, 4)], 3, 1).unwrap();
+/// let id = cascade.store_vec(&[2., 3., 4.]).unwrap();
+/// assert!(cascade.query_until(&[2., 3., 4.], 1).unwrap().contains(&id));
+/// ```
+pub struct CascadingLSH<T: HashTables, H: VecHash> {
+    stages: Vec<LSH<T, H>>,
+}
+
+impl<T: HashTables> CascadingLSH<T, crate::SignRandomProjections> {
+    /// Create a cascade of SignRandomProjections LSH's. Stage `i` is seeded with `seed + i`.
+    ///
+    /// # Arguments
+    ///
+    /// * `groups` - `(n_projections, n_hash_tables)` per stage, with increasing `n_projections`.
+    /// * `dim` - Dimensions of the data points.
+    /// * `seed` - Seed of the first stage. If 0, all stages are seeded randomly.
+    pub fn build(groups: &[(usize, usize)], dim: usize, seed: u64) -> Result<Self> {
+        let stages = groups
+            .iter()
+            .enumerate()
+            .map(|(i, &(k, l))| {
+                let seed = if seed == 0 {
+                    0
+                } else {
+                    seed.wrapping_add(i as u64)
+                };
+                LSH::new(k, l, dim).seed(seed).srp()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        CascadingLSH::from_stages(stages)
+    }
+}
+
+impl<T: HashTables, H: VecHash> CascadingLSH<T, H> {
+    /// Create a cascade of existing LSH's. The stages should have the same dimension, the same
+    /// stored data points and strictly increasing `n_projections`.
+    ///
+    /// # Arguments
+    ///
+    /// * `stages` - Non empty list of LSH's.
+    pub fn from_stages(stages: Vec<LSH<T, H>>) -> Result<Self> {
+        let first = match stages.first() {
+            None => {
+                return Err(Error::Failed(
+                    "a cascade needs at least one stage".to_string(),
+                ))
+            }
+            Some(first) => first,
+        };
+        if stages.iter().any(|s| s.dim != first.dim) {
+            return Err(Error::Failed(
+                "stages should have the same dimension".to_string(),
+            ));
+        }
+        if stages
+            .windows(2)
+            .any(|w| w[0].n_projections >= w[1].n_projections)
+        {
+            return Err(Error::Failed(
+                "n_projections of the stages should be increasing".to_string(),
+            ));
+        }
+        Ok(CascadingLSH { stages })
+    }
+
+    /// Stages of the cascade, from coarse to fine.
+    pub fn stages(&self) -> &[LSH<T, H>] {
+        &self.stages
+    }
+
+    /// Store a single data point in all stages. Returns id. If a stage fails or assigns
+    /// another id, the data point is deleted from the stages that already stored it.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn store_vec(&mut self, v: &DataPointSlice) -> Result<DataId> {
+        self.stages[0].validate_vec(v)?;
+        let mut ids: Vec<DataId> = Vec::with_capacity(self.stages.len());
+        for i in 0..self.stages.len() {
+            let err = match self.stages[i].store_vec(v) {
+                Ok(id) if ids.first().is_none_or(|&first| first == id) => {
+                    ids.push(id);
+                    continue;
+                }
+                Ok(id) => {
+                    ids.push(id);
+                    Error::Failed("stages assigned different ids to the data point".to_string())
+                }
+                Err(e) => e,
+            };
+            for (s, &id) in self.stages.iter_mut().zip(&ids) {
+                s.delete_by_id(id)?;
+            }
+            return Err(err);
+        }
+        Ok(ids[0])
+    }
+
+    /// Store multiple data points in all stages. Returns the ids.
+    ///
+    /// # Arguments
+    /// * `vs` - Data points.
+    pub fn store_vecs(&mut self, vs: &[DataPoint]) -> Result<Vec<DataId>> {
+        vs.iter().map(|v| self.store_vec(v)).collect()
+    }
+
+    /// Query the stages from coarse to fine until the union of the colliding ids holds at
+    /// least `min_candidates` ids. Returns the union, sorted. If all stages together find fewer
+    /// ids, all of them are returned.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    /// * `min_candidates` - Number of candidates after which no further stages are queried.
+    pub fn query_until(&self, v: &DataPointSlice, min_candidates: usize) -> Result<Vec<DataId>> {
+        let mut ids = FnvHashSet::default();
+        for s in &self.stages {
+            ids.extend(s.query_bucket_ids(v)?);
+            if ids.len() >= min_candidates {
+                break;
+            }
+        }
+        let mut ids: Vec<DataId> = ids.into_iter().collect();
+        ids.sort_unstable();
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{LshMem, MemoryTable};
+
+    #[test]
+    fn test_query_until() {
+        let vs: Vec<DataPoint> = (0..200)
+            .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+            .collect();
+        let mut cascade =
+            CascadingLSH::<MemoryTable, _>::build(&[(2, 2), (6, 2), (10, 4)], 3, 1).unwrap();
+        cascade.store_vecs(&vs).unwrap();
+        let q = [0.3, 0.9, 0.5];
+
+        let stage_ids: Vec<Vec<DataId>> = cascade
+            .stages()
+            .iter()
+            .map(|s| s.query_bucket_ids(&q).unwrap())
+            .collect();
+        // only the first stage is needed for a single candidate.
+        let mut first = stage_ids[0].clone();
+        first.sort_unstable();
+        assert_eq!(cascade.query_until(&q, 1).unwrap(), first);
+        // all stages are queried if the candidates don't suffice.
+        let mut all: Vec<DataId> = stage_ids.into_iter().flatten().collect();
+        all.sort_unstable();
+        all.dedup();
+        assert_eq!(cascade.query_until(&q, vs.len() + 1).unwrap(), all);
+
+        assert!(CascadingLSH::<MemoryTable, _>::build(&[], 3, 1).is_err());
+        assert!(CascadingLSH::<MemoryTable, _>::build(&[(6, 2), (6, 2)], 3, 1).is_err());
+        let mut single = CascadingLSH::<MemoryTable, _>::build(&[(4, 1), (8, 1)], 3, 1).unwrap();
+        assert_eq!(single.store_vecs(&vs[..3]).unwrap(), vec![0, 1, 2]);
+
+        // stages that are out of sync don't keep a partially stored data point.
+        let mut ahead = LshMem::new(8, 2, 3).srp().unwrap();
+        ahead.store_vec(&vs[0]).unwrap();
+        let stages = vec![LshMem::new(4, 2, 3).srp().unwrap(), ahead];
+        let mut cascade = CascadingLSH::from_stages(stages).unwrap();
+        assert!(cascade.store_vec(&vs[1]).is_err());
+        assert!(cascade.stages()[0]
+            .query_bucket_ids(&vs[1])
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            cascade.stages()[1].query_bucket_ids(&vs[0]).unwrap(),
+            vec![0]
+        );
+        let stages = vec![
+            LshMem::new(4, 2, 3).srp().unwrap(),
+            LshMem::new(8, 2, 4).srp().unwrap(),
+        ];
+        assert!(CascadingLSH::from_stages(stages).is_err());
+    }
+}
@@ -0,0 +1,214 @@
+//! Read LSH parameters from a TOML file.
+//!
+//! ```toml
+//! n_projections = 9
+//! n_hash_tables = 30
+//! dim = 100
+//! seed = 12
+//! multi_probe_budget = 16
+//! hasher = "l2"
+//! r = 4.0
+//! ```
+use crate::{
+    utils::create_rng, DataPoint, Error, HashTables, MemoryTable, Result, SignRandomProjections,
+    VecHash, L2, LSH, MIPS,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// Hasher that is type erased. Used when the hash family is only known at runtime.
+pub type DynHasher = Box<dyn VecHash + Send + Sync>;
+
+/// Hash family of the LSH.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HasherType {
+    Srp,
+    L2,
+    Mips,
+}
+
+/// LSH parameters. See [LSH](../struct.LSH.html) for the meaning of the parameters.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct LshConfig {
+    pub n_projections: usize,
+    pub n_hash_tables: usize,
+    pub dim: usize,
+    /// If 0, randomness is seeded from the os.
+    #[serde(default)]
+    pub seed: u64,
+    /// If set, multi probing is enabled with this budget.
+    #[serde(default)]
+    pub multi_probe_budget: Option<usize>,
+    pub hasher: HasherType,
+    /// Parameter of the L2 and MIPS hashers.
+    #[serde(default)]
+    pub r: Option<f32>,
+    /// Parameter of the MIPS hasher.
+    #[serde(default)]
+    pub U: Option<f32>,
+    /// Parameter of the MIPS hasher.
+    #[serde(default)]
+    pub m: Option<usize>,
+}
+
+impl LshConfig {
+    /// Read the configuration from a TOML file.
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> Result<LshConfig> {
+        let s = fs::read_to_string(path)?;
+        LshConfig::from_toml_str(&s)
+    }
+
+    /// Parse the configuration from a TOML string.
+    pub fn from_toml_str(s: &str) -> Result<LshConfig> {
+        Ok(toml::from_str(s)?)
+    }
+
+    /// Create an in memory LSH from the configuration.
+    ///
+    /// MIPS hashers need to be fitted on the data before storing, so the MIPS family is built
+    /// with [build_fitted](#method.build_fitted).
+    pub fn build(&self) -> Result<LSH<MemoryTable, DynHasher>> {
+        self.build_with(None)
+    }
+
+    /// Create an in memory LSH from the configuration, of which the MIPS hashers are fitted on
+    /// `data`. The other families ignore `data`.
+    ///
+    /// # Arguments
+    /// * `data` - Data points of which the maximum norm is used by the MIPS hashers.
+    pub fn build_fitted(&self, data: &[DataPoint]) -> Result<LSH<MemoryTable, DynHasher>> {
+        self.build_with(Some(data))
+    }
+
+    fn build_with(&self, fit_data: Option<&[DataPoint]>) -> Result<LSH<MemoryTable, DynHasher>> {
+        let missing = |name| Error::Failed(format!("parameter `{}` is missing", name));
+        let r = || self.r.ok_or_else(|| missing("r"));
+        let flat = fit_data.map(|data| data.concat());
+        let mut rng = create_rng(self.seed);
+        let mut hashers: Vec<DynHasher> = Vec::with_capacity(self.n_hash_tables);
+        for _ in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let hasher: DynHasher = match self.hasher {
+                HasherType::Srp => Box::new(SignRandomProjections::new(
+                    self.n_projections,
+                    self.dim,
+                    seed,
+                )),
+                HasherType::L2 => Box::new(L2::new(self.dim, r()?, self.n_projections, seed)),
+                HasherType::Mips => {
+                    let flat = flat.as_ref().ok_or_else(|| {
+                        Error::Failed("MIPS needs to be fitted, use build_fitted".to_string())
+                    })?;
+                    let U = self.U.ok_or_else(|| missing("U"))?;
+                    let m = self.m.ok_or_else(|| missing("m"))?;
+                    let mut hasher = MIPS::new(self.dim, r()?, U, m, self.n_projections, seed);
+                    hasher.fit(flat);
+                    Box::new(hasher)
+                }
+            };
+            hashers.push(hasher);
+        }
+
+        let mut lsh = LSH::new(self.n_projections, self.n_hash_tables, self.dim);
+        lsh.seed(self.seed);
+        if let Some(budget) = self.multi_probe_budget {
+            lsh.multi_probe(budget);
+        }
+        lsh.hashers = hashers;
+        lsh.hash_tables = Some(*MemoryTable::new(self.n_hash_tables, false, "")?);
+        Ok(lsh)
+    }
+}
+
+impl LSH<MemoryTable, DynHasher> {
+    /// Create an in memory LSH from a TOML configuration file.
+    /// See [LshConfig](config/struct.LshConfig.html).
+    pub fn from_config_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        LshConfig::from_toml(path)?.build()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let tmp = dir.path().join("config.toml");
+        std::fs::write(
+            &tmp,
+            "n_projections = 5\nn_hash_tables = 10\ndim = 3\nseed = 1\nhasher = \"l2\"\nr = 4.0\n",
+        )
+        .unwrap();
+
+        let mut lsh = LSH::from_config_file(&tmp).unwrap();
+        let id = lsh.store_vec(&[2., 3., 4.]).unwrap();
+        assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&id));
+
+        // same hashers as the typed constructor.
+        let typed = LSH::<MemoryTable, _>::new(5, 10, 3).seed(1).l2(4.).unwrap();
+        assert_eq!(
+            lsh.hashers[3].hash_vec_query(&[1., 2., 3.]),
+            typed.hashers[3].hash_vec_query(&[1., 2., 3.])
+        );
+    }
+
+    #[test]
+    fn test_config_errors() {
+        let cfg = LshConfig::from_toml_str("n_projections = 5\nn_hash_tables = 10\ndim = 3\n");
+        assert!(cfg.is_err());
+        let cfg = LshConfig::from_toml_str(
+            "n_projections = 5\nn_hash_tables = 2\ndim = 3\nhasher = \"l2\"",
+        )
+        .unwrap();
+        assert!(cfg.build().is_err());
+        let cfg = LshConfig::from_toml_str(
+            "n_projections = 5\nn_hash_tables = 2\ndim = 3\nhasher = \"srp\"\nmulti_probe_budget = 4",
+        )
+        .unwrap();
+        assert_eq!(cfg.multi_probe_budget, Some(4));
+        assert!(cfg.build().is_ok());
+        let cfg = LshConfig::from_toml_str(
+            "n_projections = 5\nn_hash_tables = 2\ndim = 3\nhasher = \"mips\"\nr = 2.2",
+        )
+        .unwrap();
+        assert!(cfg.build_fitted(&[vec![1., 2., 3.]]).is_err());
+    }
+
+    #[test]
+    fn test_build_mips() {
+        let cfg = LshConfig::from_toml_str(
+            "n_projections = 5\nn_hash_tables = 3\ndim = 3\nseed = 1\nhasher = \"mips\"\n\
+             r = 2.2\nU = 0.83\nm = 3",
+        )
+        .unwrap();
+        assert!(cfg.build().is_err());
+        let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.], vec![0.5, 0.2, -0.3]];
+        let mut lsh = cfg.build_fitted(&vs).unwrap();
+        lsh.store_vecs(&vs).unwrap();
+
+        // same hashers as the typed constructor.
+        let mut typed = LSH::<MemoryTable, _>::new(5, 3, 3)
+            .seed(1)
+            .mips(2.2, 0.83, 3)
+            .unwrap();
+        for h in &mut typed.hashers {
+            h.fit(&vs.concat());
+        }
+        typed.store_vecs(&vs).unwrap();
+        for v in &vs {
+            assert_eq!(
+                lsh.hashers[2].hash_vec_put(v),
+                typed.hashers[2].hash_vec_put(v)
+            );
+            assert_eq!(
+                lsh.query_bucket_ids(v).unwrap(),
+                typed.query_bucket_ids(v).unwrap()
+            );
+        }
+    }
+}
@@ -1 +1,4 @@
 pub const DESCRIBE_MAX: u32 = 5000;
+/// `LSH::store_vec_from_stream` rebuilds the index if the maximum norm shrank below this
+/// fraction of the maximum norm of the last rebuild.
+pub const STREAM_REBUILD_SHRINK: f32 = 0.5;
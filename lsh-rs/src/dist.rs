@@ -1,5 +1,6 @@
+use crate::{utils::create_rng, DataId, DataPoint};
 use ndarray::prelude::*;
-use rayon::prelude::*;
+use rand::Rng;
 
 /// L2 norm of a single vector.
 ///
@@ -55,3 +56,111 @@ pub fn inner_prod(a: &[f32], b: &[f32]) -> f32 {
 pub fn cosine_sim(a: &[f32], b: &[f32]) -> f32 {
     inner_prod(a, b) / (l2_norm(a) * l2_norm(b))
 }
+
+/// Squared L2 distance between two vectors.
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::dist::squared_l2;
+/// assert_eq!(squared_l2(&[1., -1.], &[0., 1.]), 5.);
+/// ```
+pub fn squared_l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// L2 distance between two vectors.
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::dist::l2_dist;
+/// assert_eq!(l2_dist(&[1., -1.], &[-2., 3.]), 5.);
+/// ```
+pub fn l2_dist(a: &[f32], b: &[f32]) -> f32 {
+    squared_l2(a, b).sqrt()
+}
+
+/// Keep the `k` nearest of `(id, distance)` pairs, sorted by distance and then by id.
+/// NaN distances rank last.
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::dist::rank_candidates;
+/// let ranked = rank_candidates(vec![(3, 0.5), (1, -f32::NAN), (2, 0.1), (0, 0.5)], 3);
+/// assert_eq!(ranked, vec![(2, 0.1), (0, 0.5), (3, 0.5)]);
+/// ```
+pub fn rank_candidates(mut ranked: Vec<(DataId, f32)>, k: usize) -> Vec<(DataId, f32)> {
+    // NaN is negative on some platforms, so it's ordered explicitly.
+    let cmp = |a: &(DataId, f32), b: &(DataId, f32)| {
+        (a.1.is_nan().cmp(&b.1.is_nan()))
+            .then(a.1.total_cmp(&b.1))
+            .then(a.0.cmp(&b.0))
+    };
+    if k < ranked.len() {
+        ranked.select_nth_unstable_by(k, cmp);
+        ranked.truncate(k);
+    }
+    ranked.sort_unstable_by(cmp);
+    ranked
+}
+
+/// Metric used to compare data points exactly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DistanceMetric {
+    L2,
+    Cosine,
+    InnerProduct,
+}
+
+impl DistanceMetric {
+    /// Check if two data points are within `threshold`. For `L2` this is a distance of at most
+    /// `threshold`, for `Cosine` and `InnerProduct` a similarity of at least `threshold`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use lsh_rs::dist::DistanceMetric;
+    /// assert!(DistanceMetric::Cosine.within(&[1., 0.], &[1., 0.1], 0.9));
+    /// assert!(!DistanceMetric::L2.within(&[1., 0.], &[3., 0.], 1.));
+    /// ```
+    pub fn within(&self, a: &[f32], b: &[f32], threshold: f32) -> bool {
+        match self {
+            DistanceMetric::L2 => l2_dist(a, b) <= threshold,
+            DistanceMetric::Cosine => cosine_sim(a, b) >= threshold,
+            DistanceMetric::InnerProduct => inner_prod(a, b) >= threshold,
+        }
+    }
+
+    /// Distance of two data points, small for similar data points. For `Cosine` this is
+    /// `1 - cosine similarity` and for `InnerProduct` the negative inner product.
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            DistanceMetric::L2 => l2_dist(a, b),
+            DistanceMetric::Cosine => 1. - cosine_sim(a, b),
+            DistanceMetric::InnerProduct => -inner_prod(a, b),
+        }
+    }
+}
+
+/// Distances of `n_pairs` random pairs of distinct data points. `data` should contain at least
+/// two data points.
+pub(crate) fn sample_pair_distances(
+    data: &[DataPoint],
+    n_pairs: usize,
+    seed: u64,
+    metric: DistanceMetric,
+) -> Vec<f32> {
+    let mut rng = create_rng(seed);
+    let mut distances = Vec::with_capacity(n_pairs);
+    while distances.len() < n_pairs {
+        let i = rng.gen_range(0, data.len());
+        let j = rng.gen_range(0, data.len());
+        if i == j {
+            continue;
+        }
+        distances.push(metric.distance(&data[i], &data[j]));
+    }
+    distances
+}
@@ -0,0 +1,147 @@
+//! Union of the results of independently seeded LSH instances.
+use crate::{DataId, DataPoint, DataPointSlice, Error, HashTables, Result, VecHash, LSH};
+
+/// Ensemble of independently seeded [LSH](../struct.LSH.html) instances. Every member stores
+/// all data points and a query returns the union of the candidates of all members.
+///
+/// All members assign ids in insertion order, so the ids of the members refer to the same data
+/// points as long as they are only filled through the ensemble.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::{EnsembleLSH, MemoryTable};
+/// let mut ensemble = EnsembleLSH::<MemoryTable, _>::build(5, 2, 3, 4, 1).unwrap();
+/// let id = ensemble.store_vec(&[2., 3., 4.]).unwrap();
+/// assert!(ensemble.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&id));
+/// ```
+pub struct EnsembleLSH<T: HashTables, H: VecHash> {
+    members: Vec<LSH<T, H>>,
+}
+
+impl<T: HashTables> EnsembleLSH<T, crate::SignRandomProjections> {
+    /// Create an ensemble of SignRandomProjections LSH's. Member `i` is seeded with `seed + i`.
+    ///
+    /// # Arguments
+    ///
+    /// * `n_projections` - Hash length.
+    /// * `n_hash_tables` - Number of hash tables per member.
+    /// * `dim` - Dimensions of the data points.
+    /// * `n_ensembles` - Number of members.
+    /// * `seed` - Seed of the first member. If 0, all members are seeded randomly.
+    pub fn build(
+        n_projections: usize,
+        n_hash_tables: usize,
+        dim: usize,
+        n_ensembles: usize,
+        seed: u64,
+    ) -> Result<Self> {
+        let members = (0..n_ensembles as u64)
+            .map(|i| {
+                let seed = if seed == 0 { 0 } else { seed.wrapping_add(i) };
+                LSH::new(n_projections, n_hash_tables, dim).seed(seed).srp()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        EnsembleLSH::from_members(members)
+    }
+}
+
+impl<T: HashTables, H: VecHash> EnsembleLSH<T, H> {
+    /// Create an ensemble of existing LSH's. The members should have the same dimension and
+    /// the same stored data points.
+    ///
+    /// # Arguments
+    ///
+    /// * `members` - Non empty list of LSH's.
+    pub fn from_members(members: Vec<LSH<T, H>>) -> Result<Self> {
+        match members.first() {
+            None => Err(Error::Failed(
+                "an ensemble needs at least one member".to_string(),
+            )),
+            Some(first) if members.iter().any(|m| m.dim != first.dim) => Err(Error::Failed(
+                "members should have the same dimension".to_string(),
+            )),
+            _ => Ok(EnsembleLSH { members }),
+        }
+    }
+
+    /// Members of the ensemble.
+    pub fn members(&self) -> &[LSH<T, H>] {
+        &self.members
+    }
+
+    /// Store a single data point in all members. Returns id.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn store_vec(&mut self, v: &DataPointSlice) -> Result<DataId> {
+        let ids = self
+            .members
+            .iter_mut()
+            .map(|m| m.store_vec(v))
+            .collect::<Result<Vec<_>>>()?;
+        if ids.iter().any(|&id| id != ids[0]) {
+            return Err(Error::Failed(
+                "members assigned different ids to the data point".to_string(),
+            ));
+        }
+        Ok(ids[0])
+    }
+
+    /// Store multiple data points in all members. Returns the ids.
+    ///
+    /// # Arguments
+    /// * `vs` - Data points.
+    pub fn store_vecs(&mut self, vs: &[DataPoint]) -> Result<Vec<DataId>> {
+        vs.iter().map(|v| self.store_vec(v)).collect()
+    }
+
+    /// Query the union of the ids that collide with `v` in any member. The ids are sorted.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    pub fn query_bucket_ids(&self, v: &DataPointSlice) -> Result<Vec<DataId>> {
+        let mut ids = Vec::new();
+        for m in &self.members {
+            ids.extend(m.query_bucket_ids(v)?);
+        }
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{LshMem, MemoryTable};
+
+    #[test]
+    fn test_ensemble_union() {
+        let vs: Vec<DataPoint> = (0..100)
+            .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+            .collect();
+        let mut ensemble = EnsembleLSH::<MemoryTable, _>::build(6, 2, 3, 3, 5).unwrap();
+        ensemble.store_vecs(&vs).unwrap();
+        assert_eq!(ensemble.members().len(), 3);
+
+        // same members as seeding single LSH's with consecutive seeds.
+        let q = [0.3, 0.9, 0.5];
+        let mut expected = vec![];
+        for seed in 5..8 {
+            let mut lsh = LshMem::new(6, 2, 3).seed(seed).srp().unwrap();
+            lsh.store_vecs(&vs).unwrap();
+            expected.extend(lsh.query_bucket_ids(&q).unwrap());
+        }
+        expected.sort_unstable();
+        expected.dedup();
+        assert_eq!(ensemble.query_bucket_ids(&q).unwrap(), expected);
+
+        assert!(EnsembleLSH::<MemoryTable, _>::build(6, 2, 3, 0, 5).is_err());
+        let members = vec![
+            LshMem::new(6, 2, 3).srp().unwrap(),
+            LshMem::new(6, 2, 4).srp().unwrap(),
+        ];
+        assert!(EnsembleLSH::from_members(members).is_err());
+    }
+}
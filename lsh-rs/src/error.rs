@@ -1,21 +1,46 @@
 use thiserror::Error as ThisError;
 
+/// Errors of the LSH operations. Wrapped errors are available through
+/// [source](https://doc.rust-lang.org/std/error/trait.Error.html#method.source).
 #[derive(Debug, ThisError)]
 pub enum Error {
-    #[error("Something went wrong: {0}")]
+    #[error("LSH operation failed: {0}")]
     Failed(String),
-    #[error("Vector not found")]
+    #[error("No candidates or data point found")]
     NotFound,
-    #[error("Table does not exist")]
+    #[error("Hash table does not exist")]
     TableNotExist,
-    #[error("Not implemented")]
+    #[error("Operation is not implemented for this backend")]
     NotImplemented,
-    #[error(transparent)]
+    #[error("No candidates found within the time budget")]
+    Timeout,
+    #[error("Serialization failed: {0}")]
     SerializationFailed(#[from] std::boxed::Box<bincode::ErrorKind>),
-    #[error(transparent)]
+    #[cfg(feature = "sqlite")]
+    #[error("Sqlite operation failed: {0}")]
     SqlFailure(#[from] rusqlite::Error),
     #[error(transparent)]
     Other(#[from] anyhow::Error),
-    #[error(transparent)]
+    #[error("I/O operation failed: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Invalid configuration: {0}")]
+    ConfigFailed(#[from] toml::de::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::error::Error as StdError;
+
+    #[test]
+    fn test_display_and_source() {
+        let e = Error::Failed("ids should be unique".to_string());
+        assert_eq!(e.to_string(), "LSH operation failed: ids should be unique");
+        assert!(e.source().is_none());
+
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let e = Error::from(io);
+        assert_eq!(e.to_string(), "I/O operation failed: no such file");
+        assert_eq!(e.source().unwrap().to_string(), "no such file");
+    }
 }
@@ -0,0 +1,68 @@
+//! Evaluation of an LSH index configuration.
+use crate::{DataPoint, HashTables, Result, VecHash, LSH};
+
+/// Statistics of the number of candidates returned per query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CandidateStats {
+    pub mean: f32,
+    pub std_dev: f32,
+}
+
+/// Compute the average number of candidates (and its standard deviation) returned per query.
+/// This is the main cost metric when tuning LSH parameters.
+///
+/// # Arguments
+/// * `lsh` - LSH index.
+/// * `queries` - Query vectors.
+pub fn candidate_stats<T: HashTables, H: VecHash>(
+    lsh: &LSH<T, H>,
+    queries: &[DataPoint],
+) -> Result<CandidateStats> {
+    let lengths = queries
+        .iter()
+        .map(|q| lsh.query_bucket_ids(q).map(|ids| ids.len() as f32))
+        .collect::<Result<Vec<_>>>()?;
+    if lengths.is_empty() {
+        return Ok(CandidateStats {
+            mean: 0.,
+            std_dev: 0.,
+        });
+    }
+    let n = lengths.len() as f32;
+    let mean = lengths.iter().sum::<f32>() / n;
+    let var = lengths.iter().map(|l| (l - mean).powf(2.)).sum::<f32>() / n;
+    Ok(CandidateStats {
+        mean,
+        std_dev: var.powf(0.5),
+    })
+}
+
+impl<T: HashTables, H: VecHash> LSH<T, H> {
+    /// Average number of candidates returned per query.
+    /// See [candidate_stats](eval/fn.candidate_stats.html) for the standard deviation.
+    ///
+    /// # Arguments
+    /// * `queries` - Query vectors.
+    pub fn n_candidates_empirical(&self, queries: &[DataPoint]) -> Result<f32> {
+        candidate_stats(self, queries).map(|stats| stats.mean)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LshMem;
+
+    #[test]
+    fn test_candidate_stats() {
+        let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+        let vs = vec![vec![2., 3., 4.], vec![2., 3., 4.1], vec![-1., -1., 1.]];
+        lsh.store_vecs(&vs).unwrap();
+        let stats = candidate_stats(&lsh, &vs).unwrap();
+        // every query at least finds itself
+        assert!(stats.mean >= 1.);
+        assert!(stats.std_dev >= 0.);
+        assert_eq!(lsh.n_candidates_empirical(&vs).unwrap(), stats.mean);
+        assert_eq!(candidate_stats(&lsh, &[]).unwrap().mean, 0.);
+    }
+}
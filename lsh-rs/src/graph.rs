@@ -0,0 +1,78 @@
+//! Export of similarity graphs, e.g. from
+//! [build_similarity_graph](../struct.LSH.html#method.build_similarity_graph).
+use crate::{DataId, Result};
+use fnv::FnvHashMap as HashMap;
+use std::io::Write;
+
+fn sorted_edges(graph: &HashMap<DataId, Vec<DataId>>) -> Vec<(DataId, DataId)> {
+    let mut edges: Vec<(DataId, DataId)> = graph
+        .iter()
+        .flat_map(|(&a, neighbors)| {
+            neighbors
+                .iter()
+                .filter(move |&&b| a < b)
+                .map(move |&b| (a, b))
+        })
+        .collect();
+    edges.sort_unstable();
+    edges
+}
+
+/// Write every undirected edge once as a `"a b"` line.
+///
+/// # Arguments
+/// * `graph` - Adjacency list.
+/// * `w` - Output, e.g. a file.
+pub fn write_edge_list<W: Write>(graph: &HashMap<DataId, Vec<DataId>>, mut w: W) -> Result<()> {
+    for (a, b) in sorted_edges(graph) {
+        writeln!(w, "{} {}", a, b)?;
+    }
+    Ok(())
+}
+
+/// Write the graph in the [GraphML](http://graphml.graphdrawing.org/) format.
+///
+/// # Arguments
+/// * `graph` - Adjacency list.
+/// * `w` - Output, e.g. a file.
+pub fn write_graphml<W: Write>(graph: &HashMap<DataId, Vec<DataId>>, mut w: W) -> Result<()> {
+    writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        w,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    writeln!(w, r#"  <graph id="G" edgedefault="undirected">"#)?;
+    let mut nodes: Vec<DataId> = graph.keys().copied().collect();
+    nodes.sort_unstable();
+    for n in nodes {
+        writeln!(w, r#"    <node id="n{}"/>"#, n)?;
+    }
+    for (a, b) in sorted_edges(graph) {
+        writeln!(w, r#"    <edge source="n{}" target="n{}"/>"#, a, b)?;
+    }
+    writeln!(w, "  </graph>")?;
+    writeln!(w, "</graphml>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_edge_list() {
+        let mut graph = HashMap::default();
+        graph.insert(0, vec![2]);
+        graph.insert(2, vec![0, 3]);
+        graph.insert(3, vec![2]);
+        let mut out = vec![];
+        write_edge_list(&graph, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "0 2\n2 3\n");
+
+        let mut out = vec![];
+        write_graphml(&graph, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("<node ").count(), 3);
+        assert_eq!(out.matches("<edge ").count(), 2);
+    }
+}
@@ -1,9 +1,15 @@
 use crate::{
-    dist::l2_norm, multi_probe::QueryDirectedProbe, utils::create_rng, DataPointSlice, FloatSize,
+    dist::{l2_norm, sample_pair_distances, DistanceMetric},
+    multi_probe::QueryDirectedProbe,
+    stats::{l2_ph, srp_ph},
+    utils::create_rng,
+    DataPoint, DataPointSlice, Error, FloatSize, Result,
 };
 use ndarray::prelude::*;
 use ndarray_rand::rand_distr::{StandardNormal, Uniform};
 use ndarray_rand::RandomExt;
+use rand::Rng;
+use rustfft::{num_complex::Complex, FftPlanner};
 use serde::{Deserialize, Serialize};
 
 pub type HashPrimitive = i8;
@@ -13,9 +19,73 @@ pub trait VecHash {
     fn hash_vec_query(&self, v: &[f32]) -> Hash;
     fn hash_vec_put(&self, v: &[f32]) -> Hash;
 
+    /// Length of the hash.
+    fn n_projections(&self) -> usize;
+
+    /// Dimension of the hashed vectors.
+    fn dim(&self) -> usize;
+
     fn as_query_directed_probe(&self) -> Option<&dyn QueryDirectedProbe> {
         None
     }
+
+    /// Distance of the query to the nearest boundary of its bucket. A large margin means that
+    /// a small perturbation of the query doesn't change the hash.
+    /// Returns `None` if the hasher has no notion of a margin.
+    fn query_margin(&self, _v: &[f32]) -> Option<FloatSize> {
+        None
+    }
+
+    /// Metric the hasher is locality sensitive for, e.g. to rank candidates exactly.
+    fn metric(&self) -> DistanceMetric {
+        DistanceMetric::L2
+    }
+}
+
+/// Fractional parts within `STE_DELTA` of a slot boundary get no gradient in
+/// [L2::gradient_wrt_input](struct.L2.html#method.gradient_wrt_input).
+const STE_DELTA: FloatSize = 0.1;
+
+/// Hashers that pass gradients to their input with a straight-through estimator, so that a
+/// hash can be used in backpropagation.
+pub trait Differentiable {
+    /// Gradient of a loss with respect to `v`, given the gradient with respect to the
+    /// (real valued) hash of `v`. Panics if `upstream_grad` doesn't have a value per projection.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    /// * `upstream_grad` - Gradient with respect to the hash.
+    fn gradient_wrt_input(&self, v: &[f32], upstream_grad: &[f32]) -> Vec<f32>;
+}
+
+impl<H: VecHash + ?Sized> VecHash for Box<H> {
+    fn hash_vec_query(&self, v: &[f32]) -> Hash {
+        (**self).hash_vec_query(v)
+    }
+
+    fn hash_vec_put(&self, v: &[f32]) -> Hash {
+        (**self).hash_vec_put(v)
+    }
+
+    fn n_projections(&self) -> usize {
+        (**self).n_projections()
+    }
+
+    fn dim(&self) -> usize {
+        (**self).dim()
+    }
+
+    fn as_query_directed_probe(&self) -> Option<&dyn QueryDirectedProbe> {
+        (**self).as_query_directed_probe()
+    }
+
+    fn query_margin(&self, v: &[f32]) -> Option<FloatSize> {
+        (**self).query_margin(v)
+    }
+
+    fn metric(&self) -> DistanceMetric {
+        (**self).metric()
+    }
 }
 
 /// Also called SimHash.
@@ -31,7 +101,7 @@ impl SignRandomProjections {
     /// # Arguments
     ///
     /// * `k` - Number of hyperplanes used for determining the hash.
-    /// This will also be the hash length.
+    ///   This will also be the hash length.
     pub fn new(k: usize, dim: usize, seed: u64) -> SignRandomProjections {
         let mut rng = create_rng(seed);
         let hp = Array::random_using((dim, k), StandardNormal, &mut rng);
@@ -51,6 +121,104 @@ impl SignRandomProjections {
         }
         hash.into_iter().collect()
     }
+
+    /// Raw projection `hyperplane^T v` of bit `bit_idx`, before taking the sign. A value near 0
+    /// means that the bit is uncertain, `v` is close to the hyperplane.
+    /// Panics if `bit_idx` is not smaller than the hash length.
+    ///
+    /// # Arguments
+    ///
+    /// * `v` - Data point.
+    /// * `bit_idx` - Index of the bit.
+    pub fn bit_margin(&self, v: &[f32], bit_idx: usize) -> f32 {
+        self.hyperplanes.column(bit_idx).dot(&aview1(v))
+    }
+
+    /// Probability that a single bit of two data points collides, `1 - angle / π`. See
+    /// [srp_ph](stats/fn.srp_ph.html).
+    ///
+    /// # Arguments
+    ///
+    /// * `angle` - Angle between the data points in radians, clamped to [0, π].
+    pub fn collision_probability(&self, angle: f32) -> f32 {
+        let angle = angle.clamp(0., std::f32::consts::PI);
+        srp_ph((angle as f64).cos()) as f32
+    }
+
+    /// Replace the `proj_idx`-th hyperplane, e.g. in online learning of the projections.
+    ///
+    /// # Arguments
+    ///
+    /// * `proj_idx` - Index of the hyperplane (bit of the hash).
+    /// * `new_col` - New hyperplane of length `dim`.
+    pub fn update_hyperplane(&mut self, proj_idx: usize, new_col: ArrayView1<f32>) -> Result<()> {
+        let (dim, k) = self.hyperplanes.dim();
+        if proj_idx >= k || new_col.len() != dim {
+            return Err(Error::Failed(format!(
+                "expected a hyperplane index < {} and a hyperplane of length {}",
+                k, dim
+            )));
+        }
+        self.hyperplanes.column_mut(proj_idx).assign(&new_col);
+        Ok(())
+    }
+
+    /// Hash a vector directly to the bitset representation.
+    /// The number of hyperplanes may not exceed `CompactSrpHash::MAX_BITS`.
+    pub fn hash_vec_compact(&self, v: &[f32]) -> Result<CompactSrpHash> {
+        CompactSrpHash::from_hash(&self.hash_vec(v))
+    }
+}
+
+/// Bitset representation of a [SignRandomProjections](struct.SignRandomProjections.html) hash.
+///
+/// Every bit of the hash is packed in `u128` words. This makes comparing hashes by
+/// Hamming distance a few `xor` and `count_ones` instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CompactSrpHash {
+    words: [u128; 2],
+}
+
+impl CompactSrpHash {
+    /// Maximum hash length that can be stored.
+    pub const MAX_BITS: usize = 256;
+
+    /// Pack a SRP hash. Every non zero value is stored as a set bit.
+    ///
+    /// # Arguments
+    /// * `hash` - Hash of at most `MAX_BITS` values.
+    pub fn from_hash(hash: &[HashPrimitive]) -> Result<CompactSrpHash> {
+        if hash.len() > Self::MAX_BITS {
+            return Err(Error::Failed(format!(
+                "hash length {} does not fit in {} bits",
+                hash.len(),
+                Self::MAX_BITS
+            )));
+        }
+        let mut words = [0u128; 2];
+        for (i, &h) in hash.iter().enumerate() {
+            if h != 0 {
+                words[i / 128] |= 1 << (i % 128);
+            }
+        }
+        Ok(CompactSrpHash { words })
+    }
+
+    /// Number of bits that differ between two hashes.
+    pub fn hamming(&self, other: &Self) -> u32 {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+/// The Heaviside step of every bit is passed as identity.
+impl Differentiable for SignRandomProjections {
+    fn gradient_wrt_input(&self, _v: &[f32], upstream_grad: &[f32]) -> Vec<f32> {
+        self.hyperplanes.dot(&aview1(upstream_grad)).to_vec()
+    }
 }
 
 impl VecHash for SignRandomProjections {
@@ -61,6 +229,456 @@ impl VecHash for SignRandomProjections {
     fn hash_vec_put(&self, v: &[f32]) -> Hash {
         self.hash_vec(v)
     }
+
+    fn n_projections(&self) -> usize {
+        self.hyperplanes.ncols()
+    }
+
+    fn dim(&self) -> usize {
+        self.hyperplanes.nrows()
+    }
+
+    fn as_query_directed_probe(&self) -> Option<&dyn QueryDirectedProbe> {
+        Some(self)
+    }
+
+    /// The smallest cosine between the query and the hyperplanes.
+    /// The nearest bit to flip determines how close the query is to a neighboring hash.
+    fn query_margin(&self, v: &[f32]) -> Option<FloatSize> {
+        let v_norm = l2_norm(v);
+        if v_norm == 0. {
+            return Some(0.);
+        }
+        let projections = self.hyperplanes.t().dot(&aview1(v));
+        projections
+            .iter()
+            .zip(self.hyperplanes.gencolumns())
+            .map(|(p, h)| p.abs() / (h.dot(&h).sqrt() * v_norm))
+            .fold(None, |acc: Option<FloatSize>, m| {
+                Some(acc.map_or(m, |acc| acc.min(m)))
+            })
+    }
+
+    fn metric(&self) -> DistanceMetric {
+        DistanceMetric::Cosine
+    }
+}
+
+/// Number of random sign flip + Hadamard rounds in the pseudo random rotation.
+const FALCONN_ROTATIONS: usize = 3;
+
+/// In place (unnormalized) fast Walsh-Hadamard transform. `x.len()` must be a power of two.
+fn fwht(x: &mut [f32]) {
+    let mut h = 1;
+    while h < x.len() {
+        for i in (0..x.len()).step_by(h * 2) {
+            for j in i..i + h {
+                let (a, b) = (x[j], x[j + h]);
+                x[j] = a + b;
+                x[j + h] = a - b;
+            }
+        }
+        h *= 2;
+    }
+}
+
+/// Cross-polytope hasher family with random dimensionality reduction as in
+/// [FALCONN](https://arxiv.org/pdf/1509.02897.pdf). A family for the cosine similarity.
+///
+/// Every hash value is one cross-polytope hash: the data point is projected by a Gaussian matrix
+/// to `k` dimensions (padded to a power of two), rotated by random sign flips and Walsh-Hadamard
+/// transforms and hashed to the nearest signed basis vector.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FalconnHash {
+    /// Gaussian dimensionality reduction per hash value. Shape: `(padded_k, dim)`.
+    projections: Vec<Array2<f32>>,
+    /// Random signs per hash value. Shape: `(FALCONN_ROTATIONS, padded_k)`.
+    signs: Vec<Array2<f32>>,
+}
+
+impl FalconnHash {
+    /// Maximum `k`, so that a hash value fits in a `HashPrimitive`.
+    pub const MAX_K: usize = 64;
+
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - Target dimension of the cross-polytopes. Rounded up to a power of two; a hash
+    ///   value has `2 * k` possible values. May not exceed `MAX_K`.
+    /// * `n_projections` - Number of cross-polytope hashes that form the hash.
+    pub fn new(k: usize, n_projections: usize, dim: usize, seed: u64) -> FalconnHash {
+        let padded_k = k.max(1).next_power_of_two();
+        assert!(padded_k <= Self::MAX_K, "k may not exceed {}", Self::MAX_K);
+        let mut rng = create_rng(seed);
+        let projections = (0..n_projections)
+            .map(|_| Array::random_using((padded_k, dim), StandardNormal, &mut rng))
+            .collect();
+        let signs = (0..n_projections)
+            .map(|_| {
+                Array::from_shape_fn((FALCONN_ROTATIONS, padded_k), |_| {
+                    if rng.gen::<bool>() {
+                        1.
+                    } else {
+                        -1.
+                    }
+                })
+            })
+            .collect();
+        FalconnHash { projections, signs }
+    }
+
+    fn hash_vec(&self, v: &[f32]) -> Hash {
+        let v = aview1(v);
+        self.projections
+            .iter()
+            .zip(&self.signs)
+            .map(|(proj, signs)| {
+                let mut x = proj.dot(&v).to_vec();
+                for row in signs.genrows() {
+                    x.iter_mut().zip(row).for_each(|(xi, s)| *xi *= s);
+                    fwht(&mut x);
+                }
+                let (idx, max) = x
+                    .iter()
+                    .enumerate()
+                    .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+                    .unwrap();
+                (idx * 2 + (*max < 0.) as usize) as HashPrimitive
+            })
+            .collect()
+    }
+}
+
+impl VecHash for FalconnHash {
+    fn hash_vec_query(&self, v: &[f32]) -> Hash {
+        self.hash_vec(v)
+    }
+
+    fn hash_vec_put(&self, v: &[f32]) -> Hash {
+        self.hash_vec(v)
+    }
+
+    fn n_projections(&self) -> usize {
+        self.projections.len()
+    }
+
+    fn dim(&self) -> usize {
+        self.projections.first().map_or(0, |p| p.ncols())
+    }
+
+    fn metric(&self) -> DistanceMetric {
+        DistanceMetric::Cosine
+    }
+}
+
+/// Tensor Sketch hasher family for the polynomial kernel `(x^T y + c)^d`.
+/// [Read more.](https://www.itu.dk/people/pagh/papers/tensorsketch.pdf)
+///
+/// A data point is mapped to random features of which the inner products approximate the kernel.
+/// The features are the inverse FFT of the pointwise product of `d` FFT'd count sketches.
+/// The features are hashed with [SignRandomProjections](struct.SignRandomProjections.html).
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TensorSketch {
+    /// Count sketch bucket of every input dimension (plus the constant) per degree.
+    buckets: Array2<usize>,
+    /// Count sketch sign of every input dimension (plus the constant) per degree.
+    signs: Array2<f32>,
+    c: f32,
+    sketch_dim: usize,
+    hasher: SignRandomProjections,
+}
+
+impl TensorSketch {
+    ///
+    /// # Arguments
+    ///
+    /// * `degree` - Degree `d` of the polynomial kernel.
+    /// * `sketch_dim` - Number of random features.
+    /// * `c` - Non negative constant of the kernel.
+    /// * `n_projections` - Hash length.
+    pub fn new(
+        dim: usize,
+        degree: u32,
+        sketch_dim: usize,
+        c: f32,
+        n_projections: usize,
+        seed: u64,
+    ) -> TensorSketch {
+        let mut rng = create_rng(seed);
+        let shape = (degree as usize, dim + 1);
+        let buckets = Array::random_using(shape, Uniform::new(0, sketch_dim), &mut rng);
+        let signs = Array::from_shape_fn(shape, |_| if rng.gen::<bool>() { 1. } else { -1. });
+        let hasher = SignRandomProjections::new(n_projections, sketch_dim, rng.gen());
+        TensorSketch {
+            buckets,
+            signs,
+            c,
+            sketch_dim,
+            hasher,
+        }
+    }
+
+    /// Random features of `v`. `sketch(x)^T sketch(y)` approximates `(x^T y + c)^d`.
+    pub fn sketch(&self, v: &DataPointSlice) -> Vec<FloatSize> {
+        self.sketch_of(&vec![v; self.buckets.nrows()])
+    }
+
+    /// Random features of the tensor product of `d` data points. The inner product of the
+    /// features of `(x_1, .., x_d)` and `(y_1, .., y_d)` approximates `Π (x_i^T y_i + c)`.
+    ///
+    /// # Arguments
+    /// * `vs` - `d` data points.
+    pub fn sketch_tuple(&self, vs: &[&DataPointSlice]) -> Result<Vec<FloatSize>> {
+        if vs.len() != self.buckets.nrows() {
+            return Err(Error::Failed(format!(
+                "expected {} data points",
+                self.buckets.nrows()
+            )));
+        }
+        Ok(self.sketch_of(vs))
+    }
+
+    /// Hash the tensor product of `d` data points.
+    pub(crate) fn hash_tuple(&self, vs: &[&DataPointSlice]) -> Result<Hash> {
+        Ok(self.hasher.hash_vec(&self.sketch_tuple(vs)?))
+    }
+
+    fn sketch_of(&self, vs: &[&DataPointSlice]) -> Vec<FloatSize> {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(self.sketch_dim);
+        let mut product = vec![Complex::new(1., 0.); self.sketch_dim];
+        for ((buckets, signs), v) in self
+            .buckets
+            .genrows()
+            .into_iter()
+            .zip(self.signs.genrows())
+            .zip(vs)
+        {
+            let x = v.iter().copied().chain(Some(self.c.sqrt()));
+            let mut count_sketch = vec![Complex::new(0., 0.); self.sketch_dim];
+            for ((&b, s), xi) in buckets.iter().zip(signs).zip(x) {
+                count_sketch[b].re += s * xi;
+            }
+            fft.process(&mut count_sketch);
+            product
+                .iter_mut()
+                .zip(&count_sketch)
+                .for_each(|(p, c)| *p *= c);
+        }
+        planner
+            .plan_fft_inverse(self.sketch_dim)
+            .process(&mut product);
+        product
+            .iter()
+            .map(|p| p.re / self.sketch_dim as f32)
+            .collect()
+    }
+}
+
+impl VecHash for TensorSketch {
+    fn hash_vec_query(&self, v: &[f32]) -> Hash {
+        self.hasher.hash_vec(&self.sketch(v))
+    }
+
+    fn hash_vec_put(&self, v: &[f32]) -> Hash {
+        self.hash_vec_query(v)
+    }
+
+    fn n_projections(&self) -> usize {
+        self.hasher.n_projections()
+    }
+
+    fn dim(&self) -> usize {
+        // the last column is the constant of the kernel.
+        self.buckets.ncols() - 1
+    }
+
+    fn metric(&self) -> DistanceMetric {
+        DistanceMetric::InnerProduct
+    }
+}
+
+/// Geohash of 2D geographic coordinates `[longitude, latitude]` in degrees.
+///
+/// The longitude and latitude ranges are halved alternately, starting with the longitude. Every
+/// halving adds a bit to the hash. An optional offset shifts the grid, so that hash tables with
+/// different offsets don't share their cell boundaries.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GeoHash {
+    precision_bits: usize,
+    /// Shift of the grid in degrees: `[longitude, latitude]`.
+    offset: [f32; 2],
+}
+
+impl GeoHash {
+    ///
+    /// # Arguments
+    ///
+    /// * `precision_bits` - Number of bits of the hash.
+    /// * `offset` - Shift of the grid `[longitude, latitude]` in degrees.
+    pub fn new(precision_bits: usize, offset: [f32; 2]) -> GeoHash {
+        GeoHash {
+            precision_bits,
+            offset,
+        }
+    }
+
+    /// Cell size `[longitude, latitude]` in degrees.
+    pub fn cell_size(&self) -> [f32; 2] {
+        let lon_bits = self.precision_bits.div_ceil(2);
+        let lat_bits = self.precision_bits / 2;
+        [
+            360. / (1u64 << lon_bits) as f32,
+            180. / (1u64 << lat_bits) as f32,
+        ]
+    }
+
+    fn hash_vec(&self, v: &[f32]) -> Hash {
+        let mut ranges = [(-180f32, 180f32), (-90f32, 90f32)];
+        let coords = [v[0] + self.offset[0], v[1] + self.offset[1]];
+        (0..self.precision_bits)
+            .map(|i| {
+                let (lo, hi) = &mut ranges[i % 2];
+                let mid = (*lo + *hi) / 2.;
+                if coords[i % 2] >= mid {
+                    *lo = mid;
+                    1
+                } else {
+                    *hi = mid;
+                    0
+                }
+            })
+            .collect()
+    }
+}
+
+impl VecHash for GeoHash {
+    fn hash_vec_query(&self, v: &[f32]) -> Hash {
+        self.hash_vec(v)
+    }
+
+    fn hash_vec_put(&self, v: &[f32]) -> Hash {
+        self.hash_vec(v)
+    }
+
+    fn n_projections(&self) -> usize {
+        self.precision_bits
+    }
+
+    fn dim(&self) -> usize {
+        2
+    }
+}
+
+/// Wraps a hasher to be tolerant to small perturbations of the input.
+/// The hash of `v` and the hashes of `v + epsilon * e_i` for every basis vector `e_i` are
+/// computed and the most common hash is returned. Ties are won by the hash that occurs first,
+/// starting with the hash of `v`. This costs `dim` extra hash evaluations per hash.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FuzzyHash<H: VecHash> {
+    pub hasher: H,
+    /// Size of the perturbation in every direction.
+    pub epsilon: FloatSize,
+}
+
+impl<H: VecHash> FuzzyHash<H> {
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - The wrapped hasher.
+    /// * `epsilon` - Size of the perturbation in every direction.
+    pub fn new(hasher: H, epsilon: FloatSize) -> FuzzyHash<H> {
+        FuzzyHash { hasher, epsilon }
+    }
+
+    fn plurality_vote<F: Fn(&[f32]) -> Hash>(&self, v: &[f32], hash: F) -> Hash {
+        let mut votes: Vec<(Hash, usize)> = vec![(hash(v), 1)];
+        let mut perturbed = v.to_vec();
+        for i in 0..v.len() {
+            perturbed[i] += self.epsilon;
+            let h = hash(&perturbed);
+            perturbed[i] = v[i];
+            match votes.iter_mut().find(|(vote, _)| *vote == h) {
+                Some((_, count)) => *count += 1,
+                None => votes.push((h, 1)),
+            }
+        }
+        // max_by_key returns the last maximum, so ties would go to the latest hash.
+        let best = votes.iter().map(|(_, count)| *count).max().unwrap();
+        let idx = votes.iter().position(|(_, count)| *count == best).unwrap();
+        votes.swap_remove(idx).0
+    }
+}
+
+impl<H: VecHash> VecHash for FuzzyHash<H> {
+    fn hash_vec_query(&self, v: &[f32]) -> Hash {
+        self.plurality_vote(v, |x| self.hasher.hash_vec_query(x))
+    }
+
+    fn hash_vec_put(&self, v: &[f32]) -> Hash {
+        self.plurality_vote(v, |x| self.hasher.hash_vec_put(x))
+    }
+
+    fn n_projections(&self) -> usize {
+        self.hasher.n_projections()
+    }
+
+    fn dim(&self) -> usize {
+        self.hasher.dim()
+    }
+
+    fn metric(&self) -> DistanceMetric {
+        self.hasher.metric()
+    }
+}
+
+/// Signed Random Projections on feature weighted data points, e.g. with TF-IDF weights.
+/// Every data point `v` is hashed as `v_i * w_i`, so that the hash respects the importance of
+/// the features without modifying the stored data points.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WeightedSrp {
+    srp: SignRandomProjections,
+    /// Weight per dimension.
+    pub weights: Vec<f32>,
+}
+
+impl WeightedSrp {
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - Number of hyperplanes used for determining the hash.
+    /// * `weights` - Weight per dimension.
+    pub fn new(k: usize, weights: Vec<f32>, seed: u64) -> WeightedSrp {
+        let srp = SignRandomProjections::new(k, weights.len(), seed);
+        WeightedSrp { srp, weights }
+    }
+
+    fn weigh(&self, v: &[f32]) -> Vec<f32> {
+        v.iter().zip(&self.weights).map(|(x, w)| x * w).collect()
+    }
+}
+
+impl VecHash for WeightedSrp {
+    fn hash_vec_query(&self, v: &[f32]) -> Hash {
+        self.srp.hash_vec(&self.weigh(v))
+    }
+
+    fn hash_vec_put(&self, v: &[f32]) -> Hash {
+        self.hash_vec_query(v)
+    }
+
+    fn n_projections(&self) -> usize {
+        self.srp.n_projections()
+    }
+
+    fn dim(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Candidates are ranked by the cosine similarity of the unweighted data points.
+    fn metric(&self) -> DistanceMetric {
+        DistanceMetric::Cosine
+    }
 }
 
 /// L2 Hasher family. [Read more.](https://arxiv.org/pdf/1411.3787.pdf)
@@ -87,6 +705,46 @@ impl L2 {
         }
     }
 
+    /// Recommend a value for `r` by sampling random pairs of data points and returning the
+    /// `target_quantile` of their Euclidean distances.
+    ///
+    /// # Arguments
+    /// * `data` - Data points.
+    /// * `target_quantile` - Quantile of the pairwise distances, e.g. 0.05 for the 5th percentile.
+    /// * `n_pairs` - Number of random pairs that are sampled.
+    /// * `seed` - Seed for the RNG. If 0, RNG is seeded randomly.
+    ///
+    /// Fails if `data` contains less than two data points or `n_pairs` is 0.
+    pub fn calibrate_r(
+        data: &[DataPoint],
+        target_quantile: f64,
+        n_pairs: usize,
+        seed: u64,
+    ) -> Result<f32> {
+        if data.len() < 2 || n_pairs == 0 {
+            return Err(Error::Failed(
+                "at least two data points and one pair are needed for calibration".to_string(),
+            ));
+        }
+        let mut distances = sample_pair_distances(data, n_pairs, seed, DistanceMetric::L2);
+        distances.sort_unstable_by(|a, b| a.total_cmp(b));
+        let q = target_quantile.clamp(0., 1.);
+        let idx = ((distances.len() - 1) as f64 * q).round() as usize;
+        Ok(distances[idx])
+    }
+
+    /// Probability that a single projection of two data points at L2 distance `d` collides
+    /// (Datar et al. 2004). See [l2_ph](stats/fn.l2_ph.html).
+    ///
+    /// # Arguments
+    /// * `d` - L2 distance of the data points.
+    pub fn collision_probability(&self, d: f32) -> f32 {
+        if d <= 0. {
+            return 1.;
+        }
+        l2_ph(self.r as f64, d as f64) as f32
+    }
+
     pub(crate) fn hash_vec(&self, v: &DataPointSlice) -> Array1<FloatSize> {
         ((self.a.dot(&aview1(v)) + &self.b) / self.r).mapv(|x| x.floor())
     }
@@ -99,6 +757,27 @@ impl L2 {
     }
 }
 
+/// The floor is passed as identity where the fractional part of the projection is in
+/// `[0.1, 0.9]` and blocked near slot boundaries, where the hash is unstable.
+impl Differentiable for L2 {
+    fn gradient_wrt_input(&self, v: &[f32], upstream_grad: &[f32]) -> Vec<f32> {
+        let z = (self.a.dot(&aview1(v)) + &self.b) / self.r;
+        let grad = z
+            .iter()
+            .zip(upstream_grad)
+            .map(|(z, g)| {
+                let frac = z - z.floor();
+                if (STE_DELTA..=1. - STE_DELTA).contains(&frac) {
+                    g / self.r
+                } else {
+                    0.
+                }
+            })
+            .collect::<Array1<f32>>();
+        self.a.t().dot(&grad).to_vec()
+    }
+}
+
 impl VecHash for L2 {
     fn hash_vec_query(&self, v: &[f32]) -> Hash {
         self.hash_and_cast_vec(v)
@@ -108,9 +787,29 @@ impl VecHash for L2 {
         self.hash_and_cast_vec(v)
     }
 
+    fn n_projections(&self) -> usize {
+        self.n_projections
+    }
+
+    fn dim(&self) -> usize {
+        self.a.ncols()
+    }
+
     fn as_query_directed_probe(&self) -> Option<&dyn QueryDirectedProbe> {
         Some(self)
     }
+
+    /// The smallest xi distance (see Multi-Probe LSH) of the query to its slot boundaries.
+    fn query_margin(&self, v: &[f32]) -> Option<FloatSize> {
+        let (xi_min, xi_plus) = self.distance_to_bound(v, None);
+        xi_min
+            .iter()
+            .chain(xi_plus.iter())
+            .copied()
+            .fold(None, |acc: Option<FloatSize>, m| {
+                Some(acc.map_or(m, |acc| acc.min(m)))
+            })
+    }
 }
 
 /// Maximum Inner Product Search. [Read more.](https://papers.nips.cc/paper/5329-asymmetric-lsh-alsh-for-sublinear-time-maximum-inner-product-search-mips.pdf)
@@ -121,6 +820,14 @@ pub struct MIPS {
     m: usize,
     dim: usize,
     hasher: L2,
+    /// `M` changed after data points may have been hashed with the old value.
+    /// Not serialized, so that dumps of older versions can be loaded; dump after
+    /// [LSH::rebuild_index](struct.LSH.html#method.rebuild_index).
+    #[serde(skip)]
+    stale: bool,
+    /// `M` the stored data points were hashed with, 0 if unknown. Not serialized.
+    #[serde(skip)]
+    indexed_M: f32,
 }
 
 impl MIPS {
@@ -132,7 +839,20 @@ impl MIPS {
             m,
             dim,
             hasher: l2,
+            stale: false,
+            indexed_M: 0.,
+        }
+    }
+
+    fn set_max_norm(&mut self, max_l2: f32) {
+        // nothing can be hashed before the first fit.
+        if self.M != 0. && max_l2 != self.M {
+            self.stale = true
+        }
+        if self.indexed_M == 0. {
+            self.indexed_M = max_l2
         }
+        self.M = max_l2
     }
 
     pub fn fit(&mut self, v: &[f32]) {
@@ -143,7 +863,58 @@ impl MIPS {
                 max_l2 = l2
             }
         }
-        self.M = max_l2
+        self.set_max_norm(max_l2)
+    }
+
+    /// Grow the maximum norm `M` if `x` has a larger L2 norm. Returns `true` if `M` changed.
+    /// Stored data points are not re-hashed, see [needs_rebuild](#method.needs_rebuild).
+    ///
+    /// # Arguments
+    /// * `x` - Data point.
+    pub fn fit_incremental(&mut self, x: &[f32]) -> bool {
+        let l2 = l2_norm(x);
+        if l2 > self.M {
+            self.set_max_norm(l2);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Track the maximum norm of a stream with an exponentially decaying maximum
+    /// `M = max(M * decay, l2_norm(x))`, so that large norms that left the stream are forgotten.
+    /// Returns `true` if `M` shrank. Stored data points are not re-hashed, see
+    /// [needs_rebuild](#method.needs_rebuild).
+    ///
+    /// # Arguments
+    /// * `x` - Data point.
+    /// * `decay` - Decay of `M` per data point in `(0, 1]`.
+    pub fn fit_from_stream(&mut self, x: &[f32], decay: f32) -> bool {
+        let max_l2 = (self.M * decay).max(l2_norm(x));
+        let shrank = max_l2 < self.M;
+        self.set_max_norm(max_l2);
+        shrank
+    }
+
+    /// Maximum norm `M` the data points are scaled with.
+    pub fn current_M(&self) -> f32 {
+        self.M
+    }
+
+    /// If `M` changed since the data points were hashed. Call
+    /// [LSH::rebuild_index](struct.LSH.html#method.rebuild_index) to re-hash them.
+    /// This flag isn't saved in dumps.
+    pub fn needs_rebuild(&self) -> bool {
+        self.stale
+    }
+
+    pub(crate) fn rebuilt(&mut self) {
+        self.stale = false;
+        self.indexed_M = self.M
+    }
+
+    pub(crate) fn indexed_max_norm(&self) -> f32 {
+        self.indexed_M
     }
 
     pub fn tranform_put(&self, x: &[f32]) -> Vec<f32> {
@@ -191,12 +962,46 @@ impl VecHash for MIPS {
         let p = self.tranform_put(v);
         self.hasher.hash_vec_query(&p)
     }
+
+    fn n_projections(&self) -> usize {
+        self.hasher.n_projections()
+    }
+
+    fn dim(&self) -> usize {
+        self.dim
+    }
+
+    fn metric(&self) -> DistanceMetric {
+        DistanceMetric::InnerProduct
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_n_projections_and_dim() {
+        let hashers: Vec<Box<dyn VecHash>> = vec![
+            Box::new(SignRandomProjections::new(7, 5, 1)),
+            Box::new(FalconnHash::new(4, 7, 5, 1)),
+            Box::new(TensorSketch::new(5, 2, 16, 1., 7, 1)),
+            Box::new(L2::new(5, 2.2, 7, 1)),
+            Box::new(MIPS::new(5, 2.2, 0.83, 3, 7, 1)),
+            Box::new(FuzzyHash::new(L2::new(5, 2.2, 7, 1), 0.1)),
+        ];
+        for h in &hashers {
+            assert_eq!((h.n_projections(), h.dim()), (7, 5));
+        }
+        let geo = GeoHash::new(12, [0., 0.]);
+        assert_eq!((geo.n_projections(), geo.dim()), (12, 2));
+        let v = [1., 2., 3., 1., 3.];
+        assert_eq!(
+            hashers[3].hash_vec_query(&v).len(),
+            hashers[3].n_projections()
+        );
+    }
+
     #[test]
     fn test_l2() {
         // Only test if it runs
@@ -212,4 +1017,239 @@ mod test {
         assert_eq!(h1, h2);
         assert_ne!(h1, h3);
     }
+
+    #[test]
+    fn test_fuzzy_hash() {
+        // hash is the sign of the sum of the coordinates.
+        struct SumSign;
+        impl VecHash for SumSign {
+            fn hash_vec_query(&self, v: &[f32]) -> Hash {
+                vec![(v.iter().sum::<f32>() >= 0.) as HashPrimitive]
+            }
+            fn hash_vec_put(&self, v: &[f32]) -> Hash {
+                self.hash_vec_query(v)
+            }
+            fn n_projections(&self) -> usize {
+                1
+            }
+            fn dim(&self) -> usize {
+                3
+            }
+        }
+
+        let v = [-0.05, 0., 0.];
+        assert_eq!(SumSign.hash_vec_query(&v), vec![0]);
+        // all 3 perturbations cross the boundary and outvote v.
+        let fuzzy = FuzzyHash::new(SumSign, 0.1);
+        assert_eq!(fuzzy.hash_vec_query(&v), vec![1]);
+        assert_eq!(fuzzy.hash_vec_put(&v), vec![1]);
+        // far from the boundary the vote is unanimous.
+        assert_eq!(fuzzy.hash_vec_query(&[-1., 0., 0.]), vec![0]);
+        // 1 vote each, the tie goes to the hash of v.
+        let fuzzy = FuzzyHash::new(SumSign, -0.1);
+        assert_eq!(fuzzy.hash_vec_query(&[0.05]), vec![1]);
+    }
+
+    #[test]
+    fn test_bit_margin() {
+        let srp = SignRandomProjections::new(6, 3, 1);
+        let v = [1., -2., 0.5];
+        let hash = srp.hash_vec_query(&v);
+        for (i, &bit) in hash.iter().enumerate() {
+            assert_eq!(srp.bit_margin(&v, i) > 0., bit == 1);
+        }
+        let doubled = srp.bit_margin(&[2., -4., 1.], 2);
+        assert!((doubled - 2. * srp.bit_margin(&v, 2)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_weighted_srp() {
+        // the documents share the rare term 5 and differ in a common term.
+        let mut a = vec![0.; 10];
+        let mut b = vec![0.; 10];
+        a[0] = 1.;
+        b[1] = 1.;
+        a[5] = 1.;
+        b[5] = 1.;
+        let mut weights = vec![1.; 10];
+        weights[5] = 5.;
+
+        let collisions = |weights: &[f32]| {
+            (0..200)
+                .filter(|&seed| {
+                    let h = WeightedSrp::new(4, weights.to_vec(), seed);
+                    h.hash_vec_query(&a) == h.hash_vec_put(&b)
+                })
+                .count()
+        };
+        let unweighted = collisions(&[1.; 10]);
+        let weighted = collisions(&weights);
+        assert!(weighted > unweighted * 2);
+
+        let h = WeightedSrp::new(4, weights, 1);
+        assert_eq!((h.n_projections(), h.dim()), (4, 10));
+        // only the direction of the weighted data point matters.
+        assert_eq!(
+            h.hash_vec_query(&a),
+            h.srp.hash_vec(&[1., 0., 0., 0., 0., 5., 0., 0., 0., 0.])
+        );
+    }
+
+    #[test]
+    fn test_mips_fit_incremental() {
+        let mut h = MIPS::new(2, 2.2, 0.83, 3, 7, 1);
+        assert!(h.fit_incremental(&[3., 4.]));
+        // the first fit doesn't need a rebuild.
+        assert!(!h.needs_rebuild());
+        assert_eq!(h.M, 5.);
+        assert!(!h.fit_incremental(&[1., 1.]));
+        assert!(!h.needs_rebuild());
+        assert!(h.fit_incremental(&[6., 8.]));
+        assert_eq!(h.M, 10.);
+        assert!(h.needs_rebuild());
+        h.rebuilt();
+        assert!(!h.needs_rebuild());
+    }
+
+    #[test]
+    fn test_gradient_wrt_input() {
+        let v = [0.3, -1.2, 2.5, 0.7];
+        let upstream = vec![1.; 6];
+        let srp = SignRandomProjections::new(6, 4, 1);
+        let grad = srp.gradient_wrt_input(&v, &upstream);
+        assert_eq!(grad.len(), 4);
+        assert!(grad.iter().any(|g| *g != 0.));
+        // a step along the gradient increases the sum of the margins.
+        let sum_margins = |x: &[f32]| (0..6).map(|i| srp.bit_margin(x, i)).sum::<f32>();
+        let stepped: Vec<f32> = v.iter().zip(&grad).map(|(x, g)| x + 0.01 * g).collect();
+        assert!(sum_margins(&stepped) > sum_margins(&v));
+
+        let l2 = L2::new(4, 4., 6, 1);
+        let grad = l2.gradient_wrt_input(&v, &upstream);
+        assert_eq!(grad.len(), 4);
+        assert!(grad.iter().any(|g| *g != 0.));
+        // the gradient is linear in the upstream gradient.
+        assert!(l2.gradient_wrt_input(&v, &[0.; 6]).iter().all(|g| *g == 0.));
+    }
+
+    #[test]
+    fn test_collision_probability() {
+        // the fraction of colliding projections approximates the collision probability.
+        let n = 4000;
+        let a = [0.; 4];
+        let b = [0.6, -0.8, 0., 0.];
+        let l2 = L2::new(4, 2., n, 1);
+        let (ha, hb) = (l2.hash_vec_query(&a), l2.hash_vec_query(&b));
+        let p = ha.iter().zip(&hb).filter(|(x, y)| x == y).count() as f32 / n as f32;
+        let expected = l2.collision_probability(1.);
+        assert!((p - expected).abs() < 0.03, "{} {}", p, expected);
+        assert_eq!(l2.collision_probability(0.), 1.);
+        assert!(l2.collision_probability(4.) < expected);
+
+        let srp = SignRandomProjections::new(n, 4, 1);
+        let a = [1., 0., 0., 0.];
+        let b = [0.5, 3f32.sqrt() / 2., 0., 0.];
+        let (ha, hb) = (srp.hash_vec_query(&a), srp.hash_vec_query(&b));
+        let p = ha.iter().zip(&hb).filter(|(x, y)| x == y).count() as f32 / n as f32;
+        let expected = srp.collision_probability(std::f32::consts::FRAC_PI_3);
+        assert!((expected - 2. / 3.).abs() < 1e-6);
+        assert!((p - expected).abs() < 0.03, "{} {}", p, expected);
+        assert_eq!(srp.collision_probability(-1.), 1.);
+        assert!(srp.collision_probability(4.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_mips_fit_from_stream() {
+        let mut h = MIPS::new(2, 2.2, 0.83, 3, 7, 1);
+        assert!(!h.fit_from_stream(&[3., 4.], 0.5));
+        assert!(h.fit_from_stream(&[0., 1.], 0.5));
+        assert_eq!(h.current_M(), 2.5);
+        assert!(h.needs_rebuild());
+
+        // on a stationary stream with norms in [0.5, 2) the decayed maximum stays close to 2.
+        let mut h = MIPS::new(2, 2.2, 0.83, 3, 7, 1);
+        let mut rng = create_rng(1);
+        for _ in 0..5000 {
+            let norm = rng.gen_range(0.5, 2.);
+            h.fit_from_stream(&[norm, 0.], 0.999);
+        }
+        assert!(h.current_M() > 1.8 && h.current_M() <= 2.);
+    }
+
+    #[test]
+    fn test_falconn() {
+        let mut x = vec![1., 0., 0., 0.];
+        fwht(&mut x);
+        assert_eq!(x, vec![1., 1., 1., 1.]);
+
+        let falconn = FalconnHash::new(6, 3, 5, 1);
+        let h1 = falconn.hash_vec_query(&[1., 2., 3., 1., 3.]);
+        assert_eq!(h1.len(), 3);
+        // k is padded to 8, so there are 16 possible values.
+        assert!(h1.iter().all(|&h| (0..16).contains(&h)));
+        // the hash only depends on the direction.
+        assert_eq!(h1, falconn.hash_vec_query(&[2., 4., 6., 2., 6.]));
+        assert_ne!(h1, falconn.hash_vec_query(&[-1., -2., -3., -1., -3.]));
+    }
+
+    #[test]
+    fn test_tensor_sketch() {
+        let x = [0.6, -0.8, 0., 0.];
+        let y = [0.8, -0.6, 0., 0.];
+        // (x^T y + c)^d = (0.96 + 1)^2
+        let kernel = 1.96f32.powi(2);
+        let n = 50;
+        let approx: f32 = (0..n)
+            .map(|seed| {
+                let ts = TensorSketch::new(4, 2, 512, 1., 8, seed);
+                crate::dist::inner_prod(&ts.sketch(&x), &ts.sketch(&y))
+            })
+            .sum::<f32>()
+            / n as f32;
+        assert!((approx - kernel).abs() < 0.2, "{} {}", approx, kernel);
+
+        let ts = TensorSketch::new(4, 2, 64, 1., 8, 1);
+        assert_eq!(ts.hash_vec_query(&x).len(), 8);
+    }
+
+    #[test]
+    fn test_geohash() {
+        let geohash = GeoHash::new(20, [0., 0.]);
+        // Amsterdam Centraal and Dam square are ~1 km apart.
+        let a = geohash.hash_vec_query(&[4.900, 52.379]);
+        let b = geohash.hash_vec_query(&[4.893, 52.373]);
+        assert_eq!(a, b);
+        assert_ne!(a, geohash.hash_vec_query(&[2.352, 48.857]));
+        // the geohash of Amsterdam starts with "u", which is 11010 in base 32.
+        assert_eq!(a[..5], [1, 1, 0, 1, 0]);
+        assert_eq!(geohash.cell_size(), [360. / 1024., 180. / 1024.]);
+    }
+
+    #[test]
+    fn test_compact_srp_hash() {
+        let a = CompactSrpHash::from_hash(&[1, 0, 1, 1]).unwrap();
+        let b = CompactSrpHash::from_hash(&[1, 1, 0, 1]).unwrap();
+        assert_eq!(a.hamming(&a), 0);
+        assert_eq!(a.hamming(&b), 2);
+
+        // the bits of both words are compared.
+        let mut h = vec![0; 256];
+        h[127] = 1;
+        h[128] = 1;
+        h[255] = 1;
+        let c = CompactSrpHash::from_hash(&h).unwrap();
+        assert_eq!(c.hamming(&CompactSrpHash::default()), 3);
+        h[200] = 1;
+        let d = CompactSrpHash::from_hash(&h).unwrap();
+        assert_eq!(c.hamming(&d), 1);
+        assert!(CompactSrpHash::from_hash(&[0; 257]).is_err());
+
+        let srp = SignRandomProjections::new(64, 3, 1);
+        let v = [1., 2., 3.];
+        let compact = srp.hash_vec_compact(&v).unwrap();
+        assert_eq!(
+            compact,
+            CompactSrpHash::from_hash(&srp.hash_vec(&v)).unwrap()
+        );
+    }
 }
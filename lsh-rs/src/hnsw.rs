@@ -0,0 +1,279 @@
+//! Multi-probe LSH around an entry point found with HNSW.
+use crate::{
+    dist::l2_dist, utils::create_rng, DataId, DataPoint, DataPointSlice, Error, HashTables, Result,
+    VecHash, LSH,
+};
+use fnv::FnvHashSet;
+use rand::rngs::SmallRng;
+use rand::Rng;
+
+/// Hierarchical Navigable Small World graph. [Read more.](https://arxiv.org/abs/1603.09320)
+/// Nodes are numbered in insertion order and neighbors are selected by distance only.
+struct Hnsw {
+    /// Maximum number of neighbors per node in the upper layers, `2 * m` in layer 0.
+    m: usize,
+    ef_construction: usize,
+    points: Vec<DataPoint>,
+    /// `neighbors[node][layer]`
+    neighbors: Vec<Vec<Vec<usize>>>,
+    entry: Option<usize>,
+    max_layer: usize,
+    rng: SmallRng,
+}
+
+impl Hnsw {
+    fn new(m: usize, ef_construction: usize, seed: u64) -> Self {
+        Hnsw {
+            m,
+            ef_construction,
+            points: vec![],
+            neighbors: vec![],
+            entry: None,
+            max_layer: 0,
+            rng: create_rng(seed),
+        }
+    }
+
+    fn random_level(&mut self) -> usize {
+        let ml = 1. / (self.m as f64).ln();
+        let u: f64 = self.rng.gen_range(f64::EPSILON, 1.);
+        (-u.ln() * ml) as usize
+    }
+
+    /// `(distance, node)` of the at most `ef` nearest nodes found in `layer`, sorted by distance.
+    fn search_layer(
+        &self,
+        q: &DataPointSlice,
+        entry: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<(f32, usize)> {
+        let mut visited: FnvHashSet<usize> = entry.iter().copied().collect();
+        let mut candidates: Vec<(f32, usize)> = entry
+            .iter()
+            .map(|&e| (l2_dist(q, &self.points[e]), e))
+            .collect();
+        let mut results = candidates.clone();
+        results.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+        results.truncate(ef);
+        while !candidates.is_empty() {
+            let nearest = (0..candidates.len())
+                .min_by(|&a, &b| candidates[a].0.total_cmp(&candidates[b].0))
+                .unwrap();
+            let (d, c) = candidates.swap_remove(nearest);
+            if results.len() >= ef && d > results[results.len() - 1].0 {
+                break;
+            }
+            for &n in &self.neighbors[c][layer] {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let dn = l2_dist(q, &self.points[n]);
+                if results.len() < ef || dn < results[results.len() - 1].0 {
+                    candidates.push((dn, n));
+                    let pos = results.partition_point(|&(x, _)| x <= dn);
+                    results.insert(pos, (dn, n));
+                    results.truncate(ef);
+                }
+            }
+        }
+        results
+    }
+
+    fn insert(&mut self, point: DataPoint) {
+        let node = self.points.len();
+        let level = self.random_level();
+        self.points.push(point);
+        self.neighbors.push(vec![vec![]; level + 1]);
+        let entry = match self.entry {
+            None => {
+                self.entry = Some(node);
+                self.max_layer = level;
+                return;
+            }
+            Some(entry) => entry,
+        };
+
+        let q = self.points[node].clone();
+        let mut eps = vec![entry];
+        for layer in (level + 1..=self.max_layer).rev() {
+            eps = vec![self.search_layer(&q, &eps, 1, layer)[0].1];
+        }
+        for layer in (0..=std::cmp::min(level, self.max_layer)).rev() {
+            let found = self.search_layer(&q, &eps, self.ef_construction, layer);
+            let max_neighbors = if layer == 0 { 2 * self.m } else { self.m };
+            let selected: Vec<usize> = found.iter().take(self.m).map(|&(_, n)| n).collect();
+            for &n in &selected {
+                self.neighbors[n][layer].push(node);
+                if self.neighbors[n][layer].len() > max_neighbors {
+                    let p = &self.points[n];
+                    let mut pruned: Vec<(f32, usize)> = self.neighbors[n][layer]
+                        .iter()
+                        .map(|&x| (l2_dist(p, &self.points[x]), x))
+                        .collect();
+                    pruned.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+                    self.neighbors[n][layer] = pruned
+                        .into_iter()
+                        .take(max_neighbors)
+                        .map(|(_, x)| x)
+                        .collect();
+                }
+            }
+            self.neighbors[node][layer] = selected;
+            eps = found.into_iter().map(|(_, n)| n).collect();
+        }
+        if level > self.max_layer {
+            self.max_layer = level;
+            self.entry = Some(node);
+        }
+    }
+
+    /// Approximate nearest node of `q`.
+    fn search(&self, q: &DataPointSlice, ef: usize) -> Option<usize> {
+        let mut eps = vec![self.entry?];
+        for layer in (1..=self.max_layer).rev() {
+            eps = vec![self.search_layer(q, &eps, 1, layer)[0].1];
+        }
+        self.search_layer(q, &eps, ef, 0).first().map(|&(_, n)| n)
+    }
+}
+
+/// [LSH](../struct.LSH.html) that probes around an entry point instead of around the query.
+/// The entry point is the approximate nearest neighbor of the query in an HNSW graph over the
+/// stored data points. The entry point may fall in a better bucket than the query itself, so
+/// with [multi_probe](../struct.LSH.html#method.multi_probe) the probes start from its hash.
+///
+/// The graph is kept in memory next to the LSH and holds a copy of every data point.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::{LSHWithHnswEntry, LshMem};
+/// let mut lsh = LshMem::new(5, 4, 3).seed(1).l2(2.).unwrap();
+/// lsh.multi_probe(8);
+/// let mut index = LSHWithHnswEntry::from_lsh(lsh, 8, 32).unwrap();
+/// let id = index.store_vec(&[2., 3., 4.]).unwrap();
+/// assert!(index.query_bucket_ids(&[2., 3., 4.1]).unwrap().contains(&id));
+/// ```
+pub struct LSHWithHnswEntry<T: HashTables, H: VecHash> {
+    lsh: LSH<T, H>,
+    hnsw: Hnsw,
+    /// Id of every node of the graph.
+    ids: Vec<DataId>,
+    ef: usize,
+}
+
+impl<T: HashTables, H: VecHash> LSHWithHnswEntry<T, H> {
+    /// Wrap an LSH. The graph is built from its stored data points. The levels of the graph
+    /// are seeded from the seed of the LSH.
+    ///
+    /// # Arguments
+    ///
+    /// * `lsh` - Underlying LSH.
+    /// * `m` - Number of neighbors of a node in the graph.
+    /// * `ef` - Size of the candidate list of the graph searches.
+    pub fn from_lsh(mut lsh: LSH<T, H>, m: usize, ef: usize) -> Result<Self> {
+        if m < 2 || ef == 0 {
+            return Err(Error::Failed(
+                "m should be at least 2 and ef at least 1".to_string(),
+            ));
+        }
+        let mut hnsw = Hnsw::new(m, ef, lsh.next_seed());
+        let ht = lsh.hash_tables.as_ref().unwrap();
+        let ids = ht.stored_ids()?;
+        for &id in &ids {
+            hnsw.insert(ht.idx_to_datapoint_owned(id)?);
+        }
+        Ok(LSHWithHnswEntry { lsh, hnsw, ids, ef })
+    }
+
+    /// Underlying LSH.
+    pub fn lsh(&self) -> &LSH<T, H> {
+        &self.lsh
+    }
+
+    /// Store a single data point. Returns id.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn store_vec(&mut self, v: &DataPointSlice) -> Result<DataId> {
+        let id = self.lsh.store_vec(v)?;
+        self.hnsw.insert(v.to_vec());
+        self.ids.push(id);
+        Ok(id)
+    }
+
+    /// Store multiple data points. Returns the ids.
+    ///
+    /// # Arguments
+    /// * `vs` - Data points.
+    pub fn store_vecs(&mut self, vs: &[DataPoint]) -> Result<Vec<DataId>> {
+        vs.iter().map(|v| self.store_vec(v)).collect()
+    }
+
+    /// Id of the approximate nearest neighbor of `v` in the graph.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    pub fn entry_point(&self, v: &DataPointSlice) -> Result<Option<DataId>> {
+        self.lsh.validate_vec(v)?;
+        Ok(self.hnsw.search(v, self.ef).map(|n| self.ids[n]))
+    }
+
+    /// Query the ids that collide with the entry point of `v`. With multi-probing the probes
+    /// are around the hash of the entry point.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    pub fn query_bucket_ids(&self, v: &DataPointSlice) -> Result<Vec<DataId>> {
+        self.lsh.validate_vec(v)?;
+        match self.hnsw.search(v, self.ef) {
+            None => Ok(vec![]),
+            Some(n) => self.lsh.query_bucket_ids(&self.hnsw.points[n]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LshMem;
+
+    #[test]
+    fn test_hnsw_entry_point() {
+        let mut rng = create_rng(1);
+        let vs: Vec<DataPoint> = (0..500)
+            .map(|_| (0..8).map(|_| rng.gen_range(-1., 1.)).collect())
+            .collect();
+        let mut lsh = LshMem::new(6, 4, 8).seed(1).l2(1.).unwrap();
+        lsh.multi_probe(4);
+        lsh.store_vecs(&vs[..100]).unwrap();
+        // the graph is built from the stored data points.
+        let mut index = LSHWithHnswEntry::from_lsh(lsh, 8, 32).unwrap();
+        let ids = index.store_vecs(&vs[100..]).unwrap();
+        assert_eq!(ids[0], 100);
+
+        let mut exact = 0;
+        for _ in 0..100 {
+            let q: DataPoint = (0..8).map(|_| rng.gen_range(-1., 1.)).collect();
+            let nn = (0..vs.len())
+                .min_by(|&a, &b| l2_dist(&q, &vs[a]).total_cmp(&l2_dist(&q, &vs[b])))
+                .unwrap() as DataId;
+            let entry = index.entry_point(&q).unwrap().unwrap();
+            if entry == nn {
+                exact += 1;
+            }
+            assert!(index.query_bucket_ids(&q).unwrap().contains(&entry));
+        }
+        assert!(exact >= 90);
+        assert!(index.query_bucket_ids(&[1., 2.]).is_err());
+        assert!(LSHWithHnswEntry::from_lsh(LshMem::new(6, 4, 8).l2(1.).unwrap(), 1, 8).is_err());
+
+        // data points that aren't in the first hash table are in the graph as well.
+        let mut lsh = LshMem::new(6, 4, 8).seed(1).l2(1.).unwrap();
+        lsh.with_max_bucket_size(1).unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        let index = LSHWithHnswEntry::from_lsh(lsh, 8, 32).unwrap();
+        assert_eq!(index.ids, (0..vs.len() as DataId).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,267 @@
+//! Exact kd-tree search for low dimensional data, LSH for high dimensional data.
+use crate::{
+    dist::{l2_dist, rank_candidates, squared_l2},
+    DataId, DataPoint, DataPointSlice, HashTables, Result, SignRandomProjections, VecHash, LSH,
+};
+
+/// Default maximum dimension for which [HybridIndex](struct.HybridIndex.html) uses a kd-tree.
+pub const DEFAULT_KD_THRESHOLD: usize = 10;
+
+struct KdNode {
+    id: DataId,
+    point: DataPoint,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+/// Unbalanced kd-tree. The split axis of a node is its depth modulo `dim`.
+struct KdTree {
+    dim: usize,
+    nodes: Vec<KdNode>,
+}
+
+impl KdTree {
+    fn new(dim: usize) -> Self {
+        KdTree { dim, nodes: vec![] }
+    }
+
+    fn insert(&mut self, id: DataId, point: DataPoint) {
+        let new = self.nodes.len();
+        let mut cur = if self.nodes.is_empty() { None } else { Some(0) };
+        let mut depth = 0;
+        while let Some(i) = cur {
+            let axis = depth % self.dim;
+            let node = &mut self.nodes[i];
+            let child = if point[axis] < node.point[axis] {
+                &mut node.left
+            } else {
+                &mut node.right
+            };
+            cur = *child;
+            if cur.is_none() {
+                *child = Some(new);
+            }
+            depth += 1;
+        }
+        self.nodes.push(KdNode {
+            id,
+            point,
+            left: None,
+            right: None,
+        });
+    }
+
+    /// `(id, squared distance)` of the `k` nearest points, sorted by distance.
+    fn knn(&self, v: &DataPointSlice, k: usize) -> Vec<(DataId, f32)> {
+        let mut best: Vec<(DataId, f32)> = Vec::with_capacity(k + 1);
+        if self.nodes.is_empty() || k == 0 {
+            return best;
+        }
+        // (node, depth, lower bound of the squared distance to the points of the subtree)
+        let mut stack = vec![(0, 0, 0.)];
+        while let Some((i, depth, bound)) = stack.pop() {
+            if best.len() == k && bound >= best[k - 1].1 {
+                continue;
+            }
+            let node = &self.nodes[i];
+            let d = squared_l2(v, &node.point);
+            if best.len() < k || d < best[k - 1].1 {
+                let pos = best.partition_point(|&(_, x)| x <= d);
+                best.insert(pos, (node.id, d));
+                best.truncate(k);
+            }
+            let diff = v[depth % self.dim] - node.point[depth % self.dim];
+            let (near, far) = if diff < 0. {
+                (node.left, node.right)
+            } else {
+                (node.right, node.left)
+            };
+            // the near side is popped first.
+            if let Some(far) = far {
+                stack.push((far, depth + 1, diff * diff));
+            }
+            if let Some(near) = near {
+                stack.push((near, depth + 1, 0.));
+            }
+        }
+        best
+    }
+}
+
+/// Nearest neighbor index that uses an exact kd-tree if `dim <= threshold` and
+/// [LSH](../struct.LSH.html) otherwise. For low dimensional data a kd-tree is usually faster
+/// and exact. The underlying LSH is always filled and assigns the ids, so the results of both
+/// searches refer to the same ids. Neighbors are ranked by L2 distance.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::HybridIndex;
+/// let mut index = HybridIndex::<lsh_rs::MemoryTable, _>::new(5, 10, 3, 1).unwrap();
+/// index.store_vecs(&[vec![0., 0., 0.], vec![1., 1., 1.]]).unwrap();
+/// assert!(index.uses_kd_tree());
+/// assert_eq!(index.query_knn(&[0.9, 1., 1.], 1).unwrap()[0].0, 1);
+/// ```
+pub struct HybridIndex<T: HashTables, H: VecHash> {
+    lsh: LSH<T, H>,
+    kd_tree: Option<KdTree>,
+}
+
+impl<T: HashTables> HybridIndex<T, SignRandomProjections> {
+    /// Create a new hybrid index with a SignRandomProjections LSH and the default threshold.
+    ///
+    /// # Arguments
+    ///
+    /// * `n_projections` - Hash length.
+    /// * `n_hash_tables` - Number of hash tables.
+    /// * `dim` - Dimensions of the data points.
+    /// * `seed` - Seed of the hash functions. If 0, randomness is seeded from the os.
+    pub fn new(n_projections: usize, n_hash_tables: usize, dim: usize, seed: u64) -> Result<Self> {
+        let lsh = LSH::new(n_projections, n_hash_tables, dim)
+            .seed(seed)
+            .srp()?;
+        HybridIndex::from_lsh(lsh, DEFAULT_KD_THRESHOLD)
+    }
+}
+
+impl<T: HashTables, H: VecHash> HybridIndex<T, H> {
+    /// Wrap an LSH. If `lsh.dim <= threshold`, a kd-tree is built from its stored data points.
+    ///
+    /// # Arguments
+    ///
+    /// * `lsh` - Underlying LSH.
+    /// * `threshold` - Maximum dimension for which a kd-tree is used.
+    pub fn from_lsh(lsh: LSH<T, H>, threshold: usize) -> Result<Self> {
+        let kd_tree = if lsh.dim <= threshold {
+            let mut tree = KdTree::new(lsh.dim);
+            let ht = lsh.hash_tables.as_ref().unwrap();
+            for id in ht.stored_ids()? {
+                tree.insert(id, ht.idx_to_datapoint_owned(id)?);
+            }
+            Some(tree)
+        } else {
+            None
+        };
+        Ok(HybridIndex { lsh, kd_tree })
+    }
+
+    /// If queries are answered by the kd-tree.
+    pub fn uses_kd_tree(&self) -> bool {
+        self.kd_tree.is_some()
+    }
+
+    /// Underlying LSH.
+    pub fn lsh(&self) -> &LSH<T, H> {
+        &self.lsh
+    }
+
+    /// Store a single data point. Returns id.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn store_vec(&mut self, v: &DataPointSlice) -> Result<DataId> {
+        let id = self.lsh.store_vec(v)?;
+        if let Some(tree) = self.kd_tree.as_mut() {
+            tree.insert(id, v.to_vec());
+        }
+        Ok(id)
+    }
+
+    /// Store multiple data points. Returns the ids.
+    ///
+    /// # Arguments
+    /// * `vs` - Data points.
+    pub fn store_vecs(&mut self, vs: &[DataPoint]) -> Result<Vec<DataId>> {
+        vs.iter().map(|v| self.store_vec(v)).collect()
+    }
+
+    /// Query the ids that collide with `v` in the underlying LSH.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    pub fn query_bucket_ids(&self, v: &DataPointSlice) -> Result<Vec<DataId>> {
+        self.lsh.query_bucket_ids(v)
+    }
+
+    /// Query the `k` nearest neighbors of `v`. Exact with the kd-tree, approximate over the
+    /// colliding candidates otherwise. Returns `(id, distance)` pairs sorted by distance.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    /// * `k` - Number of neighbors.
+    pub fn query_knn(&self, v: &DataPointSlice, k: usize) -> Result<Vec<(DataId, f32)>> {
+        self.lsh.validate_vec(v)?;
+        if let Some(tree) = &self.kd_tree {
+            return Ok(tree
+                .knn(v, k)
+                .into_iter()
+                .map(|(id, d)| (id, d.sqrt()))
+                .collect());
+        }
+        let ht = self.lsh.hash_tables.as_ref().unwrap();
+        let ids = self
+            .lsh
+            .query_bucket_ids(v)?
+            .into_iter()
+            .map(|id| Ok((id, l2_dist(&ht.idx_to_datapoint_owned(id)?, v))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(rank_candidates(ids, k))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{utils::create_rng, LshMem, MemoryTable};
+    use rand::Rng;
+
+    #[test]
+    fn test_kd_tree_is_exact() {
+        let mut rng = create_rng(1);
+        let vs: Vec<DataPoint> = (0..500)
+            .map(|_| (0..3).map(|_| rng.gen_range(-1., 1.)).collect())
+            .collect();
+        let mut index = HybridIndex::<MemoryTable, _>::new(5, 4, 3, 1).unwrap();
+        let ids = index.store_vecs(&vs).unwrap();
+        assert!(index.uses_kd_tree());
+        for _ in 0..20 {
+            let q: DataPoint = (0..3).map(|_| rng.gen_range(-1., 1.)).collect();
+            let mut brute: Vec<(DataId, f32)> = ids
+                .iter()
+                .zip(&vs)
+                .map(|(&id, v)| (id, squared_l2(&q, v).sqrt()))
+                .collect();
+            brute.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+            brute.truncate(5);
+            assert_eq!(index.query_knn(&q, 5).unwrap(), brute);
+        }
+        assert!(index.query_knn(&[1., 2.], 5).is_err());
+
+        // the tree is built from the points already stored in the LSH.
+        let mut lsh = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        let rebuilt = HybridIndex::from_lsh(lsh, DEFAULT_KD_THRESHOLD).unwrap();
+        assert_eq!(
+            rebuilt.query_knn(&vs[7], 3).unwrap(),
+            index.query_knn(&vs[7], 3).unwrap()
+        );
+
+        // data points that aren't in the first hash table are in the tree as well.
+        let mut lsh = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+        lsh.with_max_bucket_size(1).unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        let rebuilt = HybridIndex::from_lsh(lsh, DEFAULT_KD_THRESHOLD).unwrap();
+        for (id, v) in vs.iter().enumerate() {
+            assert_eq!(rebuilt.query_knn(v, 1).unwrap(), vec![(id as DataId, 0.)]);
+        }
+    }
+
+    #[test]
+    fn test_high_dim_uses_lsh() {
+        let mut index = HybridIndex::<MemoryTable, _>::new(5, 10, 20, 1).unwrap();
+        let v: DataPoint = (0..20).map(|i| i as f32).collect();
+        let id = index.store_vec(&v).unwrap();
+        assert!(!index.uses_kd_tree());
+        assert_eq!(index.query_knn(&v, 1).unwrap(), vec![(id, 0.)]);
+    }
+}
@@ -11,13 +11,19 @@
 //!
 //! * **Base LSH**
 //!     - Signed Random Projections (Cosine similarity)
+//!     - Fuzzy Signed Random Projections (Cosine similarity, tolerant to small perturbations)
+//!     - Weighted Signed Random Projections (Feature weighted cosine similarity, e.g. TF-IDF)
+//!     - Cross-polytope / FALCONN (Cosine similarity)
 //!     - L2 distance
 //!     - Maximum Inner Product (Dot products)
+//!     - Tensor Sketch (Polynomial kernels)
+//!     - Geohash (Geographic coordinates)
+//!     - Banded MinHash (Jaccard similarity) [JaccardLSH](minhash/struct.JaccardLSH.html)
 //! * **Multi Probe LSH**
 //!     - **Step wise probing**
-//!         - SRP
-//!         - L2
 //!         - MIPS
+//!     - **Hamming ranked probing**
+//!         - SRP
 //!     - **Query directed probing**
 //!         - L2
 //!
@@ -33,7 +39,7 @@
 //! let n_projections = 9;
 //! let n_hash_tables = 30;
 //! let dim = 3;
-//! let mut lsh = LshMem::new(n_projections, n_hash_tables, dim).srp();
+//! let mut lsh = LshMem::new(n_projections, n_hash_tables, dim).srp().unwrap();
 //! lsh.store_vecs(p);
 //!
 //! // Query in sublinear time.
@@ -44,8 +50,22 @@
 //! ## Signed Random Projections
 //! LSH for maximum cosine similarity search.
 //! ```rust
-//! use lsh_rs::LshSql;
-//! let mut lsh = LshSql::new(n_projections, n_hash_tables, dim).srp();
+//! use lsh_rs::LshMem;
+//! # let n_projections = 9;
+//! # let n_hash_tables = 10;
+//! # let dim = 3;
+//! let mut lsh = LshMem::new(n_projections, n_hash_tables, dim).srp();
+//! ```
+//!
+//! ## Cross-polytope (FALCONN)
+//! LSH for maximum cosine similarity search with cross-polytopes of dimension `k`.
+//! ```rust
+//! use lsh_rs::LshMem;
+//! # let n_projections = 2;
+//! # let n_hash_tables = 10;
+//! # let dim = 3;
+//! let k = 16;
+//! let mut lsh = LshMem::new(n_projections, n_hash_tables, dim).falconn(k);
 //! ```
 //!
 //! ## L2
@@ -53,21 +73,27 @@
 //!
 //! ```
 //! // hyper parameter r in https://arxiv.org/pdf/1411.3787.pdf (eq. 8)
-//! use lsh_rs::LshSql;
+//! use lsh_rs::LshMem;
+//! # let n_projections = 9;
+//! # let n_hash_tables = 10;
+//! # let dim = 3;
 //! let bucket_width = 2.2;
-//! let mut lsh = LshSql::new(n_projections, n_hash_tables, dim).l2(bucket_width);
+//! let mut lsh = LshMem::new(n_projections, n_hash_tables, dim).l2(bucket_width);
 //! ```
 //!
 //! ## Maximum Inner Product (MIPS)
 //! LSH for maximum inner product search.
 //! ```rust
-//! use lsh_rs::LshSql;
+//! use lsh_rs::LshMem;
+//! # let n_projections = 9;
+//! # let n_hash_tables = 10;
+//! # let dim = 3;
 //! let bucket_width = 2.2;
 //! // l2(x) < U < 1.0
 //! let U = 0.83;
 //! // number of concatenations
 //! let m = 3;
-//! let mut lsh = LshSql::new(n_projections, n_hash_tables, dim).mips(r, U, m);
+//! let mut lsh = LshMem::new(n_projections, n_hash_tables, dim).mips(bucket_width, U, m);
 //! ```
 //!
 //! ## Seed
@@ -75,8 +101,11 @@
 //! is taken from the system. If you want to have reproducable outcomes, you can set a manual seed.
 //!
 //! ```rust
-//! use lsh_rs::LshSql;
-//! let mut lsh = LshSql::new(n_projections, n_hash_tables, dim).seed(12).srp();
+//! use lsh_rs::LshMem;
+//! # let n_projections = 9;
+//! # let n_hash_tables = 10;
+//! # let dim = 3;
+//! let mut lsh = LshMem::new(n_projections, n_hash_tables, dim).seed(12).srp();
 //! ```
 //!
 //! ## Unique indexes
@@ -84,8 +113,11 @@
 //! hash table). You can choose to only store unique indexes of the data points. The index ids are
 //! assigned in chronological order. This will drastically decrease the required memory.
 //! ```rust
-//! use lsh_rs::LshSql;
-//! let mut lsh = LshSql::new(n_projections, n_hash_tables, dim).only_index().srp();
+//! use lsh_rs::LshMem;
+//! # let n_projections = 9;
+//! # let n_hash_tables = 10;
+//! # let dim = 3;
+//! let mut lsh = LshMem::new(n_projections, n_hash_tables, dim).only_index().srp();
 //! ```
 //!
 //! ## Builder pattern methods
@@ -96,6 +128,24 @@
 //! * [multi_probe](struct.LSH.html#method.multi_probe)
 //! * [increase_storage](struct.LSH.html#method.increase_storage)
 //!
+//! ## Monitoring
+//! With the `monitor` feature, a [Monitor](monitor/trait.Monitor.html) can be attached with
+//! [with_monitor](struct.LSH.html#method.with_monitor) to collect metrics of the store, query and
+//! delete events. Without the feature the hooks are compiled out.
+//!
+//! ## Threading
+//! `LSH<T, H>` is `Send` if `T` and `H` are, and `Sync` if `T` and `H` are. It holds no shared
+//! interior state besides the backend and the hashers, and an attached monitor is always
+//! `Send + Sync`. All hashers and the in-memory backends are `Send + Sync`, so these indexes can
+//! be queried from multiple threads, e.g. with
+//! [query_bucket_ids_batch_par](struct.LSH.html#method.query_bucket_ids_batch_par).
+//! The SQLite backends hold a `rusqlite::Connection`, which is `Send` but not `Sync`.
+//! ```compile_fail
+//! use lsh_rs::{LshSqlMem, SignRandomProjections};
+//! fn assert_sync<T: Sync>() {}
+//! assert_sync::<LshSqlMem<SignRandomProjections>>();
+//! ```
+//!
 //! ## BLAS support
 //! Utilizing [BLAS](https://en.wikipedia.org/wiki/Basic_Linear_Algebra_Subprograms) will heavily increase
 //! performance. To make use of BLAS, install `lsh-rs` w/ `"blas"` feature and reinstall `ndarray` w/ `"blas"` support.
@@ -113,6 +163,19 @@
 //! * in memory (fastest / can save state with serialization) [LshMem](type.LshMem.html)
 //! * SQLite (slower due to disk io, but automatic state preservation between sessions) [LshSql](type.LshSql.html)
 //! * in memory SQLite (can backup to SQLite when processing is done) [LshSqlMem](type.LshSqlMem.html)
+//! * in memory with delta encoded data points (for slowly varying sequences) [DeltaEncodedTable](struct.DeltaEncodedTable.html)
+//!
+//! The SQLite backends need the `sqlite` feature, which is enabled by default.
+//!
+//! For low dimensional data (`dim <= 10` by default), [HybridIndex](struct.HybridIndex.html)
+//! answers nearest neighbor queries exactly with a kd-tree instead of LSH.
+//! [AdaptiveLSH](struct.AdaptiveLSH.html) searches exactly until the dataset outgrows it.
+//! [EnsembleLSH](struct.EnsembleLSH.html) unions the results of independently seeded LSH's.
+//! [CascadingLSH](struct.CascadingLSH.html) queries coarse to fine stages until enough
+//! candidates are found.
+//! [LSHWithHnswEntry](struct.LSHWithHnswEntry.html) multi-probes around an entry point found
+//! with HNSW.
+//! [AuditedLSH](struct.AuditedLSH.html) logs every insertion to an on-disk ring buffer.
 #![allow(dead_code, non_snake_case)]
 #[cfg(feature = "blas")]
 extern crate blas_src;
@@ -120,27 +183,83 @@ extern crate crossbeam;
 extern crate ndarray;
 mod hash;
 mod lsh {
+    #[allow(clippy::module_inception)]
     pub mod lsh;
+    pub mod pool;
+    pub mod read_only;
+    pub mod streaming;
     mod test;
 }
 pub mod dist;
+pub mod eval;
+#[cfg(feature = "graph")]
+pub mod graph;
+pub mod hnsw;
+pub mod hybrid;
 mod multi_probe;
 mod table {
+    pub mod delta;
     pub mod general;
     pub mod mem;
+    #[cfg(feature = "sqlite")]
     pub mod sqlite;
+    #[cfg(feature = "sqlite")]
     pub mod sqlite_mem;
 }
+pub mod adaptive;
+pub mod analysis;
+pub mod audit;
+pub mod cascade;
+pub mod config;
 mod constants;
+pub mod ensemble;
 mod error;
+pub mod minhash;
+#[cfg(feature = "monitor")]
+pub mod monitor;
+pub mod tensor;
+pub mod text;
 pub mod utils;
-pub use crate::lsh::lsh::{LshMem, LshSql, LshSqlMem, LSH};
-pub use hash::{Hash, HashPrimitive, SignRandomProjections, VecHash, L2, MIPS};
-pub use table::{general::HashTables, mem::MemoryTable, sqlite::SqlTable, sqlite_mem::SqlTableMem};
+pub use crate::lsh::lsh::{
+    ConsistencyError, CsrMatrix, LshMem, PartialInsertResult, QueryProbeStats, QueryResult,
+    QueryTrace, TableTrace, LSH,
+};
+#[cfg(feature = "sqlite")]
+pub use crate::lsh::lsh::{LshSql, LshSqlMem};
+pub use crate::lsh::pool::ConcurrentInsertPool;
+pub use crate::lsh::read_only::ReadOnlyLsh;
+pub use crate::lsh::streaming::StreamingLSH;
+pub use adaptive::{AdaptiveLSH, FlatL2Index};
+pub use audit::{AuditEntry, AuditedLSH};
+pub use cascade::CascadingLSH;
+pub use ensemble::EnsembleLSH;
+pub use hash::{
+    CompactSrpHash, Differentiable, FalconnHash, FuzzyHash, GeoHash, Hash, HashPrimitive,
+    SignRandomProjections, TensorSketch, VecHash, WeightedSrp, L2, MIPS,
+};
+pub use hnsw::LSHWithHnswEntry;
+pub use hybrid::HybridIndex;
+pub use table::{
+    delta::DeltaEncodedTable,
+    general::HashTables,
+    mem::{MemStats, MemoryTable, ScalarQuantizer},
+};
+#[cfg(feature = "sqlite")]
+pub use table::{sqlite::SqlTable, sqlite_mem::SqlTableMem};
+pub use tensor::TensorLSH;
 pub mod stats;
 
 pub type FloatSize = f32;
+/// Id of a data point. `u32` by default, `u64` with the `id64` feature.
+#[cfg(not(feature = "id64"))]
+pub type DataId = u32;
+/// Id of a data point. `u32` by default, `u64` with the `id64` feature.
+#[cfg(feature = "id64")]
+pub type DataId = u64;
+// ids are used to index the in memory storage.
+const _: () = assert!(std::mem::size_of::<usize>() >= std::mem::size_of::<DataId>());
 pub type DataPoint = Vec<f32>;
 pub type DataPointSlice = [f32];
+pub use config::LshConfig;
 pub use error::Error;
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,22 +1,51 @@
+#[cfg(feature = "monitor")]
+use crate::monitor::Monitor;
 use crate::{
-    hash::{Hash, SignRandomProjections, VecHash, L2, MIPS},
-    table::{general::HashTables, mem::MemoryTable, sqlite_mem::SqlTableMem},
-    utils::create_rng,
+    analysis::distance_histogram,
+    constants::STREAM_REBUILD_SHRINK,
+    dist::{cosine_sim, l2_dist, rank_candidates, DistanceMetric},
+    hash::{
+        CompactSrpHash, FalconnHash, FuzzyHash, GeoHash, Hash, HashPrimitive,
+        SignRandomProjections, TensorSketch, VecHash, WeightedSrp, L2, MIPS,
+    },
+    stats::CountMinSketch,
+    table::{
+        delta::DeltaEncodedTable,
+        general::{Bucket, HashTables},
+        mem::{MemStats, MemoryTable, MEMORY_TABLE_VERSION},
+    },
+    tensor::{PairHash, TensorLSH},
+    utils::{create_rng, SeedSchedule},
     Error, FloatSize, Result,
 };
-use crate::{DataPoint, DataPointSlice, SqlTable};
+use crate::{DataId, DataPoint, DataPointSlice};
+#[cfg(feature = "sqlite")]
+use crate::{SqlTable, SqlTableMem};
 use crossbeam::channel::unbounded;
-use fnv::FnvHashSet as HashSet;
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet, FnvHasher};
+use log::warn;
 use ndarray::prelude::*;
-use rand::Rng;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_distr::{Normal, StandardNormal};
 use rayon::prelude::*;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::BinaryHeap;
 use std::fs::File;
+use std::hash::Hasher;
 use std::io::{Read, Write};
 use std::path::Path;
+#[cfg(feature = "monitor")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "sqlite")]
 pub type LshSql<H> = LSH<SqlTable, H>;
+#[cfg(feature = "sqlite")]
 pub type LshSqlMem<H> = LSH<SqlTableMem, H>;
 pub type LshMem<H> = LSH<MemoryTable, H>;
 
@@ -42,6 +71,8 @@ pub type LshMem<H> = LSH<MemoryTable, H>;
 /// * [set_database_file](struct.LSH.html#method.set_database_file)
 /// * [multi_probe](struct.LSH.html#method.multi_probe)
 /// * [increase_storage](struct.LSH.html#method.increase_storage)
+/// * [silent](struct.LSH.html#method.silent)
+/// * [noise_robust](struct.LSH.html#method.noise_robust)
 pub struct LSH<T: HashTables, H: VecHash> {
     /// Number of hash tables. `L` in literature.
     pub n_hash_tables: usize,
@@ -61,6 +92,62 @@ pub struct LSH<T: HashTables, H: VecHash> {
     /// multi probe budget
     pub(crate) _multi_probe_budget: usize,
     _db_path: String,
+    /// probability of querying a hash table.
+    _table_sampling_rate: f64,
+    /// don't show a progress bar when storing (`progress` feature).
+    _silent: bool,
+    /// standard deviation of the noise added to noisy query copies.
+    _noise_std_dev: f32,
+    /// number of noisy query copies. If 0, no noise is added.
+    _noise_trials: usize,
+    /// selects the hash tables of a sketched query.
+    _table_sketch: Option<TableSketch>,
+    /// seeds of the hashers that are created after the LSH is built.
+    _seed_schedule: SeedSchedule,
+    /// receives the store, query and delete events (`monitor` feature).
+    #[cfg(feature = "monitor")]
+    _monitor: Option<Arc<dyn Monitor>>,
+}
+
+/// Random projection of the query to `m = L / 4` dimensions and a random key per hash table.
+/// A sketched query only queries the `m` hash tables with the largest `key^T sketch`.
+#[derive(Clone, Serialize, Deserialize)]
+struct TableSketch {
+    /// Shape: `(m, dim)`.
+    projection: Array2<f32>,
+    /// Shape: `(L, m)`.
+    keys: Array2<f32>,
+}
+
+impl TableSketch {
+    fn new(dim: usize, n_hash_tables: usize, seed: u64) -> Self {
+        let m = std::cmp::max(1, n_hash_tables / 4);
+        let mut rng = create_rng(seed);
+        let mut gaussian =
+            |shape: (usize, usize)| Array2::from_shape_fn(shape, |_| rng.sample(StandardNormal));
+        TableSketch {
+            projection: gaussian((m, dim)),
+            keys: gaussian((n_hash_tables, m)),
+        }
+    }
+
+    fn select_tables(&self, v: &DataPointSlice) -> Vec<usize> {
+        let scores = self.keys.dot(&self.projection.dot(&aview1(v)));
+        let mut tables: Vec<usize> = (0..scores.len()).collect();
+        tables.sort_unstable_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        tables.truncate(self.projection.nrows());
+        tables
+    }
+}
+
+/// Fails if `v` isn't of dimension `dim`.
+pub(crate) fn validate_dim(v: &DataPointSlice, dim: usize) -> Result<()> {
+    if v.len() != dim {
+        return Err(Error::Failed(
+            "data point is not valid, are the dimensions correct?".to_string(),
+        ));
+    };
+    Ok(())
 }
 
 /// Create a new LSH instance. Used in the builder pattern
@@ -72,25 +159,58 @@ fn lsh_from_lsh<T: HashTables, H: VecHash + Serialize + DeserializeOwned>(
 
     // Load hashers if store hashers fails. (i.e. exists)
     let hashers = match ht.store_hashers(&hashers) {
-        Ok(_) => hashers,
+        Ok(_) => {
+            ht.store_params(lsh.n_projections, lsh.dim)?;
+            hashers
+        }
         Err(_) => match ht.load_hashers() {
-            Err(e) => panic!(format!("could not load hashers: {}", e)),
+            Err(e) => panic!("could not load hashers: {}", e),
             Ok(hashers) => hashers,
         },
     };
-    let lsh = LSH {
-        n_hash_tables: lsh.n_hash_tables,
-        n_projections: lsh.n_projections,
-        hashers,
-        dim: lsh.dim,
-        hash_tables: Some(ht),
-        _seed: lsh._seed,
-        only_index_storage: lsh.only_index_storage,
-        _multi_probe: lsh._multi_probe,
-        _multi_probe_budget: lsh._multi_probe_budget,
-        _db_path: lsh._db_path.clone(),
-    };
-    Ok(lsh)
+    Ok(lsh.with_hash_tables(hashers, ht))
+}
+
+impl<T: HashTables, H: VecHash + Serialize + DeserializeOwned> LSH<T, H> {
+    /// Create a new LSH from hashers that were generated elsewhere, e.g. on a server that
+    /// deploys the same hash functions to its clients. No hashers are generated.
+    ///
+    /// # Arguments
+    /// * `hashers` - Hasher per hash table.
+    /// * `n_hash_tables` - Number of hash tables.
+    /// * `n_projections` - Hash length of every hasher.
+    /// * `dim` - Dimensions of the data points.
+    ///
+    /// # Example
+    /// ```
+    /// use lsh_rs::{LshMem, SignRandomProjections};
+    /// let hashers = (0..10).map(|seed| SignRandomProjections::new(5, 3, seed)).collect();
+    /// let lsh = LshMem::from_external_hashers(hashers, 10, 5, 3).unwrap();
+    /// ```
+    pub fn from_external_hashers(
+        hashers: Vec<H>,
+        n_hash_tables: usize,
+        n_projections: usize,
+        dim: usize,
+    ) -> Result<Self> {
+        if hashers.len() != n_hash_tables {
+            return Err(Error::Failed(format!(
+                "expected {} hashers, got {}",
+                n_hash_tables,
+                hashers.len()
+            )));
+        }
+        if let Some(i) = hashers
+            .iter()
+            .position(|h| h.n_projections() != n_projections || h.dim() != dim)
+        {
+            return Err(Error::Failed(format!(
+                "hasher {} should have {} projections and dimension {}",
+                i, n_projections, dim
+            )));
+        }
+        lsh_from_lsh(&mut LSH::new(n_projections, n_hash_tables, dim), hashers)
+    }
 }
 
 impl<T: HashTables> LSH<T, SignRandomProjections> {
@@ -106,6 +226,87 @@ impl<T: HashTables> LSH<T, SignRandomProjections> {
         }
         lsh_from_lsh(self, hashers)
     }
+
+    /// Create a new SignRandomProjections LSH of which the hashes can be packed in a
+    /// [CompactSrpHash](struct.CompactSrpHash.html), e.g. to query by Hamming distance with
+    /// [query_bucket_ids_hamming](struct.LSH.html#method.query_bucket_ids_hamming). Fails if
+    /// `n_projections` exceeds `CompactSrpHash::MAX_BITS` (256).
+    pub fn compact_srp(&mut self) -> Result<Self> {
+        if self.n_projections > CompactSrpHash::MAX_BITS {
+            return Err(Error::Failed(format!(
+                "n_projections should be <= {} for compact hashes",
+                CompactSrpHash::MAX_BITS
+            )));
+        }
+        self.srp()
+    }
+
+    /// Raw projection values of `v` per hash table and bit, see
+    /// [SignRandomProjections::bit_margin](struct.SignRandomProjections.html#method.bit_margin).
+    /// Useful to debug which bits of a hash are uncertain.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn bit_margins(&self, v: &DataPointSlice) -> Result<Vec<Vec<f32>>> {
+        self.validate_vec(v)?;
+        Ok(self
+            .hashers
+            .iter()
+            .map(|h| {
+                (0..self.n_projections)
+                    .map(|i| h.bit_margin(v, i))
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Replace hyperplane `proj_idx` of the hasher of table `table_idx` and re-hash the data
+    /// points of that table. See
+    /// [SignRandomProjections::update_hyperplane](struct.SignRandomProjections.html#method.update_hyperplane).
+    ///
+    /// # Arguments
+    /// * `table_idx` - Index of the hash table.
+    /// * `proj_idx` - Index of the hyperplane.
+    /// * `new_col` - New hyperplane of length `dim`.
+    pub fn update_hasher_projection(
+        &mut self,
+        table_idx: usize,
+        proj_idx: usize,
+        new_col: ArrayView1<f32>,
+    ) -> Result<()> {
+        self.rehash_tables(&[table_idx], |h| h.update_hyperplane(proj_idx, new_col))
+    }
+
+    /// Binary embedding of `v` for downstream models: the hash bits of all hash tables
+    /// concatenated as `0.` / `1.` values, of length `L * K`. The Hamming distance between two
+    /// embeddings divided by `L * K` estimates the angle between the data points divided by `pi`,
+    /// so it approximates their cosine distance.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn embed(&self, v: &DataPointSlice) -> Result<Vec<f32>> {
+        self.validate_vec(v)?;
+        Ok(self
+            .hashers
+            .iter()
+            .flat_map(|h| h.hash_vec_query(v))
+            .map(|bit| bit as f32)
+            .collect())
+    }
+
+    /// [embed](struct.LSH.html#method.embed) a batch of data points. Returns an array of shape
+    /// `(vs.len(), L * K)`.
+    ///
+    /// # Arguments
+    /// * `vs` - Data points.
+    pub fn embed_batch(&self, vs: &[DataPoint]) -> Result<Array2<f32>> {
+        let width = self.n_hash_tables * self.n_projections;
+        let mut out = Array2::zeros((vs.len(), width));
+        for (v, mut row) in vs.iter().zip(out.axis_iter_mut(Axis(0))) {
+            row.assign(&aview1(&self.embed(v)?));
+        }
+        Ok(out)
+    }
 }
 
 impl<T: HashTables> LSH<T, L2> {
@@ -130,6 +331,209 @@ impl<T: HashTables> LSH<T, L2> {
         }
         lsh_from_lsh(self, hashers)
     }
+
+    /// Create a new multi-resolution L2 LSH for data with varying inter-point distances. The hash
+    /// tables are split in `r_values.len()` contiguous groups of (nearly) equal size and the tables
+    /// of group `i` use `r_values[i]`. Queries union the buckets of all groups.
+    ///
+    /// # Arguments
+    ///
+    /// * `r_values` - Parameter `r` of the hash function per group.
+    pub fn multi_scale_l2(&mut self, r_values: &[f32]) -> Result<Self> {
+        if r_values.is_empty() || r_values.len() > self.n_hash_tables {
+            return Err(Error::Failed(format!(
+                "the number of r values should be in 1..={}",
+                self.n_hash_tables
+            )));
+        }
+        if r_values.iter().any(|&r| r.is_nan() || r <= 0.) {
+            return Err(Error::Failed("r values should be positive".to_string()));
+        }
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+        for i in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let r = r_values[i * r_values.len() / self.n_hash_tables];
+            hashers.push(L2::new(self.dim, r, self.n_projections, seed));
+        }
+        lsh_from_lsh(self, hashers)
+    }
+
+    /// Create a new L2 LSH where `r` is calibrated on the data.
+    /// See [L2::calibrate_r](struct.L2.html#method.calibrate_r). Fails if the calibrated `r`
+    /// isn't positive, e.g. if most sampled pairs are duplicates.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Data points used for calibration.
+    /// * `quantile` - Quantile of the pairwise distances that is used as `r`.
+    pub fn l2_calibrated(&mut self, data: &[DataPoint], quantile: f64) -> Result<Self> {
+        let r = L2::calibrate_r(data, quantile, 1000, self._seed)?;
+        if r <= 0. {
+            return Err(Error::Failed(format!(
+                "the calibrated r should be positive, got {}",
+                r
+            )));
+        }
+        self.l2(r)
+    }
+}
+
+impl<T: HashTables> LSH<T, FalconnHash> {
+    /// Create a new cross-polytope LSH with random dimensionality reduction (FALCONN).
+    /// Every one of the `n_projections` hash values is a cross-polytope hash.
+    ///
+    /// # Arguments
+    /// * `k` - Target dimension of the cross-polytopes. See [FalconnHash](struct.FalconnHash.html).
+    pub fn falconn(&mut self, k: usize) -> Result<Self> {
+        if k == 0 || k.next_power_of_two() > FalconnHash::MAX_K {
+            return Err(Error::Failed(format!(
+                "k should be in 1..={}",
+                FalconnHash::MAX_K
+            )));
+        }
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+        for _ in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let hasher = FalconnHash::new(k, self.n_projections, self.dim, seed);
+            hashers.push(hasher);
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<T: HashTables> LSH<T, TensorSketch> {
+    /// Create a new Tensor Sketch LSH for the polynomial kernel `(x^T y + c)^degree`.
+    ///
+    /// # Arguments
+    /// * `degree` - Degree of the polynomial kernel.
+    /// * `sketch_dim` - Number of random features the hash is computed on.
+    /// * `c` - Non negative constant of the kernel.
+    pub fn tensor_sketch(&mut self, degree: u32, sketch_dim: usize, c: f32) -> Result<Self> {
+        if degree == 0 || sketch_dim == 0 || c < 0. {
+            return Err(Error::Failed(
+                "degree and sketch_dim should be positive and c non negative".to_string(),
+            ));
+        }
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+        for _ in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let hasher =
+                TensorSketch::new(self.dim, degree, sketch_dim, c, self.n_projections, seed);
+            hashers.push(hasher);
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<T: HashTables> LSH<T, FuzzyHash<SignRandomProjections>> {
+    /// Create a new SignRandomProjections LSH that is tolerant to small perturbations of the
+    /// data points near the hyperplanes. See [FuzzyHash](struct.FuzzyHash.html).
+    ///
+    /// # Arguments
+    /// * `epsilon` - Size of the perturbation in every direction.
+    pub fn fuzzy_srp(&mut self, epsilon: f32) -> Result<Self> {
+        if !(epsilon.is_finite() && epsilon >= 0.) {
+            return Err(Error::Failed(
+                "epsilon should be finite and non negative".to_string(),
+            ));
+        }
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+        for _ in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            let srp = SignRandomProjections::new(self.n_projections, self.dim, seed);
+            hashers.push(FuzzyHash::new(srp, epsilon));
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<T: HashTables> LSH<T, WeightedSrp> {
+    /// Create a new SignRandomProjections LSH on feature weighted data points, e.g. TF-IDF
+    /// weighted text embeddings. See [WeightedSrp](struct.WeightedSrp.html).
+    ///
+    /// # Arguments
+    /// * `weights` - Weight per dimension.
+    pub fn weighted_srp(&mut self, weights: Vec<f32>) -> Result<Self> {
+        if weights.len() != self.dim {
+            return Err(Error::Failed(format!(
+                "expected {} weights, got {}",
+                self.dim,
+                weights.len()
+            )));
+        }
+        if !weights.iter().all(|w| w.is_finite() && *w >= 0.) {
+            return Err(Error::Failed(
+                "weights should be finite and non negative".to_string(),
+            ));
+        }
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+        for _ in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            hashers.push(WeightedSrp::new(self.n_projections, weights.clone(), seed));
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<T: HashTables> LSH<T, GeoHash> {
+    /// Create a new geohash LSH for `[longitude, latitude]` coordinates. The first hash table uses
+    /// the standard geohash grid, the others a randomly shifted grid, so that close locations on
+    /// a cell boundary still collide in some table.
+    ///
+    /// # Arguments
+    /// * `precision_bits` - Number of bits of the geohash.
+    pub fn geohash(&mut self, precision_bits: usize) -> Result<Self> {
+        if self.dim != 2 {
+            return Err(Error::Failed(
+                "geohash needs 2D [longitude, latitude] data points".to_string(),
+            ));
+        }
+        if precision_bits == 0 || precision_bits > 64 {
+            return Err(Error::Failed(
+                "precision_bits should be in 1..=64".to_string(),
+            ));
+        }
+        let cell = GeoHash::new(precision_bits, [0., 0.]).cell_size();
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+        for i in 0..self.n_hash_tables {
+            let offset = if i == 0 {
+                [0., 0.]
+            } else {
+                [rng.gen::<f32>() * cell[0], rng.gen::<f32>() * cell[1]]
+            };
+            hashers.push(GeoHash::new(precision_bits, offset));
+        }
+        lsh_from_lsh(self, hashers)
+    }
+}
+
+impl<T: HashTables> LSH<T, PairHash> {
+    /// Create a new LSH for pairs of vectors `(u, v)` of dimension `dim`. The sketch of the tensor
+    /// product `u ⊗ v` is hashed with sign random projections.
+    ///
+    /// # Arguments
+    /// * `m` - Dimension of the sketch of `u ⊗ v`.
+    pub fn tensor_srp(&mut self, m: usize) -> Result<TensorLSH<T>> {
+        if m == 0 {
+            return Err(Error::Failed("m should be positive".to_string()));
+        }
+        let mut rng = create_rng(self._seed);
+        let mut hashers = Vec::with_capacity(self.n_hash_tables);
+        for _ in 0..self.n_hash_tables {
+            let seed = rng.gen();
+            hashers.push(PairHash::new(self.dim, m, self.n_projections, seed));
+        }
+        let mut lsh = lsh_from_lsh(self, hashers)?;
+        // the pairs are stored concatenated.
+        lsh.dim = 2 * self.dim;
+        Ok(TensorLSH { lsh })
+    }
 }
 
 impl<T: HashTables> LSH<T, MIPS> {
@@ -156,6 +560,54 @@ impl<T: HashTables> LSH<T, MIPS> {
         }
         lsh_from_lsh(self, hashers)
     }
+
+    /// Re-hash all stored data points with the current `M` of the hashers, e.g. after
+    /// [MIPS::fit_incremental](struct.MIPS.html#method.fit_incremental) grew it.
+    /// Needs the stored data points.
+    pub fn rebuild_index(&mut self) -> Result<()> {
+        let tables: Vec<usize> = (0..self.n_hash_tables).collect();
+        self.rehash_tables(&tables, |h| {
+            h.rebuilt();
+            Ok(())
+        })
+    }
+
+    /// Store a data point of a stream. The maximum norm of the hashers is tracked with
+    /// [MIPS::fit_from_stream](struct.MIPS.html#method.fit_from_stream) and if it shrank to less
+    /// than half of the maximum norm of the last rebuild, all stored data points are re-hashed
+    /// with [rebuild_index](#method.rebuild_index). A rebuild re-hashes the whole index, so
+    /// this bounds the number of rebuilds to the logarithm of the total shrinkage.
+    ///
+    /// In between the buckets are stale: the stored data points were hashed with another
+    /// maximum norm than the queries, which lowers the recall, see
+    /// [MIPS::needs_rebuild](struct.MIPS.html#method.needs_rebuild). If the maximum norm only
+    /// grew, the index isn't rebuilt.
+    ///
+    /// The data point is stored before the rebuild. If the backend can't re-hash the index, e.g.
+    /// without stored data points, the failure is logged and the id is still returned, so that
+    /// the data point isn't stored twice when the call is retried. The index stays stale.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    /// * `decay` - Decay of the maximum norm per data point in `(0, 1]`.
+    pub fn store_vec_from_stream(&mut self, v: &DataPointSlice, decay: f32) -> Result<DataId> {
+        self.validate_vec(v)?;
+        if !(decay > 0. && decay <= 1.) {
+            return Err(Error::Failed("decay should be in (0, 1]".to_string()));
+        }
+        let mut rebuild = false;
+        for h in &mut self.hashers {
+            let shrank = h.fit_from_stream(v, decay);
+            rebuild |= shrank && h.current_M() < STREAM_REBUILD_SHRINK * h.indexed_max_norm();
+        }
+        let id = self.store_vec(v)?;
+        if rebuild {
+            if let Err(e) = self.rebuild_index() {
+                warn!("could not rebuild the index of the stream: {}", e);
+            }
+        }
+        Ok(id)
+    }
 }
 
 impl<H: VecHash + Sync, T: HashTables + Sync> LSH<T, H> {
@@ -163,7 +615,7 @@ impl<H: VecHash + Sync, T: HashTables + Sync> LSH<T, H> {
     ///
     /// # Arguments
     /// * `vs` - Array of data points.
-    pub fn query_bucket_ids_batch_par(&self, vs: &[DataPoint]) -> Result<Vec<Vec<u32>>> {
+    pub fn query_bucket_ids_batch_par(&self, vs: &[DataPoint]) -> Result<Vec<Vec<DataId>>> {
         vs.into_par_iter()
             .map(|v| self.query_bucket_ids(v))
             .collect()
@@ -176,12 +628,32 @@ impl<H: VecHash + Sync, T: HashTables + Sync> LSH<T, H> {
     pub fn query_bucket_ids_batch_arr_par(
         &self,
         vs: ArrayView2<FloatSize>,
-    ) -> Result<Vec<Vec<u32>>> {
+    ) -> Result<Vec<Vec<DataId>>> {
         vs.axis_iter(Axis(0))
             .into_par_iter()
             .map(|v| self.query_bucket_ids(v.as_slice().unwrap()))
             .collect()
     }
+
+    /// Parallel version of [similarity_join](struct.LSH.html#method.similarity_join).
+    /// Returns all `(other_idx, candidate_id)` pairs.
+    ///
+    /// # Arguments
+    /// * `other_vecs` - Data points that are joined with this index.
+    pub fn par_similarity_join(&self, other_vecs: &[DataPoint]) -> Result<Vec<(DataId, DataId)>> {
+        let pairs = other_vecs
+            .par_iter()
+            .enumerate()
+            .map(|(i, v)| {
+                self.query_bucket_ids(v).map(|ids| {
+                    ids.into_iter()
+                        .map(|id| (i as DataId, id))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(pairs.into_iter().flatten().collect())
+    }
 }
 
 impl<H: VecHash + Sync, T: HashTables> LSH<T, H> {
@@ -193,18 +665,17 @@ impl<H: VecHash + Sync, T: HashTables> LSH<T, H> {
     ///
     /// # Examples
     ///```
-    /// use lsh_rs::LshSql;
-    /// let mut lsh = LshSql::new(5, 10, 3).srp();
-    /// let vs = &[&[2., 3., 4.],
-    ///            &[-1., -1., 1.]];
+    /// use lsh_rs::LshMem;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let vs = &[vec![2., 3., 4.],
+    ///            vec![-1., -1., 1.]];
     /// let ids = lsh.store_vecs(vs);
     /// ```
-    pub fn store_vecs(&mut self, vs: &[DataPoint]) -> Result<Vec<u32>> {
+    pub fn store_vecs(&mut self, vs: &[DataPoint]) -> Result<Vec<DataId>> {
         self.validate_vec(&vs[0])?;
-        self.hash_tables
-            .as_mut()
-            .unwrap()
-            .increase_storage(vs.len());
+        let ht = self.hash_tables.as_mut().unwrap();
+        ht.increase_storage(vs.len());
+        ht.fit_storage(&vs.iter().map(|v| &v[..]).collect::<Vec<_>>());
 
         // one thread prepares hashes, while the other loads the hashes in the hashtables.
         let (tx, rx) = unbounded();
@@ -222,12 +693,26 @@ impl<H: VecHash + Sync, T: HashTables> LSH<T, H> {
         })
         .expect("something went wrong in the thread that prepares the hashes.");
 
+        #[cfg(feature = "progress")]
+        let pb = self.progress_bar(vs.len());
+
         let mut ht = self.hash_tables.take().unwrap();
         let mut insert_idx = Vec::with_capacity(vs.len());
         for (hash, v, i) in rx {
-            insert_idx.push(ht.put(hash, v, i)?);
+            let idx = ht.put(hash, v, i)?;
+            insert_idx.push(idx);
+            #[cfg(feature = "progress")]
+            if i == self.n_hash_tables - 1 {
+                pb.inc(1)
+            }
+            #[cfg(feature = "monitor")]
+            if i == self.n_hash_tables - 1 {
+                self.notify(|m| m.on_store(idx, self.n_hash_tables));
+            }
         }
         self.hash_tables.replace(ht);
+        #[cfg(feature = "progress")]
+        pb.finish();
         Ok(insert_idx)
     }
 
@@ -239,18 +724,21 @@ impl<H: VecHash + Sync, T: HashTables> LSH<T, H> {
     ///
     /// # Examples
     ///```
-    /// use lsh_rs::LshSql;
+    /// use lsh_rs::LshMem;
     /// use ndarray::prelude::*;
-    /// let mut lsh = LshSql::new(5, 10, 3).srp();
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
     /// let vs = array![[1., 2., 3.], [4., 5., 6.]];
-    /// let ids = lsh.store_array(vs);
+    /// let ids = lsh.store_array(vs.view());
     /// ```
-    pub fn store_array(&mut self, vs: ArrayView2<FloatSize>) -> Result<Vec<u32>> {
+    pub fn store_array(&mut self, vs: ArrayView2<FloatSize>) -> Result<Vec<DataId>> {
         self.validate_vec(vs.slice(s![0, ..]).as_slice().unwrap())?;
-        self.hash_tables
-            .as_mut()
-            .unwrap()
-            .increase_storage(vs.len());
+        let ht = self.hash_tables.as_mut().unwrap();
+        ht.increase_storage(vs.len());
+        ht.fit_storage(
+            &vs.outer_iter()
+                .map(|v| v.to_slice().unwrap())
+                .collect::<Vec<_>>(),
+        );
 
         // one thread prepares hashes, while the other loads the hashes in the hashtables.
         let (tx, rx) = unbounded();
@@ -271,11 +759,108 @@ impl<H: VecHash + Sync, T: HashTables> LSH<T, H> {
         let mut ht = self.hash_tables.take().unwrap();
         let mut insert_idx = Vec::with_capacity(vs.len());
         for (hash, v, i) in rx {
-            insert_idx.push(ht.put(hash, v.as_slice().unwrap(), i)?);
+            let idx = ht.put(hash, v.as_slice().unwrap(), i)?;
+            insert_idx.push(idx);
+            #[cfg(feature = "monitor")]
+            if i == self.n_hash_tables - 1 {
+                self.notify(|m| m.on_store(idx, self.n_hash_tables));
+            }
         }
         self.hash_tables.replace(ht);
         Ok(insert_idx)
     }
+
+    /// Store a 2D array of `f64` values in storage. The values are cast to `FloatSize` before
+    /// storing.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    pub fn store_array_f64(&mut self, vs: ArrayView2<f64>) -> Result<Vec<DataId>> {
+        let vs = vs.mapv(|x| x as FloatSize);
+        self.store_array(vs.view())
+    }
+
+    /// Store a 2D array in storage under the given ids. Before storing the storage capacity is
+    /// possibly increased to match the data points.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    /// * `ids` - Unique id for every row in `vs`.
+    ///
+    /// # Examples
+    ///```
+    /// use lsh_rs::LshMem;
+    /// use ndarray::prelude::*;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let vs = array![[1., 2., 3.], [4., 5., 6.]];
+    /// lsh.store_array_with_ids(vs.view(), &[10, 20]);
+    /// ```
+    pub fn store_array_with_ids(
+        &mut self,
+        vs: ArrayView2<FloatSize>,
+        ids: &[DataId],
+    ) -> Result<()> {
+        if ids.len() != vs.nrows() {
+            return Err(Error::Failed(
+                "number of ids should match the number of data points".to_string(),
+            ));
+        }
+        if vs.nrows() == 0 {
+            return Ok(());
+        }
+        self.validate_vec(vs.slice(s![0, ..]).as_slice().unwrap())?;
+        let unique: HashSet<DataId> = ids.iter().copied().collect();
+        if unique.len() != ids.len() {
+            return Err(Error::Failed("ids should be unique".to_string()));
+        }
+        let only_index_storage = self.only_index_storage;
+        let ht = self.hash_tables.as_mut().unwrap();
+        if !ht.supports_put_with_id() {
+            return Err(Error::NotImplemented);
+        }
+        let already_stored = if only_index_storage {
+            // without the data points, the stored ids are only in the buckets.
+            let stored: HashSet<DataId> = ht
+                .to_bucket_map()?
+                .into_iter()
+                .flat_map(|tbl| tbl.into_values().flatten())
+                .collect();
+            ids.iter().any(|id| stored.contains(id))
+        } else {
+            ids.iter().any(|&id| ht.idx_to_datapoint_owned(id).is_ok())
+        };
+        if already_stored {
+            return Err(Error::Failed("ids are already stored".to_string()));
+        }
+        ht.increase_storage(vs.nrows());
+        ht.fit_storage(
+            &vs.outer_iter()
+                .map(|v| v.to_slice().unwrap())
+                .collect::<Vec<_>>(),
+        );
+
+        // one thread prepares hashes, while the other loads the hashes in the hashtables.
+        let (tx, rx) = unbounded();
+        let hashers = &self.hashers;
+        crossbeam::scope(|s| {
+            s.spawn(|_| {
+                vs.axis_iter(Axis(0)).zip(ids).for_each(|(v, &idx)| {
+                    for (i, proj) in hashers.iter().enumerate() {
+                        let hash = proj.hash_vec_put(v.as_slice().unwrap());
+                        tx.send((hash, v, i, idx)).unwrap();
+                    }
+                });
+                drop(tx)
+            });
+        })
+        .expect("something went wrong in the thread that prepares the hashes.");
+
+        let ht = self.hash_tables.as_mut().unwrap();
+        for (hash, v, i, idx) in rx {
+            ht.put_with_id(idx, hash, v.as_slice().unwrap(), i)?;
+        }
+        Ok(())
+    }
 }
 
 impl<H: VecHash, T: HashTables> LSH<T, H> {
@@ -286,9 +871,8 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
     /// * `n_projections` - Hash length. Every projections creates an hashed integer
     /// * `n_hash_tables` - Increases the chance of finding the closest but has a performance and space cost.
     /// * `dim` - Dimensions of the data points.
-
     pub fn new(n_projections: usize, n_hash_tables: usize, dim: usize) -> Self {
-        let lsh = LSH {
+        LSH {
             n_hash_tables,
             n_projections,
             hashers: Vec::with_capacity(0),
@@ -299,17 +883,47 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
             _multi_probe: false,
             _multi_probe_budget: 16,
             _db_path: "./lsh.db3".to_string(),
-        };
-        lsh
+            _table_sampling_rate: 1.,
+            _silent: false,
+            _noise_std_dev: 0.,
+            _noise_trials: 0,
+            _table_sketch: None,
+            _seed_schedule: SeedSchedule::new(0),
+            #[cfg(feature = "monitor")]
+            _monitor: None,
+        }
+    }
+
+    /// Copy the configuration of this LSH to a new LSH with other hashers and hash tables.
+    fn with_hash_tables<T2: HashTables, H2: VecHash>(
+        &self,
+        hashers: Vec<H2>,
+        ht: T2,
+    ) -> LSH<T2, H2> {
+        LSH {
+            n_hash_tables: self.n_hash_tables,
+            n_projections: self.n_projections,
+            hashers,
+            dim: self.dim,
+            hash_tables: Some(ht),
+            _seed: self._seed,
+            only_index_storage: self.only_index_storage,
+            _multi_probe: self._multi_probe,
+            _multi_probe_budget: self._multi_probe_budget,
+            _db_path: self._db_path.clone(),
+            _table_sampling_rate: self._table_sampling_rate,
+            _silent: self._silent,
+            _noise_std_dev: self._noise_std_dev,
+            _noise_trials: self._noise_trials,
+            _table_sketch: self._table_sketch.clone(),
+            _seed_schedule: self._seed_schedule.clone(),
+            #[cfg(feature = "monitor")]
+            _monitor: self._monitor.clone(),
+        }
     }
 
     pub(crate) fn validate_vec(&self, v: &DataPointSlice) -> Result<()> {
-        if !(v.len() == self.dim) {
-            return Err(Error::Failed(
-                "data point is not valid, are the dimensions correct?".to_string(),
-            ));
-        };
-        Ok(())
+        validate_dim(v, self.dim)
     }
 
     /// Set seed of LSH
@@ -317,9 +931,17 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
     /// * `seed` - Seed for the RNG's if 0, RNG's are seeded randomly.
     pub fn seed(&mut self, seed: u64) -> &mut Self {
         self._seed = seed;
+        self._seed_schedule = SeedSchedule::new(seed);
         self
     }
 
+    /// Next seed of the [SeedSchedule](utils/struct.SeedSchedule.html) that is derived from the
+    /// seed of the LSH. Use it to seed hashers that are created after the LSH is built, e.g. for
+    /// new hash tables in an online learning epoch. The seeds are reproducible and unique.
+    pub fn next_seed(&mut self) -> u64 {
+        self._seed_schedule.next()
+    }
+
     /// Only store indexes of data points. The mapping of data point to indexes is done outside
     /// of the LSH struct.
     pub fn only_index(&mut self) -> &mut Self {
@@ -337,11 +959,120 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
         self
     }
 
-    pub fn base(&mut self) -> &mut Self {
-        self._multi_probe = false;
-        self
-    }
-
+    /// Only query a random subset of the hash tables. Every table is queried with probability
+    /// `p`. This trades recall for query speed. The sampling is seeded by the query vector, so
+    /// the same query always results in the same tables.
+    ///
+    /// # Arguments
+    /// * `p` - Probability of querying a hash table, between 0 and 1.
+    pub fn set_table_sampling_rate(&mut self, p: f64) -> Result<&mut Self> {
+        if !(0. ..=1.).contains(&p) {
+            return Err(Error::Failed(
+                "the table sampling rate should be between 0 and 1".to_string(),
+            ));
+        }
+        self._table_sampling_rate = p;
+        Ok(self)
+    }
+
+    /// Enable [query_bucket_ids_sketched](struct.LSH.html#method.query_bucket_ids_sketched).
+    /// Creates a random projection of the queries to `L / 4` dimensions that selects the
+    /// `L / 4` hash tables of a sketched query.
+    pub fn sketched_query(&mut self) -> &mut Self {
+        self._table_sketch = Some(TableSketch::new(self.dim, self.n_hash_tables, self._seed));
+        self
+    }
+
+    /// Report the store, query and delete events to `monitor` (`monitor` feature).
+    /// See [Monitor](monitor/trait.Monitor.html).
+    #[cfg(feature = "monitor")]
+    pub fn with_monitor(&mut self, monitor: Box<dyn Monitor>) -> &mut Self {
+        self._monitor = Some(Arc::from(monitor));
+        self
+    }
+
+    #[cfg(feature = "monitor")]
+    fn notify<F: FnOnce(&dyn Monitor)>(&self, f: F) {
+        if let Some(monitor) = &self._monitor {
+            f(monitor.as_ref())
+        }
+    }
+
+    /// Don't show a progress bar in [store_vecs](struct.LSH.html#method.store_vecs) when the
+    /// `progress` feature is enabled.
+    pub fn silent(&mut self) -> &mut Self {
+        self._silent = true;
+        self
+    }
+
+    #[cfg(feature = "progress")]
+    fn progress_bar(&self, n: usize) -> indicatif::ProgressBar {
+        if self._silent {
+            return indicatif::ProgressBar::hidden();
+        }
+        let pb = indicatif::ProgressBar::new(n as u64);
+        pb.set_style(
+            indicatif::ProgressStyle::with_template("Stored {pos}/{len} vectors {wide_bar}")
+                .unwrap(),
+        );
+        pb
+    }
+
+    /// Also query `n_trials` noisy copies of every query vector. Every copy is perturbed by
+    /// `Normal(0, std_dev)` noise. This improves the recall for queries near the hash boundaries
+    /// at the cost of `n_trials` times more bucket lookups.
+    ///
+    /// # Arguments
+    /// * `std_dev` - Standard deviation of the noise, finite and not negative.
+    /// * `n_trials` - Number of noisy copies per query.
+    pub fn noise_robust(&mut self, std_dev: f32, n_trials: usize) -> Result<&mut Self> {
+        if !std_dev.is_finite() || std_dev < 0. {
+            return Err(Error::Failed(
+                "std_dev should be finite and not negative".to_string(),
+            ));
+        }
+        self._noise_std_dev = std_dev;
+        self._noise_trials = n_trials;
+        Ok(self)
+    }
+
+    /// Random generator that only depends on the query vector, so that queries are reproducible.
+    fn query_rng(v: &DataPointSlice) -> SmallRng {
+        let mut hasher = FnvHasher::default();
+        for x in v {
+            hasher.write_u32(x.to_bits());
+        }
+        SmallRng::seed_from_u64(hasher.finish())
+    }
+
+    fn noisy_copies(&self, v: &DataPointSlice) -> Result<Vec<DataPoint>> {
+        if self._noise_trials == 0 {
+            return Ok(vec![]);
+        }
+        let normal = Normal::new(0., self._noise_std_dev)
+            .map_err(|e| Error::Failed(format!("invalid noise: {:?}", e)))?;
+        let mut rng = Self::query_rng(v);
+        Ok((0..self._noise_trials)
+            .map(|_| v.iter().map(|x| x + rng.sample(normal)).collect())
+            .collect())
+    }
+
+    /// Indexes of the hash tables that should be queried for `v`.
+    pub(crate) fn sample_tables(&self, v: &DataPointSlice) -> Vec<usize> {
+        if self._table_sampling_rate >= 1. {
+            return (0..self.n_hash_tables).collect();
+        }
+        let mut rng = Self::query_rng(v);
+        (0..self.n_hash_tables)
+            .filter(|_| rng.gen_bool(self._table_sampling_rate))
+            .collect()
+    }
+
+    pub fn base(&mut self) -> &mut Self {
+        self._multi_probe = false;
+        self
+    }
+
     /// Increase storage of the `hash_tables` backend. This can reduce system calls.
     ///
     /// # Arguments
@@ -374,6 +1105,324 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
         self.hash_tables.as_ref().unwrap().describe()
     }
 
+    /// Histogram of the distances of random pairs of the stored data points. At most
+    /// `2 * n_pairs` data points are sampled. See
+    /// [analysis::distance_histogram](analysis/fn.distance_histogram.html).
+    /// Needs the stored data points.
+    ///
+    /// # Arguments
+    /// * `n_pairs` - Number of random pairs that are sampled.
+    /// * `n_bins` - Number of bins.
+    /// * `metric` - Distance of a pair.
+    pub fn distance_histogram(
+        &self,
+        n_pairs: usize,
+        n_bins: usize,
+        metric: DistanceMetric,
+    ) -> Result<Vec<(f32, usize)>> {
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "the data points are needed, but only the indexes are stored".to_string(),
+            ));
+        }
+        let ht = self.hash_tables.as_ref().unwrap();
+        let data = ht
+            .sample_ids(2 * n_pairs, self._seed)?
+            .into_iter()
+            .map(|id| ht.idx_to_datapoint_owned(id))
+            .collect::<Result<Vec<_>>>()?;
+        if data.len() < 2 {
+            return Err(Error::Failed(
+                "at least two data points are needed".to_string(),
+            ));
+        }
+        Ok(distance_histogram(
+            &data, n_pairs, n_bins, self._seed, metric,
+        ))
+    }
+
+    /// Check that every id in a bucket has a stored data point that hashes to the key of the
+    /// bucket, e.g. after a crash recovery or a migration. Returns the inconsistent entries.
+    /// Expensive operation: all `n * L` bucket entries are re-hashed.
+    /// Needs the stored data points.
+    pub fn verify_consistency(&self) -> Result<Vec<ConsistencyError>> {
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "cannot verify an index without stored data points".to_string(),
+            ));
+        }
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut errors = vec![];
+        for (table_idx, tbl) in ht.to_bucket_map()?.into_iter().enumerate() {
+            for (bucket_hash, ids) in tbl {
+                for id in ids {
+                    let expected_hash = match ht.idx_to_datapoint_owned(id) {
+                        Ok(d) => Some(self.hashers[table_idx].hash_vec_put(&d)),
+                        Err(Error::NotFound) => None,
+                        Err(e) => return Err(e),
+                    };
+                    if expected_hash.as_ref() != Some(&bucket_hash) {
+                        errors.push(ConsistencyError {
+                            table_idx,
+                            bucket_hash: bucket_hash.clone(),
+                            id,
+                            expected_hash,
+                        });
+                    }
+                }
+            }
+        }
+        errors.sort_by_key(|e| (e.table_idx, e.id));
+        Ok(errors)
+    }
+
+    /// Approximate Personalized PageRank of `source_id` by a random walk with restart on the
+    /// implicit graph in which data points are neighbors if they share a bucket. At every step the
+    /// walk restarts at `source_id` with probability `alpha`, otherwise it moves to a random member
+    /// of the bucket of a random hash table. Returns the visit frequency per id.
+    /// Requires the data points to be stored.
+    ///
+    /// # Arguments
+    /// * `source_id` - Id of the data point to personalize on.
+    /// * `n_steps` - Number of steps of the walk.
+    /// * `alpha` - Restart probability.
+    pub fn approximate_ppr(
+        &self,
+        source_id: DataId,
+        n_steps: usize,
+        alpha: f32,
+    ) -> Result<HashMap<DataId, f32>> {
+        if !(0. ..=1.).contains(&alpha) {
+            return Err(Error::Failed("alpha should be in [0, 1]".to_string()));
+        }
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut rng = create_rng(self._seed);
+        // hashes of the visited data points.
+        let mut hashes: HashMap<DataId, Vec<Hash>> = HashMap::default();
+        let mut visits: HashMap<DataId, usize> = HashMap::default();
+        let mut current = source_id;
+
+        for _ in 0..n_steps {
+            let current_hashes = match hashes.entry(current) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => {
+                    let v = ht.idx_to_datapoint_owned(current)?;
+                    e.insert(self.hashers.iter().map(|h| h.hash_vec_put(&v)).collect())
+                }
+            };
+            let next = if rng.gen::<f32>() < alpha {
+                source_id
+            } else {
+                let table = rng.gen_range(0, self.n_hash_tables);
+                match ht.query_bucket(&current_hashes[table], table) {
+                    Ok(bucket) => {
+                        let neighbors: Vec<DataId> =
+                            bucket.into_iter().filter(|&id| id != current).collect();
+                        if neighbors.is_empty() {
+                            source_id
+                        } else {
+                            neighbors[rng.gen_range(0, neighbors.len())]
+                        }
+                    }
+                    Err(Error::NotFound) => source_id,
+                    Err(e) => return Err(e),
+                }
+            };
+            *visits.entry(next).or_default() += 1;
+            current = next;
+        }
+        Ok(visits
+            .into_iter()
+            .map(|(id, n)| (id, n as f32 / n_steps as f32))
+            .collect())
+    }
+
+    /// Semi-supervised label propagation on the implicit graph in which data points are neighbors
+    /// if they share a bucket. At every step, every unlabeled data point takes the plurality class
+    /// of its labeled neighbors, counted once per shared bucket. Ties go to the smallest class.
+    /// The given labels are fixed. Stops early if no label changes.
+    /// Returns the labels of all stored ids that are reached in `n_steps`; ids without labeled
+    /// neighbors are left out.
+    ///
+    /// # Arguments
+    /// * `n_classes` - Number of classes. Classes are in `0..n_classes`.
+    /// * `labeled` - `(id, class)` of the labeled data points.
+    /// * `n_steps` - Maximum number of propagation steps.
+    pub fn label_propagation(
+        &self,
+        n_classes: usize,
+        labeled: &[(DataId, usize)],
+        n_steps: usize,
+    ) -> Result<HashMap<DataId, usize>> {
+        if let Some((_, class)) = labeled.iter().find(|(_, class)| *class >= n_classes) {
+            return Err(Error::Failed(format!(
+                "class {} should be smaller than n_classes {}",
+                class, n_classes
+            )));
+        }
+        let buckets: Vec<Vec<DataId>> = self
+            .hash_tables
+            .as_ref()
+            .unwrap()
+            .to_bucket_map()?
+            .into_iter()
+            .flat_map(|tbl| tbl.into_values())
+            .filter(|ids| ids.len() > 1)
+            .collect();
+        let fixed: HashSet<DataId> = labeled.iter().map(|(id, _)| *id).collect();
+        let mut labels: HashMap<DataId, usize> = labeled.iter().copied().collect();
+
+        for _ in 0..n_steps {
+            let mut votes: HashMap<DataId, Vec<usize>> = HashMap::default();
+            for bucket in &buckets {
+                let mut counts = vec![0; n_classes];
+                bucket
+                    .iter()
+                    .filter_map(|id| labels.get(id))
+                    .for_each(|&class| counts[class] += 1);
+                for id in bucket.iter().filter(|id| !fixed.contains(id)) {
+                    let node_votes = votes.entry(*id).or_insert_with(|| vec![0; n_classes]);
+                    node_votes
+                        .iter_mut()
+                        .zip(&counts)
+                        .for_each(|(v, c)| *v += c);
+                    // a data point doesn't vote for itself.
+                    if let Some(&class) = labels.get(id) {
+                        node_votes[class] -= 1;
+                    }
+                }
+            }
+            let mut changed = false;
+            for (id, node_votes) in votes {
+                let best = *node_votes.iter().max().unwrap();
+                if best == 0 {
+                    continue;
+                }
+                let class = node_votes.iter().position(|&v| v == best).unwrap();
+                if labels.insert(id, class) != Some(class) {
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        Ok(labels)
+    }
+
+    /// Export the buckets as a sparse matrix with a row per bucket and a column per id, e.g. for
+    /// spectral clustering on the implicit LSH graph.
+    pub fn to_csr(&self) -> Result<CsrMatrix> {
+        let (row_ptr, col_idx, bucket_table_ids) =
+            self.hash_tables.as_ref().unwrap().export_as_csr()?;
+        let n_cols = col_idx.iter().max().map_or(0, |&id| id as usize + 1);
+        Ok(CsrMatrix {
+            row_ptr,
+            col_idx,
+            bucket_table_ids,
+            n_cols,
+        })
+    }
+
+    /// Uniformly sample `n` stored ids, e.g. to build an evaluation set. If fewer than `n` ids are
+    /// stored, all ids are returned and a warning is logged.
+    ///
+    /// # Arguments
+    /// * `n` - Number of ids.
+    /// * `seed` - Seed for the sampling. If 0, randomness is seeded from the os.
+    pub fn sample(&self, n: usize, seed: u64) -> Result<Vec<DataId>> {
+        let ids = self.hash_tables.as_ref().unwrap().sample_ids(n, seed)?;
+        if ids.len() < n {
+            warn!("only {} ids stored, {} requested", ids.len(), n);
+        }
+        Ok(ids)
+    }
+
+    /// Fraction of non-empty buckets over all hash tables: `1 - n_empty / n_total`. Buckets only
+    /// become empty by deleting data points, so a low ratio means many stale buckets remain after
+    /// deletes, which cost memory and hashing work in multi-probe queries. Returns 1.0 if nothing is stored.
+    pub fn occupancy_ratio(&self) -> Result<f64> {
+        let (n_empty, n_total) = self.hash_tables.as_ref().unwrap().count_empty_buckets()?;
+        if n_total == 0 {
+            return Ok(1.);
+        }
+        Ok(1. - n_empty as f64 / n_total as f64)
+    }
+
+    /// Hash `v` in all hash tables. Returns `L` hashes. Useful for debugging query results.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn debug_hash(&self, v: &DataPointSlice) -> Result<Vec<Hash>> {
+        self.validate_vec(v)?;
+        Ok(self.hashers.iter().map(|h| h.hash_vec_query(v)).collect())
+    }
+
+    /// Trace which buckets a query hits in every hash table. Shows why a known neighbor is not
+    /// returned. Multi-probing, table sampling and noise robust querying are not traced.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn debug_query_trace(&self, v: &DataPointSlice) -> Result<QueryTrace> {
+        let ht = self.hash_tables.as_ref().unwrap();
+        let tables = self
+            .debug_hash(v)?
+            .into_iter()
+            .enumerate()
+            .map(|(i, hash)| {
+                let bucket = match ht.query_bucket(&hash, i) {
+                    Ok(bucket) => {
+                        let mut ids: Vec<DataId> = bucket.into_iter().collect();
+                        ids.sort_unstable();
+                        Some(ids)
+                    }
+                    Err(Error::NotFound) => None,
+                    Err(e) => return Err(e),
+                };
+                Ok(TableTrace { hash, bucket })
+            })
+            .collect::<Result<_>>()?;
+        Ok(QueryTrace { tables })
+    }
+
+    /// Build an undirected similarity graph of the stored data points. Only pairs of ids that share
+    /// a bucket are compared exactly, so the graph may miss edges that LSH doesn't find.
+    /// Returns an adjacency list with sorted neighbors; ids without neighbors are left out.
+    /// Requires the data points to be stored.
+    ///
+    /// # Arguments
+    /// * `threshold` - Maximum distance for `L2`, minimum similarity otherwise.
+    /// * `metric` - Metric for the exact comparison.
+    pub fn build_similarity_graph(
+        &self,
+        threshold: f32,
+        metric: DistanceMetric,
+    ) -> Result<HashMap<DataId, Vec<DataId>>> {
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut pairs: HashSet<(DataId, DataId)> = HashSet::default();
+        for table in ht.to_bucket_map()? {
+            for ids in table.values() {
+                for (i, &a) in ids.iter().enumerate() {
+                    for &b in &ids[i + 1..] {
+                        pairs.insert((a.min(b), a.max(b)));
+                    }
+                }
+            }
+        }
+
+        let mut graph: HashMap<DataId, Vec<DataId>> = HashMap::default();
+        for (a, b) in pairs {
+            let va = ht.idx_to_datapoint_owned(a)?;
+            let vb = ht.idx_to_datapoint_owned(b)?;
+            if metric.within(&va, &vb, threshold) {
+                graph.entry(a).or_default().push(b);
+                graph.entry(b).or_default().push(a);
+            }
+        }
+        graph.values_mut().for_each(|n| n.sort_unstable());
+        Ok(graph)
+    }
+
     /// Store a single vector in storage. Returns id.
     ///
     /// # Arguments
@@ -381,21 +1430,83 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
     ///
     /// # Examples
     /// ```
-    /// use lsh_rs::LshSql;
-    /// let mut lshd = LshSql::new(5, 10, 3).srp();
+    /// use lsh_rs::LshMem;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
     /// let v = &[2., 3., 4.];
     /// let id = lsh.store_vec(v);
     /// ```
-    pub fn store_vec(&mut self, v: &DataPointSlice) -> Result<u32> {
+    pub fn store_vec(&mut self, v: &DataPointSlice) -> Result<DataId> {
         self.validate_vec(v)?;
+        let hashes = self.hashers.iter().map(|h| h.hash_vec_put(v)).collect();
+        self.store_hashed(v, hashes)
+    }
+
+    /// Store a validated data point under its hashes, one per hash table. Returns id.
+    pub(crate) fn store_hashed(&mut self, v: &DataPointSlice, hashes: Vec<Hash>) -> Result<DataId> {
+        let mut idx = 0;
+        let ht = self.hash_tables.as_mut().unwrap();
+        for (i, hash) in hashes.into_iter().enumerate() {
+            idx = ht.put(hash, v, i)?;
+        }
+        #[cfg(feature = "monitor")]
+        self.notify(|m| m.on_store(idx, self.n_hash_tables));
+        Ok(idx)
+    }
+
+    /// Store the context embeddings of a sequence, e.g. of word vectors. Every pair
+    /// `(vecs[i], vecs[j])` with `0 < j - i <= window` is stored as the element-wise average of the
+    /// pair. Returns the ids, ordered by `i` and then `j`.
+    ///
+    /// # Arguments
+    /// * `vecs` - Sequence of data points.
+    /// * `window` - Maximum distance of the pairs in the sequence.
+    ///
+    /// # Examples
+    /// ```
+    /// use lsh_rs::LshMem;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// let seq = vec![vec![1., 0., 0.], vec![0., 1., 0.], vec![0., 0., 1.]];
+    /// let ids = lsh.store_sequence(&seq, 1).unwrap();
+    /// assert_eq!(ids.len(), 2);
+    /// assert!(lsh.query_bucket_ids(&[0.5, 0.5, 0.]).unwrap().contains(&ids[0]));
+    /// ```
+    pub fn store_sequence(&mut self, vecs: &[DataPoint], window: usize) -> Result<Vec<DataId>> {
+        if window == 0 {
+            return Err(Error::Failed("window should be at least 1".to_string()));
+        }
+        // validate all data points first, so that nothing is stored on an error.
+        for v in vecs {
+            self.validate_vec(v)?;
+        }
+        let mut ids = Vec::new();
+        for (i, a) in vecs.iter().enumerate() {
+            for b in vecs.iter().skip(i + 1).take(window) {
+                let avg: DataPoint = a.iter().zip(b).map(|(x, y)| (x + y) / 2.).collect();
+                ids.push(self.store_vec(&avg)?);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Store a single vector with a label or metadata blob. Returns id.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    /// * `label` - Metadata stored alongside the data point.
+    pub fn store_vec_labeled(&mut self, v: &DataPointSlice, label: &[u8]) -> Result<DataId> {
+        self.validate_vec(v)?;
+        let ht = self.hash_tables.as_mut().unwrap();
+        if !ht.supports_metadata() {
+            return Err(Error::NotImplemented);
+        }
 
         let mut idx = 0;
-        let mut ht = self.hash_tables.take().unwrap();
         for (i, proj) in self.hashers.iter().enumerate() {
             let hash = proj.hash_vec_put(v);
-            idx = ht.put(hash, v, i)?;
+            idx = ht.put_with_metadata(hash, v, i, label.to_vec())?;
         }
-        self.hash_tables.replace(ht);
+        #[cfg(feature = "monitor")]
+        self.notify(|m| m.on_store(idx, self.n_hash_tables));
         Ok(idx)
     }
 
@@ -407,7 +1518,7 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
     /// * `old_v` - Old data point. Needed to remove the old hash.
     pub fn update_by_idx(
         &mut self,
-        idx: u32,
+        idx: DataId,
         new_v: &DataPointSlice,
         old_v: &DataPointSlice,
     ) -> Result<()> {
@@ -421,19 +1532,48 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
         Ok(())
     }
 
-    fn query_bucket_union(&self, v: &DataPointSlice) -> Result<HashSet<u32>> {
+    fn query_bucket_union(&self, v: &DataPointSlice) -> Result<HashSet<DataId>> {
+        let mut bucket_union = HashSet::default();
+        self.query_bucket_union_into(v, &mut bucket_union)?;
+        Ok(bucket_union)
+    }
+
+    /// Add the union of the matching buckets to `bucket_union`.
+    fn query_bucket_union_into(
+        &self,
+        v: &DataPointSlice,
+        bucket_union: &mut HashSet<DataId>,
+    ) -> Result<()> {
+        #[cfg(feature = "monitor")]
+        let start = Instant::now();
+        self.query_bucket_union_unmonitored(v, bucket_union)?;
+        #[cfg(feature = "monitor")]
+        self.notify(|m| m.on_query(bucket_union.len(), start.elapsed()));
+        Ok(())
+    }
+
+    fn query_bucket_union_unmonitored(
+        &self,
+        v: &DataPointSlice,
+        bucket_union: &mut HashSet<DataId>,
+    ) -> Result<()> {
         self.validate_vec(v)?;
         if self._multi_probe {
-            return self.multi_probe_bucket_union(v);
+            return self.multi_probe_bucket_union_stats(v, None, bucket_union);
         }
 
-        let mut bucket_union = HashSet::default();
-
-        for (i, proj) in self.hashers.iter().enumerate() {
-            let hash = proj.hash_vec_query(v);
-            self.process_bucket_union_result(&hash, i, &mut bucket_union)?;
+        let tables = self.sample_tables(v);
+        for &i in &tables {
+            let hash = self.hashers[i].hash_vec_query(v);
+            self.process_bucket_union_result(&hash, i, bucket_union)?;
         }
-        Ok(bucket_union)
+        for noisy in self.noisy_copies(v)? {
+            for &i in &tables {
+                let hash = self.hashers[i].hash_vec_query(&noisy);
+                self.process_bucket_union_result(&hash, i, bucket_union)?;
+            }
+        }
+        Ok(())
     }
 
     /// Query all buckets in the hash tables. The union of the matching buckets over the `L`
@@ -452,96 +1592,1357 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
 
         bucket_union
             .iter()
-            .map(|&idx| Ok(self.hash_tables.as_ref().unwrap().idx_to_datapoint(idx)?))
+            .map(|&idx| self.hash_tables.as_ref().unwrap().idx_to_datapoint(idx))
             .collect()
     }
 
-    /// Query all buckets in the hash tables and return the data point indexes. The union of the
-    /// matching buckets of `L` hash tables is returned.
+    /// Query all buckets in the hash tables and return copies of the data points. Unlike
+    /// [query_bucket](struct.LSH.html#method.query_bucket) this also works for backends that
+    /// don't store the data points as `DataPoint`, e.g. scalar quantized storage.
     ///
     /// # Arguments
     /// * `v` - Query vector
-    pub fn query_bucket_ids(&self, v: &DataPointSlice) -> Result<Vec<u32>> {
+    pub fn query_bucket_owned(&self, v: &DataPointSlice) -> Result<Vec<DataPoint>> {
         self.validate_vec(v)?;
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "cannot query bucket, use query_bucket_ids".to_string(),
+            ));
+        }
         let bucket_union = self.query_bucket_union(v)?;
-        Ok(bucket_union.iter().copied().collect())
+        let ht = self.hash_tables.as_ref().unwrap();
+        bucket_union
+            .iter()
+            .map(|&idx| ht.idx_to_datapoint_owned(idx))
+            .collect()
     }
 
-    /// Query bucket collision for a batch of data points.
+    /// Query all buckets in the hash tables and return the data point indexes. The union of the
+    /// matching buckets of `L` hash tables is returned.
     ///
     /// # Arguments
-    /// * `vs` - Array of data points.
-    pub fn query_bucket_ids_batch(&self, vs: &[DataPoint]) -> Result<Vec<Vec<u32>>> {
-        vs.iter().map(|v| self.query_bucket_ids(v)).collect()
+    /// * `v` - Query vector
+    pub fn query_bucket_ids(&self, v: &DataPointSlice) -> Result<Vec<DataId>> {
+        self.validate_vec(v)?;
+        let bucket_union = self.query_bucket_union(v)?;
+        Ok(bucket_union.iter().copied().collect())
     }
 
-    /// Query bucket collision for a batch of data points.
+    /// Query the ids that don't collide with `v` in any hash table, e.g. to retrieve
+    /// dissimilar data points. The ids are sorted.
     ///
     /// # Arguments
-    /// * `vs` - Array of data points.
-    pub fn query_bucket_ids_batch_arr(&self, vs: ArrayView2<FloatSize>) -> Result<Vec<Vec<u32>>> {
-        vs.axis_iter(Axis(0))
-            .map(|v| self.query_bucket_ids(v.as_slice().unwrap()))
-            .collect()
+    /// * `v` - Query vector
+    pub fn query_bucket_ids_negated(&self, v: &DataPointSlice) -> Result<Vec<DataId>> {
+        self.validate_vec(v)?;
+        let bucket_union = self.query_bucket_union(v)?;
+        self.hash_tables.as_ref().unwrap().ids_not_in(&bucket_union)
     }
 
-    /// Delete data point from storage. This does not free memory as the storage vector isn't resized.
+    /// Query bucket collision within a latency budget. The hash tables are queried one by one
+    /// until `timeout` has passed; the tables queried before contribute to the result.
+    /// Returns the ids and whether all tables were queried.
+    /// Returns `Error::Timeout` if no candidates were found in time. Multi probing is not supported.
     ///
     /// # Arguments
-    /// * `v` - Data point
-    pub fn delete_vec(&mut self, v: &DataPointSlice) -> Result<()> {
+    /// * `v` - Query vector
+    /// * `timeout` - Time budget of the query.
+    pub fn query_bucket_ids_with_timeout(
+        &self,
+        v: &DataPointSlice,
+        timeout: Duration,
+    ) -> Result<(Vec<DataId>, bool)> {
         self.validate_vec(v)?;
-        for (i, proj) in self.hashers.iter().enumerate() {
-            let hash = proj.hash_vec_query(v);
-            let mut ht = self.hash_tables.take().unwrap();
-            ht.delete(&hash, v, i).unwrap_or_default();
-            self.hash_tables = Some(ht)
+        if self._multi_probe {
+            return Err(Error::Failed(
+                "a query with timeout doesn't support multi probing".to_string(),
+            ));
         }
-        Ok(())
+        let deadline = Instant::now() + timeout;
+        let mut bucket_union = HashSet::default();
+        let tables = self.sample_tables(v);
+        let mut complete = true;
+        'outer: for q in std::iter::once(v.to_vec()).chain(self.noisy_copies(v)?) {
+            for &i in &tables {
+                if Instant::now() >= deadline {
+                    complete = false;
+                    break 'outer;
+                }
+                let hash = self.hashers[i].hash_vec_query(&q);
+                self.process_bucket_union_result(&hash, i, &mut bucket_union)?;
+            }
+        }
+        if !complete && bucket_union.is_empty() {
+            return Err(Error::Timeout);
+        }
+        Ok((bucket_union.into_iter().collect(), complete))
     }
 
-    pub(crate) fn process_bucket_union_result(
+    /// Query bucket collision and measure the wall-clock time spent in every hash table, e.g. to
+    /// find slow tables of a backend. Only the bucket lookup is timed, not the hashing: a
+    /// `HashMap` lookup for `MemoryTable` or an SQL query for `SqlTable`. Returns the ids and
+    /// the duration per hash table; tables that are skipped by table sampling take zero time.
+    /// Multi probing is not supported.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_with_table_timing(
         &self,
-        hash: &Hash,
-        hash_table_idx: usize,
-        bucket_union: &mut HashSet<u32>,
-    ) -> Result<()> {
-        match self
-            .hash_tables
-            .as_ref()
-            .unwrap()
-            .query_bucket(hash, hash_table_idx)
-        {
-            Err(Error::NotFound) => Ok(()),
-            Ok(bucket) => {
-                *bucket_union = bucket_union.union(&bucket).copied().collect();
-                Ok(())
+        v: &DataPointSlice,
+    ) -> Result<(Vec<DataId>, Vec<Duration>)> {
+        self.validate_vec(v)?;
+        if self._multi_probe {
+            return Err(Error::Failed(
+                "a query with table timing doesn't support multi probing".to_string(),
+            ));
+        }
+        let mut bucket_union = HashSet::default();
+        let mut durations = vec![Duration::default(); self.n_hash_tables];
+        let tables = self.sample_tables(v);
+        for q in std::iter::once(v.to_vec()).chain(self.noisy_copies(v)?) {
+            for &i in &tables {
+                let hash = self.hashers[i].hash_vec_query(&q);
+                let start = Instant::now();
+                self.process_bucket_union_result(&hash, i, &mut bucket_union)?;
+                durations[i] += start.elapsed();
             }
-            Err(e) => Err(e),
         }
+        Ok((bucket_union.into_iter().collect(), durations))
     }
-}
 
-impl<T: VecHash + Serialize> LSH<SqlTable, T> {
-    /// Commit SqlTable backend
-    pub fn commit(&mut self) -> Result<()> {
-        let ht = self.hash_tables.as_mut().unwrap();
-        ht.commit()?;
-        Ok(())
+    /// Query only `L / 4` of the hash tables, selected by a cheap random projection of `v`.
+    /// Only `L / 4` hashes are computed, which trades recall for query speed when `L` is very
+    /// large. Fails if [sketched_query](struct.LSH.html#method.sketched_query) wasn't called.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket_ids_sketched(&self, v: &DataPointSlice) -> Result<Vec<DataId>> {
+        self.validate_vec(v)?;
+        let sketch = self
+            ._table_sketch
+            .as_ref()
+            .ok_or_else(|| Error::Failed("sketched query is not enabled".to_string()))?;
+        let mut bucket_union = HashSet::default();
+        for i in sketch.select_tables(v) {
+            let hash = self.hashers[i].hash_vec_query(v);
+            self.process_bucket_union_result(&hash, i, &mut bucket_union)?;
+        }
+        Ok(bucket_union.into_iter().collect())
     }
 
-    /// Init transaction of SqlTable backend.
-    pub fn init_transaction(&mut self) -> Result<()> {
+    /// Query two indexes and union the candidates per query. If both indexes store the same data
+    /// points in the same order, e.g. built with different seeds, the ids refer to the same data
+    /// points. Improves recall at the cost of doubling the query latency.
+    ///
+    /// # Arguments
+    /// * `queries` - Query vectors.
+    /// * `index_b` - Second index with the same dimension.
+    pub fn query_bucket_ids_multi_index<H2: VecHash, T2: HashTables>(
+        &self,
+        queries: &[DataPoint],
+        index_b: &LSH<T2, H2>,
+    ) -> Result<Vec<Vec<DataId>>> {
+        if self.dim != index_b.dim {
+            return Err(Error::Failed(format!(
+                "dimensions of the indexes differ: {} and {}",
+                self.dim, index_b.dim
+            )));
+        }
+        queries
+            .iter()
+            .map(|q| {
+                let mut ids = self.query_bucket_union(q)?;
+                ids.extend(index_b.query_bucket_union(q)?);
+                let mut ids: Vec<DataId> = ids.into_iter().collect();
+                ids.sort_unstable();
+                Ok(ids)
+            })
+            .collect()
+    }
+
+    /// Multi-probe query that also records how many new candidates every probe adds. Useful to
+    /// tune the `budget` of [multi_probe](struct.LSH.html#method.multi_probe) empirically.
+    /// Fails if multi-probing isn't enabled.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket_ids_with_probe_stats(
+        &self,
+        v: &DataPointSlice,
+    ) -> Result<(Vec<DataId>, QueryProbeStats)> {
+        if !self._multi_probe {
+            return Err(Error::Failed("multi-probing is not enabled".to_string()));
+        }
+        let mut stats = QueryProbeStats::default();
+        let mut bucket_union = HashSet::default();
+        self.multi_probe_bucket_union_stats(v, Some(&mut stats), &mut bucket_union)?;
+        Ok((bucket_union.into_iter().collect(), stats))
+    }
+
+    /// Query the `k` nearest neighbors by the [metric](trait.VecHash.html#method.metric) of the
+    /// hashers with a soft recall guarantee. If the buckets contain fewer than `k / min_recall`
+    /// candidates, a random fraction `min_recall` of all stored data points is searched exactly
+    /// as well. Requires a backend that stores the data points and implements `stored_ids`.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `k` - Number of neighbors.
+    /// * `min_recall` - Targeted recall in `(0, 1]`.
+    pub fn query_knn_with_guarantee(
+        &self,
+        v: &DataPointSlice,
+        k: usize,
+        min_recall: f32,
+    ) -> Result<QueryResult> {
+        if !(min_recall > 0. && min_recall <= 1.) {
+            return Err(Error::Failed("min_recall should be in (0, 1]".to_string()));
+        }
+        let mut candidates = self.query_bucket_union(v)?;
+        let used_fallback = (candidates.len() as f32) < k as f32 / min_recall;
+        let ht = self.hash_tables.as_ref().unwrap();
+        if used_fallback {
+            let all_ids = ht.stored_ids()?;
+            let n = (min_recall * all_ids.len() as f32).ceil() as usize;
+            let mut rng = Self::query_rng(v);
+            candidates.extend(all_ids.choose_multiple(&mut rng, n));
+        }
+
+        let metric = self.hashers[0].metric();
+        let ids = candidates
+            .into_iter()
+            .map(|idx| Ok((idx, metric.distance(&ht.idx_to_datapoint_owned(idx)?, v))))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(QueryResult {
+            ids: rank_candidates(ids, k),
+            used_fallback,
+        })
+    }
+
+    /// Candidates of `v` as `(id, cosine similarity with v)`, most similar first.
+    fn ranked_by_cosine(&self, v: &DataPointSlice) -> Result<Vec<(DataId, f32)>> {
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut ranked = self
+            .query_bucket_union(v)?
+            .into_iter()
+            // NaN, e.g. of zero vectors, ranks last.
+            .map(|idx| {
+                Ok((
+                    idx,
+                    cosine_sim(&ht.idx_to_datapoint_owned(idx)?, v).max(-1.),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        ranked.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        Ok(ranked)
+    }
+
+    /// Pseudo-relevance feedback: expand `v` with the centroid of its `n_feedback` candidates
+    /// that are most cosine similar. Returns `alpha * v + (1 - alpha) * centroid`, or `v` if there
+    /// are no candidates.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    /// * `n_feedback` - Number of candidates in the centroid.
+    /// * `alpha` - Weight of the original query in `[0, 1]`.
+    pub fn expand_query(
+        &self,
+        v: &DataPointSlice,
+        n_feedback: usize,
+        alpha: f32,
+    ) -> Result<DataPoint> {
+        if n_feedback == 0 || !(0. ..=1.).contains(&alpha) {
+            return Err(Error::Failed(
+                "n_feedback should be positive and alpha in [0, 1]".to_string(),
+            ));
+        }
+        let ranked = self.ranked_by_cosine(v)?;
+        if ranked.is_empty() {
+            return Ok(v.to_vec());
+        }
+        let ht = self.hash_tables.as_ref().unwrap();
+        let feedback = &ranked[..n_feedback.min(ranked.len())];
+        let mut centroid = vec![0.; self.dim];
+        for &(idx, _) in feedback {
+            let d = ht.idx_to_datapoint_owned(idx)?;
+            centroid.iter_mut().zip(&d).for_each(|(c, x)| *c += x);
+        }
+        let n = feedback.len() as f32;
+        Ok(v.iter()
+            .zip(centroid)
+            .map(|(x, c)| alpha * x + (1. - alpha) * c / n)
+            .collect())
+    }
+
+    /// Query with the [expanded query](struct.LSH.html#method.expand_query) of `v`. Returns the
+    /// `k` candidates of the expanded query that are most cosine similar to it.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector.
+    /// * `n_feedback` - Number of candidates in the centroid.
+    /// * `alpha` - Weight of the original query in `[0, 1]`.
+    /// * `k` - Number of results.
+    pub fn query_with_expansion(
+        &self,
+        v: &DataPointSlice,
+        n_feedback: usize,
+        alpha: f32,
+        k: usize,
+    ) -> Result<Vec<DataId>> {
+        let expanded = self.expand_query(v, n_feedback, alpha)?;
+        Ok(self
+            .ranked_by_cosine(&expanded)?
+            .into_iter()
+            .take(k)
+            .map(|(idx, _)| idx)
+            .collect())
+    }
+
+    /// Query the `k` ids that collide with the query in the most hash tables. The counts are
+    /// estimated with a [CountMinSketch](stats/struct.CountMinSketch.html), so only `k`
+    /// candidate ids are kept in memory. Returns `(id, estimated count)` pairs sorted by count.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `k` - Number of ids to return.
+    /// * `epsilon` - Relative error of the count estimates in `(0, 1)`.
+    pub fn query_bucket_ids_approximate_count(
+        &self,
+        v: &DataPointSlice,
+        k: usize,
+        epsilon: f64,
+    ) -> Result<Vec<(DataId, f64)>> {
+        self.validate_vec(v)?;
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut cms = CountMinSketch::new(epsilon)?;
+        // estimates of the candidates and a min-heap of them. Entries of candidates whose
+        // estimate grew or that were evicted are stale and skipped.
+        let mut top_k: HashMap<DataId, u16> = HashMap::default();
+        let mut heap: BinaryHeap<Reverse<(u16, DataId)>> = BinaryHeap::new();
+        let mut bucket = Bucket::default();
+        for &i in &self.sample_tables(v) {
+            let hash = self.hashers[i].hash_vec_query(v);
+            bucket.clear();
+            ht.extend_with_bucket(&hash, i, &mut bucket)?;
+            for &id in &bucket {
+                cms.add(id);
+                let est = cms.estimate(id);
+                if top_k.len() < k || top_k.contains_key(&id) {
+                    top_k.insert(id, est);
+                    heap.push(Reverse((est, id)));
+                    continue;
+                }
+                while let Some(&Reverse((c, min_id))) = heap.peek() {
+                    if top_k.get(&min_id) == Some(&c) {
+                        break;
+                    }
+                    heap.pop();
+                }
+                if let Some(&Reverse((min, min_id))) = heap.peek() {
+                    if est > min {
+                        heap.pop();
+                        top_k.remove(&min_id);
+                        top_k.insert(id, est);
+                        heap.push(Reverse((est, id)));
+                    }
+                }
+            }
+        }
+        let mut out: Vec<(DataId, f64)> = top_k.into_iter().map(|(id, c)| (id, c as f64)).collect();
+        out.sort_unstable_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        Ok(out)
+    }
+
+    /// Same as [query_bucket_ids](struct.LSH.html#method.query_bucket_ids), but clears `output`
+    /// and collects the union of the buckets in it, so that its allocation is reused in query
+    /// loops. With the in memory backends the buckets aren't copied either.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `output` - Buffer for the data point indexes.
+    pub fn query_bucket_ids_into(
+        &self,
+        v: &DataPointSlice,
+        output: &mut HashSet<DataId>,
+    ) -> Result<()> {
+        output.clear();
+        self.query_bucket_union_into(v, output)
+    }
+
+    /// Query all buckets in the hash tables and return only the data point indexes that collide
+    /// with the query in all `L` hash tables. Maximum precision at the cost of recall.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket_ids_strict(&self, v: &DataPointSlice) -> Result<Vec<DataId>> {
+        self.validate_vec(v)?;
+        let hashes: Vec<Hash> = self.hashers.iter().map(|h| h.hash_vec_query(v)).collect();
+        let tables: Vec<usize> = (0..self.n_hash_tables).collect();
+        let bucket = self
+            .hash_tables
+            .as_ref()
+            .unwrap()
+            .query_intersection(&hashes, &tables)?;
+        Ok(bucket.into_iter().collect())
+    }
+
+    /// Query all buckets in the hash tables and return the data point indexes that collide
+    /// with the query in at least `min_tables` hash tables.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `min_tables` - Minimal number of collisions.
+    pub fn query_bucket_ids_atleast(
+        &self,
+        v: &DataPointSlice,
+        min_tables: usize,
+    ) -> Result<Vec<DataId>> {
+        self.validate_vec(v)?;
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut counts: HashMap<DataId, usize> = HashMap::default();
+        for (i, proj) in self.hashers.iter().enumerate() {
+            let hash = proj.hash_vec_query(v);
+            match ht.query_bucket(&hash, i) {
+                Err(Error::NotFound) => (),
+                Ok(bucket) => bucket
+                    .into_iter()
+                    .for_each(|idx| *counts.entry(idx).or_default() += 1),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(counts
+            .into_iter()
+            .filter(|(_, c)| *c >= min_tables)
+            .map(|(idx, _)| idx)
+            .collect())
+    }
+
+    /// Query only the `n_tables` hash tables in which the query lies furthest from a bucket
+    /// boundary, i.e. the tables in which the query hash is most reliable. The margin is the
+    /// Hamming margin for SRP and the xi-distance for L2. Hashers without a margin query the
+    /// first `n_tables` tables.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `n_tables` - Number of hash tables to query.
+    pub fn query_bucket_ids_top_tables(
+        &self,
+        v: &DataPointSlice,
+        n_tables: usize,
+    ) -> Result<Vec<DataId>> {
+        self.validate_vec(v)?;
+        let mut tables: Vec<(usize, FloatSize)> = self
+            .hashers
+            .iter()
+            .enumerate()
+            .map(|(i, h)| (i, h.query_margin(v).unwrap_or(0.)))
+            .collect();
+        // stable sort: equal margins keep the table order.
+        tables.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        let mut bucket_union = HashSet::default();
+        for &(i, _) in tables.iter().take(n_tables) {
+            let hash = self.hashers[i].hash_vec_query(v);
+            self.process_bucket_union_result(&hash, i, &mut bucket_union)?;
+        }
+        Ok(bucket_union.iter().copied().collect())
+    }
+
+    /// Query all buckets in the hash tables and return the data point indexes with their labels.
+    /// Data points stored without a label get an empty label.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket_labeled(&self, v: &DataPointSlice) -> Result<Vec<(DataId, Vec<u8>)>> {
+        let bucket_union = self.query_bucket_union(v)?;
+        let ht = self.hash_tables.as_ref().unwrap();
+        bucket_union
+            .iter()
+            .map(|&idx| match ht.get_metadata(idx) {
+                Ok(m) => Ok((idx, m)),
+                Err(Error::NotFound) => Ok((idx, vec![])),
+                Err(e) => Err(e),
+            })
+            .collect()
+    }
+
+    /// Query all buckets in the hash tables with a `f64` query vector. The query is cast to
+    /// `FloatSize` before hashing.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket_f64(&self, v: ArrayView1<f64>) -> Result<Vec<&DataPoint>> {
+        let v: DataPoint = v.iter().map(|&x| x as FloatSize).collect();
+        self.query_bucket(&v)
+    }
+
+    /// Query all buckets in the hash tables with a `f64` query vector and return the data point
+    /// indexes. The query is cast to `FloatSize` before hashing.
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    pub fn query_bucket_ids_f64(&self, v: ArrayView1<f64>) -> Result<Vec<DataId>> {
+        let v: DataPoint = v.iter().map(|&x| x as FloatSize).collect();
+        self.query_bucket_ids(&v)
+    }
+
+    /// Join other data points with this index by approximate similarity. For every vector in
+    /// `other_vecs` the buckets are queried and `(other_idx, candidate_id)` pairs are yielded.
+    /// The join is lazy, so the full result is never materialized.
+    ///
+    /// # Arguments
+    /// * `other_vecs` - Data points that are joined with this index.
+    pub fn similarity_join<'a>(
+        &'a self,
+        other_vecs: &'a [DataPoint],
+    ) -> impl Iterator<Item = Result<(DataId, DataId)>> + 'a {
+        other_vecs.iter().enumerate().flat_map(move |(i, v)| {
+            let pairs: Vec<Result<(DataId, DataId)>> = match self.query_bucket_ids(v) {
+                Ok(ids) => ids.into_iter().map(|id| Ok((i as DataId, id))).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            pairs.into_iter()
+        })
+    }
+
+    /// Query bucket collision for a batch of data points.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    pub fn query_bucket_ids_batch(&self, vs: &[DataPoint]) -> Result<Vec<Vec<DataId>>> {
+        vs.iter().map(|v| self.query_bucket_ids(v)).collect()
+    }
+
+    /// Query bucket collision for a batch of data points that contains duplicates, e.g.
+    /// augmented copies of the same image. Every distinct data point (compared by its bits) is
+    /// queried once and its result is copied to the duplicates.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    pub fn query_bucket_ids_batch_dedup(&self, vs: &[DataPoint]) -> Result<Vec<Vec<DataId>>> {
+        let mut unique: HashMap<Vec<u32>, usize> = HashMap::default();
+        let mut results = Vec::new();
+        let slots = vs
+            .iter()
+            .map(|v| {
+                let bits = v.iter().map(|x| x.to_bits()).collect();
+                match unique.entry(bits) {
+                    Entry::Occupied(e) => Ok(*e.get()),
+                    Entry::Vacant(e) => {
+                        results.push(self.query_bucket_ids(v)?);
+                        Ok(*e.insert(results.len() - 1))
+                    }
+                }
+            })
+            .collect::<Result<Vec<usize>>>()?;
+        Ok(slots.into_iter().map(|i| results[i].clone()).collect())
+    }
+
+    /// Query bucket collision for a batch of data points.
+    ///
+    /// # Arguments
+    /// * `vs` - Array of data points.
+    pub fn query_bucket_ids_batch_arr(
+        &self,
+        vs: ArrayView2<FloatSize>,
+    ) -> Result<Vec<Vec<DataId>>> {
+        vs.axis_iter(Axis(0))
+            .map(|v| self.query_bucket_ids(v.as_slice().unwrap()))
+            .collect()
+    }
+
+    /// Delete data point from storage. This does not free memory as the storage vector isn't resized.
+    ///
+    /// # Arguments
+    /// * `v` - Data point
+    pub fn delete_vec(&mut self, v: &DataPointSlice) -> Result<()> {
+        self.validate_vec(v)?;
+        for (i, proj) in self.hashers.iter().enumerate() {
+            let hash = proj.hash_vec_query(v);
+            let mut ht = self.hash_tables.take().unwrap();
+            ht.delete(&hash, v, i).unwrap_or_default();
+            self.hash_tables = Some(ht)
+        }
+        Ok(())
+    }
+
+    /// Delete a data point by id. See [batch_delete_by_ids](struct.LSH.html#method.batch_delete_by_ids).
+    ///
+    /// # Arguments
+    /// * `id` - Id of the data point.
+    pub fn delete_by_id(&mut self, id: DataId) -> Result<()> {
+        self.batch_delete_by_ids(&[id])
+    }
+
+    /// Delete data points by id. If the backend stores the data points, they are re-hashed to
+    /// locate the buckets. Otherwise the backend removes the ids from all hash tables, which is
+    /// more expensive for the in memory backends.
+    ///
+    /// # Arguments
+    /// * `ids` - Ids of the data points.
+    pub fn batch_delete_by_ids(&mut self, ids: &[DataId]) -> Result<()> {
+        let mut ht = self.hash_tables.take().unwrap();
+        let mut deleted = vec![];
+        let mut not_located = vec![];
+        for &id in ids {
+            let d = match ht.idx_to_datapoint_owned(id) {
+                Ok(d) => d,
+                Err(_) => {
+                    not_located.push(id);
+                    continue;
+                }
+            };
+            let located = self
+                .hashers
+                .iter()
+                .enumerate()
+                .all(|(i, proj)| ht.remove_id(id, &proj.hash_vec_put(&d), i).is_ok());
+            if located {
+                deleted.push(id);
+            } else {
+                not_located.push(id);
+            }
+        }
+        let res = if not_located.is_empty() {
+            Ok(vec![])
+        } else {
+            ht.delete_ids(&not_located)
+        };
+        self.hash_tables.replace(ht);
+        deleted.extend(res?);
+        // only ids that were stored are reported.
+        #[cfg(feature = "monitor")]
+        deleted
+            .iter()
+            .for_each(|&id| self.notify(|m| m.on_delete(id)));
+        Ok(())
+    }
+
+    /// Remove all entries of hash table `i`, e.g. before re-hashing it with a new hasher.
+    /// The other hash tables are left untouched.
+    ///
+    /// # Arguments
+    /// * `i` - Index of the hash table.
+    pub fn clear_table(&mut self, i: usize) -> Result<()> {
+        if i >= self.n_hash_tables {
+            return Err(Error::TableNotExist);
+        }
+        self.hash_tables.as_mut().unwrap().clear_table(i)
+    }
+
+    /// Apply `update` to the hashers of `tables` and re-hash the stored data points in those
+    /// tables. Needs the stored data points and a backend that supports `put_with_id`. If an
+    /// update fails, the old hashers are restored and the tables are left untouched.
+    fn rehash_tables<F: FnMut(&mut H) -> Result<()>>(
+        &mut self,
+        tables: &[usize],
+        mut update: F,
+    ) -> Result<()>
+    where
+        H: Clone,
+    {
+        if tables.iter().any(|&i| i >= self.n_hash_tables) {
+            return Err(Error::TableNotExist);
+        }
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "cannot re-hash a table without stored data points".to_string(),
+            ));
+        }
+        let ht = self.hash_tables.as_mut().unwrap();
+        if !ht.supports_put_with_id() {
+            return Err(Error::NotImplemented);
+        }
+        // buckets may not hold all ids, e.g. with a maximum bucket size.
+        let points = ht
+            .stored_ids()?
+            .into_iter()
+            .map(|id| Ok((id, ht.idx_to_datapoint_owned(id)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let hashers = &mut self.hashers;
+        let old_hashers: Vec<H> = tables.iter().map(|&i| hashers[i].clone()).collect();
+        let restore = |hashers: &mut Vec<H>| {
+            for (&i, h) in tables.iter().zip(old_hashers) {
+                hashers[i] = h
+            }
+        };
+        if let Err(e) = tables.iter().try_for_each(|&i| update(&mut hashers[i])) {
+            restore(hashers);
+            return Err(e);
+        }
+        if let Err(e) = LSH::<T, H>::fill_tables(ht, hashers, tables, &points) {
+            restore(hashers);
+            // the tables were cleared, insert the data points with the old hashers again.
+            LSH::<T, H>::fill_tables(ht, hashers, tables, &points)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Clear `tables` and insert `points` with their hashers.
+    fn fill_tables(
+        ht: &mut T,
+        hashers: &[H],
+        tables: &[usize],
+        points: &[(DataId, DataPoint)],
+    ) -> Result<()> {
+        for &i in tables {
+            ht.clear_table(i)?;
+        }
+        for (id, d) in points {
+            for &i in tables {
+                ht.put_with_id(*id, hashers[i].hash_vec_put(d), d, i)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn process_bucket_union_result(
+        &self,
+        hash: &Hash,
+        hash_table_idx: usize,
+        bucket_union: &mut HashSet<DataId>,
+    ) -> Result<()> {
+        self.hash_tables
+            .as_ref()
+            .unwrap()
+            .extend_with_bucket(hash, hash_table_idx, bucket_union)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl<T: VecHash + Serialize> LSH<SqlTable, T> {
+    /// Commit SqlTable backend
+    pub fn commit(&mut self) -> Result<()> {
+        let ht = self.hash_tables.as_mut().unwrap();
+        ht.commit()?;
+        Ok(())
+    }
+
+    /// Init transaction of SqlTable backend.
+    pub fn init_transaction(&mut self) -> Result<()> {
         let ht = self.hash_tables.as_mut().unwrap();
         ht.init_transaction()?;
         Ok(())
     }
+
+    /// Reclaim disk space and update the query planner statistics of the SqlTable backend
+    /// by running `VACUUM` and `ANALYZE`. Useful after many incremental updates.
+    pub fn vacuum(&mut self) -> Result<()> {
+        self.hash_tables.as_ref().unwrap().vacuum()
+    }
+
+    /// Estimate the size of the database in bytes.
+    pub fn estimate_db_size(&self) -> Result<u64> {
+        self.hash_tables.as_ref().unwrap().estimate_db_size()
+    }
+
+    /// Mark deleted ids with tombstones instead of removing their rows. See
+    /// [SqlTable::set_tombstone_delete](struct.SqlTable.html#method.set_tombstone_delete) for
+    /// the tradeoffs.
+    pub fn set_tombstone_delete(&mut self, tombstone: bool) -> Result<&mut Self> {
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .set_tombstone_delete(tombstone)?;
+        Ok(self)
+    }
+
+    /// Remove the rows of the tombstoned ids from the SqlTable backend.
+    pub fn purge_tombstones(&mut self) -> Result<()> {
+        self.hash_tables.as_mut().unwrap().purge_tombstones()
+    }
+
+    /// Log a warning when `estimate_db_size` exceeds `n_bytes`.
+    pub fn set_db_size_warning(&mut self, n_bytes: u64) -> &mut Self {
+        self.hash_tables.as_mut().unwrap().db_size_warning = Some(n_bytes);
+        self
+    }
 }
 
+#[cfg(feature = "sqlite")]
+impl<H: VecHash + Sync> LSH<SqlTableMem, H> {
+    /// Build the index in memory and write the database to `db_path` at the end, so no rows are
+    /// committed to disk during the build. Prefer this for batch builds on slow storage. An
+    /// existing database at `db_path` is overwritten. After flushing, the backend uses the
+    /// database on disk. The database can be opened with
+    /// [open_existing](struct.LSH.html#method.open_existing).
+    ///
+    /// # Arguments
+    /// * `vs` - Data points to store.
+    /// * `db_path` - Path of the database file.
+    pub fn build_in_memory_then_flush<P: AsRef<Path>>(
+        &mut self,
+        vs: &[DataPoint],
+        db_path: P,
+    ) -> Result<Vec<DataId>> {
+        let ids = self.store_vecs(vs)?;
+        let ht = self.hash_tables.as_mut().unwrap();
+        ht.commit()?;
+        ht.to_db(db_path)?;
+        Ok(ids)
+    }
+}
+
+impl<H: VecHash + Sync> LSH<MemoryTable, H> {
+    /// Query the `k` nearest neighbors by L2 distance for a batch of data points in parallel.
+    /// Per query the candidates are ranked directly from the bucket union, without collecting
+    /// the ids first. Returns `(id, distance)` pairs sorted by distance.
+    ///
+    /// # Arguments
+    /// * `queries` - Query vectors.
+    /// * `k` - Number of neighbors per query.
+    pub fn batch_query_rank(
+        &self,
+        queries: &[DataPoint],
+        k: usize,
+    ) -> Result<Vec<Vec<(DataId, f32)>>> {
+        let ht = self.hash_tables.as_ref().unwrap();
+        queries
+            .par_iter()
+            .map(|v| {
+                let ranked = self
+                    .query_bucket_union(v)?
+                    .into_iter()
+                    .map(|idx| match ht.idx_to_datapoint(idx) {
+                        Ok(d) => Ok((idx, l2_dist(d, v))),
+                        // quantized data points are decoded.
+                        Err(_) => Ok((idx, l2_dist(&ht.idx_to_datapoint_owned(idx)?, v))),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(rank_candidates(ranked, k))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl<H: VecHash + DeserializeOwned> LSH<SqlTable, H> {
+    /// Open an existing Sqlite index, e.g. to append data points. The hashers and parameters are
+    /// loaded from the database and the stored hashes are left untouched. New data points get ids
+    /// after the ids already stored.
+    ///
+    /// This differs from [load](struct.LSH.html#method.load) of the in memory backend, which
+    /// replaces all state with a serialized dump.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the Sqlite database file.
+    pub fn open_existing<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let (ht, n_projections, dim) = SqlTable::open_existing(&path)?;
+        let mut lsh = LSH::new(n_projections, ht.n_hash_tables(), dim);
+        lsh.hashers = ht.load_hashers()?;
+        lsh.only_index_storage = true;
+        lsh._db_path = path.as_ref().to_string_lossy().to_string();
+        lsh.hash_tables = Some(ht);
+        Ok(lsh)
+    }
+}
+
+impl<H: VecHash> LSH<MemoryTable, H> {
+    /// Copy the buckets into `new_lsh`, e.g. after changing `n_projections`. The hashes are
+    /// translated with `remap(hash, table_idx)`, which should return the hash the hashers of
+    /// `new_lsh` give. Returns the mapping from old id to new id. See
+    /// [MemoryTable::compact_into](struct.MemoryTable.html#method.compact_into).
+    ///
+    /// # Arguments
+    /// * `new_lsh` - LSH with the same dimension and number of hash tables.
+    /// * `remap` - Translates a hash of `self` to a hash of `new_lsh`.
+    pub fn migrate_to<F: Fn(Hash, usize) -> Hash>(
+        &self,
+        new_lsh: &mut Self,
+        remap: F,
+    ) -> Result<HashMap<DataId, DataId>> {
+        if self.dim != new_lsh.dim {
+            return Err(Error::Failed("dimensions don't match".to_string()));
+        }
+        self.hash_tables
+            .as_ref()
+            .unwrap()
+            .compact_into(new_lsh.hash_tables.as_mut().unwrap(), remap)
+    }
+
+    /// Iterate over the stored data points with the hashes under which they are stored. See
+    /// [MemoryTable::iter_with_data](struct.MemoryTable.html#method.iter_with_data).
+    #[allow(clippy::type_complexity)]
+    pub fn iter_with_data(
+        &self,
+    ) -> Result<impl Iterator<Item = (DataId, &DataPoint, Vec<(usize, &Hash)>)> + '_> {
+        self.hash_tables.as_ref().unwrap().iter_with_data()
+    }
+
+    /// Build the index from `(id, data point)` pairs, e.g. loaded from an external key-value
+    /// store or database. Existing content is replaced. See
+    /// [MemoryTable::rebuild_from_pairs](struct.MemoryTable.html#method.rebuild_from_pairs).
+    ///
+    /// # Arguments
+    /// * `pairs` - Unique ids and data points.
+    ///
+    /// # Example
+    /// ```
+    /// use lsh_rs::LshMem;
+    /// let pairs = vec![(10, vec![2., 3., 4.]), (20, vec![-1., -1., 1.])];
+    /// let lsh = LshMem::new(5, 10, 3)
+    ///     .srp()
+    ///     .unwrap()
+    ///     .build_from_pairs(pairs.into_iter())
+    ///     .unwrap();
+    /// assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&10));
+    /// ```
+    pub fn build_from_pairs(
+        mut self,
+        pairs: impl Iterator<Item = (DataId, DataPoint)>,
+    ) -> Result<Self> {
+        let dim = self.dim;
+        let mut wrong_dim = None;
+        let pairs = pairs.take_while(|(idx, d)| {
+            if d.len() != dim {
+                wrong_dim = Some(*idx);
+            }
+            wrong_dim.is_none()
+        });
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .rebuild_from_pairs(&self.hashers, pairs)?;
+        if let Some(idx) = wrong_dim {
+            return Err(Error::Failed(format!(
+                "data point {} should have dimension {}",
+                idx, dim
+            )));
+        }
+        Ok(self)
+    }
+
+    /// Transform every stored data point in place and re-hash it, e.g. to normalize the data
+    /// points after storing them. See [MemoryTable::apply](struct.MemoryTable.html#method.apply).
+    /// All data points are transformed and validated before the index is changed, so on an error
+    /// the index is left as it was.
+    ///
+    /// # Arguments
+    /// * `f` - Transformation of a data point.
+    pub fn apply_to_all<F: Fn(&mut DataPoint)>(&mut self, f: F) -> Result<()> {
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "cannot transform, only indexes are stored".to_string(),
+            ));
+        }
+        let (hashers, dim) = (&self.hashers, self.dim);
+        let ht = self.hash_tables.as_mut().unwrap();
+        let ids = ht.stored_ids();
+        let (old_hashes, new_vs) = ids
+            .iter()
+            .map(|&idx| {
+                let mut v = ht.idx_to_datapoint_owned(idx)?;
+                let old_hashes: Vec<Hash> = hashers.iter().map(|h| h.hash_vec_put(&v)).collect();
+                f(&mut v);
+                validate_dim(&v, dim)?;
+                Ok((old_hashes, v))
+            })
+            .collect::<Result<(Vec<_>, Vec<_>)>>()?;
+        // apply visits the data points in the order of `ids`.
+        let mut new_vs = new_vs.into_iter();
+        ht.apply(|_| new_vs.next().unwrap())?;
+
+        for (&idx, old_hashes) in ids.iter().zip(old_hashes) {
+            let v = ht.idx_to_datapoint_owned(idx)?;
+            for (i, (h, old_hash)) in hashers.iter().zip(old_hashes).enumerate() {
+                let new_hash = h.hash_vec_put(&v);
+                if new_hash != old_hash {
+                    ht.update_by_idx(&old_hash, new_hash, idx, i)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Switch to the [DeltaEncodedTable](struct.DeltaEncodedTable.html) backend, which stores
+    /// data points as deltas from the previously stored data point. Must be called before
+    /// storing data points.
+    ///
+    /// # Example
+    /// ```
+    /// use lsh_rs::LshMem;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap().delta_encoded().unwrap();
+    /// lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// lsh.query_bucket_owned(&[2., 3., 4.]).unwrap();
+    /// ```
+    pub fn delta_encoded(mut self) -> Result<LSH<DeltaEncodedTable, H>> {
+        let ht = self.hash_tables.as_ref().unwrap();
+        if ht.to_vec_of_buckets().iter().any(|tbl| !tbl.is_empty()) {
+            return Err(Error::Failed(
+                "delta encoding should be set before storing data points".to_string(),
+            ));
+        }
+        let ht = *DeltaEncodedTable::new(self.n_hash_tables, self.only_index_storage, "")?;
+        let hashers = std::mem::take(&mut self.hashers);
+        Ok(self.with_hash_tables(hashers, ht))
+    }
+
+    /// Export all stored data points as an `(n, dim)` matrix and the `n` parallel ids, ordered by
+    /// id. If only the indexes are stored, the matrix is empty and only the ids are returned.
+    /// See [MemoryTable::to_numpy_arrays](struct.MemoryTable.html#method.to_numpy_arrays).
+    pub fn export_to_arrays(&self) -> Result<(Array2<f32>, Array1<DataId>)> {
+        let (data, ids) = self.hash_tables.as_ref().unwrap().to_numpy_arrays();
+        if data.nrows() == 0 {
+            return Ok((Array2::zeros((0, self.dim)), ids));
+        }
+        Ok((data, ids))
+    }
+
+    /// Store the data points scalar quantized. Needs to be called before storing.
+    /// See [MemoryTable::with_scalar_quantization](struct.MemoryTable.html#method.with_scalar_quantization).
+    pub fn scalar_quantization(&mut self) -> Result<&mut Self> {
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .with_scalar_quantization()?;
+        Ok(self)
+    }
+
+    /// Skip the insertion in hash tables where the bucket already holds `max_bucket_size` ids.
+    /// See [MemoryTable::with_max_bucket_size](struct.MemoryTable.html#method.with_max_bucket_size).
+    ///
+    /// # Arguments
+    /// * `max_bucket_size` - Maximum number of ids in a bucket.
+    pub fn with_max_bucket_size(&mut self, max_bucket_size: usize) -> Result<&mut Self> {
+        self.hash_tables
+            .as_mut()
+            .unwrap()
+            .with_max_bucket_size(max_bucket_size)?;
+        Ok(self)
+    }
+
+    /// Same as [store_vec](struct.LSH.html#method.store_vec), but also reports in how many hash
+    /// tables the id was skipped because of a full bucket.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn store_vec_partial(
+        &mut self,
+        v: &DataPointSlice,
+    ) -> Result<(DataId, PartialInsertResult)> {
+        self.validate_vec(v)?;
+        let hashes: Vec<Hash> = self.hashers.iter().map(|h| h.hash_vec_put(v)).collect();
+        let ht = self.hash_tables.as_ref().unwrap();
+        let n_tables_skipped = hashes
+            .iter()
+            .enumerate()
+            .filter(|(i, hash)| ht.bucket_is_full(hash, *i))
+            .count();
+        let idx = self.store_hashed(v, hashes)?;
+        Ok((idx, PartialInsertResult { n_tables_skipped }))
+    }
+
+    /// Reassign ids contiguously after deletions. Returns the mapping from old id to new id.
+    pub fn defragment(&mut self) -> Result<HashMap<DataId, DataId>> {
+        Ok(self.hash_tables.as_mut().unwrap().defragment())
+    }
+}
+
+impl<H: VecHash + Clone> LSH<MemoryTable, H> {
+    /// Create a new LSH with the same hashers that only contains the given ids.
+    /// The data points are not re-hashed and keep their original ids.
+    ///
+    /// # Arguments
+    /// * `ids` - Ids of the data points that should be in the sub index.
+    pub fn sub_index(&self, ids: &[DataId]) -> Result<LSH<MemoryTable, H>> {
+        let ids: HashSet<DataId> = ids.iter().copied().collect();
+        let ht = self.hash_tables.as_ref().unwrap().sub_table(&ids);
+        Ok(self.with_hash_tables(self.hashers.clone(), ht))
+    }
+}
+
+impl<H: VecHash + Serialize + DeserializeOwned> LSH<MemoryTable, H> {
+    /// Create an in memory LSH from the hashers and buckets of an index built elsewhere, e.g. by
+    /// another library. See [MemoryTable::from_bucket_map](struct.MemoryTable.html#method.from_bucket_map).
+    ///
+    /// # Arguments
+    /// * `hashers` - Hasher per hash table.
+    /// * `tables` - Buckets per hash table.
+    /// * `data` - Data points by id.
+    pub fn from_bucket_map(
+        hashers: Vec<H>,
+        tables: Vec<HashMap<Hash, Bucket>>,
+        data: Vec<DataPoint>,
+    ) -> Result<Self> {
+        let (n_projections, dim) = match hashers.first() {
+            Some(h) => (h.n_projections(), h.dim()),
+            None => return Err(Error::Failed("no hashers given".to_string())),
+        };
+        if tables.len() != hashers.len() {
+            return Err(Error::Failed(format!(
+                "expected {} hash tables, got {}",
+                hashers.len(),
+                tables.len()
+            )));
+        }
+        if data.iter().any(|d| d.len() != dim) {
+            return Err(Error::Failed(format!(
+                "data points should have dimension {}",
+                dim
+            )));
+        }
+        let n_hash_tables = hashers.len();
+        let mut lsh = LSH::from_external_hashers(hashers, n_hash_tables, n_projections, dim)?;
+        lsh.hash_tables = Some(*MemoryTable::from_bucket_map(tables, data)?);
+        Ok(lsh)
+    }
+}
+
+impl<H: VecHash + Serialize> LSH<MemoryTable, H> {
+    /// Approximate memory usage per component. The hashers are measured by their serialized
+    /// size, the hash tables by the capacity of their hash maps and buckets.
+    ///
+    /// # Example
+    /// ```
+    /// use lsh_rs::LshMem;
+    /// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+    /// lsh.store_vec(&[2., 3., 4.]).unwrap();
+    /// let stats = lsh.memory_usage().unwrap();
+    /// assert!(stats.hasher_bytes >= 10 * 5 * 3 * 4);
+    /// ```
+    pub fn memory_usage(&self) -> Result<MemStats> {
+        let mut stats = self
+            .hash_tables
+            .as_ref()
+            .unwrap()
+            .approximate_memory_usage();
+        stats.hasher_bytes = bincode::serialized_size(&self.hashers)? as usize;
+        stats.total_bytes += stats.hasher_bytes;
+        Ok(stats)
+    }
+
+    /// Merge another in memory LSH into this one. Both need to have the same hashers, e.g. by
+    /// using the same seed. The ids of `other` are offset by the number of ids in `self`.
+    ///
+    /// # Arguments
+    /// * `other` - LSH that is merged into `self`.
+    pub fn union_with(&mut self, other: LSH<MemoryTable, H>) -> Result<()> {
+        if self.n_hash_tables != other.n_hash_tables
+            || self.n_projections != other.n_projections
+            || self.dim != other.dim
+            || bincode::serialize(&self.hashers)? != bincode::serialize(&other.hashers)?
+        {
+            return Err(Error::Failed("hashers are not compatible".to_string()));
+        }
+        let ht = self
+            .hash_tables
+            .as_ref()
+            .unwrap()
+            .union(other.hash_tables.as_ref().unwrap())?;
+        self.hash_tables.replace(ht);
+        Ok(())
+    }
+}
+
+/// Inconsistent bucket entry, see [verify_consistency](struct.LSH.html#method.verify_consistency).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsistencyError {
+    pub table_idx: usize,
+    /// Key of the bucket that holds the id.
+    pub bucket_hash: Hash,
+    pub id: DataId,
+    /// Hash of the stored data point. `None` if the id has no data point.
+    pub expected_hash: Option<Hash>,
+}
+
+/// Result of [store_vec_partial](struct.LSH.html#method.store_vec_partial).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialInsertResult {
+    /// Number of hash tables in which the id wasn't inserted, because the bucket was full.
+    pub n_tables_skipped: usize,
+}
+
+/// Result of [query_knn_with_guarantee](struct.LSH.html#method.query_knn_with_guarantee).
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryResult {
+    /// `(id, distance)` pairs sorted by distance. See
+    /// [DistanceMetric::distance](dist/enum.DistanceMetric.html#method.distance).
+    pub ids: Vec<(DataId, f32)>,
+    /// If the exact search over a random subset was needed.
+    pub used_fallback: bool,
+}
+
+/// Trace of a query in a single hash table. See [QueryTrace](struct.QueryTrace.html).
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableTrace {
+    /// Hash of the query in this table.
+    pub hash: Hash,
+    /// Sorted ids in the matching bucket. `None` if the bucket doesn't exist.
+    pub bucket: Option<Vec<DataId>>,
+}
+
+/// Per hash table trace of a query, made by
+/// [debug_query_trace](struct.LSH.html#method.debug_query_trace).
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryTrace {
+    pub tables: Vec<TableTrace>,
+}
+
+impl QueryTrace {
+    /// Ids of the hash tables in which the bucket doesn't exist.
+    pub fn missing_buckets(&self) -> Vec<usize> {
+        self.tables
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.bucket.is_none())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Ids of the hash tables in which the bucket exists but is empty, e.g. after deletion.
+    pub fn empty_buckets(&self) -> Vec<usize> {
+        self.tables
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.bucket.as_ref().is_some_and(|b| b.is_empty()))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Ids of the hash tables in which `idx` was found.
+    pub fn tables_containing(&self, idx: DataId) -> Vec<usize> {
+        self.tables
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.bucket.as_ref().is_some_and(|b| b.contains(&idx)))
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+/// Contribution of the probes of a multi-probe query, made by
+/// [query_bucket_ids_with_probe_stats](struct.LSH.html#method.query_bucket_ids_with_probe_stats).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueryProbeStats {
+    /// `(perturbation, n_new_candidates)` in probing order, summed over the hash tables. The
+    /// perturbation is the difference between the probed hash and the hash of the query, so the
+    /// first element (all zeros) are the candidates of the query's own buckets.
+    pub per_probe: Vec<(Vec<HashPrimitive>, usize)>,
+}
+
+impl QueryProbeStats {
+    pub(crate) fn record(&mut self, perturbation: Vec<HashPrimitive>, n_new_candidates: usize) {
+        match self.per_probe.iter_mut().find(|(p, _)| *p == perturbation) {
+            Some((_, n)) => *n += n_new_candidates,
+            None => self.per_probe.push((perturbation, n_new_candidates)),
+        }
+    }
+}
+
+/// Buckets as a CSR (compressed sparse row) matrix with a row per bucket and a column per id,
+/// made by [to_csr](struct.LSH.html#method.to_csr). The arrays can be passed to sparse matrix
+/// libraries, e.g. `sprs::CsMat::new((n_rows, n_cols), row_ptr, col_idx, vec![1.; nnz])`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsrMatrix {
+    /// Offsets of the rows in `col_idx`. Has `n_rows + 1` elements.
+    pub row_ptr: Vec<usize>,
+    /// Ids in the buckets. Sorted per row.
+    pub col_idx: Vec<DataId>,
+    /// Hash table of every row.
+    pub bucket_table_ids: Vec<usize>,
+    /// Number of columns: the largest id + 1.
+    pub n_cols: usize,
+}
+
+impl CsrMatrix {
+    /// Number of rows (buckets).
+    pub fn n_rows(&self) -> usize {
+        self.row_ptr.len() - 1
+    }
+
+    /// Number of stored entries.
+    pub fn nnz(&self) -> usize {
+        self.col_idx.len()
+    }
+
+    /// Ids in bucket `i`.
+    pub fn row(&self, i: usize) -> &[DataId] {
+        &self.col_idx[self.row_ptr[i]..self.row_ptr[i + 1]]
+    }
+}
+
+/// Start of the dumps of version 1 and later. Version 0 dumps start with the length of the
+/// serialized hash tables, which would have to exceed 4 GB to look like this header.
+const BLOB_MAGIC: &[u8; 4] = b"LSHB";
+/// Version of the `IntermediatBlob` that is written by `dump`.
+const BLOB_VERSION: u32 = 4;
+
 /// Intermediate data structure for serialization. Only contains the absolute
 /// necessities for reproducible results.
 #[derive(Serialize, Deserialize)]
 struct IntermediatBlob {
+    version: u32,
+    /// Layout of `hash_tables`, see `MemoryTable::decode`. 0 for the dumps before version 3.
+    table_version: u32,
+    hash_tables: Vec<u8>,
+    hashers: Vec<u8>,
+    n_hash_tables: usize,
+    n_projections: usize,
+    dim: usize,
+    _seed: u64,
+    only_index_storage: bool,
+    _multi_probe: bool,
+    _multi_probe_budget: usize,
+    /// See [sketched_query](struct.LSH.html#method.sketched_query). `None` for the dumps before
+    /// version 4.
+    table_sketch: Option<TableSketch>,
+    /// FNV-1a hash of the serialized bytes of all other fields. Serialized last, so that it
+    /// can be checked on the raw bytes of the dump.
+    checksum: u64,
+}
+
+/// Blob of the version 3 dumps, before `table_sketch` was added.
+#[derive(Deserialize)]
+struct IntermediatBlobV3 {
+    version: u32,
+    table_version: u32,
+    hash_tables: Vec<u8>,
+    hashers: Vec<u8>,
+    n_hash_tables: usize,
+    n_projections: usize,
+    dim: usize,
+    _seed: u64,
+    only_index_storage: bool,
+    _multi_probe: bool,
+    _multi_probe_budget: usize,
+    checksum: u64,
+}
+
+/// Blob of the version 2 dumps, before `table_version` was added.
+#[derive(Deserialize)]
+struct IntermediatBlobV2 {
+    version: u32,
+    hash_tables: Vec<u8>,
+    hashers: Vec<u8>,
+    n_hash_tables: usize,
+    n_projections: usize,
+    dim: usize,
+    _seed: u64,
+    only_index_storage: bool,
+    _multi_probe: bool,
+    _multi_probe_budget: usize,
+    checksum: u64,
+}
+
+/// Blob of the version 1 dumps, before `checksum` was added.
+#[derive(Deserialize)]
+struct IntermediatBlobV1 {
+    version: u32,
+    hash_tables: Vec<u8>,
+    hashers: Vec<u8>,
+    n_hash_tables: usize,
+    n_projections: usize,
+    dim: usize,
+    _seed: u64,
+    only_index_storage: bool,
+    _multi_probe: bool,
+    _multi_probe_budget: usize,
+}
+
+/// Unversioned blob of the dumps before `version` was added.
+#[derive(Deserialize)]
+struct IntermediatBlobV0 {
     hash_tables: Vec<u8>,
     hashers: Vec<u8>,
     n_hash_tables: usize,
@@ -550,43 +2951,274 @@ struct IntermediatBlob {
     _seed: u64,
 }
 
+impl From<IntermediatBlobV0> for IntermediatBlob {
+    fn from(ib: IntermediatBlobV0) -> Self {
+        IntermediatBlob {
+            version: BLOB_VERSION,
+            table_version: 0,
+            hash_tables: ib.hash_tables,
+            hashers: ib.hashers,
+            n_hash_tables: ib.n_hash_tables,
+            n_projections: ib.n_projections,
+            dim: ib.dim,
+            _seed: ib._seed,
+            only_index_storage: false,
+            _multi_probe: false,
+            _multi_probe_budget: 16,
+            table_sketch: None,
+            checksum: 0,
+        }
+    }
+}
+
+impl From<IntermediatBlobV3> for IntermediatBlob {
+    fn from(ib: IntermediatBlobV3) -> Self {
+        IntermediatBlob {
+            version: BLOB_VERSION,
+            table_version: ib.table_version,
+            hash_tables: ib.hash_tables,
+            hashers: ib.hashers,
+            n_hash_tables: ib.n_hash_tables,
+            n_projections: ib.n_projections,
+            dim: ib.dim,
+            _seed: ib._seed,
+            only_index_storage: ib.only_index_storage,
+            _multi_probe: ib._multi_probe,
+            _multi_probe_budget: ib._multi_probe_budget,
+            table_sketch: None,
+            checksum: 0,
+        }
+    }
+}
+
+impl From<IntermediatBlobV2> for IntermediatBlob {
+    fn from(ib: IntermediatBlobV2) -> Self {
+        IntermediatBlob {
+            version: BLOB_VERSION,
+            table_version: 0,
+            hash_tables: ib.hash_tables,
+            hashers: ib.hashers,
+            n_hash_tables: ib.n_hash_tables,
+            n_projections: ib.n_projections,
+            dim: ib.dim,
+            _seed: ib._seed,
+            only_index_storage: ib.only_index_storage,
+            _multi_probe: ib._multi_probe,
+            _multi_probe_budget: ib._multi_probe_budget,
+            table_sketch: None,
+            checksum: 0,
+        }
+    }
+}
+
+impl From<IntermediatBlobV1> for IntermediatBlob {
+    fn from(ib: IntermediatBlobV1) -> Self {
+        IntermediatBlob {
+            version: BLOB_VERSION,
+            table_version: 0,
+            hash_tables: ib.hash_tables,
+            hashers: ib.hashers,
+            n_hash_tables: ib.n_hash_tables,
+            n_projections: ib.n_projections,
+            dim: ib.dim,
+            _seed: ib._seed,
+            only_index_storage: ib.only_index_storage,
+            _multi_probe: ib._multi_probe,
+            _multi_probe_budget: ib._multi_probe_budget,
+            table_sketch: None,
+            checksum: 0,
+        }
+    }
+}
+
+fn blob_checksum(bytes: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+impl IntermediatBlob {
+    /// Deserialize a dump of any version and migrate it to the current version.
+    fn decode(buf: &[u8]) -> Result<IntermediatBlob> {
+        if !buf.starts_with(BLOB_MAGIC) {
+            let ib: IntermediatBlobV0 = bincode::deserialize(buf)?;
+            return Ok(ib.into());
+        }
+        let buf = &buf[BLOB_MAGIC.len()..];
+        let version: u32 = bincode::deserialize(buf)?;
+        match version {
+            1 => {
+                let ib: IntermediatBlobV1 = bincode::deserialize(buf)?;
+                Ok(ib.into())
+            }
+            2 => {
+                if !IntermediatBlob::checksum_matches(buf) {
+                    return Err(Error::Failed("checksum mismatch".to_string()));
+                }
+                let ib: IntermediatBlobV2 = bincode::deserialize(buf)?;
+                Ok(ib.into())
+            }
+            3 => {
+                if !IntermediatBlob::checksum_matches(buf) {
+                    return Err(Error::Failed("checksum mismatch".to_string()));
+                }
+                let ib: IntermediatBlobV3 = bincode::deserialize(buf)?;
+                Ok(ib.into())
+            }
+            BLOB_VERSION => {
+                if !IntermediatBlob::checksum_matches(buf) {
+                    return Err(Error::Failed("checksum mismatch".to_string()));
+                }
+                Ok(bincode::deserialize(buf)?)
+            }
+            v => Err(Error::Failed(format!(
+                "dump version {} is not supported, the latest version is {}",
+                v, BLOB_VERSION
+            ))),
+        }
+    }
+
+    /// Check the trailing checksum of a serialized blob of the current version.
+    fn checksum_matches(buf: &[u8]) -> bool {
+        if buf.len() < 8 {
+            return false;
+        }
+        let (body, checksum) = buf.split_at(buf.len() - 8);
+        checksum == blob_checksum(body).to_le_bytes()
+    }
+
+    /// Check whether a dump of any version can be loaded, without deserializing the tables.
+    fn verify(buf: &[u8]) -> Result<bool> {
+        if !buf.starts_with(BLOB_MAGIC) {
+            return Ok(bincode::deserialize::<IntermediatBlobV0>(buf).is_ok());
+        }
+        let buf = &buf[BLOB_MAGIC.len()..];
+        match bincode::deserialize::<u32>(buf) {
+            Ok(1) => Ok(bincode::deserialize::<IntermediatBlobV1>(buf).is_ok()),
+            Ok(2) | Ok(3) | Ok(BLOB_VERSION) => Ok(IntermediatBlob::checksum_matches(buf)),
+            Ok(v) => Err(Error::Failed(format!(
+                "dump version {} is not supported, the latest version is {}",
+                v, BLOB_VERSION
+            ))),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Serialize with the magic header and the checksum.
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = BLOB_MAGIC.to_vec();
+        bytes.extend(bincode::serialize(self)?);
+        // bincode writes the checksum as the last 8 little endian bytes.
+        let n = bytes.len() - 8;
+        let checksum = blob_checksum(&bytes[BLOB_MAGIC.len()..n]);
+        bytes[n..].copy_from_slice(&checksum.to_le_bytes());
+        Ok(bytes)
+    }
+
+    fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut f = File::create(path)?;
+        f.write_all(&self.to_bytes()?)?;
+        Ok(())
+    }
+}
+
 impl<H> LSH<MemoryTable, H>
 where
     H: Serialize + DeserializeOwned + VecHash,
 {
-    /// Deserialize MemoryTable backend
+    /// Deserialize MemoryTable backend. Dumps of older versions are migrated.
     pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
         let mut f = File::open(path)?;
         let mut buf: Vec<u8> = vec![];
         f.read_to_end(&mut buf)?;
 
-        let ib: IntermediatBlob = bincode::deserialize(&buf)?;
+        let ib = IntermediatBlob::decode(&buf)?;
         self.hashers = bincode::deserialize(&ib.hashers)?;
-        self.hash_tables = bincode::deserialize(&ib.hash_tables)?;
+        self.hash_tables = MemoryTable::decode(&ib.hash_tables, ib.table_version)?;
         self.n_hash_tables = ib.n_hash_tables;
         self.n_projections = ib.n_projections;
         self.dim = ib.dim;
         self._seed = ib._seed;
+        self.only_index_storage = ib.only_index_storage;
+        self._multi_probe = ib._multi_probe;
+        self._multi_probe_budget = ib._multi_probe_budget;
+        self._table_sketch = ib.table_sketch;
 
         Ok(())
     }
 
+    /// Rewrite a dump of an older version in the current format. The migrated dump is loaded
+    /// again and compared before it replaces the old dump, which is left untouched on error.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the dump.
+    pub fn migrate_dump<P: AsRef<Path>>(path: P) -> Result<()> {
+        let path = path.as_ref();
+        let ib = IntermediatBlob::decode(&std::fs::read(path)?)?;
+        let hash_tables = MemoryTable::decode(&ib.hash_tables, ib.table_version)?;
+        let hashers: Vec<H> = bincode::deserialize(&ib.hashers)?;
+        let migrated = IntermediatBlob {
+            table_version: MEMORY_TABLE_VERSION,
+            hash_tables: bincode::serialize(&hash_tables)?,
+            hashers: bincode::serialize(&hashers)?,
+            ..ib
+        };
+        let bytes = migrated.to_bytes()?;
+
+        let ib = IntermediatBlob::decode(&bytes)?;
+        let hashers: Vec<H> = bincode::deserialize(&ib.hashers)?;
+        if ib.table_version != MEMORY_TABLE_VERSION
+            || MemoryTable::decode(&ib.hash_tables, ib.table_version)? != hash_tables
+            || bincode::serialize(&hashers)? != migrated.hashers
+            || (ib.n_hash_tables, ib.n_projections, ib.dim, ib._seed)
+                != (
+                    migrated.n_hash_tables,
+                    migrated.n_projections,
+                    migrated.dim,
+                    migrated._seed,
+                )
+        {
+            return Err(Error::Failed(
+                "the migrated dump doesn't round trip".to_string(),
+            ));
+        }
+        // write next to the old dump and rename, so that it is replaced atomically.
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".migrate");
+        std::fs::write(&tmp, &bytes)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Check the integrity of a dump without loading it. Dumps of older versions have no
+    /// checksum, they are only checked to deserialize.
+    ///
+    /// # Arguments
+    /// * `path` - Path of the dump.
+    pub fn verify_dump<P: AsRef<Path>>(path: P) -> Result<bool> {
+        IntermediatBlob::verify(&std::fs::read(path)?)
+    }
+
     /// Serialize MemoryTable backend
     pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let hash_tables = bincode::serialize(&self.hash_tables)?;
         let hashers = bincode::serialize(&self.hashers)?;
 
         let ib = IntermediatBlob {
+            version: BLOB_VERSION,
+            table_version: MEMORY_TABLE_VERSION,
             hash_tables,
             hashers,
             n_hash_tables: self.n_hash_tables,
             n_projections: self.n_projections,
             dim: self.dim,
             _seed: self._seed,
+            only_index_storage: self.only_index_storage,
+            _multi_probe: self._multi_probe,
+            _multi_probe_budget: self._multi_probe_budget,
+            table_sketch: self._table_sketch.clone(),
+            checksum: 0,
         };
-        let mut f = File::create(path)?;
-        let blob = bincode::serialize(&ib)?;
-        f.write(&blob)?;
-        Ok(())
+        ib.write(path)
     }
 }
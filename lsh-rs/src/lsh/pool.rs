@@ -0,0 +1,142 @@
+use super::lsh::{validate_dim, LSH};
+use crate::{DataId, DataPoint, Error, HashTables, Result, VecHash};
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+type Job = (DataPoint, Sender<Result<DataId>>);
+
+/// Multi-threaded insertion for [LSH](struct.LSH.html) with a pool of worker threads.
+///
+/// The workers compute the hashes concurrently, which is the expensive part of storing a data
+/// point. The hash tables are behind a lock, so a worker holds it only while it puts the hashes
+/// of one data point in all `L` hash tables.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::{ConcurrentInsertPool, LshMem};
+/// let lsh = LshMem::new(5, 10, 3).srp().unwrap();
+/// let pool = ConcurrentInsertPool::new(lsh, 4).unwrap();
+/// let handle = pool.submit(vec![2., 3., 4.]).unwrap();
+/// let id = handle.recv().unwrap().unwrap();
+/// let lsh = pool.into_inner();
+/// assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&id));
+/// ```
+pub struct ConcurrentInsertPool<T: HashTables, H: VecHash> {
+    lsh: Arc<Mutex<LSH<T, H>>>,
+    jobs: Sender<Job>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl<T, H> ConcurrentInsertPool<T, H>
+where
+    T: HashTables + Send + 'static,
+    H: VecHash + Clone + Send + Sync + 'static,
+{
+    /// Start `n_workers` threads that store the submitted data points in `lsh`.
+    ///
+    /// # Arguments
+    /// * `lsh` - LSH that stores the data points.
+    /// * `n_workers` - Number of worker threads.
+    pub fn new(lsh: LSH<T, H>, n_workers: usize) -> Result<Self> {
+        if n_workers == 0 {
+            return Err(Error::Failed("n_workers should be positive".to_string()));
+        }
+        let hashers = Arc::new(lsh.hashers.clone());
+        let dim = lsh.dim;
+        let lsh = Arc::new(Mutex::new(lsh));
+        let (jobs, rx): (Sender<Job>, Receiver<Job>) = unbounded();
+        let workers = (0..n_workers)
+            .map(|_| {
+                let (rx, hashers, lsh) = (rx.clone(), hashers.clone(), lsh.clone());
+                std::thread::spawn(move || {
+                    for (v, result) in rx {
+                        let id = validate_dim(&v, dim).and_then(|_| {
+                            let hashes = hashers.iter().map(|h| h.hash_vec_put(&v)).collect();
+                            lsh.lock()
+                                .map_err(|_| {
+                                    Error::Failed("a worker thread panicked.".to_string())
+                                })?
+                                .store_hashed(&v, hashes)
+                        });
+                        // the caller may have dropped the handle.
+                        result.send(id).unwrap_or_default();
+                    }
+                })
+            })
+            .collect();
+        Ok(ConcurrentInsertPool { lsh, jobs, workers })
+    }
+
+    /// Queue a data point for insertion. Returns a handle that receives the id once the data
+    /// point is stored. Fails if the worker threads stopped.
+    ///
+    /// # Arguments
+    /// * `v` - Data point.
+    pub fn submit(&self, v: DataPoint) -> Result<Receiver<Result<DataId>>> {
+        let (tx, rx) = bounded(1);
+        self.jobs
+            .send((v, tx))
+            .map_err(|_| Error::Failed("the worker threads stopped.".to_string()))?;
+        Ok(rx)
+    }
+
+    /// Wait until all submitted data points are stored and return the LSH.
+    pub fn into_inner(self) -> LSH<T, H> {
+        drop(self.jobs);
+        for worker in self.workers {
+            worker.join().expect("a worker thread panicked.");
+        }
+        match Arc::try_unwrap(self.lsh) {
+            Ok(lsh) => lsh.into_inner().unwrap(),
+            Err(_) => unreachable!("the worker threads are joined"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LshMem;
+
+    #[test]
+    fn test_concurrent_insert_pool() {
+        let lsh = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+        let pool = ConcurrentInsertPool::new(lsh, 4).unwrap();
+        let vs: Vec<DataPoint> = (0..100)
+            .map(|i| vec![(i as f32).sin(), (i as f32).cos(), i as f32])
+            .collect();
+        let handles: Vec<_> = vs.iter().map(|v| pool.submit(v.clone()).unwrap()).collect();
+        let ids: Vec<DataId> = handles
+            .into_iter()
+            .map(|h| h.recv().unwrap().unwrap())
+            .collect();
+        assert!(pool.submit(vec![1., 2.]).unwrap().recv().unwrap().is_err());
+
+        let mut sorted = ids.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..100).collect::<Vec<DataId>>());
+        let lsh = pool.into_inner();
+        let ht = lsh.hash_tables.as_ref().unwrap();
+        for (id, v) in ids.iter().zip(&vs) {
+            assert_eq!(ht.idx_to_datapoint(*id).unwrap(), v);
+            assert!(lsh.query_bucket_ids(v).unwrap().contains(id));
+        }
+        assert!(ConcurrentInsertPool::new(LshMem::new(5, 4, 3).srp().unwrap(), 0).is_err());
+
+        // a poisoned lock fails the insertions instead of the worker threads.
+        let pool = ConcurrentInsertPool::new(LshMem::new(5, 4, 3).srp().unwrap(), 2).unwrap();
+        let lsh = pool.lsh.clone();
+        std::thread::spawn(move || {
+            let _guard = lsh.lock().unwrap();
+            panic!("poison the lock");
+        })
+        .join()
+        .unwrap_err();
+        for _ in 0..4 {
+            let handle = pool.submit(vec![1., 2., 3.]).unwrap();
+            assert!(matches!(handle.recv().unwrap(), Err(Error::Failed(_))));
+        }
+    }
+}
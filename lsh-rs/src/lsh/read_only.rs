@@ -0,0 +1,120 @@
+use super::lsh::LSH;
+use crate::{DataId, DataPoint, DataPointSlice, MemoryTable, Result, VecHash};
+
+/// Frozen [LSH](struct.LSH.html) that can only be queried, e.g. after the index is built.
+///
+/// A `ReadOnlyLsh` can't be mutated and the `MemoryTable` has no interior mutability, so it is
+/// `Send + Sync` if the hashers are. It can be shared between threads with an `Arc`, without
+/// a `RwLock`.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::LshMem;
+/// use std::sync::Arc;
+/// let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+/// let id = lsh.store_vec(&[2., 3., 4.]).unwrap();
+/// let ro = Arc::new(lsh.into_read_only());
+/// let handle = {
+///     let ro = ro.clone();
+///     std::thread::spawn(move || ro.query_bucket_ids(&[2., 3., 4.]).unwrap())
+/// };
+/// assert!(handle.join().unwrap().contains(&id));
+/// ```
+pub struct ReadOnlyLsh<H: VecHash> {
+    /// Invariant: `lsh.hash_tables` is `Some`.
+    lsh: LSH<MemoryTable, H>,
+}
+
+impl<H: VecHash> LSH<MemoryTable, H> {
+    /// Freeze the index. See [ReadOnlyLsh](struct.ReadOnlyLsh.html).
+    pub fn into_read_only(self) -> ReadOnlyLsh<H> {
+        assert!(self.hash_tables.is_some(), "LSH has no hash tables");
+        ReadOnlyLsh { lsh: self }
+    }
+}
+
+impl<H: VecHash> ReadOnlyLsh<H> {
+    /// Dimension of the data points.
+    pub fn dim(&self) -> usize {
+        self.lsh.dim
+    }
+
+    /// The hash tables.
+    pub fn table(&self) -> &MemoryTable {
+        self.lsh.hash_tables.as_ref().unwrap()
+    }
+
+    /// Frozen LSH. Only its `&self` methods can be used, e.g. the other `query_*` methods.
+    pub fn as_lsh(&self) -> &LSH<MemoryTable, H> {
+        &self.lsh
+    }
+
+    /// See [LSH::query_bucket](struct.LSH.html#method.query_bucket).
+    pub fn query_bucket(&self, v: &DataPointSlice) -> Result<Vec<&DataPoint>> {
+        self.lsh.query_bucket(v)
+    }
+
+    /// See [LSH::query_bucket_owned](struct.LSH.html#method.query_bucket_owned).
+    pub fn query_bucket_owned(&self, v: &DataPointSlice) -> Result<Vec<DataPoint>> {
+        self.lsh.query_bucket_owned(v)
+    }
+
+    /// See [LSH::query_bucket_ids](struct.LSH.html#method.query_bucket_ids).
+    pub fn query_bucket_ids(&self, v: &DataPointSlice) -> Result<Vec<DataId>> {
+        self.lsh.query_bucket_ids(v)
+    }
+
+    /// See [LSH::query_bucket_ids_batch](struct.LSH.html#method.query_bucket_ids_batch).
+    pub fn query_bucket_ids_batch(&self, vs: &[DataPoint]) -> Result<Vec<Vec<DataId>>> {
+        self.lsh.query_bucket_ids_batch(vs)
+    }
+
+    /// Unfreeze the index.
+    pub fn into_inner(self) -> LSH<MemoryTable, H> {
+        self.lsh
+    }
+}
+
+impl<H: VecHash + Sync> ReadOnlyLsh<H> {
+    /// See [LSH::query_bucket_ids_batch_par](struct.LSH.html#method.query_bucket_ids_batch_par).
+    pub fn query_bucket_ids_batch_par(&self, vs: &[DataPoint]) -> Result<Vec<Vec<DataId>>> {
+        self.lsh.query_bucket_ids_batch_par(vs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{LshMem, SignRandomProjections};
+    use rayon::prelude::*;
+    use std::sync::Arc;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_parallel_queries() {
+        assert_send_sync::<ReadOnlyLsh<SignRandomProjections>>();
+        let vs: Vec<DataPoint> = (0..100)
+            .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+            .collect();
+        let mut lsh = LshMem::new(6, 4, 3).seed(1).srp().unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        let expected = lsh.query_bucket_ids_batch(&vs).unwrap();
+
+        let ro = Arc::new(lsh.into_read_only());
+        let results: Vec<Vec<DataId>> = (0..100)
+            .into_par_iter()
+            .map(|i| {
+                let ro = ro.clone();
+                ro.query_bucket_ids(&vs[i]).unwrap()
+            })
+            .collect();
+        assert_eq!(results, expected);
+        assert_eq!(ro.query_bucket_ids_batch_par(&vs).unwrap(), expected);
+        assert_eq!(ro.dim(), 3);
+
+        let lsh = Arc::try_unwrap(ro).ok().unwrap().into_inner();
+        assert_eq!(lsh.query_bucket_ids_batch(&vs).unwrap(), expected);
+    }
+}
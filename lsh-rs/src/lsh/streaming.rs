@@ -0,0 +1,175 @@
+use super::lsh::LSH;
+use crate::{DataId, DataPoint, Error, Hash, HashTables, Result, VecHash};
+
+impl<T: HashTables, H: VecHash> LSH<T, H> {
+    /// Store the data points of an iterator one at a time. Unlike
+    /// [store_vecs](struct.LSH.html#method.store_vecs) the total number of data points doesn't
+    /// need to be known, so no storage is allocated upfront. Returns the ids.
+    ///
+    /// # Arguments
+    /// * `iter` - Data points.
+    pub fn stream_store(&mut self, iter: impl Iterator<Item = DataPoint>) -> Result<Vec<DataId>> {
+        iter.map(|v| self.store_vec(&v)).collect()
+    }
+}
+
+/// Streaming ingestion for [LSH](struct.LSH.html) with optional backpressure.
+///
+/// Data points are pulled from an iterator and stored one at a time. If a maximum bucket load is
+/// set, pulling stops at the first data point that would land in a bucket that already holds
+/// that many ids. That data point isn't stored but kept as [pending](#method.pending) and the
+/// remaining data points stay in the iterator, so the caller can act (e.g. re-tune the
+/// parameters) and continue. The next call to [store](#method.store) starts with the pending
+/// data point.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::{LshMem, StreamingLSH};
+/// let lsh = LshMem::new(5, 5, 3).srp().unwrap();
+/// let mut stream = StreamingLSH::new(lsh);
+/// stream.max_bucket_load(1000);
+/// let mut vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.]].into_iter();
+/// let ids = stream.store(&mut vs).unwrap();
+/// assert_eq!(ids.len(), 2);
+/// ```
+pub struct StreamingLSH<T: HashTables, H: VecHash> {
+    lsh: LSH<T, H>,
+    max_bucket_load: Option<usize>,
+    backpressure: bool,
+    pending: Option<DataPoint>,
+}
+
+impl<T: HashTables, H: VecHash> StreamingLSH<T, H> {
+    pub fn new(lsh: LSH<T, H>) -> Self {
+        StreamingLSH {
+            lsh,
+            max_bucket_load: None,
+            backpressure: false,
+            pending: None,
+        }
+    }
+
+    /// Stop pulling data points when a bucket reaches `max_bucket_load` ids.
+    ///
+    /// # Arguments
+    /// * `max_bucket_load` - Maximum number of ids in a bucket.
+    pub fn max_bucket_load(&mut self, max_bucket_load: usize) -> &mut Self {
+        self.max_bucket_load = Some(max_bucket_load);
+        self
+    }
+
+    /// If the last call to [store](struct.StreamingLSH.html#method.store) stopped due to a full
+    /// bucket.
+    pub fn backpressure(&self) -> bool {
+        self.backpressure
+    }
+
+    /// Data point that wasn't stored by the last call to [store](#method.store) because
+    /// one of its buckets is full.
+    pub fn pending(&self) -> Option<&DataPoint> {
+        self.pending.as_ref()
+    }
+
+    /// Take the pending data point, so that it isn't stored by the next call to
+    /// [store](#method.store).
+    pub fn take_pending(&mut self) -> Option<DataPoint> {
+        self.pending.take()
+    }
+
+    fn bucket_full(&self, hashes: &[Hash]) -> Result<bool> {
+        let max = match self.max_bucket_load {
+            None => return Ok(false),
+            Some(max) => max,
+        };
+        let ht = self.lsh.hash_tables.as_ref().unwrap();
+        for (i, hash) in hashes.iter().enumerate() {
+            match ht.query_bucket(hash, i) {
+                Ok(bucket) if bucket.len() >= max => return Ok(true),
+                Ok(_) | Err(Error::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Store the pending data point and pull and store data points until `iter` is exhausted
+    /// or a bucket is full. Returns the ids of the stored data points.
+    ///
+    /// # Arguments
+    /// * `iter` - Data points.
+    pub fn store<I: Iterator<Item = DataPoint>>(&mut self, iter: &mut I) -> Result<Vec<DataId>> {
+        self.backpressure = false;
+        let mut ids = vec![];
+        let pending = self.pending.take();
+        for v in pending.into_iter().chain(iter) {
+            self.lsh.validate_vec(&v)?;
+            let hashes: Vec<Hash> = self
+                .lsh
+                .hashers
+                .iter()
+                .map(|h| h.hash_vec_put(&v))
+                .collect();
+            if self.bucket_full(&hashes)? {
+                self.pending = Some(v);
+                self.backpressure = true;
+                break;
+            }
+            ids.push(self.lsh.store_hashed(&v, hashes)?);
+        }
+        Ok(ids)
+    }
+
+    pub fn lsh(&self) -> &LSH<T, H> {
+        &self.lsh
+    }
+
+    pub fn into_inner(self) -> LSH<T, H> {
+        self.lsh
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LshMem;
+
+    #[test]
+    fn test_streaming() {
+        let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+        let ids = lsh
+            .stream_store((0..10).map(|i| vec![i as f32, 1., -1.]))
+            .unwrap();
+        assert_eq!(ids, (0..10).collect::<Vec<_>>());
+
+        let mut stream = StreamingLSH::new(LshMem::new(5, 3, 3).seed(1).srp().unwrap());
+        stream.max_bucket_load(2);
+        // all data points are equal and end up in the same buckets.
+        let mut vs = (0..10).map(|_| vec![1., 2., 3.]);
+        assert_eq!(stream.store(&mut vs).unwrap(), vec![0, 1]);
+        assert!(stream.backpressure());
+        // the third data point isn't stored.
+        assert_eq!(vs.len(), 7);
+        assert_eq!(stream.pending(), Some(&vec![1., 2., 3.]));
+        assert!(stream.store(&mut vs).unwrap().is_empty());
+        assert!(stream.backpressure());
+        assert_eq!(vs.len(), 7);
+
+        // the pending data point is stored first.
+        stream.max_bucket_load(3);
+        assert_eq!(stream.store(&mut vs).unwrap(), vec![2]);
+        assert_eq!(vs.len(), 6);
+        assert_eq!(stream.take_pending(), Some(vec![1., 2., 3.]));
+        let mut other = vec![vec![-1., -2., -3.]].into_iter();
+        assert_eq!(stream.store(&mut other).unwrap(), vec![3]);
+        assert!(!stream.backpressure());
+        assert_eq!(
+            stream
+                .into_inner()
+                .query_bucket_ids(&[1., 2., 3.])
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+}
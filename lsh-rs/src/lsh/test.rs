@@ -1,6 +1,6 @@
 #![cfg(test)]
 use super::lsh::*;
-use crate::MemoryTable;
+use crate::{DataId, Error, HashTables, MemoryTable, SignRandomProjections, VecHash};
 
 #[test]
 fn test_hash_table() {
@@ -9,7 +9,7 @@ fn test_hash_table() {
     let v2 = &[-1., -1., 1.];
     lsh.store_vec(v1).unwrap();
     lsh.store_vec(v2).unwrap();
-    assert!(lsh.query_bucket(v2).unwrap().len() > 0);
+    assert!(!lsh.query_bucket(v2).unwrap().is_empty());
 
     let bucket_len_before = lsh.query_bucket(v1).unwrap().len();
     lsh.delete_vec(v1).unwrap();
@@ -50,7 +50,262 @@ fn test_serialization() {
     println!("{:?}", lsh.hash_tables)
 }
 
+/// Data points of the dumps in `tests/fixtures`, written by lsh-rs 0.2.3 with
+/// `LshMem::new(5, 3, 3).seed(1)` and `store_vecs`. The MIPS hashers were fitted on all points.
+const FIXTURE_VS: [[f32; 3]; 4] = [[2., 3., 4.], [-1., -1., 1.], [0., 1., 0.], [0.5, 0.2, -0.3]];
+
+fn fixture(name: &str) -> std::path::PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "fixtures", name]
+        .iter()
+        .collect()
+}
+
+fn sorted_fixture_ids<H: VecHash>(lsh: &LSH<MemoryTable, H>) -> Vec<Vec<DataId>> {
+    FIXTURE_VS
+        .iter()
+        .map(|v| {
+            let mut ids = lsh.query_bucket_ids(v).unwrap();
+            ids.sort_unstable();
+            ids
+        })
+        .collect()
+}
+
+#[test]
+fn test_load_v0_dump() {
+    // the query results of lsh-rs 0.2.3.
+    let srp_ids = vec![vec![0, 2], vec![1], vec![0, 2], vec![3]];
+    let mips_ids = vec![vec![0], vec![], vec![], vec![]];
+
+    let mut lsh = LshMem::new(5, 3, 3).srp().unwrap();
+    lsh.load(fixture("dump_v0_srp.bin")).unwrap();
+    assert_eq!(sorted_fixture_ids(&lsh), srp_ids);
+    assert_eq!(
+        lsh.query_bucket(&FIXTURE_VS[1]).unwrap(),
+        vec![&FIXTURE_VS[1]]
+    );
+
+    let mut lsh = LshMem::new(5, 3, 3).mips(2.2, 0.83, 3).unwrap();
+    lsh.load(fixture("dump_v0_mips.bin")).unwrap();
+    assert_eq!(sorted_fixture_ids(&lsh), mips_ids);
+}
+
+#[test]
+fn test_migrate_dump() {
+    let dir = tempfile::tempdir().unwrap();
+    let tmp = dir.path().join("srp.bincode");
+    std::fs::copy(fixture("dump_v0_srp.bin"), &tmp).unwrap();
+    assert!(LshMem::<SignRandomProjections>::verify_dump(&tmp).unwrap());
+
+    LshMem::<SignRandomProjections>::migrate_dump(&tmp).unwrap();
+    assert!(std::fs::read(&tmp).unwrap().starts_with(b"LSHB"));
+    assert!(LshMem::<SignRandomProjections>::verify_dump(&tmp).unwrap());
+    let mut migrated = LshMem::new(5, 3, 3).srp().unwrap();
+    migrated.multi_probe(4);
+    migrated.load(&tmp).unwrap();
+    assert_eq!(
+        sorted_fixture_ids(&migrated),
+        vec![vec![0, 2], vec![1], vec![0, 2], vec![3]]
+    );
+    // missing fields are filled with the defaults.
+    assert_eq!(migrated._multi_probe_budget, 16);
+    // the id counter is migrated as well.
+    assert_eq!(migrated.store_vec(&[1., 1., 1.]).unwrap(), 4);
+
+    // migrating a migrated dump doesn't change it.
+    let buf = std::fs::read(&tmp).unwrap();
+    LshMem::<SignRandomProjections>::migrate_dump(&tmp).unwrap();
+    assert_eq!(std::fs::read(&tmp).unwrap(), buf);
+
+    let tmp = dir.path().join("mips.bincode");
+    std::fs::copy(fixture("dump_v0_mips.bin"), &tmp).unwrap();
+    LshMem::<crate::MIPS>::migrate_dump(&tmp).unwrap();
+    let mut migrated = LshMem::new(5, 3, 3).mips(2.2, 0.83, 3).unwrap();
+    migrated.load(&tmp).unwrap();
+    assert_eq!(
+        sorted_fixture_ids(&migrated),
+        vec![vec![0], vec![], vec![], vec![]]
+    );
+
+    // a dump that can't be migrated is left untouched.
+    std::fs::write(&tmp, b"LSHB\x07\x00\x00\x00").unwrap();
+    assert!(migrated.load(&tmp).is_err());
+    assert!(LshMem::<crate::MIPS>::migrate_dump(&tmp).is_err());
+    assert_eq!(std::fs::read(&tmp).unwrap(), b"LSHB\x07\x00\x00\x00");
+}
+
+#[test]
+fn test_dump_checksum() {
+    let mut lsh: LSH<MemoryTable, _> = LSH::new(5, 9, 3).seed(1).srp().unwrap();
+    let v1 = &[2., 3., 4.];
+    lsh.store_vec(v1).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let tmp = dir.path().join("checksum.bincode");
+    lsh.dump(&tmp).unwrap();
+    assert!(LshMem::<SignRandomProjections>::verify_dump(&tmp).unwrap());
+
+    let mut buf = std::fs::read(&tmp).unwrap();
+    let mid = buf.len() / 2;
+    buf[mid] ^= 1;
+    std::fs::write(&tmp, &buf).unwrap();
+    assert!(!LshMem::<SignRandomProjections>::verify_dump(&tmp).unwrap());
+    match lsh.load(&tmp) {
+        Err(Error::Failed(msg)) => assert_eq!(msg, "checksum mismatch"),
+        _ => panic!("corrupted dump should not load"),
+    }
+
+    // version 1 dumps have no checksum.
+    let v1_blob = (
+        1u32,
+        bincode::serialize(&lsh.hash_tables).unwrap(),
+        bincode::serialize(&lsh.hashers).unwrap(),
+        lsh.n_hash_tables,
+        lsh.n_projections,
+        lsh.dim,
+        1u64,
+        false,
+        false,
+        16usize,
+    );
+    let mut buf = b"LSHB".to_vec();
+    buf.extend(bincode::serialize(&v1_blob).unwrap());
+    std::fs::write(&tmp, &buf).unwrap();
+    assert!(LshMem::<SignRandomProjections>::verify_dump(&tmp).unwrap());
+    let mut loaded: LSH<MemoryTable, _> = LSH::new(5, 9, 3).srp().unwrap();
+    loaded.load(&tmp).unwrap();
+    assert_eq!(loaded.query_bucket_ids(v1).unwrap(), vec![0]);
+}
+
 #[test]
+fn test_query_bucket_ids_negated() {
+    let vs: Vec<Vec<f32>> = (0..50)
+        .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+        .collect();
+    let q = [0.3, 0.9, 0.5];
+    let mut mem = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    #[cfg(feature = "sqlite")]
+    let mut sql = LshSqlMem::new(5, 3, 3).seed(1).srp().unwrap();
+    mem.store_vecs(&vs).unwrap();
+    #[cfg(feature = "sqlite")]
+    sql.store_vecs(&vs).unwrap();
+
+    let hits = mem.query_bucket_ids(&q).unwrap();
+    let negated = mem.query_bucket_ids_negated(&q).unwrap();
+    assert!(!hits.is_empty() && !negated.is_empty());
+    assert_eq!(hits.len() + negated.len(), vs.len());
+    assert!(negated.iter().all(|id| !hits.contains(id)));
+    #[cfg(feature = "sqlite")]
+    assert_eq!(sql.query_bucket_ids_negated(&q).unwrap(), negated);
+
+    // ids that are skipped in the first hash table are still found in the others.
+    let mut capped = LshMem::new(2, 3, 3).seed(1).srp().unwrap();
+    capped.with_max_bucket_size(4).unwrap();
+    capped.store_vecs(&vs).unwrap();
+    let mut in_any: Vec<DataId> = capped
+        .hash_tables
+        .as_ref()
+        .unwrap()
+        .to_bucket_map()
+        .unwrap()
+        .into_iter()
+        .flat_map(|tbl| tbl.into_values().flatten())
+        .collect();
+    in_any.sort_unstable();
+    in_any.dedup();
+    let mut all = capped.query_bucket_ids(&q).unwrap();
+    all.extend(capped.query_bucket_ids_negated(&q).unwrap());
+    all.sort_unstable();
+    assert_eq!(all, in_any);
+}
+
+#[test]
+fn test_store_sequence() {
+    let seq: Vec<Vec<f32>> = (0..5).map(|i| vec![i as f32, 1., -(i as f32)]).collect();
+    let mut lsh = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+    assert!(lsh.store_sequence(&seq, 0).is_err());
+    // 4 pairs at distance 1 and 3 at distance 2.
+    let ids = lsh.store_sequence(&seq, 2).unwrap();
+    assert_eq!(ids, (0..7).collect::<Vec<DataId>>());
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    // pair (0, 2) is stored after (0, 1).
+    assert_eq!(ht.idx_to_datapoint(1).unwrap(), &vec![1., 1., -1.]);
+    assert!(lsh.query_bucket_ids(&[1., 1., -1.]).unwrap().contains(&1));
+    assert!(lsh
+        .store_sequence(&[vec![1., 2., 3.], vec![1., 2., 4.], vec![1., 2.]], 1)
+        .is_err());
+    // the valid pair before the invalid data point isn't stored.
+    assert_eq!(lsh.store_vec(&[0., 0., 0.]).unwrap(), 7);
+}
+
+#[test]
+fn test_migrate_to() {
+    let vs: Vec<Vec<f32>> = (0..40)
+        .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+        .collect();
+    let mut old = LshMem::new(8, 3, 3).seed(1).srp().unwrap();
+    old.store_vecs(&vs).unwrap();
+    old.delete_vec(&vs[0]).unwrap();
+    let mut new = LshMem::new(16, 3, 3).seed(2).srp().unwrap();
+    new.store_vec(&[1., 1., 1.]).unwrap();
+
+    // the 8 new bits are 0.
+    let remap = |mut hash: Vec<i8>, _| {
+        hash.resize(16, 0);
+        hash
+    };
+    let mapping = old.migrate_to(&mut new, remap).unwrap();
+    assert_eq!(mapping.len(), 39);
+    assert_eq!(mapping[&1], 1);
+    assert_eq!(mapping[&39], 39);
+
+    let old_ht = old.hash_tables.as_ref().unwrap();
+    let new_ht = new.hash_tables.as_ref().unwrap();
+    for (i, tbl) in old_ht.to_bucket_map().unwrap().into_iter().enumerate() {
+        for (hash, ids) in tbl.into_iter().filter(|(_, ids)| !ids.is_empty()) {
+            let bucket = new_ht.query_bucket(&remap(hash, i), i).unwrap();
+            assert!(ids.iter().all(|id| bucket.contains(&mapping[id])));
+        }
+    }
+    assert_eq!(new_ht.idx_to_datapoint(mapping[&5]).unwrap(), &vs[5]);
+
+    let mut other_dim = LshMem::new(16, 3, 4).srp().unwrap();
+    assert!(old.migrate_to(&mut other_dim, remap).is_err());
+}
+
+#[test]
+fn test_lsh_distance_histogram() {
+    use crate::dist::DistanceMetric;
+    let vs: Vec<Vec<f32>> = (0..40).map(|i| vec![i as f32, 1., 0.]).collect();
+    let mut lsh = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+    assert!(lsh.distance_histogram(10, 4, DistanceMetric::L2).is_err());
+    lsh.store_vecs(&vs).unwrap();
+    let hist = lsh.distance_histogram(100, 4, DistanceMetric::L2).unwrap();
+    assert_eq!(hist.len(), 4);
+    assert_eq!(hist.iter().map(|(_, c)| c).sum::<usize>(), 100);
+
+    let mut index_only = LshMem::new(5, 2, 3).only_index().srp().unwrap();
+    index_only.store_vecs(&vs).unwrap();
+    assert!(index_only
+        .distance_histogram(10, 4, DistanceMetric::L2)
+        .is_err());
+}
+
+fn assert_send<T: Send>() {}
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_send_sync() {
+    use crate::{DeltaEncodedTable, MIPS};
+    assert_send_sync::<LshMem<SignRandomProjections>>();
+    assert_send_sync::<LSH<DeltaEncodedTable, MIPS>>();
+    #[cfg(feature = "sqlite")]
+    assert_send::<LshSql<SignRandomProjections>>();
+    #[cfg(feature = "sqlite")]
+    assert_send::<LshSqlMem<SignRandomProjections>>();
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
 fn test_db() {
     let v1 = &[2., 3., 4.];
     {
@@ -68,6 +323,7 @@ fn test_db() {
 }
 
 #[test]
+#[cfg(feature = "sqlite")]
 fn test_mem_db() {
     let v1 = &[2., 3., 4.];
     let mut lsh = LshSqlMem::new(5, 2, 3).seed(2).srp().unwrap();
@@ -75,3 +331,1774 @@ fn test_mem_db() {
     assert!(lsh.query_bucket_ids(v1).unwrap().contains(&0));
     lsh.describe().unwrap();
 }
+
+#[test]
+fn test_compact_srp() {
+    let lsh = LshMem::new(64, 2, 3).seed(1).compact_srp().unwrap();
+    let h = lsh.hashers[0].hash_vec_compact(&[1., 2., 3.]).unwrap();
+    assert_eq!(h.hamming(&h), 0);
+    let lsh = LshMem::new(200, 2, 3).seed(1).compact_srp().unwrap();
+    let h = lsh.hashers[0].hash_vec_compact(&[1., 2., 3.]).unwrap();
+    let h_neg = lsh.hashers[0].hash_vec_compact(&[-1., -2., -3.]).unwrap();
+    assert_eq!(h.hamming(&h_neg), 200);
+    assert!(LshMem::new(256, 2, 3).compact_srp().is_ok());
+    assert!(LshMem::new(257, 2, 3).compact_srp().is_err());
+
+    use crate::utils::{create_rng, rand_unit_vec};
+    use crate::DataPoint;
+    let mut lsh = LshMem::new(8, 2, 3).seed(1).compact_srp().unwrap();
+    let mut rng = create_rng(1);
+    let vs: Vec<DataPoint> = (0..50).map(|_| rand_unit_vec(3, &mut rng)).collect();
+    lsh.store_vecs(&vs).unwrap();
+    let q = &vs[0];
+    let sorted = |mut ids: Vec<DataId>| {
+        ids.sort_unstable();
+        ids
+    };
+    // an exact match equals the bucket query.
+    assert_eq!(
+        sorted(lsh.query_bucket_ids_hamming(q, 0).unwrap()),
+        sorted(lsh.query_bucket_ids(q).unwrap())
+    );
+    let near = lsh.query_bucket_ids_hamming(q, 2).unwrap();
+    assert!(lsh
+        .query_bucket_ids(q)
+        .unwrap()
+        .iter()
+        .all(|id| near.contains(id)));
+    // all buckets are within 8 bits.
+    assert_eq!(lsh.query_bucket_ids_hamming(q, 8).unwrap().len(), 50);
+}
+
+#[test]
+fn test_store_array_with_ids() {
+    use ndarray::prelude::*;
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    let vs = array![[2., 3., 4.], [-1., -1., 1.]];
+    lsh.store_array_with_ids(vs.view(), &[10, 3]).unwrap();
+    assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&10));
+    assert_eq!(
+        lsh.query_bucket(&[-1., -1., 1.]).unwrap()[0],
+        &vec![-1., -1., 1.]
+    );
+
+    // next id is assigned after the largest given id.
+    assert_eq!(lsh.store_vec(&[1., 1., 1.]).unwrap(), 11);
+
+    assert!(lsh.store_array_with_ids(vs.view(), &[1]).is_err());
+    assert!(lsh.store_array_with_ids(vs.view(), &[1, 1]).is_err());
+    // stored ids can't be reused.
+    assert!(lsh.store_array_with_ids(vs.view(), &[1, 3]).is_err());
+    assert!(lsh.query_bucket_ids(&[-1., -1., 1.]).unwrap().contains(&3));
+    // an empty array stores nothing.
+    lsh.store_array_with_ids(Array2::zeros((0, 3)).view(), &[])
+        .unwrap();
+
+    // stored ids of an index without data points can't be reused either.
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).only_index().srp().unwrap();
+    lsh.store_array_with_ids(vs.view(), &[10, 3]).unwrap();
+    assert!(lsh.store_array_with_ids(vs.view(), &[1, 3]).is_err());
+    assert!(!lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&1));
+
+    // the tables stay usable if the backend doesn't support `put_with_id`.
+    let mut lsh: LSH<crate::DeltaEncodedTable, SignRandomProjections> =
+        LSH::new(5, 2, 3).seed(1).srp().unwrap();
+    assert!(matches!(
+        lsh.store_array_with_ids(vs.view(), &[10, 3]),
+        Err(Error::NotImplemented)
+    ));
+    let id = lsh.store_vec(&[2., 3., 4.]).unwrap();
+    assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![id]);
+}
+
+#[test]
+fn test_sub_index() {
+    let mut lsh = LshMem::new(2, 5, 3).seed(1).srp().unwrap();
+    let vs = vec![
+        vec![2., 3., 4.],
+        vec![2.1, 3., 4.],
+        vec![2., 3.1, 4.],
+        vec![-1., -1., 1.],
+    ];
+    lsh.store_vecs(&vs).unwrap();
+    let sub = lsh.sub_index(&[0, 2]).unwrap();
+    for v in &vs {
+        for idx in sub.query_bucket_ids(v).unwrap() {
+            assert!(idx == 0 || idx == 2);
+        }
+    }
+    assert!(sub.query_bucket_ids(&vs[0]).unwrap().contains(&2));
+    assert_eq!(sub.query_bucket(&vs[2]).unwrap().len(), 2);
+}
+
+#[test]
+fn test_f64_input() {
+    use ndarray::prelude::*;
+    let vs: Array2<f64> = array![[2., 3., 4.], [-1., -1., 1.]];
+    let mut lsh64 = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    let mut lsh32 = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    lsh64.store_array_f64(vs.view()).unwrap();
+    lsh32.store_array(vs.mapv(|x| x as f32).view()).unwrap();
+
+    for (q64, q32) in vs.axis_iter(Axis(0)).zip(&[[2., 3., 4.], [-1., -1., 1.]]) {
+        let mut a = lsh64.query_bucket_ids_f64(q64).unwrap();
+        let mut b = lsh32.query_bucket_ids(q32).unwrap();
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+        assert_eq!(
+            lsh64.query_bucket_f64(q64).unwrap().len(),
+            lsh32.query_bucket(q32).unwrap().len()
+        );
+    }
+}
+
+#[test]
+fn test_similarity_join() {
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., -1., 1.]])
+        .unwrap();
+    let other = vec![vec![2., 3., 4.1], vec![-1., -1., 1.1]];
+    let mut pairs = lsh
+        .similarity_join(&other)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert!(pairs.contains(&(0, 0)));
+    assert!(pairs.contains(&(1, 1)));
+
+    let mut par_pairs = lsh.par_similarity_join(&other).unwrap();
+    pairs.sort_unstable();
+    par_pairs.sort_unstable();
+    assert_eq!(pairs, par_pairs);
+
+    // errors are yielded
+    assert!(lsh.similarity_join(&[vec![1.]]).next().unwrap().is_err());
+}
+
+#[test]
+fn test_defragment() {
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.], vec![1., -2., 1.]];
+    lsh.store_vecs(&vs).unwrap();
+    lsh.delete_vec(&vs[1]).unwrap();
+
+    let mapping = lsh.defragment().unwrap();
+    assert_eq!(mapping.len(), 2);
+    assert_eq!(mapping[&0], 0);
+    assert_eq!(mapping[&2], 1);
+    assert_eq!(lsh.hash_tables.as_ref().unwrap().vec_store.map.len(), 2);
+
+    assert!(lsh.query_bucket_ids(&vs[2]).unwrap().contains(&1));
+    assert!(lsh.query_bucket(&vs[2]).unwrap().contains(&&vs[2]));
+    assert_eq!(lsh.store_vec(&vs[1]).unwrap(), 2);
+}
+
+#[test]
+fn test_l2_calibrated() {
+    use crate::utils::create_rng;
+    use crate::L2;
+    use rand::Rng;
+    use rand_distr::StandardNormal;
+
+    let mut rng = create_rng(3);
+    let dim = 8;
+    let vs: Vec<Vec<f32>> = (0..200)
+        .map(|_| (0..dim).map(|_| rng.sample(StandardNormal)).collect())
+        .collect();
+    let qs: Vec<Vec<f32>> = vs
+        .iter()
+        .map(|v| {
+            v.iter()
+                .map(|x| x + 0.3 * rng.sample::<f32, _>(StandardNormal))
+                .collect()
+        })
+        .collect();
+
+    let recall = |lsh: &LshMem<_>| recall_at_k(qs.iter().map(|q| lsh.query_bucket_ids(q).unwrap()));
+
+    let mut lsh = LshMem::new(4, 5, dim).seed(1).l2(1.).unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let mut calibrated = LshMem::new(4, 5, dim)
+        .seed(1)
+        .l2_calibrated(&vs, 0.05)
+        .unwrap();
+    calibrated.store_vecs(&vs).unwrap();
+    assert!(recall(&calibrated) > recall(&lsh));
+
+    assert!(L2::calibrate_r(&vs, 0.05, 0, 1).is_err());
+    assert!(L2::calibrate_r(&vs[..1], 0.05, 10, 1).is_err());
+    // duplicates result in r = 0.
+    let dups = vec![vs[0].clone(); 10];
+    assert!(LshMem::new(4, 5, dim).l2_calibrated(&dups, 0.05).is_err());
+}
+
+#[test]
+fn test_labeled() {
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    let id = lsh.store_vec_labeled(&[2., 3., 4.], b"cat").unwrap();
+    lsh.store_vec(&[2., 3., 4.1]).unwrap();
+    let res = lsh.query_bucket_labeled(&[2., 3., 4.]).unwrap();
+    assert!(res.contains(&(id, b"cat".to_vec())));
+    assert!(res.contains(&(1, vec![])));
+
+    // the metadata is removed with the data point.
+    lsh.delete_by_id(id).unwrap();
+    let id = lsh.store_vec(&[2., 3., 4.]).unwrap();
+    assert!(lsh.hash_tables.as_ref().unwrap().get_metadata(0).is_err());
+    assert!(!lsh
+        .query_bucket_labeled(&[2., 3., 4.])
+        .unwrap()
+        .contains(&(0, b"cat".to_vec())));
+    assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&id));
+
+    #[cfg(feature = "sqlite")]
+    {
+        // the tables stay usable if the backend doesn't support metadata.
+        let mut lsh = LshSqlMem::new(5, 2, 3).seed(1).srp().unwrap();
+        assert!(matches!(
+            lsh.store_vec_labeled(&[2., 3., 4.], b"cat"),
+            Err(Error::NotImplemented)
+        ));
+        let id = lsh.store_vec(&[2., 3., 4.]).unwrap();
+        assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![id]);
+    }
+}
+
+/// Unit vectors `vs` and queries `qs` with a fixed cosine similarity to the vector with the same index.
+fn correlated_pairs(n: usize, dim: usize, cos: f32, seed: u64) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+    use crate::utils::{create_rng, rand_unit_vec};
+
+    let mut rng = create_rng(seed);
+    let mut vs = vec![];
+    let mut qs = vec![];
+    for _ in 0..n {
+        let x = rand_unit_vec(dim, &mut rng);
+        let z = rand_unit_vec(dim, &mut rng);
+        // make z orthogonal to x and scale both to unit length
+        let x_norm = crate::dist::l2_norm(&x);
+        let x: Vec<f32> = x.iter().map(|a| a / x_norm).collect();
+        let dot = crate::dist::inner_prod(&x, &z);
+        let z: Vec<f32> = z.iter().zip(&x).map(|(a, b)| a - dot * b).collect();
+        let z_norm = crate::dist::l2_norm(&z);
+        let q: Vec<f32> = x
+            .iter()
+            .zip(&z)
+            .map(|(a, b)| cos * a + (1. - cos * cos).sqrt() * b / z_norm)
+            .collect();
+        vs.push(x);
+        qs.push(q);
+    }
+    (vs, qs)
+}
+
+/// Fraction of the candidate lists that contain their own index. With the candidates of the
+/// queries of [correlated_pairs](fn.correlated_pairs.html) in order, this is the recall of the
+/// stored pairs.
+fn recall_at_k<I: IntoIterator<Item = Vec<DataId>>>(candidates: I) -> f32 {
+    let (mut hits, mut n) = (0, 0);
+    for (i, ids) in candidates.into_iter().enumerate() {
+        hits += ids.contains(&(i as DataId)) as usize;
+        n += 1;
+    }
+    hits as f32 / n as f32
+}
+
+#[test]
+fn test_table_sampling_rate() {
+    use crate::stats::srp_ph;
+
+    let (dim, k, l, p) = (20, 8, 20, 0.25);
+    let cos: f32 = 0.9;
+    let (vs, qs) = correlated_pairs(500, dim, cos, 2);
+
+    let mut lsh = LshMem::new(k, l, dim)
+        .seed(1)
+        .set_table_sampling_rate(p)
+        .unwrap()
+        .srp()
+        .unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let recall = recall_at_k(qs.iter().map(|q| lsh.query_bucket_ids(q).unwrap())) as f64;
+
+    let p1 = srp_ph(cos as f64);
+    let expected = 1. - (1. - p1.powf(k as f64)).powf(p * l as f64);
+    assert!((recall - expected).abs() < 0.1, "{} {}", recall, expected);
+
+    // deterministic per query
+    assert_eq!(lsh.sample_tables(&qs[0]), lsh.sample_tables(&qs[0]));
+    for p in [f64::NAN, -0.1, 1.5] {
+        assert!(lsh.set_table_sampling_rate(p).is_err());
+    }
+}
+
+#[test]
+fn test_ensemble_recall() {
+    use crate::{stats::srp_ph, EnsembleLSH};
+
+    let (dim, k, cos) = (20, 8, 0.8);
+    let (vs, qs) = correlated_pairs(1000, dim, cos, 3);
+    let recall =
+        |query: &dyn Fn(&[f32]) -> Vec<DataId>| recall_at_k(qs.iter().map(|q| query(q))) as f64;
+
+    let mut single = LshMem::new(k, 8, dim).seed(1).srp().unwrap();
+    single.store_vecs(&vs).unwrap();
+    let mut member = LshMem::new(k, 2, dim).seed(1).srp().unwrap();
+    member.store_vecs(&vs).unwrap();
+    let mut ensemble = EnsembleLSH::<MemoryTable, _>::build(k, 2, dim, 4, 1).unwrap();
+    ensemble.store_vecs(&vs).unwrap();
+
+    let r_single = recall(&|q| single.query_bucket_ids(q).unwrap());
+    let r_member = recall(&|q| member.query_bucket_ids(q).unwrap());
+    let r_ensemble = recall(&|q| ensemble.query_bucket_ids(q).unwrap());
+    // with the same total number of tables, the tables are equally independent.
+    let expected = 1. - (1. - srp_ph(cos as f64).powf(k as f64)).powf(8.);
+    assert!(
+        (r_ensemble - expected).abs() < 0.05,
+        "{} {}",
+        r_ensemble,
+        expected
+    );
+    assert!((r_ensemble - r_single).abs() < 0.05);
+    assert!(r_ensemble > r_member + 0.1);
+}
+
+#[test]
+fn test_union_with() {
+    use std::sync::mpsc::channel;
+
+    let vs: Vec<Vec<f32>> = (0..40)
+        .map(|i| vec![i as f32, (i % 5) as f32 - 2., 1.])
+        .collect();
+    let (tx, rx) = channel();
+    for (i, chunk) in vs.chunks(10).enumerate() {
+        let tx = tx.clone();
+        let chunk = chunk.to_vec();
+        rayon::spawn(move || {
+            let mut lsh = LshMem::new(5, 6, 3).seed(1).srp().unwrap();
+            lsh.store_vecs(&chunk).unwrap();
+            tx.send((i, lsh)).unwrap();
+        });
+    }
+    drop(tx);
+    let mut parts: Vec<_> = rx.iter().collect();
+    parts.sort_by_key(|(i, _)| *i);
+
+    let mut parts = parts.into_iter().map(|(_, lsh)| lsh);
+    let mut lsh = parts.next().unwrap();
+    for part in parts {
+        lsh.union_with(part).unwrap();
+    }
+
+    let mut single = LshMem::new(5, 6, 3).seed(1).srp().unwrap();
+    single.store_vecs(&vs).unwrap();
+    for v in &vs {
+        let mut a = lsh.query_bucket_ids(v).unwrap();
+        let mut b = single.query_bucket_ids(v).unwrap();
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+    }
+    // the data points are merged as well
+    assert_eq!(
+        lsh.query_bucket(&vs[39]).unwrap(),
+        single.query_bucket(&vs[39]).unwrap()
+    );
+    assert_eq!(lsh.store_vec(&[1., 2., 3.]).unwrap(), 40);
+
+    let other = LshMem::new(5, 6, 3).seed(2).srp().unwrap();
+    assert!(lsh.union_with(other).is_err());
+}
+
+#[test]
+fn test_query_top_tables() {
+    let vs: Vec<Vec<f32>> = (0..100)
+        .map(|i| {
+            let i = i as f32;
+            vec![(i * 0.3).sin(), (i * 0.7).cos(), (i * 0.1).sin() + 0.5]
+        })
+        .collect();
+    let mut srp = LshMem::new(6, 20, 3).seed(1).srp().unwrap();
+    let mut l2 = LshMem::new(3, 20, 3).seed(1).l2(0.5).unwrap();
+    srp.store_vecs(&vs).unwrap();
+    l2.store_vecs(&vs).unwrap();
+
+    let q = [0.2, 0.5, 0.6];
+    let all = srp.query_bucket_ids(&q).unwrap();
+    let top = srp.query_bucket_ids_top_tables(&q, 5).unwrap();
+    assert!(top.len() <= all.len());
+    assert!(top.iter().all(|id| all.contains(id)));
+    let mut full = srp.query_bucket_ids_top_tables(&q, 20).unwrap();
+    let mut all = all;
+    full.sort_unstable();
+    all.sort_unstable();
+    assert_eq!(full, all);
+
+    let all = l2.query_bucket_ids(&q).unwrap();
+    let top = l2.query_bucket_ids_top_tables(&q, 5).unwrap();
+    assert!(top.iter().all(|id| all.contains(id)));
+    // stored points always find themselves
+    assert!(l2
+        .query_bucket_ids_top_tables(&vs[3], 1)
+        .unwrap()
+        .contains(&3));
+}
+
+#[test]
+fn test_silent() {
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).silent().srp().unwrap();
+    lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., -1., 1.]])
+        .unwrap();
+    assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&0));
+}
+
+#[test]
+fn test_clear_table() {
+    let v = &[2., 3., 4.];
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.store_vec(v).unwrap();
+    lsh.clear_table(0).unwrap();
+    let hash = lsh.hashers[0].hash_vec_query(v);
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    assert!(ht.query_bucket(&hash, 0).is_err());
+    assert!(lsh.query_bucket_ids(v).unwrap().contains(&0));
+    assert!(lsh.clear_table(3).is_err());
+
+    #[cfg(feature = "sqlite")]
+    {
+        let mut lsh = LshSqlMem::new(5, 2, 3).seed(1).srp().unwrap();
+        lsh.store_vec(v).unwrap();
+        lsh.clear_table(1).unwrap();
+        let hash = lsh.hashers[1].hash_vec_query(v);
+        let ht = lsh.hash_tables.as_ref().unwrap();
+        assert!(ht.query_bucket(&hash, 1).unwrap().is_empty());
+        assert!(ht
+            .query_bucket(&lsh.hashers[0].hash_vec_query(v), 0)
+            .unwrap()
+            .contains(&0));
+    }
+}
+
+#[test]
+fn test_to_bucket_map() {
+    let vs: Vec<Vec<f32>> = (0..30)
+        .map(|i| vec![i as f32, (i % 4) as f32 - 2., 1.])
+        .collect();
+    let mut lsh = LshMem::new(4, 3, 3).seed(1).only_index().srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    let buckets = ht.to_vec_of_buckets();
+    assert_eq!(buckets.len(), 3);
+    assert_eq!(buckets[0].iter().map(|b| b.len()).sum::<usize>(), 30);
+
+    // rebuild a fresh table from the bucket map
+    let map = ht.to_bucket_map().unwrap();
+    let mut fresh = *MemoryTable::new(3, true, "").unwrap();
+    for (i, tbl) in map.iter().enumerate() {
+        for (hash, ids) in tbl {
+            for &id in ids {
+                fresh.put_with_id(id, hash.clone(), &[], i).unwrap();
+            }
+        }
+    }
+    assert_eq!(fresh.to_vec_of_buckets(), buckets);
+    let lsh_ids: Vec<_> = vs
+        .iter()
+        .map(|v| lsh.query_bucket_ids(v).unwrap())
+        .collect();
+    lsh.hash_tables.replace(fresh);
+    for (v, ids) in vs.iter().zip(lsh_ids) {
+        let mut a = lsh.query_bucket_ids(v).unwrap();
+        let mut b = ids;
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+    }
+
+    #[cfg(feature = "sqlite")]
+    {
+        let mut sql = LshSqlMem::new(4, 3, 3).seed(1).srp().unwrap();
+        sql.store_vecs(&vs).unwrap();
+        assert_eq!(
+            sql.hash_tables.as_ref().unwrap().to_bucket_map().unwrap(),
+            map
+        );
+    }
+}
+
+#[test]
+fn test_noise_robust() {
+    let (dim, k, l) = (20, 12, 2);
+    let (vs, qs) = correlated_pairs(300, dim, 0.9, 3);
+    let recall = |lsh: &LshMem<_>| recall_at_k(qs.iter().map(|q| lsh.query_bucket_ids(q).unwrap()));
+
+    let mut single = LshMem::new(k, l, dim).seed(1).srp().unwrap();
+    single.store_vecs(&vs).unwrap();
+    let mut noisy = LshMem::new(k, l, dim)
+        .seed(1)
+        .noise_robust(0.05, 10)
+        .unwrap()
+        .srp()
+        .unwrap();
+    noisy.store_vecs(&vs).unwrap();
+
+    let (r_single, r_noisy) = (recall(&single), recall(&noisy));
+    assert!(r_noisy > r_single + 0.1, "{} {}", r_noisy, r_single);
+    // deterministic per query
+    assert_eq!(
+        noisy.query_bucket_ids(&qs[0]).unwrap(),
+        noisy.query_bucket_ids(&qs[0]).unwrap()
+    );
+
+    assert!(LshMem::<SignRandomProjections>::new(k, l, dim)
+        .noise_robust(-0.05, 10)
+        .is_err());
+    assert!(LshMem::<SignRandomProjections>::new(k, l, dim)
+        .noise_robust(f32::NAN, 10)
+        .is_err());
+}
+
+#[test]
+fn test_query_strict_atleast() {
+    let vs: Vec<Vec<f32>> = (0..50)
+        .map(|i| {
+            let i = i as f32;
+            vec![(i * 0.3).sin(), (i * 0.7).cos(), (i * 0.1).sin() + 0.5]
+        })
+        .collect();
+    let mut lsh = LshMem::new(3, 8, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let q = &vs[7];
+    let mut all = lsh.query_bucket_ids(q).unwrap();
+    let mut strict = lsh.query_bucket_ids_strict(q).unwrap();
+    let mut one = lsh.query_bucket_ids_atleast(q, 1).unwrap();
+    let mut eight = lsh.query_bucket_ids_atleast(q, 8).unwrap();
+    for ids in [&mut all, &mut strict, &mut one, &mut eight] {
+        ids.sort_unstable()
+    }
+    assert!(strict.contains(&7));
+    assert!(strict.len() <= lsh.query_bucket_ids_atleast(q, 4).unwrap().len());
+    assert_eq!(one, all);
+    assert_eq!(eight, strict);
+    assert!(lsh.query_bucket_ids_atleast(q, 9).unwrap().is_empty());
+}
+
+#[test]
+fn test_scalar_quantization() {
+    let vs: Vec<Vec<f32>> = (0..100)
+        .map(|i| {
+            let i = i as f32;
+            vec![(i * 0.3).sin(), (i * 0.7).cos() * 10., i]
+        })
+        .collect();
+    let mut lsh = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+    lsh.scalar_quantization().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    assert!(lsh.query_bucket(&vs[5]).is_err());
+
+    let bucket = lsh.query_bucket_owned(&vs[5]).unwrap();
+    // the error is at most half a quantization step per dimension.
+    let max_err = [2. / 255. / 2., 20. / 255. / 2., 99. / 255. / 2.];
+    assert!(bucket.iter().any(|d| d
+        .iter()
+        .zip(&vs[5])
+        .zip(&max_err)
+        .all(|((a, b), e)| (a - b).abs() <= e + 1e-5)));
+
+    lsh.delete_vec(&vs[5]).unwrap();
+    assert!(!lsh.query_bucket_ids(&vs[5]).unwrap().contains(&5));
+    // values outside of the fitted range are clipped.
+    let id = lsh.store_vec(&[0., 0., 1000.]).unwrap();
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    assert_eq!(ht.idx_to_datapoint_owned(id).unwrap()[2], 99.);
+
+    let mut lsh = LshMem::new(5, 4, 3).seed(1).only_index().srp().unwrap();
+    assert!(lsh.scalar_quantization().is_err());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_delete_by_ids() {
+    let vs: Vec<Vec<f32>> = (0..20)
+        .map(|i| vec![i as f32, (i % 3) as f32 - 1., 1.])
+        .collect();
+    let contains_any =
+        |ids: &[DataId], deleted: &[DataId]| ids.iter().any(|id| deleted.contains(id));
+
+    let mut mem = LshMem::new(4, 3, 3).seed(1).srp().unwrap();
+    let mut index_only = LshMem::new(4, 3, 3).seed(1).only_index().srp().unwrap();
+    let mut sql = LshSqlMem::new(4, 3, 3).seed(1).srp().unwrap();
+    mem.store_vecs(&vs).unwrap();
+    index_only.store_vecs(&vs).unwrap();
+    sql.store_vecs(&vs).unwrap();
+
+    mem.delete_by_id(2).unwrap();
+    mem.batch_delete_by_ids(&[4, 5]).unwrap();
+    index_only.batch_delete_by_ids(&[2, 4, 5]).unwrap();
+    sql.batch_delete_by_ids(&[2, 4, 5]).unwrap();
+    for v in &vs {
+        assert!(!contains_any(&mem.query_bucket_ids(v).unwrap(), &[2, 4, 5]));
+        assert!(!contains_any(
+            &index_only.query_bucket_ids(v).unwrap(),
+            &[2, 4, 5]
+        ));
+        assert!(!contains_any(&sql.query_bucket_ids(v).unwrap(), &[2, 4, 5]));
+    }
+    assert!(mem.query_bucket_ids(&vs[3]).unwrap().contains(&3));
+    assert!(sql.query_bucket_ids(&vs[3]).unwrap().contains(&3));
+    assert!(mem
+        .hash_tables
+        .as_ref()
+        .unwrap()
+        .idx_to_datapoint(2)
+        .is_err());
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_open_existing() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lsh_open_existing.db3");
+    let db = path.to_str().unwrap();
+    assert!(LshSql::<crate::SignRandomProjections>::open_existing(db).is_err());
+
+    let mut lsh = LshSql::new(5, 3, 3)
+        .seed(1)
+        .set_database_file(db)
+        .srp()
+        .unwrap();
+    lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., -1., 1.]])
+        .unwrap();
+    lsh.commit().unwrap();
+    let expected = lsh.query_bucket_ids(&[2., 3., 4.]).unwrap();
+    drop(lsh);
+
+    let mut lsh = LshSql::<crate::SignRandomProjections>::open_existing(db).unwrap();
+    assert_eq!((lsh.n_projections, lsh.n_hash_tables, lsh.dim), (5, 3, 3));
+    assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap(), expected);
+    // appended ids don't collide with the stored ids
+    assert_eq!(lsh.store_vec(&[2., 3., 4.1]).unwrap(), 2);
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_build_in_memory_then_flush() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("lsh_build_then_flush.db3");
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.]];
+
+    let mut lsh = LshSqlMem::new(5, 3, 3).seed(1).srp().unwrap();
+    assert_eq!(lsh.build_in_memory_then_flush(&vs, &path).unwrap().len(), 6);
+    let expected = lsh.query_bucket_ids(&[2., 3., 4.]).unwrap();
+    assert!(expected.contains(&0));
+    drop(lsh);
+
+    let lsh =
+        LshSql::<crate::SignRandomProjections>::open_existing(path.to_str().unwrap()).unwrap();
+    assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap(), expected);
+}
+
+#[test]
+fn test_similarity_graph() {
+    let vs = vec![
+        vec![1., 0., 0.],
+        vec![1., 0.05, 0.],
+        vec![0., 0., 1.],
+        vec![0., 0.05, 1.],
+        vec![-1., 1., -1.],
+    ];
+    let mut lsh = LshMem::new(4, 10, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let graph = lsh
+        .build_similarity_graph(0.99, crate::dist::DistanceMetric::Cosine)
+        .unwrap();
+    assert_eq!(graph[&0], vec![1]);
+    assert_eq!(graph[&3], vec![2]);
+    assert!(!graph.contains_key(&4));
+}
+
+#[test]
+fn test_query_into() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.], vec![2., 3.1, 4.]];
+    let mut lsh = LshMem::new(5, 5, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let mut ids = fnv::FnvHashSet::default();
+    ids.insert(99);
+    for v in &vs {
+        lsh.query_bucket_ids_into(v, &mut ids).unwrap();
+        let expected = lsh.query_bucket_ids(v).unwrap();
+        assert_eq!(ids.len(), expected.len());
+        assert!(expected.iter().all(|id| ids.contains(id)));
+    }
+    // multi-probing fills the same buffer.
+    lsh.multi_probe(4);
+    lsh.query_bucket_ids_into(&vs[0], &mut ids).unwrap();
+    assert_eq!(ids.len(), lsh.query_bucket_ids(&vs[0]).unwrap().len());
+}
+
+#[test]
+fn test_falconn_vs_srp() {
+    // equal projection cost: 2 cross-polytopes of k = 16 vs 32 hyperplanes.
+    let (dim, l) = (64, 10);
+    let (vs, qs) = correlated_pairs(300, dim, 0.8, 4);
+    fn recall<H: VecHash>(lsh: &LSH<MemoryTable, H>, qs: &[Vec<f32>]) -> f32 {
+        recall_at_k(qs.iter().map(|q| lsh.query_bucket_ids(q).unwrap()))
+    }
+    let mut srp = LshMem::new(32, l, dim).seed(1).srp().unwrap();
+    srp.store_vecs(&vs).unwrap();
+    let mut falconn = LshMem::new(2, l, dim).seed(1).falconn(16).unwrap();
+    falconn.store_vecs(&vs).unwrap();
+    let (r_falconn, r_srp) = (recall(&falconn, &qs), recall(&srp, &qs));
+    assert!(r_falconn > r_srp + 0.3, "{} {}", r_falconn, r_srp);
+    assert!(LshMem::new(2, l, dim).falconn(65).is_err());
+}
+
+#[test]
+fn test_fuzzy_srp() {
+    let mut lsh = LshMem::new(8, 4, 3).seed(1).fuzzy_srp(0.01).unwrap();
+    lsh.store_vec(&[2., 3., 4.]).unwrap();
+    assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap(), vec![0]);
+    assert_eq!(lsh.query_bucket_ids(&[2., 3., 4.005]).unwrap(), vec![0]);
+    assert!(LshMem::new(8, 4, 3).fuzzy_srp(-1.).is_err());
+}
+
+#[test]
+fn test_from_external_hashers() {
+    use crate::SignRandomProjections;
+    let server = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+    let hashers = server.hashers.clone();
+    let mut client = LshMem::from_external_hashers(hashers.clone(), 4, 5, 3).unwrap();
+    let v = [2., 3., 4.];
+    for (a, b) in server.hashers.iter().zip(&client.hashers) {
+        assert_eq!(a.hash_vec_query(&v), b.hash_vec_query(&v));
+    }
+    client.store_vec(&v).unwrap();
+    assert_eq!(client.query_bucket_ids(&v).unwrap(), vec![0]);
+
+    assert!(LshMem::from_external_hashers(hashers.clone(), 3, 5, 3).is_err());
+    assert!(LshMem::from_external_hashers(hashers.clone(), 4, 6, 3).is_err());
+    assert!(LshMem::from_external_hashers(hashers, 4, 5, 2).is_err());
+    let mixed = vec![
+        SignRandomProjections::new(5, 3, 1),
+        SignRandomProjections::new(4, 3, 1),
+    ];
+    assert!(LshMem::from_external_hashers(mixed, 2, 5, 3).is_err());
+}
+
+#[test]
+fn test_deterministic_snapshot() {
+    let vs: Vec<Vec<f32>> = (0..200)
+        .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+        .collect();
+    let build = || {
+        let mut lsh = LshMem::new(5, 4, 3).seed(3).srp().unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        lsh
+    };
+    let (a, b) = (build(), build());
+    let (a, b) = (
+        a.hash_tables.as_ref().unwrap(),
+        b.hash_tables.as_ref().unwrap(),
+    );
+    assert_eq!(
+        bincode::serialize(a).unwrap(),
+        bincode::serialize(b).unwrap()
+    );
+    let order = |ht: &MemoryTable| -> Vec<DataId> {
+        ht.to_bucket_map().unwrap()[0]
+            .values()
+            .flatten()
+            .copied()
+            .collect()
+    };
+    assert_eq!(order(a), order(b));
+}
+
+#[test]
+fn test_from_bucket_map() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.], vec![2., 3.1, 4.]];
+    let mut original = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    original.store_vecs(&vs).unwrap();
+    let tables = original
+        .hash_tables
+        .as_ref()
+        .unwrap()
+        .to_bucket_map()
+        .unwrap();
+    let tables: Vec<_> = tables
+        .into_iter()
+        .map(|tbl| {
+            tbl.into_iter()
+                .map(|(hash, ids)| (hash, ids.into_iter().collect()))
+                .collect()
+        })
+        .collect();
+
+    let mut lsh =
+        LshMem::from_bucket_map(original.hashers.clone(), tables.clone(), vs.clone()).unwrap();
+    for v in &vs {
+        let mut a = lsh.query_bucket_ids(v).unwrap();
+        let mut b = original.query_bucket_ids(v).unwrap();
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+    }
+    // new ids follow the imported ids.
+    assert_eq!(lsh.store_vec(&[0., 1., 0.]).unwrap(), 3);
+
+    let hashers = original.hashers.clone();
+    assert!(LshMem::from_bucket_map(hashers.clone(), tables.clone(), vs[..2].to_vec()).is_err());
+    assert!(LshMem::from_bucket_map(hashers.clone(), tables[..2].to_vec(), vs.clone()).is_err());
+    assert!(LshMem::from_bucket_map(hashers, tables, vec![vec![1.]; 3]).is_err());
+}
+
+#[test]
+fn test_query_expansion() {
+    let mut lsh = LshMem::new(3, 5, 2).seed(1).srp().unwrap();
+    lsh.store_vecs(&[vec![1., 0.2], vec![1., -0.2], vec![-1., 0.]])
+        .unwrap();
+    let q = [1., 0.1];
+    // the two nearest candidates average to [1, 0].
+    let expanded = lsh.expand_query(&q, 2, 0.5).unwrap();
+    assert!(lsh.query_bucket_ids(&q).unwrap().len() >= 2);
+    assert_eq!(expanded, vec![1., 0.05]);
+    assert_eq!(lsh.expand_query(&q, 1, 1.).unwrap(), q.to_vec());
+
+    let ids = lsh.query_with_expansion(&q, 2, 0.5, 2).unwrap();
+    assert_eq!(ids.len(), 2);
+    assert!(!ids.contains(&2));
+    assert!(lsh.expand_query(&q, 0, 0.5).is_err());
+    assert!(lsh.expand_query(&q, 1, 1.5).is_err());
+
+    // zero vectors have no cosine similarity, which doesn't break the ranking.
+    let mut lsh = LshMem::new(3, 5, 2).seed(1).srp().unwrap();
+    lsh.store_vecs(&vec![vec![0., 0.]; 3]).unwrap();
+    let mut ids = lsh.query_with_expansion(&[0., 0.], 1, 1., 3).unwrap();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![0, 1, 2]);
+}
+
+#[test]
+fn test_max_bucket_size() {
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.with_max_bucket_size(2).unwrap();
+    // equal data points end up in the same buckets.
+    for i in 0..2 {
+        let (idx, res) = lsh.store_vec_partial(&[1., 2., 3.]).unwrap();
+        assert_eq!((idx, res.n_tables_skipped), (i, 0));
+    }
+    let (idx, res) = lsh.store_vec_partial(&[1., 2., 3.]).unwrap();
+    assert_eq!((idx, res.n_tables_skipped), (2, 3));
+    lsh.store_vec(&[1., 2., 3.]).unwrap();
+    assert_eq!(lsh.query_bucket_ids(&[1., 2., 3.]).unwrap().len(), 2);
+    // the skipped data points are still stored.
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    assert_eq!(ht.idx_to_datapoint(3).unwrap(), &vec![1., 2., 3.]);
+    let (idx, res) = lsh.store_vec_partial(&[-1., 2., -3.]).unwrap();
+    assert_eq!((idx, res.n_tables_skipped), (4, 0));
+    assert!(lsh.with_max_bucket_size(0).is_err());
+}
+
+#[test]
+fn test_bit_margins() {
+    let lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    let v = [2., 3., 4.];
+    let margins = lsh.bit_margins(&v).unwrap();
+    assert_eq!(margins.len(), 3);
+    for (m, h) in margins.iter().zip(&lsh.hashers) {
+        let bits: Vec<i8> = m.iter().map(|&x| (x > 0.) as i8).collect();
+        assert_eq!(bits, h.hash_vec_query(&v));
+    }
+    assert!(lsh.bit_margins(&[1.]).is_err());
+}
+
+#[test]
+fn test_verify_consistency() {
+    let mut lsh = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.]];
+    lsh.store_vecs(&vs).unwrap();
+    assert!(lsh.verify_consistency().unwrap().is_empty());
+
+    let wrong: Vec<i8> = lsh.hashers[1]
+        .hash_vec_put(&vs[0])
+        .iter()
+        .map(|h| 1 - h)
+        .collect();
+    let ht = lsh.hash_tables.as_mut().unwrap();
+    // id 0 in a wrong bucket of table 1 and an id without data point.
+    ht.put_with_id(0, wrong.clone(), &vs[0], 1).unwrap();
+    ht.put_with_id(7, wrong.clone(), &vs[0], 1).unwrap();
+    let errors = lsh.verify_consistency().unwrap();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].table_idx, 1);
+    assert_eq!(errors[0].id, 0);
+    assert_eq!(errors[0].bucket_hash, wrong);
+    assert_eq!(
+        errors[0].expected_hash,
+        Some(lsh.hashers[1].hash_vec_put(&vs[0]))
+    );
+    assert_eq!(errors[1].id, 7);
+    assert_eq!(errors[1].expected_hash, None);
+
+    let lsh = LshMem::new(5, 2, 3).only_index().srp().unwrap();
+    assert!(lsh.verify_consistency().is_err());
+}
+
+#[test]
+fn test_query_with_timeout() {
+    use std::time::Duration;
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    let vs = vec![vec![2., 3., 4.], vec![2., 3., 4.1], vec![-1., -1., 1.]];
+    lsh.store_vecs(&vs).unwrap();
+    let (mut ids, complete) = lsh
+        .query_bucket_ids_with_timeout(&vs[0], Duration::from_secs(10))
+        .unwrap();
+    assert!(complete);
+    let mut expected = lsh.query_bucket_ids(&vs[0]).unwrap();
+    ids.sort_unstable();
+    expected.sort_unstable();
+    assert_eq!(ids, expected);
+    assert!(matches!(
+        lsh.query_bucket_ids_with_timeout(&vs[0], Duration::from_secs(0)),
+        Err(crate::Error::Timeout)
+    ));
+    lsh.multi_probe(4);
+    assert!(lsh
+        .query_bucket_ids_with_timeout(&vs[0], Duration::from_secs(10))
+        .is_err());
+}
+
+#[test]
+fn test_query_with_table_timing() {
+    let vs = vec![vec![2., 3., 4.], vec![2., 3., 4.1], vec![-1., -1., 1.]];
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let (mut ids, durations) = lsh.query_with_table_timing(&vs[0]).unwrap();
+    assert_eq!(durations.len(), 10);
+    let mut expected = lsh.query_bucket_ids(&vs[0]).unwrap();
+    ids.sort_unstable();
+    expected.sort_unstable();
+    assert_eq!(ids, expected);
+    lsh.multi_probe(4);
+    assert!(lsh.query_with_table_timing(&vs[0]).is_err());
+
+    #[cfg(feature = "sqlite")]
+    {
+        let mut lsh = crate::LshSqlMem::new(5, 4, 3).seed(1).srp().unwrap();
+        lsh.store_vecs(&vs).unwrap();
+        let (ids, durations) = lsh.query_with_table_timing(&vs[0]).unwrap();
+        assert!(ids.contains(&0));
+        // every table runs an SQL query.
+        assert!(durations
+            .iter()
+            .all(|d| *d > std::time::Duration::default()));
+    }
+}
+
+#[test]
+fn test_next_seed() {
+    let mut a = LshMem::new(5, 2, 3).seed(7).srp().unwrap();
+    let mut b = LshMem::new(5, 2, 3).seed(7).srp().unwrap();
+    let seeds: Vec<u64> = (0..3).map(|_| a.next_seed()).collect();
+    assert_eq!(seeds, (0..3).map(|_| b.next_seed()).collect::<Vec<_>>());
+    assert_ne!(seeds[0], seeds[1]);
+    let mut c = LshMem::new(5, 2, 3).seed(8).srp().unwrap();
+    assert_ne!(c.next_seed(), seeds[0]);
+}
+
+#[test]
+fn test_store_vec_from_stream() {
+    let mut lsh = LshMem::new(4, 3, 3).seed(1).mips(1., 0.83, 3).unwrap();
+    assert!(lsh.store_vec_from_stream(&[1., 2., 3.], 0.).is_err());
+    for i in 1..20 {
+        let v = [(i as f32).sin() * 4., (i as f32).cos(), 0.5];
+        lsh.store_vec_from_stream(&v, 0.9).unwrap();
+    }
+    // the buckets can be stale between the rebuilds.
+    lsh.rebuild_index().unwrap();
+    assert!(lsh.verify_consistency().unwrap().is_empty());
+
+    // the maximum norm decays by 0.9 per small data point, the index is rebuilt once it halved.
+    let mut lsh = LshMem::new(4, 3, 3).seed(1).mips(1., 0.83, 3).unwrap();
+    lsh.store_vec_from_stream(&[0., 10., 0.], 0.9).unwrap();
+    for _ in 0..6 {
+        lsh.store_vec_from_stream(&[0.1, 0.1, 0.1], 0.9).unwrap();
+        assert!(lsh.hashers.iter().all(|h| h.needs_rebuild()));
+    }
+    lsh.store_vec_from_stream(&[0.1, 0.1, 0.1], 0.9).unwrap();
+    assert!(lsh.hashers.iter().all(|h| !h.needs_rebuild()));
+    assert!(lsh.hashers[0].current_M() < 5.);
+
+    // invalid data points don't change the maximum norm.
+    let m = lsh.hashers[0].current_M();
+    assert!(lsh.store_vec_from_stream(&[100., 1.], 0.9).is_err());
+    assert_eq!(lsh.hashers[0].current_M(), m);
+
+    // a failed rebuild doesn't fail the store, so every data point is stored once.
+    let mut lsh = LshMem::new(4, 3, 3)
+        .seed(1)
+        .only_index()
+        .mips(1., 0.83, 3)
+        .unwrap();
+    lsh.store_vec_from_stream(&[0., 10., 0.], 0.9).unwrap();
+    for i in 1..10 {
+        assert_eq!(lsh.store_vec_from_stream(&[0.1, 0.1, 0.1], 0.9).unwrap(), i);
+    }
+    assert!(lsh.hashers.iter().all(|h| h.needs_rebuild()));
+}
+
+#[test]
+fn test_mips_rebuild_index() {
+    let vs: Vec<Vec<f32>> = (1..40)
+        .map(|i| vec![(i as f32).sin() * i as f32, (i as f32).cos(), 0.5])
+        .collect();
+    let mut lsh = LshMem::new(4, 3, 3).seed(1).mips(1., 0.83, 3).unwrap();
+    for v in &vs {
+        for h in &mut lsh.hashers {
+            h.fit_incremental(v);
+        }
+        lsh.store_vec(v).unwrap();
+    }
+    assert!(lsh.hashers.iter().all(|h| h.needs_rebuild()));
+    lsh.rebuild_index().unwrap();
+    assert!(lsh.hashers.iter().all(|h| !h.needs_rebuild()));
+
+    // same buckets as fitting on all data points up front.
+    let mut expected = LshMem::new(4, 3, 3).seed(1).mips(1., 0.83, 3).unwrap();
+    for h in &mut expected.hashers {
+        vs.iter().for_each(|v| {
+            h.fit_incremental(v);
+        });
+    }
+    expected.store_vecs(&vs).unwrap();
+    let sorted = |lsh: &LshMem<crate::MIPS>| {
+        lsh.hash_tables
+            .as_ref()
+            .unwrap()
+            .to_bucket_map()
+            .unwrap()
+            .into_iter()
+            .map(|t| {
+                let mut t: Vec<_> = t
+                    .into_iter()
+                    .map(|(h, b)| {
+                        let mut b: Vec<DataId> = b.into_iter().collect();
+                        b.sort_unstable();
+                        (h, b)
+                    })
+                    .collect();
+                t.sort();
+                t
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(sorted(&lsh), sorted(&expected));
+
+    // a backend without `put_with_id` fails before the index is changed.
+    let mut lsh: LSH<crate::DeltaEncodedTable, crate::MIPS> =
+        LSH::new(4, 3, 3).seed(1).mips(1., 0.83, 3).unwrap();
+    for v in &vs {
+        for h in &mut lsh.hashers {
+            h.fit_incremental(v);
+        }
+        lsh.store_vec(v).unwrap();
+    }
+    let before = lsh.hash_tables.as_ref().unwrap().to_bucket_map().unwrap();
+    assert!(matches!(lsh.rebuild_index(), Err(Error::NotImplemented)));
+    assert!(lsh.hashers.iter().all(|h| h.needs_rebuild()));
+    let after = lsh.hash_tables.as_ref().unwrap().to_bucket_map().unwrap();
+    assert_eq!(before, after);
+}
+
+#[test]
+fn test_update_hasher_projection() {
+    use ndarray::aview1;
+    let mut lsh = LshMem::new(1, 2, 2).seed(1).srp().unwrap();
+    lsh.store_vecs(&[vec![1., 0.1], vec![-1., 0.1]]).unwrap();
+    let bucket_of_first = |lsh: &LshMem<crate::SignRandomProjections>| {
+        let hash = lsh.hashers[0].hash_vec_query(&[1., 0.1]);
+        let mut ids: Vec<DataId> = lsh
+            .hash_tables
+            .as_ref()
+            .unwrap()
+            .query_bucket(&hash, 0)
+            .unwrap()
+            .into_iter()
+            .collect();
+        ids.sort_unstable();
+        ids
+    };
+    // the bit is the sign of x.
+    lsh.update_hasher_projection(0, 0, aview1(&[1., 0.]))
+        .unwrap();
+    assert_eq!(bucket_of_first(&lsh), vec![0]);
+    // the bit is the sign of y.
+    lsh.update_hasher_projection(0, 0, aview1(&[0., 1.]))
+        .unwrap();
+    assert_eq!(bucket_of_first(&lsh), vec![0, 1]);
+    assert_eq!(lsh.query_bucket_ids(&[1., 0.1]).unwrap().len(), 2);
+
+    assert!(lsh
+        .update_hasher_projection(2, 0, aview1(&[0., 1.]))
+        .is_err());
+    assert!(lsh
+        .update_hasher_projection(0, 1, aview1(&[0., 1.]))
+        .is_err());
+    assert!(lsh.update_hasher_projection(0, 0, aview1(&[1.])).is_err());
+    // failed updates leave the hashers and the buckets untouched.
+    assert_eq!(bucket_of_first(&lsh), vec![0, 1]);
+
+    // ids that didn't fit in a full bucket are re-hashed as well.
+    let mut lsh = LshMem::new(1, 2, 2).seed(1).srp().unwrap();
+    lsh.with_max_bucket_size(1).unwrap();
+    lsh.update_hasher_projection(0, 0, aview1(&[0., 1.]))
+        .unwrap();
+    lsh.store_vecs(&[vec![1., 0.1], vec![-1., 0.1]]).unwrap();
+    assert_eq!(bucket_of_first(&lsh), vec![0]);
+    lsh.update_hasher_projection(0, 0, aview1(&[1., 0.]))
+        .unwrap();
+    let hash = lsh.hashers[0].hash_vec_query(&[-1., 0.1]);
+    let bucket = lsh.hash_tables.as_ref().unwrap().query_bucket(&hash, 0);
+    assert!(bucket.unwrap().contains(&1));
+
+    let mut only_index = LshMem::new(1, 1, 2).seed(1).only_index().srp().unwrap();
+    assert!(only_index
+        .update_hasher_projection(0, 0, aview1(&[0., 1.]))
+        .is_err());
+}
+
+#[test]
+fn test_embed() {
+    use crate::{dist::cosine_sim, utils::rand_unit_vec};
+    use rand::{rngs::SmallRng, SeedableRng};
+    let lsh = LshMem::new(16, 20, 10).seed(1).srp().unwrap();
+    let vs: Vec<Vec<f32>> = (0..40)
+        .map(|i| rand_unit_vec(10, SmallRng::seed_from_u64(i)))
+        .collect();
+    let emb = lsh.embed_batch(&vs).unwrap();
+    assert_eq!(emb.dim(), (40, 320));
+    assert_eq!(emb.row(3).to_vec(), lsh.embed(&vs[3]).unwrap());
+    assert!(emb.iter().all(|&x| x == 0. || x == 1.));
+
+    // hamming distance of the embeddings vs cosine distance of the data points.
+    let mut pairs = vec![];
+    for i in 0..vs.len() {
+        for j in i + 1..vs.len() {
+            let hamming = (&emb.row(i) - &emb.row(j)).mapv(f32::abs).sum() / 320.;
+            pairs.push((hamming, 1. - cosine_sim(&vs[i], &vs[j])));
+        }
+    }
+    let n = pairs.len() as f32;
+    let (mean_h, mean_c) = pairs
+        .iter()
+        .fold((0., 0.), |(h, c), (x, y)| (h + x / n, c + y / n));
+    let cov: f32 = pairs.iter().map(|(x, y)| (x - mean_h) * (y - mean_c)).sum();
+    let var_h: f32 = pairs.iter().map(|(x, _)| (x - mean_h).powi(2)).sum();
+    let var_c: f32 = pairs.iter().map(|(_, y)| (y - mean_c).powi(2)).sum();
+    let corr = cov / (var_h * var_c).sqrt();
+    assert!(corr > 0.9, "correlation {}", corr);
+    assert!(lsh.embed(&[1., 2.]).is_err());
+}
+
+#[test]
+fn test_memory_usage() {
+    let vs: Vec<Vec<f32>> = (0..100)
+        .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+        .collect();
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    let empty = lsh.memory_usage().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let stats = lsh.memory_usage().unwrap();
+    assert_eq!(stats.hasher_bytes, empty.hasher_bytes);
+    assert!(stats.data_store_bytes >= 100 * 3 * 4);
+    assert!(stats.bucket_map_bytes > empty.bucket_map_bytes);
+    assert_eq!(
+        stats.total_bytes,
+        stats.hasher_bytes + stats.bucket_map_bytes + stats.data_store_bytes
+    );
+
+    let mut only_index = LshMem::new(5, 10, 3).seed(1).only_index().srp().unwrap();
+    only_index.store_vecs(&vs).unwrap();
+    let index_stats = only_index.memory_usage().unwrap();
+    assert!(index_stats.data_store_bytes < stats.data_store_bytes);
+    assert_eq!(index_stats.bucket_map_bytes, stats.bucket_map_bytes);
+}
+
+#[test]
+fn test_query_batch_dedup() {
+    let mut lsh = LshMem::new(5, 10, 3).seed(1).srp().unwrap();
+    let vs: Vec<Vec<f32>> = (0..20)
+        .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+        .collect();
+    lsh.store_vecs(&vs).unwrap();
+    let qs = vec![
+        vs[0].clone(),
+        vs[3].clone(),
+        vs[0].clone(),
+        vec![0.1, 0.2, 0.3],
+        vs[3].clone(),
+    ];
+    let mut expected = lsh.query_bucket_ids_batch(&qs).unwrap();
+    let mut dedup = lsh.query_bucket_ids_batch_dedup(&qs).unwrap();
+    expected.iter_mut().for_each(|ids| ids.sort_unstable());
+    dedup.iter_mut().for_each(|ids| ids.sort_unstable());
+    assert_eq!(dedup, expected);
+    assert!(lsh.query_bucket_ids_batch_dedup(&[vec![1.]]).is_err());
+}
+
+#[test]
+fn test_weighted_srp() {
+    let mut lsh = LshMem::new(8, 4, 3)
+        .seed(1)
+        .weighted_srp(vec![1., 0.5, 2.])
+        .unwrap();
+    lsh.store_vec(&[2., 3., 4.]).unwrap();
+    // the stored data point isn't weighted.
+    assert_eq!(
+        lsh.query_bucket(&[2., 3., 4.]).unwrap(),
+        vec![&vec![2., 3., 4.]]
+    );
+    assert!(LshMem::new(8, 4, 3).weighted_srp(vec![1., 1.]).is_err());
+    assert!(LshMem::new(8, 4, 3)
+        .weighted_srp(vec![1., -1., 1.])
+        .is_err());
+}
+
+#[test]
+fn test_query_probe_stats() {
+    let vs: Vec<Vec<f32>> = (0..50)
+        .map(|i| vec![(i as f32).sin(), (i as f32).cos(), 0.5])
+        .collect();
+    let q = [0.3, 0.9, 0.5];
+    let mut srp = LshMem::new(6, 2, 3).seed(1).srp().unwrap();
+    srp.store_vecs(&vs).unwrap();
+    assert!(srp.query_bucket_ids_with_probe_stats(&q).is_err());
+    // MIPS isn't query directed, so it uses step wise probing.
+    let mut mips = LshMem::new(4, 2, 3).seed(1).mips(1., 0.83, 3).unwrap();
+    for h in &mut mips.hashers {
+        vs.iter().for_each(|v| h.fit(v));
+    }
+    mips.store_vecs(&vs).unwrap();
+
+    srp.multi_probe(5);
+    mips.multi_probe(5);
+    for (ids, stats) in [
+        srp.query_bucket_ids_with_probe_stats(&q).unwrap(),
+        mips.query_bucket_ids_with_probe_stats(&q).unwrap(),
+    ] {
+        assert!(stats.per_probe[0].0.iter().all(|&p| p == 0));
+        let n: usize = stats.per_probe.iter().map(|(_, n)| n).sum();
+        assert_eq!(n, ids.len());
+    }
+    let mut expected = srp.query_bucket_ids(&q).unwrap();
+    let mut ids = srp.query_bucket_ids_with_probe_stats(&q).unwrap().0;
+    expected.sort_unstable();
+    ids.sort_unstable();
+    assert_eq!(ids, expected);
+}
+
+#[test]
+fn test_multi_index() {
+    let (dim, k, l) = (32, 12, 4);
+    let (vs, qs) = correlated_pairs(300, dim, 0.85, 5);
+    let mut a = LshMem::new(k, l, dim).seed(1).only_index().srp().unwrap();
+    let mut b = LshMem::new(k, l, dim).seed(2).only_index().srp().unwrap();
+    a.store_vecs(&vs).unwrap();
+    b.store_vecs(&vs).unwrap();
+
+    let r_a = recall_at_k(a.query_bucket_ids_batch_par(&qs).unwrap());
+    let r_ab = recall_at_k(a.query_bucket_ids_multi_index(&qs, &b).unwrap());
+    assert!(r_ab > r_a + 0.05, "{} {}", r_ab, r_a);
+
+    let c = LshMem::new(k, l, dim + 1).srp().unwrap();
+    assert!(a.query_bucket_ids_multi_index(&qs, &c).is_err());
+}
+
+#[test]
+fn test_debug_query_trace() {
+    let mut lsh = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., -1., 1.]])
+        .unwrap();
+    let hashes = lsh.debug_hash(&[2., 3., 4.]).unwrap();
+    assert_eq!(hashes.len(), 4);
+    assert_eq!(hashes[1], lsh.hashers[1].hash_vec_query(&[2., 3., 4.]));
+    assert!(lsh.debug_hash(&[2., 3.]).is_err());
+
+    let trace = lsh.debug_query_trace(&[2., 3., 4.]).unwrap();
+    assert_eq!(trace.tables_containing(0), vec![0, 1, 2, 3]);
+    assert!(trace.missing_buckets().is_empty());
+    assert_eq!(trace.tables[0].hash, hashes[0]);
+
+    lsh.delete_by_id(0).unwrap();
+    let trace = lsh.debug_query_trace(&[2., 3., 4.]).unwrap();
+    assert!(trace.tables_containing(0).is_empty());
+    assert_eq!(
+        trace.missing_buckets().len() + trace.empty_buckets().len(),
+        4 - trace.tables_containing(1).len()
+    );
+}
+
+#[test]
+fn test_approximate_ppr() {
+    // two clusters, the walk shouldn't leave the cluster of the source.
+    let vs = vec![
+        vec![1., 0.1, 0.],
+        vec![1., 0., 0.1],
+        vec![1., 0.05, 0.05],
+        vec![-1., 0., 0.],
+        vec![-1., 0.1, 0.],
+    ];
+    let mut lsh = LshMem::new(3, 5, 3).seed(2).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let ppr = lsh.approximate_ppr(0, 1000, 0.2).unwrap();
+    assert!((ppr.values().sum::<f32>() - 1.).abs() < 1e-4);
+    assert!(ppr[&0] > 0.2);
+    assert!(!ppr.contains_key(&3) && !ppr.contains_key(&4));
+    assert!(lsh.approximate_ppr(0, 10, 1.5).is_err());
+}
+
+#[test]
+fn test_query_knn_with_guarantee() {
+    let vs: Vec<Vec<f32>> = (0..100)
+        .map(|i| vec![(i as f32 * 0.7).sin(), (i as f32 * 0.3).cos(), 1.])
+        .collect();
+    // many projections, so that the buckets are small.
+    let mut lsh = LshMem::new(24, 2, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let q = &vs[10];
+
+    let res = lsh.query_knn_with_guarantee(q, 5, 1.).unwrap();
+    assert!(res.used_fallback);
+    // a full exact search
+    assert_eq!(res.ids.len(), 5);
+    assert_eq!(res.ids[0].0, 10);
+    assert!(res.ids[0].1.abs() < 1e-6);
+    assert!(res.ids.windows(2).all(|w| w[0].1 <= w[1].1));
+
+    let res = lsh.query_knn_with_guarantee(q, 1, 1.).unwrap();
+    assert!(!res.used_fallback);
+    assert_eq!(res.ids[0].0, 10);
+    assert!(lsh.query_knn_with_guarantee(q, 1, 0.).is_err());
+
+    // SRP ranks by cosine similarity, L2 by distance.
+    let vs = vec![vec![1., 0.], vec![10., 1.]];
+    let q = &[10., 0.];
+    let mut lsh = LshMem::new(24, 2, 2).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    assert_eq!(lsh.query_knn_with_guarantee(q, 2, 1.).unwrap().ids[0].0, 0);
+    let mut lsh = LshMem::new(24, 2, 2).seed(1).l2(4.).unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    assert_eq!(lsh.query_knn_with_guarantee(q, 2, 1.).unwrap().ids[0].0, 1);
+}
+
+#[test]
+fn test_batch_query_rank() {
+    let vs: Vec<Vec<f32>> = (0..100)
+        .map(|i| vec![(i as f32 * 0.7).sin(), (i as f32 * 0.3).cos(), 1.])
+        .collect();
+    let mut lsh = LshMem::new(6, 4, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let queries = &vs[..10];
+
+    let ranked = lsh.batch_query_rank(queries, 5).unwrap();
+    assert_eq!(ranked.len(), queries.len());
+    for (i, (q, r)) in queries.iter().zip(&ranked).enumerate() {
+        // same as ranking the queried ids afterwards.
+        let mut expected: Vec<(DataId, f32)> = lsh
+            .query_bucket_ids(q)
+            .unwrap()
+            .into_iter()
+            .map(|id| (id, crate::dist::l2_dist(&vs[id as usize], q)))
+            .collect();
+        expected.sort_unstable_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        expected.truncate(5);
+        assert_eq!(r, &expected);
+        assert_eq!(r[0], (i as DataId, 0.));
+    }
+    assert!(lsh.batch_query_rank(&[vec![1., 2.]], 5).is_err());
+
+    // quantized data points are decoded for the distances.
+    let mut lsh = LshMem::new(6, 4, 3).seed(1).srp().unwrap();
+    lsh.scalar_quantization().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let ranked = lsh.batch_query_rank(queries, 1).unwrap();
+    assert!(ranked
+        .iter()
+        .enumerate()
+        .all(|(i, r)| r[0].0 == i as DataId));
+}
+
+#[test]
+fn test_iter_with_data() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.], vec![0., 1., 0.]];
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    let ids = lsh.store_vecs(&vs).unwrap();
+    lsh.delete_by_id(1).unwrap();
+    let items: Vec<_> = lsh.iter_with_data().unwrap().collect();
+    let iter_ids: Vec<DataId> = items.iter().map(|(idx, _, _)| *idx).collect();
+    assert_eq!(iter_ids, vec![0, 2]);
+    assert!(ids.iter().all(|idx| *idx == 1 || iter_ids.contains(idx)));
+    for (idx, d, hashes) in items {
+        assert_eq!(d, &vs[idx as usize]);
+        assert_eq!(hashes.len(), 3);
+        for (i, hash) in hashes {
+            assert_eq!(hash, &lsh.hashers[i].hash_vec_put(d));
+        }
+    }
+
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).only_index().srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    assert!(lsh.iter_with_data().is_err());
+}
+
+#[test]
+fn test_export_to_arrays() {
+    let vs = vec![vec![2., 3., 4.], vec![-1., -1., 1.], vec![0., 1., 0.]];
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    lsh.delete_by_id(1).unwrap();
+    let (data, ids) = lsh.export_to_arrays().unwrap();
+    assert_eq!(ids.to_vec(), vec![0, 2]);
+    assert_eq!(data.row(1).to_vec(), vs[2]);
+
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).only_index().srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let (data, ids) = lsh.export_to_arrays().unwrap();
+    assert_eq!(data.shape(), &[0, 3]);
+    assert_eq!(ids.to_vec(), vec![0, 1, 2]);
+}
+
+#[test]
+fn test_tensor_sketch() {
+    let mut lsh = LshMem::new(6, 5, 3)
+        .seed(1)
+        .tensor_sketch(2, 64, 1.)
+        .unwrap();
+    lsh.store_vecs(&[vec![2., 3., 4.], vec![-1., -1., 1.]])
+        .unwrap();
+    assert!(lsh.query_bucket_ids(&[2., 3., 4.]).unwrap().contains(&0));
+    assert!(LshMem::new(6, 5, 3).tensor_sketch(0, 64, 1.).is_err());
+}
+
+#[test]
+fn test_query_approximate_count() {
+    let vs = vec![
+        vec![1., 0., 0.],
+        vec![1., 0.02, 0.],
+        vec![1., 0.3, 0.3],
+        vec![-1., 0., 0.],
+    ];
+    let mut lsh = LshMem::new(6, 20, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let top = lsh
+        .query_bucket_ids_approximate_count(&vs[0], 2, 0.01)
+        .unwrap();
+    assert_eq!(top.len(), 2);
+    // the query itself collides in all tables.
+    assert_eq!(top[0], (0, 20.));
+    assert_eq!(top[1].0, 1);
+    assert!(lsh
+        .query_bucket_ids_approximate_count(&vs[0], 2, 0.)
+        .is_err());
+    assert!(lsh
+        .query_bucket_ids_approximate_count(&vs[0], 2, 1.)
+        .is_err());
+}
+
+#[test]
+fn test_geohash() {
+    let mut lsh = LshMem::new(1, 4, 2).seed(1).geohash(20).unwrap();
+    lsh.store_vecs(&[vec![4.900, 52.379], vec![2.352, 48.857]])
+        .unwrap();
+    assert_eq!(lsh.query_bucket_ids(&[4.893, 52.373]).unwrap(), vec![0]);
+    assert!(LshMem::new(1, 4, 3).geohash(20).is_err());
+}
+
+#[test]
+fn test_sample() {
+    let vs: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, 1., -1.]).collect();
+    let mut lsh = LshMem::new(5, 2, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let mut sample = lsh.sample(5, 1).unwrap();
+    assert_eq!(sample, lsh.sample(5, 1).unwrap());
+    sample.sort_unstable();
+    sample.dedup();
+    assert_eq!(sample.len(), 5);
+    assert_eq!(lsh.sample(50, 1).unwrap().len(), 20);
+
+    #[cfg(feature = "sqlite")]
+    {
+        let mut sql = LshSqlMem::new(5, 2, 3).srp().unwrap();
+        sql.store_vecs(&vs).unwrap();
+        let sample = sql.sample(5, 1).unwrap();
+        assert_eq!(sample.len(), 5);
+        assert!(sample.iter().all(|&id| id < 20));
+        assert_eq!(sql.sample(50, 1).unwrap().len(), 20);
+    }
+}
+
+#[test]
+fn test_label_propagation() {
+    // two well separated clusters.
+    let mut vs: Vec<Vec<f32>> = (0..20).map(|i| vec![1., 0.01 * i as f32, 0.]).collect();
+    vs.extend((0..20).map(|i| vec![-1., 0., 0.01 * i as f32]));
+    let mut lsh = LshMem::new(6, 8, 3).seed(1).only_index().srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+
+    let labels = lsh.label_propagation(2, &[(0, 0), (20, 1)], 5).unwrap();
+    for id in 0..40 {
+        if let Some(&class) = labels.get(&id) {
+            assert_eq!(class, (id >= 20) as usize, "{}", id);
+        }
+    }
+    assert!(labels.len() > 30);
+    assert!(lsh.label_propagation(2, &[(0, 2)], 5).is_err());
+}
+
+#[test]
+fn test_sketched_query() {
+    let (dim, l) = (16, 40);
+    let (vs, qs) = correlated_pairs(200, dim, 0.9, 6);
+    let mut lsh = LshMem::new(8, l, dim).seed(1).only_index().srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    assert!(lsh.query_bucket_ids_sketched(&qs[0]).is_err());
+    lsh.sketched_query();
+
+    let recall = recall_at_k(qs.iter().map(|q| {
+        let sketched = lsh.query_bucket_ids_sketched(q).unwrap();
+        let full = lsh.query_bucket_ids(q).unwrap();
+        assert!(sketched.iter().all(|id| full.contains(id)));
+        sketched
+    }));
+    // 10 of the 40 tables are queried.
+    assert!(recall > 0.5, "{}", recall);
+
+    // the sketch is dumped, so the loaded LSH selects the same tables.
+    let dir = tempfile::tempdir().unwrap();
+    let tmp = dir.path().join("sketched.bincode");
+    lsh.dump(&tmp).unwrap();
+    let mut loaded = LshMem::new(8, l, dim).only_index().srp().unwrap();
+    loaded.load(&tmp).unwrap();
+    for q in &qs[..20] {
+        let mut expected = lsh.query_bucket_ids_sketched(q).unwrap();
+        let mut ids = loaded.query_bucket_ids_sketched(q).unwrap();
+        expected.sort_unstable();
+        ids.sort_unstable();
+        assert_eq!(ids, expected);
+    }
+}
+
+#[test]
+fn test_apply_to_all() {
+    let vs: Vec<Vec<f32>> = (1..30)
+        .map(|i| vec![i as f32, (i % 4) as f32 - 2., 0.5 * i as f32])
+        .collect();
+    let mut lsh = LshMem::new(4, 3, 3).seed(1).l2(0.5).unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    lsh.apply_to_all(|v| {
+        let norm = crate::dist::l2_norm(v);
+        v.iter_mut().for_each(|x| *x /= norm);
+    })
+    .unwrap();
+
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    for idx in 0..vs.len() as DataId {
+        let v = ht.idx_to_datapoint(idx).unwrap();
+        assert!((crate::dist::l2_norm(v) - 1.).abs() < 1e-5);
+        // the buckets match the transformed data points.
+        assert!(lsh.query_bucket_ids(v).unwrap().contains(&idx));
+    }
+    let n_ids: usize = ht.to_bucket_map().unwrap()[0]
+        .values()
+        .map(|b| b.len())
+        .sum();
+    assert_eq!(n_ids, vs.len());
+
+    // a transformation that changes the dimension of the last data point changes nothing.
+    let buckets = ht.to_vec_of_buckets();
+    let before = ht.idx_to_datapoint(5).unwrap().clone();
+    let n_calls = std::cell::Cell::new(0);
+    assert!(lsh
+        .apply_to_all(|v| {
+            n_calls.set(n_calls.get() + 1);
+            if n_calls.get() == vs.len() {
+                v.pop();
+            }
+            v.iter_mut().for_each(|x| *x *= 2.);
+        })
+        .is_err());
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    assert_eq!(ht.idx_to_datapoint(5).unwrap(), &before);
+    assert_eq!(ht.to_vec_of_buckets(), buckets);
+
+    let mut index_only = LshMem::new(4, 3, 3).only_index().srp().unwrap();
+    assert!(index_only.apply_to_all(|_| {}).is_err());
+}
+
+#[test]
+fn test_multi_scale_l2() {
+    use crate::utils::create_rng;
+    use rand::Rng;
+    use rand_distr::StandardNormal;
+
+    // a tight and a spread cluster. The queries are perturbed data points, with a perturbation
+    // on the scale of their cluster. The hash values stay within the range of a HashPrimitive.
+    let (dim, n) = (8, 100);
+    let mut rng = create_rng(3);
+    let mut noise = |scale: f32| -> Vec<f32> {
+        (0..dim)
+            .map(|_| scale * rng.sample::<f32, _>(StandardNormal))
+            .collect()
+    };
+    let mut vs = vec![];
+    let mut qs: Vec<Vec<f32>> = vec![];
+    for i in 0..2 * n {
+        let scale = if i < n { 0.05 } else { 1. };
+        let v = noise(scale * 5.);
+        qs.push(v.iter().zip(noise(scale)).map(|(a, b)| a + b).collect());
+        vs.push(v);
+    }
+    let recall =
+        |lsh: &LshMem<crate::L2>| recall_at_k(qs.iter().map(|q| lsh.query_bucket_ids(q).unwrap()));
+    let mut single = LshMem::new(6, 8, dim).seed(1).only_index().l2(1.).unwrap();
+    let mut multi = LshMem::new(6, 8, dim)
+        .seed(1)
+        .only_index()
+        .multi_scale_l2(&[1., 20.])
+        .unwrap();
+    assert_eq!(multi.hashers[3].r, 1.);
+    assert_eq!(multi.hashers[4].r, 20.);
+    single.store_vecs(&vs).unwrap();
+    multi.store_vecs(&vs).unwrap();
+    let (r_single, r_multi) = (recall(&single), recall(&multi));
+    assert!(r_multi > r_single + 0.3, "{} {}", r_multi, r_single);
+
+    assert!(LshMem::new(6, 2, dim)
+        .multi_scale_l2(&[1., 2., 3.])
+        .is_err());
+    assert!(LshMem::new(6, 2, dim).multi_scale_l2(&[0.]).is_err());
+}
+
+#[test]
+fn test_build_from_pairs() {
+    let pairs: Vec<(DataId, Vec<f32>)> = (0..10)
+        .map(|i| (100 + 3 * i, vec![i as f32, 1., -1.]))
+        .collect();
+    let mut lsh = LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    lsh.store_vec(&[5., 5., 5.]).unwrap();
+    let lsh = lsh.build_from_pairs(pairs.clone().into_iter()).unwrap();
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    for (idx, v) in &pairs {
+        assert_eq!(ht.idx_to_datapoint(*idx).unwrap(), v);
+        assert!(lsh.query_bucket_ids(v).unwrap().contains(idx));
+    }
+    // the previous content is replaced.
+    assert!(!lsh.query_bucket_ids(&[5., 5., 5.]).unwrap().contains(&0));
+
+    let mut duplicates = pairs.clone();
+    duplicates.push(pairs[0].clone());
+    let new = || LshMem::new(5, 3, 3).seed(1).srp().unwrap();
+    assert!(new().build_from_pairs(duplicates.into_iter()).is_err());
+    let wrong_dim = vec![(1, vec![1., 2.])];
+    assert!(new().build_from_pairs(wrong_dim.into_iter()).is_err());
+}
+
+#[test]
+fn test_to_csr() {
+    let vs: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, 1., -1.]).collect();
+    let mut lsh = LshMem::new(3, 4, 3).seed(1).srp().unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let csr = lsh.to_csr().unwrap();
+    let buckets = lsh.hash_tables.as_ref().unwrap().to_bucket_map().unwrap();
+
+    assert_eq!(
+        csr.n_rows(),
+        buckets.iter().map(|tbl| tbl.len()).sum::<usize>()
+    );
+    assert_eq!(csr.nnz(), 4 * 20);
+    assert_eq!(csr.n_cols, 20);
+    for i in 0..csr.n_rows() {
+        let tbl = &buckets[csr.bucket_table_ids[i]];
+        assert!(tbl.values().any(|ids| ids == csr.row(i)));
+    }
+}
+
+#[test]
+fn test_occupancy_ratio() {
+    let vs: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, 1., -1.]).collect();
+    let mut lsh = LshMem::new(5, 2, 3).seed(1).only_index().srp().unwrap();
+    assert_eq!(lsh.occupancy_ratio().unwrap(), 1.);
+    lsh.store_vecs(&vs).unwrap();
+    assert_eq!(lsh.occupancy_ratio().unwrap(), 1.);
+    let (_, n_total) = lsh
+        .hash_tables
+        .as_ref()
+        .unwrap()
+        .count_empty_buckets()
+        .unwrap();
+
+    let ids: Vec<DataId> = (0..20).collect();
+    lsh.batch_delete_by_ids(&ids).unwrap();
+    let (n_empty, n_after) = lsh
+        .hash_tables
+        .as_ref()
+        .unwrap()
+        .count_empty_buckets()
+        .unwrap();
+    assert_eq!((n_empty, n_after), (n_total, n_total));
+    assert_eq!(lsh.occupancy_ratio().unwrap(), 0.);
+
+    #[cfg(feature = "sqlite")]
+    {
+        let mut sql = LshSqlMem::new(5, 2, 3).seed(1).srp().unwrap();
+        sql.store_vecs(&vs).unwrap();
+        let (n_empty, n_sql) = sql
+            .hash_tables
+            .as_ref()
+            .unwrap()
+            .count_empty_buckets()
+            .unwrap();
+        assert_eq!((n_empty, n_sql), (0, n_total));
+    }
+}
+
+#[test]
+fn test_delta_encoded() {
+    use crate::utils::create_rng;
+    use rand::Rng;
+
+    let dim = 50;
+    let mut rng = create_rng(1);
+    let mut v: Vec<f32> = (0..dim).map(|_| rng.gen::<f32>() * 1000.).collect();
+    let mut vs = vec![];
+    for step in 0..100 {
+        // perturb a few dimensions per step
+        for _ in 0..3 {
+            let i = rng.gen_range(0, dim);
+            v[i] += rng.gen::<f32>() * 0.1 - 0.05;
+        }
+        // a jump whose delta is rounded.
+        if step % 10 == 5 {
+            v[0] = rng.gen::<f32>() * 1e-3;
+        }
+        vs.push(v.clone());
+    }
+
+    let mut lsh = LshMem::new(5, 3, dim)
+        .seed(1)
+        .srp()
+        .unwrap()
+        .delta_encoded()
+        .unwrap();
+    lsh.store_vecs(&vs).unwrap();
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    for (i, v) in vs.iter().enumerate() {
+        assert_eq!(&ht.idx_to_datapoint_owned(i as DataId).unwrap(), v);
+    }
+    assert!(ht.n_stored_values() < 100 * dim / 4);
+    assert!(!lsh.query_bucket_owned(&vs[42]).unwrap().is_empty());
+
+    lsh.delete_by_id(10).unwrap();
+    let ht = lsh.hash_tables.as_ref().unwrap();
+    assert!(ht.idx_to_datapoint_owned(10).is_err());
+    assert!(ht.idx_to_datapoint_owned(11).is_ok());
+}
@@ -0,0 +1,255 @@
+//! Banded MinHash LSH for the Jaccard similarity of sets, e.g. of [shingles](../text/fn.shingle.html).
+use crate::{utils::create_rng, DataId, Error, Result};
+use fnv::FnvHashMap as HashMap;
+use rand::Rng;
+
+/// A family of hashers of which a single hasher computes the MinHash values of one band.
+pub trait MinHashFamily {
+    /// # Arguments
+    /// * `n_rows` - Number of MinHash values (rows) in a band.
+    /// * `n_items` - Number of possible items. Items are in `0..n_items`.
+    /// * `seed` - Seed for the RNG's.
+    fn new(n_rows: usize, n_items: u32, seed: u64) -> Self;
+
+    /// MinHash values of a non empty set.
+    fn min_hash(&self, items: &[u32]) -> Vec<u64>;
+}
+
+fn sorted_set(items: &[u32]) -> Vec<u32> {
+    let mut set = items.to_vec();
+    set.sort_unstable();
+    set.dedup();
+    set
+}
+
+/// Exact Jaccard similarity of two sorted and deduplicated sets.
+fn jaccard_sorted(a: &[u32], b: &[u32]) -> f32 {
+    let (mut i, mut j, mut intersection) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                intersection += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    let union = a.len() + b.len() - intersection;
+    if union == 0 {
+        return 0.;
+    }
+    intersection as f32 / union as f32
+}
+
+/// SplitMix64 finalizer. A bijective mix of all bits.
+fn mix64(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// MinHash with seeded SplitMix64 hash functions as approximate permutations.
+/// Linear hash functions are avoided, as they are biased for ranges of consecutive items.
+#[derive(Debug, Clone)]
+pub struct MinHash {
+    seeds: Vec<u64>,
+}
+
+impl MinHashFamily for MinHash {
+    fn new(n_rows: usize, _n_items: u32, seed: u64) -> Self {
+        let mut rng = create_rng(seed);
+        MinHash {
+            seeds: (0..n_rows).map(|_| rng.gen()).collect(),
+        }
+    }
+
+    fn min_hash(&self, items: &[u32]) -> Vec<u64> {
+        self.seeds
+            .iter()
+            .map(|&seed| {
+                items
+                    .iter()
+                    .map(|&x| mix64(x as u64 ^ seed))
+                    .min()
+                    .unwrap_or(u64::MAX)
+            })
+            .collect()
+    }
+}
+
+/// LSH for the Jaccard similarity. Sets are candidates if their MinHash signatures agree in all
+/// rows of any band. Two sets with Jaccard similarity `s` collide with probability
+/// `1 - (1 - s^r)^b`, with `r` rows per band and `b` bands.
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::minhash::{JaccardLSH, MinHash};
+/// let mut lsh: JaccardLSH<MinHash> = JaccardLSH::new(20, 5, 1000, 1);
+/// let id = lsh.store_set(&[1, 2, 3, 4]).unwrap();
+/// assert!(lsh.query_set(&[1, 2, 3, 4]).unwrap().contains(&id));
+/// ```
+pub struct JaccardLSH<H: MinHashFamily> {
+    /// Number of bands. Every band has its own hash table.
+    pub n_hash_tables: usize,
+    /// Number of rows per band.
+    pub n_projections: usize,
+    /// Number of possible items.
+    pub n_items: u32,
+    /// Hasher per band.
+    pub hashers: Vec<H>,
+    hash_tables: Vec<HashMap<Vec<u64>, Vec<DataId>>>,
+    counter: DataId,
+    /// Stored sets, sorted and deduplicated. Indexed by id.
+    sets: Vec<Vec<u32>>,
+}
+
+impl<H: MinHashFamily> JaccardLSH<H> {
+    /// Create a new banded MinHash LSH.
+    ///
+    /// # Arguments
+    /// * `n_bands` - Number of bands `b`.
+    /// * `rows_per_band` - Number of MinHash values per band `r`.
+    /// * `n_items` - Number of possible items. Items are in `0..n_items`.
+    /// * `seed` - Seed for the RNG's. If 0, RNG's are seeded randomly.
+    pub fn new(n_bands: usize, rows_per_band: usize, n_items: u32, seed: u64) -> Self {
+        let mut rng = create_rng(seed);
+        let hashers = (0..n_bands)
+            .map(|_| H::new(rows_per_band, n_items, rng.gen()))
+            .collect();
+        JaccardLSH {
+            n_hash_tables: n_bands,
+            n_projections: rows_per_band,
+            n_items,
+            hashers,
+            hash_tables: vec![HashMap::default(); n_bands],
+            counter: 0,
+            sets: vec![],
+        }
+    }
+
+    fn validate_set(&self, items: &[u32]) -> Result<()> {
+        if items.is_empty() {
+            return Err(Error::Failed("set is empty".to_string()));
+        }
+        if items.iter().any(|&x| x >= self.n_items) {
+            return Err(Error::Failed(format!(
+                "items should be smaller than {}",
+                self.n_items
+            )));
+        }
+        Ok(())
+    }
+
+    /// Store a set of items. Returns id.
+    ///
+    /// # Arguments
+    /// * `items` - Non empty set of items.
+    pub fn store_set(&mut self, items: &[u32]) -> Result<DataId> {
+        self.validate_set(items)?;
+        let idx = self.counter;
+        for (hasher, table) in self.hashers.iter().zip(self.hash_tables.iter_mut()) {
+            table.entry(hasher.min_hash(items)).or_default().push(idx);
+        }
+        self.counter += 1;
+        self.sets.push(sorted_set(items));
+        Ok(idx)
+    }
+
+    /// Query the ids of the sets that collide with `items` in at least one band.
+    ///
+    /// # Arguments
+    /// * `items` - Non empty set of items.
+    pub fn query_set(&self, items: &[u32]) -> Result<Vec<DataId>> {
+        self.validate_set(items)?;
+        let mut ids: Vec<DataId> = self
+            .hashers
+            .iter()
+            .zip(&self.hash_tables)
+            .filter_map(|(hasher, table)| table.get(&hasher.min_hash(items)))
+            .flatten()
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// Query the candidates of `items` and re-rank them by their exact Jaccard similarity with
+    /// `items`. Returns the `top_k` `(id, similarity)` pairs, most similar first.
+    ///
+    /// # Arguments
+    /// * `query_set` - Non empty set of items.
+    /// * `top_k` - Maximum number of results.
+    pub fn query_and_rerank_jaccard(
+        &self,
+        query_set: &[u32],
+        top_k: usize,
+    ) -> Result<Vec<(DataId, f32)>> {
+        let query = sorted_set(query_set);
+        let mut ranked: Vec<(DataId, f32)> = self
+            .query_set(query_set)?
+            .into_iter()
+            .map(|id| (id, jaccard_sorted(&query, &self.sets[id as usize])))
+            .collect();
+        // ties are ordered by id.
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(top_k);
+        Ok(ranked)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collision_probability() {
+        let (b, r) = (10, 4);
+        // |A ∩ B| = 60, |A ∪ B| = 120 -> s = 0.5
+        let a: Vec<u32> = (0..90).collect();
+        let c: Vec<u32> = (30..120).collect();
+        let s: f64 = 0.5;
+        let expected = 1. - (1. - s.powi(r as i32)).powi(b as i32);
+
+        let n = 400;
+        let collisions = (1..=n)
+            .filter(|&seed| {
+                let mut lsh: JaccardLSH<MinHash> = JaccardLSH::new(b, r, 200, seed);
+                lsh.store_set(&a).unwrap();
+                !lsh.query_set(&c).unwrap().is_empty()
+            })
+            .count();
+        let p = collisions as f64 / n as f64;
+        assert!((p - expected).abs() < 0.08, "{} {}", p, expected);
+
+        let lsh: JaccardLSH<MinHash> = JaccardLSH::new(b, r, 200, 1);
+        assert!(lsh.query_set(&[]).is_err());
+        assert!(lsh.query_set(&[200]).is_err());
+    }
+
+    #[test]
+    fn test_rerank_jaccard() {
+        assert_eq!(jaccard_sorted(&[1, 2, 3], &[2, 3, 4, 5]), 0.4);
+        let mut lsh: JaccardLSH<MinHash> = JaccardLSH::new(20, 2, 100, 1);
+        let far = lsh.store_set(&(0..20).collect::<Vec<_>>()).unwrap();
+        let near = lsh
+            .store_set(&(0..11).chain(50..52).collect::<Vec<_>>())
+            .unwrap();
+        let exact = lsh.store_set(&(0..12).rev().collect::<Vec<_>>()).unwrap();
+
+        // duplicates in the query are ignored.
+        let query: Vec<u32> = (0..12).chain(0..3).collect();
+        let ranked = lsh.query_and_rerank_jaccard(&query, 3).unwrap();
+        assert_eq!(
+            ranked.iter().map(|r| r.0).collect::<Vec<_>>(),
+            vec![exact, near, far]
+        );
+        assert_eq!(ranked[0].1, 1.);
+        assert_eq!(ranked[1].1, 11. / 14.);
+        assert_eq!(ranked[2].1, 12. / 20.);
+        assert_eq!(lsh.query_and_rerank_jaccard(&query, 1).unwrap().len(), 1);
+    }
+}
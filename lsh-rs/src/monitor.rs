@@ -0,0 +1,93 @@
+//! Hooks into the insertion, query and delete events of [LSH](../struct.LSH.html), e.g. to
+//! collect metrics. Only available with the `monitor` feature, without it the hooks are
+//! compiled out.
+//!
+//! ```
+//! use lsh_rs::{monitor::LoggingMonitor, LshMem};
+//! let mut lsh = LshMem::new(5, 10, 3).srp().unwrap();
+//! lsh.with_monitor(Box::new(LoggingMonitor));
+//! lsh.store_vec(&[2., 3., 4.]).unwrap();
+//! ```
+use crate::DataId;
+use log::info;
+use std::time::Duration;
+
+/// Receives the events of an [LSH](../struct.LSH.html) index.
+pub trait Monitor: Send + Sync {
+    /// A data point is stored in `n_tables` hash tables.
+    fn on_store(&self, id: DataId, n_tables: usize);
+
+    /// A query returned `n_candidates` candidates after `duration`.
+    fn on_query(&self, n_candidates: usize, duration: Duration);
+
+    /// A stored data point is deleted by id.
+    fn on_delete(&self, id: DataId);
+}
+
+/// Logs every event at the `info` level with the `log` crate.
+pub struct LoggingMonitor;
+
+impl Monitor for LoggingMonitor {
+    fn on_store(&self, id: DataId, n_tables: usize) {
+        info!("stored id {} in {} hash tables", id, n_tables)
+    }
+
+    fn on_query(&self, n_candidates: usize, duration: Duration) {
+        info!(
+            "query returned {} candidates in {:?}",
+            n_candidates, duration
+        )
+    }
+
+    fn on_delete(&self, id: DataId) {
+        info!("deleted id {}", id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LshMem;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Events {
+        stored: Vec<(DataId, usize)>,
+        candidates: Vec<usize>,
+        deleted: Vec<DataId>,
+    }
+
+    struct Recorder(Arc<Mutex<Events>>);
+
+    impl Monitor for Recorder {
+        fn on_store(&self, id: DataId, n_tables: usize) {
+            self.0.lock().unwrap().stored.push((id, n_tables))
+        }
+        fn on_query(&self, n_candidates: usize, _duration: Duration) {
+            self.0.lock().unwrap().candidates.push(n_candidates)
+        }
+        fn on_delete(&self, id: DataId) {
+            self.0.lock().unwrap().deleted.push(id)
+        }
+    }
+
+    #[test]
+    fn test_monitor() {
+        let events = Arc::new(Mutex::new(Events::default()));
+        let mut lsh = LshMem::new(5, 4, 3).seed(1).srp().unwrap();
+        lsh.with_monitor(Box::new(Recorder(events.clone())));
+        lsh.store_vec(&[2., 3., 4.]).unwrap();
+        lsh.store_vecs(&[vec![1., 1., 1.], vec![-1., 2., 0.]])
+            .unwrap();
+        lsh.query_bucket_ids(&[2., 3., 4.]).unwrap();
+        lsh.delete_by_id(1).unwrap();
+        // unknown and already deleted ids aren't reported.
+        lsh.batch_delete_by_ids(&[1, 10]).unwrap();
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.stored, vec![(0, 4), (1, 4), (2, 4)]);
+        assert_eq!(events.candidates.len(), 1);
+        assert!(events.candidates[0] >= 1);
+        assert_eq!(events.deleted, vec![1]);
+    }
+}
@@ -1,6 +1,7 @@
 use crate::utils::create_rng;
 use crate::{
-    DataPointSlice, Error, FloatSize, Hash, HashPrimitive, HashTables, Result, VecHash, L2, LSH,
+    CompactSrpHash, DataId, DataPointSlice, Error, FloatSize, Hash, HashPrimitive, HashTables,
+    MemoryTable, QueryProbeStats, Result, SignRandomProjections, VecHash, L2, LSH,
 };
 use fnv::FnvHashSet;
 use itertools::Itertools;
@@ -18,7 +19,6 @@ use std::collections::BinaryHeap;
 /// Liv, Q., Josephson, W., Whang, L., Charikar, M., & Li, K. (n.d.).
 /// Multi-Probe LSH: Efficient Indexing for High-Dimensional Similarity Search
 /// Retrieved from https://www.cs.princeton.edu/cass/papers/mplsh_vldb07.pdf
-
 pub trait QueryDirectedProbe {
     fn query_directed_probe(&self, q: &DataPointSlice, budget: usize) -> Result<Vec<Hash>>;
 }
@@ -119,6 +119,28 @@ pub fn step_wise_probing(hash_len: usize, budget: usize) -> Vec<Vec<HashPrimitiv
     hash_perturbs
 }
 
+/// Number of positions at which two hashes differ.
+pub fn hamming_distance(h1: &Hash, h2: &Hash) -> u32 {
+    h1.iter().zip(h2).filter(|(a, b)| a != b).count() as u32
+}
+
+/// Lazily generate all hashes that differ from a binary hash in at most `max_dist` bits,
+/// ordered by the Hamming distance to the original hash. The original hash is not included.
+///
+/// # Arguments
+/// * `hash` - Binary hash, e.g. a SRP hash.
+/// * `max_dist` - Maximum Hamming distance to the original hash.
+pub fn hamming_probing(hash: &Hash, max_dist: usize) -> impl Iterator<Item = Hash> + '_ {
+    let len = hash.len();
+    (1..=std::cmp::min(max_dist, len))
+        .flat_map(move |d| (0..len).combinations(d))
+        .map(move |flip| {
+            let mut h = hash.clone();
+            flip.iter().for_each(|&i| h[i] = 1 - h[i]);
+            h
+        })
+}
+
 #[derive(PartialEq, Clone)]
 struct PerturbState<'a> {
     // original sorted zj
@@ -203,13 +225,13 @@ impl<'a> PerturbState<'a> {
 // implement ordering so that we can create a min heap
 impl Ord for PerturbState<'_> {
     fn cmp(&self, other: &PerturbState) -> Ordering {
-        self.partial_cmp(other).unwrap()
+        other.score().total_cmp(&self.score())
     }
 }
 
 impl PartialOrd for PerturbState<'_> {
     fn partial_cmp(&self, other: &PerturbState) -> Option<Ordering> {
-        other.score().partial_cmp(&self.score())
+        Some(self.cmp(other))
     }
 }
 
@@ -220,7 +242,7 @@ impl L2 {
     ///
     /// As stated by Multi-Probe LSH paper:
     /// For δ ∈ {−1, +1}, let xi(δ) be the distance of q from the boundary of the slot
-    fn distance_to_bound(
+    pub(crate) fn distance_to_bound(
         &self,
         q: &DataPointSlice,
         hash: Option<&Hash>,
@@ -252,7 +274,7 @@ impl QueryDirectedProbe for L2 {
         // all below is an argsort
         let z = distances.clone();
         let mut z = z.iter().enumerate().collect::<Vec<_>>();
-        z.sort_unstable_by(|(_idx_a, a), (_idx_b, b)| a.partial_cmp(b).unwrap());
+        z.sort_unstable_by(|(_idx_a, a), (_idx_b, b)| a.total_cmp(b));
         let z = z.iter().map(|(idx, _)| *idx).collect::<Vec<_>>();
 
         let mut hashes = Vec::with_capacity(budget + 1);
@@ -284,29 +306,66 @@ impl QueryDirectedProbe for L2 {
     }
 }
 
+impl QueryDirectedProbe for SignRandomProjections {
+    fn query_directed_probe(&self, q: &DataPointSlice, budget: usize) -> Result<Vec<Hash>> {
+        let hash = self.hash_vec_query(q);
+        let mut hashes = Vec::with_capacity(budget + 1);
+        hashes.push(hash.clone());
+        hashes.extend(hamming_probing(&hash, hash.len()).take(budget));
+        Ok(hashes)
+    }
+}
+
 impl<H: VecHash, T: HashTables> LSH<T, H> {
-    pub fn multi_probe_bucket_union(&self, v: &DataPointSlice) -> Result<FnvHashSet<u32>> {
-        self.validate_vec(v)?;
+    pub fn multi_probe_bucket_union(&self, v: &DataPointSlice) -> Result<FnvHashSet<DataId>> {
         let mut bucket_union = FnvHashSet::default();
+        self.multi_probe_bucket_union_stats(v, None, &mut bucket_union)?;
+        Ok(bucket_union)
+    }
+
+    /// Add the multi-probe union of the buckets to `bucket_union`. If `stats` is given, the
+    /// number of new candidates of every probe is recorded.
+    pub(crate) fn multi_probe_bucket_union_stats(
+        &self,
+        v: &DataPointSlice,
+        mut stats: Option<&mut QueryProbeStats>,
+        bucket_union: &mut FnvHashSet<DataId>,
+    ) -> Result<()> {
+        self.validate_vec(v)?;
+        let mut probe = |hash: &Hash,
+                         original_hash: &Hash,
+                         i: usize,
+                         bucket_union: &mut FnvHashSet<DataId>|
+         -> Result<()> {
+            let n_before = bucket_union.len();
+            self.process_bucket_union_result(hash, i, bucket_union)?;
+            if let Some(stats) = stats.as_mut() {
+                let perturbation = hash.iter().zip(original_hash).map(|(a, b)| a - b).collect();
+                stats.record(perturbation, bucket_union.len() - n_before);
+            }
+            Ok(())
+        };
 
         // Check if hasher has implemented this trait. If so follow this more specialized path.
-        // Only L2 should have implemented it. This is the trick to choose a different function
-        // path for the L2 struct.
+        // Only L2 (query directed) and SRP (Hamming ranked) implemented it. This is the trick to
+        // choose a different function path for these structs.
+        let tables = self.sample_tables(v);
         if self.hashers[0].as_query_directed_probe().is_some() {
-            for (i, hasher) in self.hashers.iter().enumerate() {
-                if let Some(h) = hasher.as_query_directed_probe() {
+            for i in tables {
+                if let Some(h) = self.hashers[i].as_query_directed_probe() {
                     let hashes = h.query_directed_probe(v, self._multi_probe_budget)?;
-                    for hash in hashes {
-                        self.process_bucket_union_result(&hash, i, &mut bucket_union)?
+                    // the first hash is the original hash.
+                    for hash in &hashes {
+                        probe(hash, &hashes[0], i, bucket_union)?
                     }
                 }
             }
         } else {
             let probing_seq = step_wise_probing(self.n_projections, self._multi_probe_budget);
-            for (i, proj) in self.hashers.iter().enumerate() {
+            for i in tables {
                 // fist process the original query
-                let original_hash = proj.hash_vec_query(v);
-                self.process_bucket_union_result(&original_hash, i, &mut bucket_union)?;
+                let original_hash = self.hashers[i].hash_vec_query(v);
+                probe(&original_hash, &original_hash, i, bucket_union)?;
 
                 for pertub in &probing_seq {
                     let hash = original_hash
@@ -314,11 +373,41 @@ impl<H: VecHash, T: HashTables> LSH<T, H> {
                         .zip(pertub)
                         .map(|(&a, &b)| a + b)
                         .collect();
-                    self.process_bucket_union_result(&hash, i, &mut bucket_union)?;
+                    probe(&hash, &original_hash, i, bucket_union)?;
                 }
             }
         }
-        Ok(bucket_union)
+        Ok(())
+    }
+}
+
+impl LSH<MemoryTable, SignRandomProjections> {
+    /// Query the ids of all buckets of which the hash differs from the query hash in at most
+    /// `max_dist` bits. Instead of generating the probes, every bucket of the sampled tables is
+    /// compared as [CompactSrpHash](struct.CompactSrpHash.html), which is cheap when
+    /// `max_dist` would need a lot of probes. Needs an index built with
+    /// [compact_srp](struct.LSH.html#method.compact_srp).
+    ///
+    /// # Arguments
+    /// * `v` - Query vector
+    /// * `max_dist` - Maximum Hamming distance between the query hash and a bucket hash.
+    pub fn query_bucket_ids_hamming(
+        &self,
+        v: &DataPointSlice,
+        max_dist: u32,
+    ) -> Result<Vec<DataId>> {
+        self.validate_vec(v)?;
+        let ht = self.hash_tables.as_ref().unwrap();
+        let mut bucket_union = FnvHashSet::default();
+        for i in self.sample_tables(v) {
+            let query = self.hashers[i].hash_vec_compact(v)?;
+            for (hash, bucket) in ht.buckets(i) {
+                if CompactSrpHash::from_hash(hash)?.hamming(&query) <= max_dist {
+                    bucket_union.extend(bucket)
+                }
+            }
+        }
+        Ok(bucket_union.into_iter().collect())
     }
 }
 
@@ -349,6 +438,48 @@ mod test {
         assert_eq!(vec![0, 1, -1, 0], a[a.len() - 1]);
     }
 
+    #[test]
+    fn test_hamming_probing() {
+        assert_eq!(hamming_distance(&vec![1, 0, 1], &vec![0, 0, 0]), 2);
+        let hashes: Vec<Hash> = hamming_probing(&vec![1, 0, 1], 2).collect();
+        // 3 single bit flips and 3 double bit flips
+        assert_eq!(hashes.len(), 6);
+        assert_eq!(hashes[0], vec![0, 0, 1]);
+        assert!(hashes
+            .windows(2)
+            .all(|w| hamming_distance(&vec![1, 0, 1], &w[0])
+                <= hamming_distance(&vec![1, 0, 1], &w[1])));
+
+        let srp = SignRandomProjections::new(4, 3, 1);
+        let probes = srp.query_directed_probe(&[1., 2., 3.], 6).unwrap();
+        assert_eq!(probes.len(), 7);
+        assert!(probes[1..]
+            .iter()
+            .all(|h| h.iter().all(|&v| v == 0 || v == 1)));
+        // budget larger than all possible perturbations
+        assert_eq!(
+            srp.query_directed_probe(&[1., 2., 3.], 100).unwrap().len(),
+            16
+        );
+
+        // a data point in a bucket 1 bit away is only found by probing.
+        let q = [1., 2., 3.];
+        let mut lsh = LshMem::new(4, 1, 3).seed(1).multi_probe(4).srp().unwrap();
+        let mut plain = LshMem::new(4, 1, 3).seed(1).srp().unwrap();
+        let hash = lsh.hashers[0].hash_vec_query(&q);
+        let mut rng = create_rng(1);
+        let v: Vec<f32> =
+            std::iter::repeat_with(|| (0..3).map(|_| rng.gen_range(-1., 1.)).collect())
+                .find(|v: &Vec<f32>| {
+                    hamming_distance(&lsh.hashers[0].hash_vec_query(v), &hash) == 1
+                })
+                .unwrap();
+        let id = lsh.store_vec(&v).unwrap();
+        plain.store_vec(&v).unwrap();
+        assert!(lsh.query_bucket_ids(&q).unwrap().contains(&id));
+        assert!(!plain.query_bucket_ids(&q).unwrap().contains(&id));
+    }
+
     #[test]
     fn test_l2_xi_distances() {
         let l2 = L2::new(4, 4., 3, 1);
@@ -380,7 +511,7 @@ mod test {
         //   delta:             [-1, 1]
 
         let mut ae = a0.clone();
-        ae.expand();
+        ae.expand().unwrap();
         assert_eq!(ae.gen_hash(), [0, -1, 1, 0]);
         assert_eq!(ae.score(), 0.1 + 0.8);
         assert_eq!(ae.selection, [0, 1]);
@@ -391,7 +522,7 @@ mod test {
         //   index:             2
         //   delta:             1
         let mut a_s = a0.clone();
-        a_s.shift();
+        a_s.shift().unwrap();
         assert_eq!(a_s.gen_hash(), [0, 0, 1, 0]);
         assert_eq!(a_s.score(), 0.8);
         assert_eq!(a_s.selection, [1]);
@@ -1,12 +1,20 @@
-use crate::{dist::l2_norm, hash::HashPrimitive, DataPoint, HashTables, LshMem, Result, VecHash};
-use fnv::FnvHashSet;
+use crate::{
+    dist::l2_norm, hash::HashPrimitive, DataId, DataPoint, Error, HashTables, LshMem, Result,
+    VecHash,
+};
+#[cfg(feature = "sqlite")]
+use crate::{utils::create_rng, SqlTable, LSH};
+use fnv::{FnvHashSet, FnvHasher};
 use ndarray::aview1;
+#[cfg(feature = "sqlite")]
+use rand::Rng;
 use rayon::prelude::*;
 use statrs::{
     consts::SQRT_2PI,
     distribution::{Normal, Univariate},
 };
 use std::f64::consts::PI;
+use std::hash::Hasher;
 use std::time::Instant;
 
 /// Hash collision probability for L2 distance.
@@ -73,18 +81,18 @@ fn lsh_to_result<T: 'static + VecHash + Send + Sync + Clone>(
         bucket_lengths.push(bucket_ids.len());
 
         let t0 = Instant::now();
-        let q = aview1(&v);
+        let q = aview1(v);
         bucket_ids.par_sort_by_key(|&idx| {
             let p = &vs[idx as usize];
-            let dist = &aview1(&p) - &q;
+            let dist = &aview1(p) - &q;
             let l2 = l2_norm(dist.as_slice().unwrap());
             (l2 * 1e5) as i32
         });
         let duration = t0.elapsed();
         search_time += duration.as_secs_f64();
     }
-    let min = *bucket_lengths.iter().min().unwrap_or(&(0 as usize));
-    let max = *bucket_lengths.iter().max().unwrap_or(&(0 as usize));
+    let min = *bucket_lengths.iter().min().unwrap_or(&0_usize);
+    let max = *bucket_lengths.iter().max().unwrap_or(&0_usize);
     let avg = bucket_lengths.iter().sum::<usize>() as f32 / bucket_lengths.len() as f32;
     let unique_hash_values = lsh.hash_tables.unwrap().get_unique_hash_int();
     Ok(OptRes {
@@ -145,9 +153,9 @@ pub fn optimize_l2_params(
 ) -> Result<Vec<OptRes>> {
     let mut params = vec![];
     let r = 4.0;
-    let p1 = l2_ph(r as f64, 1.);
+    let p1 = l2_ph(r, 1.);
     for _k in k {
-        let l = estimate_l(delta, p1, *_k as usize);
+        let l = estimate_l(delta, p1, *_k);
         params.push((r, *_k, l))
     }
     let result = params
@@ -160,6 +168,137 @@ pub fn optimize_l2_params(
     result
 }
 
+/// Statistics of a (sample of the) bucket sizes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketStats {
+    /// Number of buckets the statistics are computed on.
+    pub n_samples: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub min: u32,
+    pub max: u32,
+}
+
+impl BucketStats {
+    fn from_sizes(sizes: &[u32]) -> BucketStats {
+        if sizes.is_empty() {
+            return BucketStats {
+                n_samples: 0,
+                mean: 0.,
+                std_dev: 0.,
+                min: 0,
+                max: 0,
+            };
+        }
+        let n = sizes.len() as f64;
+        let mean = sizes.iter().map(|&s| s as f64).sum::<f64>() / n;
+        let var = sizes
+            .iter()
+            .map(|&s| (s as f64 - mean).powf(2.))
+            .sum::<f64>()
+            / n;
+        BucketStats {
+            n_samples: sizes.len(),
+            mean,
+            std_dev: var.powf(0.5),
+            min: sizes.iter().copied().min().unwrap_or(0),
+            max: sizes.iter().copied().max().unwrap_or(0),
+        }
+    }
+}
+
+/// Approximate bucket statistics for large databases.
+#[cfg(feature = "sqlite")]
+pub struct ReservoirSampler;
+
+#[cfg(feature = "sqlite")]
+impl ReservoirSampler {
+    /// Estimate the bucket size statistics of a `SqlTable` from `n_samples` buckets drawn
+    /// uniformly with reservoir sampling. This is an alternative to `describe` for large databases:
+    /// the buckets are streamed once and only `n_samples` sizes are kept in memory.
+    ///
+    /// The standard error of the estimated mean is roughly `std_dev / sqrt(n_samples)`.
+    /// `min` and `max` are the extremes of the sample and thus bounded by the true extremes.
+    ///
+    /// # Arguments
+    /// * `lsh` - LSH with a Sqlite backend.
+    /// * `n_samples` - Number of buckets to sample.
+    /// * `seed` - Seed for the sampling. If 0, randomness is seeded from the os.
+    pub fn sample_bucket_sizes<H: VecHash>(
+        lsh: &LSH<SqlTable, H>,
+        n_samples: usize,
+        seed: u64,
+    ) -> Result<BucketStats> {
+        let mut rng = create_rng(seed);
+        let mut reservoir = vec![];
+        let mut i = 0;
+        lsh.hash_tables
+            .as_ref()
+            .unwrap()
+            .for_each_bucket_size(|size| {
+                if reservoir.len() < n_samples {
+                    reservoir.push(size)
+                } else {
+                    let j = rng.gen_range(0, i + 1);
+                    if j < n_samples {
+                        reservoir[j] = size
+                    }
+                }
+                i += 1;
+            })?;
+        Ok(BucketStats::from_sizes(&reservoir))
+    }
+}
+
+/// Number of hash functions (rows) of a [CountMinSketch](struct.CountMinSketch.html).
+const CMS_DEPTH: usize = 4;
+
+/// Count-min sketch of id frequencies with saturating `u16` counters.
+/// Estimates never underestimate and overestimate by at most `epsilon * total count` with
+/// probability `1 - e^-4`.
+pub struct CountMinSketch {
+    counters: Vec<Vec<u16>>,
+}
+
+impl CountMinSketch {
+    /// # Arguments
+    /// * `epsilon` - Relative error of the estimates in `(0, 1)`. The width of the sketch is
+    ///   `⌈e / epsilon⌉`.
+    pub fn new(epsilon: f64) -> Result<CountMinSketch> {
+        if !(epsilon > 0. && epsilon < 1.) {
+            return Err(Error::Failed("epsilon should be in (0, 1)".to_string()));
+        }
+        let width = (std::f64::consts::E / epsilon).ceil() as usize;
+        Ok(CountMinSketch {
+            counters: vec![vec![0; width]; CMS_DEPTH],
+        })
+    }
+
+    // `DataId` is `u64` with the `id64` feature.
+    #[allow(clippy::useless_conversion)]
+    fn index(&self, row: usize, id: DataId) -> usize {
+        let mut hasher = FnvHasher::with_key(row as u64);
+        hasher.write_u64(u64::from(id));
+        hasher.finish() as usize % self.counters[row].len()
+    }
+
+    /// Increment the count of `id`.
+    pub fn add(&mut self, id: DataId) {
+        for row in 0..CMS_DEPTH {
+            let i = self.index(row, id);
+            self.counters[row][i] = self.counters[row][i].saturating_add(1);
+        }
+    }
+
+    /// Estimated count of `id`.
+    pub fn estimate(&self, id: DataId) -> u16 {
+        (0..CMS_DEPTH)
+            .map(|row| self.counters[row][self.index(row, id)])
+            .min()
+            .unwrap_or(0)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -169,7 +308,7 @@ mod test {
         // tested w/ numpy
         let r = 2.0;
         let c = 1.0;
-        assert_eq!(0.609548422215397, l2_ph(r, c) as f32);
+        assert_eq!(0.609_548_45, l2_ph(r, c) as f32);
     }
 
     #[test]
@@ -179,4 +318,47 @@ mod test {
         let k = 5;
         assert_eq!(20, estimate_l(delta, p1, k));
     }
+
+    #[test]
+    fn test_count_min_sketch() {
+        assert!(CountMinSketch::new(0.).is_err());
+        assert!(CountMinSketch::new(1.).is_err());
+        assert!(CountMinSketch::new(f64::NAN).is_err());
+        let mut cms = CountMinSketch::new(0.01).unwrap();
+        for id in 0..100 {
+            for _ in 0..(id % 5) {
+                cms.add(id)
+            }
+        }
+        for id in 0..100 {
+            // total count is 200, so the error is at most 2 with high probability.
+            let est = cms.estimate(id);
+            assert!(est >= (id % 5) as u16 && est <= (id % 5) as u16 + 2);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "sqlite")]
+    fn test_reservoir_sampler() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lsh_reservoir.db3");
+
+        let mut lsh = LSH::<SqlTable, _>::new(4, 2, 3)
+            .seed(1)
+            .only_index()
+            .set_database_file(path.to_str().unwrap())
+            .srp()
+            .unwrap();
+        let vs: Vec<DataPoint> = (0..200)
+            .map(|i| vec![(i as f32).sin(), (i as f32 * 0.3).cos(), 0.5])
+            .collect();
+        lsh.store_vecs(&vs).unwrap();
+
+        let exact = ReservoirSampler::sample_bucket_sizes(&lsh, 10_000, 1).unwrap();
+        // every vector is in one bucket per table
+        assert_eq!(exact.mean * exact.n_samples as f64, 400.);
+        let approx = ReservoirSampler::sample_bucket_sizes(&lsh, 5, 1).unwrap();
+        assert_eq!(approx.n_samples, 5);
+        assert!(approx.min >= exact.min && approx.max <= exact.max);
+    }
 }
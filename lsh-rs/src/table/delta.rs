@@ -0,0 +1,195 @@
+use crate::{
+    hash::{Hash, HashPrimitive},
+    table::general::{Bucket, HashTables},
+    table::mem::MemoryTable,
+    DataId, DataPoint, DataPointSlice, Error, Result,
+};
+use fnv::{FnvHashMap as HashMap, FnvHashSet};
+use serde::{Deserialize, Serialize};
+
+/// Every `CHECKPOINT`-th data point is stored verbatim. Bounds the cost of the reconstruction.
+const CHECKPOINT: usize = 64;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+enum Delta {
+    Full(DataPoint),
+    /// `(dimension, current - previous)` of the dimensions that changed.
+    Sparse(Vec<(u32, f32)>),
+}
+
+/// In memory backend for [LSH](struct.LSH.html) that stores data points as deltas from the
+/// previously stored data point. Only the changed dimensions are stored, which reduces storage for
+/// slowly varying sequences, e.g. time series. Data points are reconstructed by cumulative
+/// summation, so use [query_bucket_owned](struct.LSH.html#method.query_bucket_owned) to query
+/// the data points.
+#[derive(Deserialize, Serialize)]
+pub struct DeltaEncodedTable {
+    index: MemoryTable,
+    n_hash_tables: usize,
+    only_index_storage: bool,
+    deltas: Vec<Delta>,
+    previous: Option<DataPoint>,
+    deleted: FnvHashSet<DataId>,
+}
+
+impl DeltaEncodedTable {
+    fn push(&mut self, d: &DataPointSlice) {
+        let delta = match self.previous.as_ref() {
+            Some(prev)
+                if !self.deltas.len().is_multiple_of(CHECKPOINT) && prev.len() == d.len() =>
+            {
+                let changed: Vec<(u32, f32)> = d
+                    .iter()
+                    .zip(prev)
+                    .enumerate()
+                    .filter(|(_, (c, p))| c != p)
+                    .map(|(i, (c, p))| (i as u32, c - p))
+                    .collect();
+                // a delta that doesn't round trip is stored in full, so that the reconstruction
+                // is exact and no error accumulates.
+                let exact = changed
+                    .iter()
+                    .all(|&(i, x)| prev[i as usize] + x == d[i as usize]);
+                // a sparse delta takes two values per changed dimension.
+                if exact && changed.len() * 2 < d.len() {
+                    Delta::Sparse(changed)
+                } else {
+                    Delta::Full(d.to_vec())
+                }
+            }
+            _ => Delta::Full(d.to_vec()),
+        };
+        self.deltas.push(delta);
+        // equal to the reconstruction of the data point, as all deltas are exact.
+        self.previous = Some(d.to_vec());
+    }
+
+    fn reconstruct(&self, idx: DataId) -> Option<DataPoint> {
+        let idx = idx as usize;
+        if idx >= self.deltas.len() || self.deleted.contains(&(idx as DataId)) {
+            return None;
+        }
+        let start = (0..=idx)
+            .rev()
+            .find(|&i| matches!(self.deltas[i], Delta::Full(_)))?;
+        let mut d = match &self.deltas[start] {
+            Delta::Full(d) => d.clone(),
+            Delta::Sparse(_) => unreachable!(),
+        };
+        for delta in &self.deltas[start + 1..=idx] {
+            match delta {
+                Delta::Full(full) => d.clone_from(full),
+                Delta::Sparse(changed) => changed.iter().for_each(|&(i, x)| d[i as usize] += x),
+            }
+        }
+        Some(d)
+    }
+
+    /// Number of stored floating point values (including the dimension indexes of the deltas).
+    pub fn n_stored_values(&self) -> usize {
+        self.deltas
+            .iter()
+            .map(|delta| match delta {
+                Delta::Full(d) => d.len(),
+                Delta::Sparse(changed) => changed.len() * 2,
+            })
+            .sum()
+    }
+}
+
+impl HashTables for DeltaEncodedTable {
+    fn new(n_hash_tables: usize, only_index_storage: bool, db_path: &str) -> Result<Box<Self>> {
+        Ok(Box::new(DeltaEncodedTable {
+            index: *MemoryTable::new(n_hash_tables, true, db_path)?,
+            n_hash_tables,
+            only_index_storage,
+            deltas: vec![],
+            previous: None,
+            deleted: FnvHashSet::default(),
+        }))
+    }
+
+    fn put(&mut self, hash: Hash, d: &DataPointSlice, hash_table: usize) -> Result<DataId> {
+        let idx = self.index.put(hash, d, hash_table)?;
+        if hash_table == 0 && !self.only_index_storage {
+            self.push(d);
+        }
+        Ok(idx)
+    }
+
+    fn remove_id(&mut self, idx: DataId, hash: &Hash, hash_table: usize) -> Result<()> {
+        self.index.remove_id(idx, hash, hash_table)?;
+        // the delta is kept, later data points depend on it.
+        if hash_table == self.n_hash_tables - 1 {
+            self.deleted.insert(idx);
+        }
+        Ok(())
+    }
+
+    fn delete_ids(&mut self, ids: &[DataId]) -> Result<Vec<DataId>> {
+        let found = self.index.delete_ids(ids)?;
+        self.deleted.extend(ids);
+        Ok(found)
+    }
+
+    fn clear_table(&mut self, hash_table: usize) -> Result<()> {
+        self.index.clear_table(hash_table)
+    }
+
+    fn query_bucket(&self, hash: &Hash, hash_table: usize) -> Result<Bucket> {
+        self.index.query_bucket(hash, hash_table)
+    }
+
+    fn extend_with_bucket(
+        &self,
+        hash: &Hash,
+        hash_table: usize,
+        bucket_union: &mut Bucket,
+    ) -> Result<()> {
+        self.index
+            .extend_with_bucket(hash, hash_table, bucket_union)
+    }
+
+    fn idx_to_datapoint(&self, _idx: DataId) -> Result<&DataPoint> {
+        Err(Error::Failed(
+            "data points are delta encoded, use query_bucket_owned".to_string(),
+        ))
+    }
+
+    fn idx_to_datapoint_owned(&self, idx: DataId) -> Result<DataPoint> {
+        self.reconstruct(idx).ok_or(Error::NotFound)
+    }
+
+    fn increase_storage(&mut self, size: usize) {
+        self.index.increase_storage(size)
+    }
+
+    fn describe(&self) -> Result<String> {
+        let mut out = self.index.describe()?;
+        out.push_str(&format!(
+            "No. of stored values: {}\n",
+            self.n_stored_values()
+        ));
+        Ok(out)
+    }
+
+    fn get_unique_hash_int(&self) -> FnvHashSet<HashPrimitive> {
+        self.index.get_unique_hash_int()
+    }
+
+    fn to_bucket_map(&self) -> Result<Vec<HashMap<Hash, Vec<DataId>>>> {
+        self.index.to_bucket_map()
+    }
+
+    fn count_empty_buckets(&self) -> Result<(usize, usize)> {
+        self.index.count_empty_buckets()
+    }
+
+    fn sample_ids(&self, n: usize, seed: u64) -> Result<Vec<DataId>> {
+        self.index.sample_ids(n, seed)
+    }
+
+    fn ids_not_in(&self, exclude: &Bucket) -> Result<Vec<DataId>> {
+        self.index.ids_not_in(exclude)
+    }
+}
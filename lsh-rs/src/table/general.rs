@@ -1,12 +1,14 @@
 use crate::{
     hash::{Hash, HashPrimitive},
-    DataPoint, DataPointSlice, Error, Result, VecHash,
+    utils::create_rng,
+    DataId, DataPoint, DataPointSlice, Error, Result, VecHash,
 };
-use fnv::{FnvHashSet as HashSet, FnvHashSet};
+use fnv::{FnvHashMap as HashMap, FnvHashSet as HashSet, FnvHashSet};
+use rand::seq::SliceRandom;
 use serde::{de::DeserializeOwned, Serialize};
 
 /// Bucket contains indexes to VecStore
-pub type Bucket = HashSet<u32>;
+pub type Bucket = HashSet<DataId>;
 
 /// Hashtable consisting of `L` Hash tables.
 pub trait HashTables {
@@ -17,31 +19,166 @@ pub trait HashTables {
     /// * `hash` - hashed vector.
     /// * `d` - Vector to store in the buckets.
     /// * `hash_table` - Number of the hash_table to store the vector. Ranging from 0 to L.
-    fn put(&mut self, hash: Hash, d: &DataPointSlice, hash_table: usize) -> Result<u32>;
+    fn put(&mut self, hash: Hash, d: &DataPointSlice, hash_table: usize) -> Result<DataId>;
+
+    /// Store a vector under a given id instead of an id assigned by the backend.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - Id of the vector.
+    /// * `hash` - hashed vector.
+    /// * `d` - Vector to store in the buckets.
+    /// * `hash_table` - Number of the hash_table to store the vector. Ranging from 0 to L.
+    fn put_with_id(
+        &mut self,
+        _idx: DataId,
+        _hash: Hash,
+        _d: &DataPointSlice,
+        _hash_table: usize,
+    ) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// If the backend implements `put_with_id`.
+    fn supports_put_with_id(&self) -> bool {
+        false
+    }
+
+    /// Store a vector together with a metadata blob, e.g. a label or a JSON string.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash` - hashed vector.
+    /// * `d` - Vector to store in the buckets.
+    /// * `hash_table` - Number of the hash_table to store the vector. Ranging from 0 to L.
+    /// * `metadata` - Metadata stored alongside the vector.
+    fn put_with_metadata(
+        &mut self,
+        _hash: Hash,
+        _d: &DataPointSlice,
+        _hash_table: usize,
+        _metadata: Vec<u8>,
+    ) -> Result<DataId> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Retrieve the metadata stored with `put_with_metadata`.
+    fn get_metadata(&self, _idx: DataId) -> Result<Vec<u8>> {
+        Err(Error::NotImplemented)
+    }
+
+    /// If the backend implements `put_with_metadata`.
+    fn supports_metadata(&self) -> bool {
+        false
+    }
 
     fn delete(&mut self, _hash: &Hash, _d: &DataPointSlice, _hash_table: usize) -> Result<()> {
         Err(Error::NotImplemented)
     }
 
+    /// Remove an id from the bucket of `hash`. Fails with `Error::NotFound` if the bucket doesn't
+    /// contain the id. The stored data point is removed when the last hash table is reached.
+    ///
+    /// # Arguments
+    ///
+    /// * `idx` - Id of the vector.
+    /// * `hash` - hashed vector.
+    /// * `hash_table` - Number of the hash_table. Ranging from 0 to L.
+    fn remove_id(&mut self, _idx: DataId, _hash: &Hash, _hash_table: usize) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Remove ids from all hash tables without knowing their hashes. Returns the ids that were
+    /// stored.
+    fn delete_ids(&mut self, _ids: &[DataId]) -> Result<Vec<DataId>> {
+        Err(Error::NotImplemented)
+    }
+
     fn update_by_idx(
         &mut self,
         _old_hash: &Hash,
         _new_hash: Hash,
-        _idx: u32,
+        _idx: DataId,
         _hash_table: usize,
     ) -> Result<()> {
         Err(Error::NotImplemented)
     }
 
+    /// Remove all entries of a single hash table. The other hash tables are left untouched.
+    ///
+    /// # Arguments
+    ///
+    /// * `hash_table` - Number of the hash_table to clear. Ranging from 0 to L.
+    fn clear_table(&mut self, _hash_table: usize) -> Result<()> {
+        Err(Error::NotImplemented)
+    }
+
     /// Query the whole bucket
     fn query_bucket(&self, hash: &Hash, hash_table: usize) -> Result<Bucket>;
 
-    fn idx_to_datapoint(&self, _idx: u32) -> Result<&DataPoint> {
+    /// Add the ids of a bucket to `bucket_union`. A missing bucket adds nothing. Backends that
+    /// hold the buckets in memory override this to avoid copying the bucket.
+    fn extend_with_bucket(
+        &self,
+        hash: &Hash,
+        hash_table: usize,
+        bucket_union: &mut Bucket,
+    ) -> Result<()> {
+        match self.query_bucket(hash, hash_table) {
+            Ok(bucket) => {
+                bucket_union.extend(bucket);
+                Ok(())
+            }
+            Err(Error::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Query the buckets of multiple hash tables and return the ids that are in all buckets.
+    ///
+    /// # Arguments
+    ///
+    /// * `hashes` - hashed vector per hash table.
+    /// * `table_indices` - Number of the hash_table of every hash.
+    fn query_intersection(&self, hashes: &[Hash], table_indices: &[usize]) -> Result<Bucket> {
+        let mut intersection: Option<Bucket> = None;
+        for (hash, &i) in hashes.iter().zip(table_indices) {
+            let bucket = match self.query_bucket(hash, i) {
+                Err(Error::NotFound) => return Ok(Bucket::default()),
+                res => res?,
+            };
+            intersection = Some(match intersection {
+                None => bucket,
+                Some(prev) => prev.intersection(&bucket).copied().collect(),
+            });
+            if intersection.as_ref().is_some_and(|b| b.is_empty()) {
+                break;
+            }
+        }
+        Ok(intersection.unwrap_or_default())
+    }
+
+    fn idx_to_datapoint(&self, _idx: DataId) -> Result<&DataPoint> {
         Err(Error::NotImplemented)
     }
 
+    /// Ids of the stored data points, sorted.
+    fn stored_ids(&self) -> Result<Vec<DataId>> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Retrieve a data point by value. Backends that don't store the data points as `DataPoint`
+    /// (e.g. quantized storage) need to override this.
+    fn idx_to_datapoint_owned(&self, idx: DataId) -> Result<DataPoint> {
+        self.idx_to_datapoint(idx).cloned()
+    }
+
     fn increase_storage(&mut self, _size: usize) {}
 
+    /// Called with every batch of data points before they are stored. Backends can use this to
+    /// fit storage parameters on the data.
+    fn fit_storage(&mut self, _vs: &[&DataPointSlice]) {}
+
     fn describe(&self) -> Result<String> {
         Err(Error::NotImplemented)
     }
@@ -51,6 +188,11 @@ pub trait HashTables {
         Ok(())
     }
 
+    // Store the LSH parameters that are needed to reopen an existing index.
+    fn store_params(&mut self, _n_projections: usize, _dim: usize) -> Result<()> {
+        Ok(())
+    }
+
     // If store_hashers fails, load_hasher can be executed
     fn load_hashers<H: VecHash + DeserializeOwned>(&self) -> Result<Vec<H>> {
         // just chose an error to make a default trait implementation
@@ -58,4 +200,66 @@ pub trait HashTables {
     }
 
     fn get_unique_hash_int(&self) -> FnvHashSet<HashPrimitive>;
+
+    /// Materialize all buckets: one map per hash table from hash to the sorted ids in the bucket.
+    fn to_bucket_map(&self) -> Result<Vec<HashMap<Hash, Vec<DataId>>>> {
+        Err(Error::NotImplemented)
+    }
+
+    /// Number of empty buckets and total number of buckets over all hash tables: `(n_empty, n_total)`.
+    /// Buckets become empty when all of their ids are deleted.
+    fn count_empty_buckets(&self) -> Result<(usize, usize)> {
+        Ok(self
+            .to_bucket_map()?
+            .iter()
+            .flat_map(|tbl| tbl.values())
+            .fold((0, 0), |(empty, total), ids| {
+                (empty + ids.is_empty() as usize, total + 1)
+            }))
+    }
+
+    /// Export the buckets as a CSR (compressed sparse row) matrix with a row per bucket and a
+    /// column per id. Returns `(row_ptr, col_idx, bucket_table_ids)`: the ids of bucket `i` are
+    /// `col_idx[row_ptr[i]..row_ptr[i + 1]]` and it is a bucket of hash table
+    /// `bucket_table_ids[i]`. Rows are ordered by hash table and hash, columns are sorted.
+    fn export_as_csr(&self) -> Result<(Vec<usize>, Vec<DataId>, Vec<usize>)> {
+        let mut row_ptr = vec![0];
+        let mut col_idx = vec![];
+        let mut bucket_table_ids = vec![];
+        for (i, tbl) in self.to_bucket_map()?.into_iter().enumerate() {
+            let mut buckets: Vec<(Hash, Vec<DataId>)> = tbl.into_iter().collect();
+            buckets.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+            for (_, ids) in buckets {
+                col_idx.extend(ids);
+                row_ptr.push(col_idx.len());
+                bucket_table_ids.push(i);
+            }
+        }
+        Ok((row_ptr, col_idx, bucket_table_ids))
+    }
+
+    /// Uniformly sample at most `n` of the ids in the first hash table.
+    fn sample_ids(&self, n: usize, seed: u64) -> Result<Vec<DataId>> {
+        let ids: Vec<DataId> = self
+            .to_bucket_map()?
+            .swap_remove(0)
+            .into_values()
+            .flatten()
+            .collect();
+        let mut rng = create_rng(seed);
+        Ok(ids.choose_multiple(&mut rng, n).copied().collect())
+    }
+
+    /// All ids in any hash table that are not in `exclude`, sorted.
+    fn ids_not_in(&self, exclude: &Bucket) -> Result<Vec<DataId>> {
+        let mut ids: Vec<DataId> = self
+            .to_bucket_map()?
+            .into_iter()
+            .flat_map(|tbl| tbl.into_values().flatten())
+            .filter(|id| !exclude.contains(id))
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
 }
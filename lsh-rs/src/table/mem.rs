@@ -2,65 +2,918 @@ use crate::{
     constants::DESCRIBE_MAX,
     hash::{Hash, HashPrimitive},
     table::general::{Bucket, HashTables},
-    utils::{all_eq, increase_capacity},
-    DataPoint, DataPointSlice, Error, Result,
+    utils::{all_eq, create_rng, increase_capacity},
+    DataId, DataPoint, DataPointSlice, Error, Result, VecHash,
 };
 use fnv::{FnvHashMap as HashMap, FnvHashSet};
+use ndarray::{Array1, Array2};
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 use std::iter::FromIterator;
 
 /// Indexible vector storage.
 /// indexes will be stored in hashtables. The original vectors can be looked up in this data structure.
-#[derive(Debug, Deserialize, Serialize)]
+/// Slots are `None` if no data point is stored under that index.
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct VecStore {
-    pub map: Vec<DataPoint>,
+    pub map: Vec<Option<DataPoint>>,
 }
 
 impl VecStore {
-    fn push(&mut self, d: DataPoint) -> u32 {
-        self.map.push(d);
-        (self.map.len() - 1) as u32
+    pub(crate) fn push(&mut self, d: DataPoint) -> DataId {
+        self.map.push(Some(d));
+        (self.map.len() - 1) as DataId
     }
 
-    fn position(&self, d: &DataPointSlice) -> Option<u32> {
-        self.map.iter().position(|x| all_eq(x, d)).map(|x| x as u32)
+    pub(crate) fn insert(&mut self, idx: DataId, d: DataPoint) {
+        let idx = idx as usize;
+        if idx >= self.map.len() {
+            self.map.resize(idx + 1, None)
+        }
+        self.map[idx] = Some(d);
     }
 
-    fn get(&self, idx: u32) -> &DataPoint {
-        &self.map[idx as usize]
+    pub(crate) fn position(&self, d: &DataPointSlice) -> Option<DataId> {
+        self.map
+            .iter()
+            .position(|x| x.as_ref().is_some_and(|x| all_eq(x, d)))
+            .map(|x| x as DataId)
     }
 
-    fn increase_storage(&mut self, size: usize) {
+    pub(crate) fn remove(&mut self, idx: DataId) {
+        if let Some(d) = self.map.get_mut(idx as usize) {
+            *d = None
+        }
+    }
+
+    pub(crate) fn get(&self, idx: DataId) -> Option<&DataPoint> {
+        self.map.get(idx as usize).and_then(|x| x.as_ref())
+    }
+
+    pub(crate) fn increase_storage(&mut self, size: usize) {
         increase_capacity(size, &mut self.map);
     }
 }
 
+/// Per dimension linear quantization of data points to `u8`.
+/// The range of every dimension is fitted on the first batch of data points. Values outside of
+/// this range are clipped.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ScalarQuantizer {
+    min: Vec<f32>,
+    scale: Vec<f32>,
+}
+
+impl ScalarQuantizer {
+    pub fn fit(vs: &[&DataPointSlice]) -> ScalarQuantizer {
+        let dim = vs.first().map_or(0, |v| v.len());
+        let mut min = vec![f32::MAX; dim];
+        let mut max = vec![f32::MIN; dim];
+        for v in vs {
+            for (i, &x) in v.iter().enumerate() {
+                min[i] = min[i].min(x);
+                max[i] = max[i].max(x);
+            }
+        }
+        let scale = min.iter().zip(&max).map(|(a, b)| (b - a) / 255.).collect();
+        ScalarQuantizer { min, scale }
+    }
+
+    pub fn quantize(&self, v: &DataPointSlice) -> Vec<u8> {
+        v.iter()
+            .zip(self.min.iter().zip(&self.scale))
+            .map(|(x, (min, scale))| {
+                if *scale == 0. {
+                    0
+                } else {
+                    ((x - min) / scale).round().clamp(0., 255.) as u8
+                }
+            })
+            .collect()
+    }
+
+    pub fn dequantize(&self, code: &[u8]) -> DataPoint {
+        code.iter()
+            .zip(self.min.iter().zip(&self.scale))
+            .map(|(&c, (min, scale))| min + c as f32 * scale)
+            .collect()
+    }
+}
+
+/// Scalar quantized data points. Replaces the `VecStore` in quantized mode.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+struct QuantizedStore {
+    quantizer: Option<ScalarQuantizer>,
+    codes: Vec<Option<Vec<u8>>>,
+}
+
+impl QuantizedStore {
+    fn quantizer(&mut self, d: &DataPointSlice) -> &ScalarQuantizer {
+        // if no batch was seen, the range is fitted on the first data point.
+        self.quantizer
+            .get_or_insert_with(|| ScalarQuantizer::fit(&[d]))
+    }
+
+    fn insert(&mut self, idx: DataId, d: &DataPointSlice) {
+        let code = self.quantizer(d).quantize(d);
+        let idx = idx as usize;
+        if idx >= self.codes.len() {
+            self.codes.resize(idx + 1, None)
+        }
+        self.codes[idx] = Some(code);
+    }
+
+    fn push(&mut self, d: &DataPointSlice) {
+        let code = self.quantizer(d).quantize(d);
+        self.codes.push(Some(code));
+    }
+
+    fn position(&self, d: &DataPointSlice) -> Option<DataId> {
+        let code = self.quantizer.as_ref()?.quantize(d);
+        self.codes
+            .iter()
+            .position(|c| c.as_ref() == Some(&code))
+            .map(|x| x as DataId)
+    }
+
+    fn get(&self, idx: DataId) -> Option<DataPoint> {
+        let code = self.codes.get(idx as usize)?.as_ref()?;
+        Some(self.quantizer.as_ref()?.dequantize(code))
+    }
+}
+
+/// Approximate memory usage in bytes, see
+/// [LSH::memory_usage](struct.LSH.html#method.memory_usage).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemStats {
+    /// Serialized size of the hash functions.
+    pub hasher_bytes: usize,
+    /// Hash maps of the hash tables, incl. the hashes and the buckets.
+    pub bucket_map_bytes: usize,
+    /// Stored data points, quantized codes and metadata.
+    pub data_store_bytes: usize,
+    pub total_bytes: usize,
+}
+
+/// Bytes of a `HashMap` or `HashSet` with `capacity` entries. The hashbrown tables behind the std
+/// collections store one control byte per entry.
+fn hash_map_bytes<T>(capacity: usize) -> usize {
+    capacity * (std::mem::size_of::<T>() + 1)
+}
+
+fn vec_bytes<T>(v: &[Option<Vec<T>>], capacity: usize) -> usize {
+    capacity * std::mem::size_of::<Option<Vec<T>>>()
+        + v.iter()
+            .flatten()
+            .map(|x| x.capacity() * std::mem::size_of::<T>())
+            .sum::<usize>()
+}
+
 /// In memory backend for [LSH](struct.LSH.html).
-#[derive(Deserialize, Serialize)]
+///
+/// The hash maps use the unseeded FNV hasher, so with the same seed and the same insertion
+/// order, iteration order and serialized snapshots are identical between runs.
+#[derive(PartialEq, Deserialize, Serialize)]
 pub struct MemoryTable {
     hash_tables: Vec<HashMap<Hash, Bucket>>,
     n_hash_tables: usize,
     pub vec_store: VecStore,
     only_index_storage: bool,
+    counter: DataId,
+    /// Optional metadata per id. Parallel to the `VecStore`.
+    metadata: Vec<Option<Vec<u8>>>,
+    /// If set, data points are scalar quantized and stored here instead of in the `VecStore`.
+    #[serde(default)]
+    quantized: Option<QuantizedStore>,
+    /// If set, ids aren't inserted in buckets that already hold this many ids.
+    #[serde(default)]
+    max_bucket_size: Option<usize>,
+}
+
+/// Version of the serialized `MemoryTable` layout, stored in dumps next to the table.
+/// Dumps without a table version hold one of the unversioned layouts below.
+pub(crate) const MEMORY_TABLE_VERSION: u32 = 1;
+
+/// Deserialize `bytes` completely, i.e. fail if they hold a different layout.
+fn deserialize_exact<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    use bincode::Options;
+    Ok(bincode::options()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)?)
+}
+
+/// Unversioned layout before the deletes, i.e. a dense `VecStore` and `u32` ids.
+#[derive(Deserialize)]
+struct MemoryTableLayout0 {
+    hash_tables: Vec<HashMap<Hash, FnvHashSet<u32>>>,
+    n_hash_tables: usize,
+    vec_store: Vec<DataPoint>,
+    only_index_storage: bool,
     counter: u32,
 }
 
+/// Unversioned layout before the metadata.
+#[derive(Deserialize)]
+struct MemoryTableLayout1 {
+    hash_tables: Vec<HashMap<Hash, Bucket>>,
+    n_hash_tables: usize,
+    vec_store: VecStore,
+    only_index_storage: bool,
+    counter: DataId,
+}
+
+/// Unversioned layout before the scalar quantization.
+#[derive(Deserialize)]
+struct MemoryTableLayout2 {
+    hash_tables: Vec<HashMap<Hash, Bucket>>,
+    n_hash_tables: usize,
+    vec_store: VecStore,
+    only_index_storage: bool,
+    counter: DataId,
+    metadata: Vec<Option<Vec<u8>>>,
+}
+
+/// Unversioned layout before the maximum bucket size.
+#[derive(Deserialize)]
+struct MemoryTableLayout3 {
+    hash_tables: Vec<HashMap<Hash, Bucket>>,
+    n_hash_tables: usize,
+    vec_store: VecStore,
+    only_index_storage: bool,
+    counter: DataId,
+    metadata: Vec<Option<Vec<u8>>>,
+    quantized: Option<QuantizedStore>,
+}
+
+// the ids are only converted with the `id64` feature.
+#[allow(clippy::useless_conversion)]
+impl From<MemoryTableLayout0> for MemoryTableLayout1 {
+    fn from(t: MemoryTableLayout0) -> Self {
+        let hash_tables = t
+            .hash_tables
+            .into_iter()
+            .map(|tbl| {
+                tbl.into_iter()
+                    .map(|(h, b)| (h, b.into_iter().map(DataId::from).collect()))
+                    .collect()
+            })
+            .collect();
+        MemoryTableLayout1 {
+            hash_tables,
+            n_hash_tables: t.n_hash_tables,
+            vec_store: VecStore {
+                map: t.vec_store.into_iter().map(Some).collect(),
+            },
+            only_index_storage: t.only_index_storage,
+            counter: DataId::from(t.counter),
+        }
+    }
+}
+
+impl From<MemoryTableLayout1> for MemoryTableLayout2 {
+    fn from(t: MemoryTableLayout1) -> Self {
+        MemoryTableLayout2 {
+            hash_tables: t.hash_tables,
+            n_hash_tables: t.n_hash_tables,
+            vec_store: t.vec_store,
+            only_index_storage: t.only_index_storage,
+            counter: t.counter,
+            metadata: vec![],
+        }
+    }
+}
+
+impl From<MemoryTableLayout2> for MemoryTableLayout3 {
+    fn from(t: MemoryTableLayout2) -> Self {
+        MemoryTableLayout3 {
+            hash_tables: t.hash_tables,
+            n_hash_tables: t.n_hash_tables,
+            vec_store: t.vec_store,
+            only_index_storage: t.only_index_storage,
+            counter: t.counter,
+            metadata: t.metadata,
+            quantized: None,
+        }
+    }
+}
+
+impl From<MemoryTableLayout3> for MemoryTable {
+    fn from(t: MemoryTableLayout3) -> Self {
+        MemoryTable {
+            hash_tables: t.hash_tables,
+            n_hash_tables: t.n_hash_tables,
+            vec_store: t.vec_store,
+            only_index_storage: t.only_index_storage,
+            counter: t.counter,
+            metadata: t.metadata,
+            quantized: t.quantized,
+            max_bucket_size: None,
+        }
+    }
+}
+
 impl MemoryTable {
-    fn remove_idx(&mut self, idx: u32, hash: &Hash, hash_table: usize) -> Result<()> {
+    /// Deserialize an optional table, as stored in the dumps, with layout `version`.
+    /// Version 0 means the dump predates the table version; the layouts are tried newest first,
+    /// a layout only matches if it consumes all bytes.
+    pub(crate) fn decode(bytes: &[u8], version: u32) -> Result<Option<MemoryTable>> {
+        match version {
+            MEMORY_TABLE_VERSION => deserialize_exact(bytes),
+            0 => {
+                if let Ok(t) = deserialize_exact::<Option<MemoryTable>>(bytes) {
+                    return Ok(t);
+                }
+                if let Ok(t) = deserialize_exact::<Option<MemoryTableLayout3>>(bytes) {
+                    return Ok(t.map(MemoryTable::from));
+                }
+                if let Ok(t) = deserialize_exact::<Option<MemoryTableLayout2>>(bytes) {
+                    return Ok(t.map(|t| MemoryTableLayout3::from(t).into()));
+                }
+                if let Ok(t) = deserialize_exact::<Option<MemoryTableLayout1>>(bytes) {
+                    return Ok(
+                        t.map(|t| MemoryTableLayout3::from(MemoryTableLayout2::from(t)).into())
+                    );
+                }
+                let t = deserialize_exact::<Option<MemoryTableLayout0>>(bytes).map_err(|_| {
+                    Error::Failed("the hash tables don't match any known layout".to_string())
+                })?;
+                Ok(t.map(|t| {
+                    MemoryTableLayout3::from(MemoryTableLayout2::from(MemoryTableLayout1::from(t)))
+                        .into()
+                }))
+            }
+            v => Err(Error::Failed(format!(
+                "hash table version {} is newer than the supported version {}",
+                v, MEMORY_TABLE_VERSION
+            ))),
+        }
+    }
+}
+
+impl MemoryTable {
+    fn remove_idx(&mut self, idx: DataId, hash: &Hash, hash_table: usize) -> Result<()> {
         let tbl = &mut self.hash_tables[hash_table];
         let bucket = tbl.get_mut(hash);
         match bucket {
-            None => return Err(Error::NotFound),
+            None => Err(Error::NotFound),
             Some(bucket) => {
                 bucket.remove(&idx);
                 Ok(())
             }
         }
     }
-    fn insert_idx(&mut self, idx: u32, hash: Hash, hash_table: usize) {
+    fn insert_idx(&mut self, idx: DataId, hash: Hash, hash_table: usize) {
         let tbl = &mut self.hash_tables[hash_table];
-        let bucket = tbl.entry(hash).or_insert_with(|| FnvHashSet::default());
-        bucket.insert(idx);
+        let bucket = tbl.entry(hash).or_default();
+        if self.max_bucket_size.is_none_or(|max| bucket.len() < max) {
+            bucket.insert(idx);
+        }
+    }
+
+    /// Create a table from pre-built buckets, e.g. of an index built by another tool. The ids are
+    /// the indexes of the data points in `data`.
+    ///
+    /// # Arguments
+    /// * `tables` - Buckets per hash table.
+    /// * `data` - Data points by id.
+    pub fn from_bucket_map(
+        tables: Vec<HashMap<Hash, Bucket>>,
+        data: Vec<DataPoint>,
+    ) -> Result<Box<MemoryTable>> {
+        if let Some(&idx) = tables
+            .iter()
+            .flat_map(|tbl| tbl.values().flatten())
+            .find(|&&idx| idx as usize >= data.len())
+        {
+            return Err(Error::Failed(format!(
+                "id {} of a bucket has no data point",
+                idx
+            )));
+        }
+        let mut m = MemoryTable::new(tables.len(), false, "")?;
+        m.counter = data.len() as DataId;
+        m.vec_store.map = data.into_iter().map(Some).collect();
+        m.hash_tables = tables;
+        Ok(m)
+    }
+
+    /// Don't insert ids in buckets that already hold `max_bucket_size` ids. This bounds the
+    /// worst case query latency for adversarial data, at the cost of the recall of the skipped
+    /// data points. The data points are still stored.
+    ///
+    /// The limit applies to every id that is put in a bucket, so ids can also be dropped when
+    /// the data points are re-hashed, e.g. by `update_by_idx`, `update_hasher_projection` or
+    /// `rebuild_index` of [LSH](../struct.LSH.html), or when they are copied into this table
+    /// with `compact_into`. Buckets that are already larger, e.g. from
+    /// [from_bucket_map](#method.from_bucket_map), are not truncated.
+    ///
+    /// # Arguments
+    /// * `max_bucket_size` - Maximum number of ids in a bucket.
+    pub fn with_max_bucket_size(&mut self, max_bucket_size: usize) -> Result<&mut Self> {
+        if max_bucket_size == 0 {
+            return Err(Error::Failed(
+                "max_bucket_size should be positive".to_string(),
+            ));
+        }
+        self.max_bucket_size = Some(max_bucket_size);
+        Ok(self)
+    }
+
+    /// Buckets of hash table `hash_table` with their hashes.
+    pub(crate) fn buckets(&self, hash_table: usize) -> impl Iterator<Item = (&Hash, &Bucket)> {
+        self.hash_tables[hash_table].iter()
+    }
+
+    /// If an id with `hash` would be skipped in table `hash_table`.
+    pub(crate) fn bucket_is_full(&self, hash: &Hash, hash_table: usize) -> bool {
+        match self.max_bucket_size {
+            None => false,
+            Some(max) => self.hash_tables[hash_table]
+                .get(hash)
+                .is_some_and(|bucket| bucket.len() >= max),
+        }
+    }
+
+    fn remove_datapoint(&mut self, idx: DataId) {
+        self.vec_store.remove(idx);
+        if let Some(m) = self.metadata.get_mut(idx as usize) {
+            *m = None
+        }
+        if let Some(c) = self
+            .quantized
+            .as_mut()
+            .and_then(|q| q.codes.get_mut(idx as usize))
+        {
+            *c = None
+        }
+    }
+
+    /// Approximate memory usage of the hash tables and the stored data points. The hashers
+    /// aren't stored in the table, so `hasher_bytes` is 0.
+    pub fn approximate_memory_usage(&self) -> MemStats {
+        let bucket_map_bytes = self
+            .hash_tables
+            .iter()
+            .map(|tbl| {
+                hash_map_bytes::<(Hash, Bucket)>(tbl.capacity())
+                    + tbl
+                        .iter()
+                        .map(|(hash, bucket)| {
+                            hash.capacity() * std::mem::size_of::<HashPrimitive>()
+                                + hash_map_bytes::<DataId>(bucket.capacity())
+                        })
+                        .sum::<usize>()
+            })
+            .sum();
+        let mut data_store_bytes = vec_bytes(&self.vec_store.map, self.vec_store.map.capacity())
+            + vec_bytes(&self.metadata, self.metadata.capacity());
+        if let Some(q) = &self.quantized {
+            data_store_bytes += vec_bytes(&q.codes, q.codes.capacity());
+            if let Some(quantizer) = &q.quantizer {
+                data_store_bytes += (quantizer.min.capacity() + quantizer.scale.capacity())
+                    * std::mem::size_of::<f32>();
+            }
+        }
+        MemStats {
+            hasher_bytes: 0,
+            bucket_map_bytes,
+            data_store_bytes,
+            total_bytes: bucket_map_bytes + data_store_bytes,
+        }
+    }
+
+    /// Replace the content of the table by the given `(id, data point)` pairs, e.g. after
+    /// deserializing from an external key-value store. Every data point is hashed with `hashers`,
+    /// one hasher per hash table. Unlike [store_vecs](struct.LSH.html#method.store_vecs) the ids
+    /// are not assigned but taken from the pairs.
+    ///
+    /// # Arguments
+    /// * `hashers` - Hasher per hash table.
+    /// * `pairs` - Unique ids and data points.
+    pub fn rebuild_from_pairs<H: VecHash>(
+        &mut self,
+        hashers: &[H],
+        pairs: impl Iterator<Item = (DataId, DataPoint)>,
+    ) -> Result<()> {
+        if hashers.len() != self.n_hash_tables {
+            return Err(Error::Failed(format!(
+                "expected {} hashers, got {}",
+                self.n_hash_tables,
+                hashers.len()
+            )));
+        }
+        self.hash_tables.iter_mut().for_each(|tbl| tbl.clear());
+        self.vec_store.map.clear();
+        self.metadata.clear();
+        if let Some(q) = self.quantized.as_mut() {
+            *q = QuantizedStore::default();
+        }
+        self.counter = 0;
+
+        let mut seen = FnvHashSet::default();
+        for (idx, d) in pairs {
+            if !seen.insert(idx) {
+                return Err(Error::Failed(format!("id {} is not unique", idx)));
+            }
+            for (i, hasher) in hashers.iter().enumerate() {
+                self.put_with_id(idx, hasher.hash_vec_put(&d), &d, i)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ids of the stored data points.
+    pub(crate) fn stored_ids(&self) -> Vec<DataId> {
+        let stored: Vec<bool> = match self.quantized.as_ref() {
+            Some(q) => q.codes.iter().map(|c| c.is_some()).collect(),
+            None => self.vec_store.map.iter().map(|d| d.is_some()).collect(),
+        };
+        stored
+            .into_iter()
+            .enumerate()
+            .filter(|(_, is_stored)| *is_stored)
+            .map(|(idx, _)| idx as DataId)
+            .collect()
+    }
+
+    /// Transform every stored data point with `f`, in the order of the ids. The buckets are not
+    /// updated, use [apply_to_all](struct.LSH.html#method.apply_to_all) to re-hash the data
+    /// points as well. Quantized data points are transformed and quantized with a range fitted
+    /// on the transformed data points.
+    ///
+    /// # Arguments
+    /// * `f` - Transformation of a data point.
+    pub fn apply<F: FnMut(DataPoint) -> DataPoint>(&mut self, mut f: F) -> Result<()> {
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "cannot transform, only indexes are stored".to_string(),
+            ));
+        }
+        if let Some(q) = self.quantized.as_mut() {
+            let vs: Vec<Option<DataPoint>> = (0..q.codes.len())
+                .map(|idx| q.get(idx as DataId).map(&mut f))
+                .collect();
+            let stored: Vec<&DataPointSlice> = vs.iter().flatten().map(|v| &v[..]).collect();
+            if stored.is_empty() {
+                return Ok(());
+            }
+            let quantizer = ScalarQuantizer::fit(&stored);
+            q.codes = vs
+                .iter()
+                .map(|v| v.as_ref().map(|v| quantizer.quantize(v)))
+                .collect();
+            q.quantizer = Some(quantizer);
+            return Ok(());
+        }
+        for d in self.vec_store.map.iter_mut().flatten() {
+            *d = f(std::mem::take(d));
+        }
+        Ok(())
+    }
+
+    /// Store data points as `u8` codes by quantizing every dimension to [0, 255]. The range per
+    /// dimension is fitted on the first stored batch. This reduces the memory of the stored data
+    /// points by a factor 4. Quantized data points can only be retrieved by value, e.g. with
+    /// [query_bucket_owned](struct.LSH.html#method.query_bucket_owned).
+    ///
+    /// Needs to be set before any data points are stored.
+    pub fn with_scalar_quantization(&mut self) -> Result<&mut Self> {
+        if self.only_index_storage {
+            return Err(Error::Failed(
+                "cannot quantize, only indexes are stored".to_string(),
+            ));
+        }
+        if !self.vec_store.map.is_empty() {
+            return Err(Error::Failed(
+                "cannot quantize, data points are already stored".to_string(),
+            ));
+        }
+        self.quantized = Some(QuantizedStore::default());
+        Ok(self)
+    }
+
+    /// Reassign the ids contiguously after deletions and free the empty slots in the `VecStore`.
+    /// Returns the mapping from old id to new id, so that external references can be updated.
+    pub fn defragment(&mut self) -> HashMap<DataId, DataId> {
+        let mut ids: Vec<DataId> = self
+            .hash_tables
+            .iter()
+            .flat_map(|tbl| tbl.values().flat_map(|bucket| bucket.iter().copied()))
+            .collect::<FnvHashSet<DataId>>()
+            .into_iter()
+            .collect();
+        ids.sort_unstable();
+        let mapping: HashMap<DataId, DataId> = ids
+            .iter()
+            .enumerate()
+            .map(|(new, &old)| (old, new as DataId))
+            .collect();
+
+        for tbl in self.hash_tables.iter_mut() {
+            tbl.retain(|_, bucket| !bucket.is_empty());
+            for bucket in tbl.values_mut() {
+                *bucket = bucket.iter().map(|idx| mapping[idx]).collect();
+            }
+        }
+
+        if !self.only_index_storage {
+            let mut map = Vec::with_capacity(ids.len());
+            for &old in &ids {
+                map.push(
+                    self.vec_store
+                        .map
+                        .get_mut(old as usize)
+                        .and_then(|d| d.take()),
+                );
+            }
+            self.vec_store = VecStore { map };
+        }
+        if let Some(q) = self.quantized.as_mut() {
+            let mut codes = Vec::with_capacity(ids.len());
+            for &old in &ids {
+                codes.push(q.codes.get_mut(old as usize).and_then(|c| c.take()));
+            }
+            q.codes = codes;
+        }
+        if !self.metadata.is_empty() {
+            let mut metadata = Vec::with_capacity(ids.len());
+            for &old in &ids {
+                metadata.push(self.metadata.get_mut(old as usize).and_then(|m| m.take()));
+            }
+            self.metadata = metadata;
+        }
+        self.counter = ids.len() as DataId;
+        mapping
+    }
+
+    /// Create a new `MemoryTable` that only contains the given ids. The ids aren't reassigned.
+    pub fn sub_table(&self, ids: &FnvHashSet<DataId>) -> MemoryTable {
+        let hash_tables = self
+            .hash_tables
+            .iter()
+            .map(|tbl| {
+                tbl.iter()
+                    .filter_map(|(hash, bucket)| {
+                        let bucket: Bucket = bucket.intersection(ids).copied().collect();
+                        if bucket.is_empty() {
+                            None
+                        } else {
+                            Some((hash.clone(), bucket))
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let map = self
+            .vec_store
+            .map
+            .iter()
+            .enumerate()
+            .map(|(idx, d)| {
+                if ids.contains(&(idx as DataId)) {
+                    d.clone()
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let metadata = self
+            .metadata
+            .iter()
+            .enumerate()
+            .map(|(idx, m)| {
+                if ids.contains(&(idx as DataId)) {
+                    m.clone()
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let quantized = self.quantized.as_ref().map(|q| QuantizedStore {
+            quantizer: q.quantizer.clone(),
+            codes: q
+                .codes
+                .iter()
+                .enumerate()
+                .map(|(idx, c)| {
+                    if ids.contains(&(idx as DataId)) {
+                        c.clone()
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+        });
+
+        MemoryTable {
+            hash_tables,
+            n_hash_tables: self.n_hash_tables,
+            vec_store: VecStore { map },
+            only_index_storage: self.only_index_storage,
+            counter: self.counter,
+            metadata,
+            quantized,
+            max_bucket_size: self.max_bucket_size,
+        }
+    }
+
+    /// All buckets as nested vectors: `table_idx → bucket_idx → [ids]`. Buckets are ordered by
+    /// hash and the ids are sorted, so that the output is deterministic.
+    pub fn to_vec_of_buckets(&self) -> Vec<Vec<Vec<DataId>>> {
+        self.hash_tables
+            .iter()
+            .map(|tbl| {
+                let mut buckets: Vec<(&Hash, Vec<DataId>)> = tbl
+                    .iter()
+                    .map(|(hash, bucket)| {
+                        let mut ids: Vec<DataId> = bucket.iter().copied().collect();
+                        ids.sort_unstable();
+                        (hash, ids)
+                    })
+                    .collect();
+                buckets.sort_unstable_by_key(|(hash, _)| *hash);
+                buckets.into_iter().map(|(_, ids)| ids).collect()
+            })
+            .collect()
+    }
+
+    /// Iterate over the stored data points, ordered by id. Every item holds the id, the data
+    /// point and the `(table_idx, hash)` pairs of the buckets that hold the id, i.e. the inverse
+    /// of a bucket lookup. Fails if only the indexes are stored or the data points are quantized.
+    #[allow(clippy::type_complexity)]
+    pub fn iter_with_data(
+        &self,
+    ) -> Result<impl Iterator<Item = (DataId, &DataPoint, Vec<(usize, &Hash)>)> + '_> {
+        if self.only_index_storage || self.quantized.is_some() {
+            return Err(Error::Failed(
+                "data points are not stored unquantized".to_string(),
+            ));
+        }
+        let mut hashes: Vec<Vec<(usize, &Hash)>> = vec![vec![]; self.vec_store.map.len()];
+        for (i, tbl) in self.hash_tables.iter().enumerate() {
+            for (hash, bucket) in tbl {
+                for &idx in bucket {
+                    if let Some(h) = hashes.get_mut(idx as usize) {
+                        h.push((i, hash));
+                    }
+                }
+            }
+        }
+        Ok(self
+            .vec_store
+            .map
+            .iter()
+            .zip(hashes)
+            .enumerate()
+            .filter_map(|(idx, (d, h))| d.as_ref().map(|d| (idx as DataId, d, h))))
+    }
+
+    /// Export the stored data points as a matrix with a data point per row and the parallel ids,
+    /// ordered by id. If only the indexes are stored, the matrix has no rows and the ids in the
+    /// first hash table are returned.
+    pub fn to_numpy_arrays(&self) -> (Array2<f32>, Array1<DataId>) {
+        if self.only_index_storage {
+            let ids: FnvHashSet<DataId> = self
+                .hash_tables
+                .first()
+                .map(|tbl| tbl.values().flatten().copied().collect())
+                .unwrap_or_default();
+            let mut ids: Vec<DataId> = ids.into_iter().collect();
+            ids.sort_unstable();
+            return (Array2::zeros((0, 0)), Array1::from(ids));
+        }
+        let n = self
+            .quantized
+            .as_ref()
+            .map_or(self.vec_store.map.len(), |q| q.codes.len());
+        let (ids, rows): (Vec<DataId>, Vec<DataPoint>) = (0..n as DataId)
+            .filter_map(|idx| self.idx_to_datapoint_owned(idx).ok().map(|d| (idx, d)))
+            .unzip();
+        let dim = rows.first().map_or(0, |d| d.len());
+        let data = Array2::from_shape_vec((rows.len(), dim), rows.concat())
+            .expect("all data points have the same dimension");
+        (data, Array1::from(ids))
+    }
+
+    /// Merge two tables. The ids of `other` are offset by the number of ids in `self`, so that
+    /// they don't collide. Buckets with the same hash are unioned.
+    pub fn union(&self, other: &Self) -> Result<MemoryTable> {
+        if self.n_hash_tables != other.n_hash_tables {
+            return Err(Error::Failed(
+                "number of hash tables don't match".to_string(),
+            ));
+        }
+        if self.only_index_storage != other.only_index_storage {
+            return Err(Error::Failed("storage modes don't match".to_string()));
+        }
+        if self.quantized.is_some() || other.quantized.is_some() {
+            return Err(Error::Failed(
+                "union of quantized tables is not supported".to_string(),
+            ));
+        }
+        let offset = std::cmp::max(self.counter as usize, self.vec_store.map.len()) as DataId;
+
+        let mut hash_tables = self.hash_tables.clone();
+        for (tbl, other_tbl) in hash_tables.iter_mut().zip(&other.hash_tables) {
+            for (hash, bucket) in other_tbl {
+                tbl.entry(hash.clone())
+                    .or_default()
+                    .extend(bucket.iter().map(|idx| idx + offset));
+            }
+        }
+
+        let mut map = self.vec_store.map.clone();
+        if !self.only_index_storage {
+            map.resize(offset as usize, None);
+            map.extend(other.vec_store.map.iter().cloned());
+        }
+
+        let mut metadata = self.metadata.clone();
+        if !other.metadata.is_empty() {
+            metadata.resize(offset as usize, None);
+            metadata.extend(other.metadata.iter().cloned());
+        }
+
+        let other_counter = std::cmp::max(other.counter as usize, other.vec_store.map.len());
+        Ok(MemoryTable {
+            hash_tables,
+            n_hash_tables: self.n_hash_tables,
+            vec_store: VecStore { map },
+            only_index_storage: self.only_index_storage,
+            counter: offset + other_counter as DataId,
+            metadata,
+            quantized: None,
+            max_bucket_size: self.max_bucket_size,
+        })
+    }
+
+    /// Copy the ids of all non-empty buckets into `other`, which may use other hashers. The hash
+    /// of a bucket of table `i` is translated with `remap(hash, i)`. The ids get new, contiguous
+    /// ids after the ids of `other`. Returns the mapping from old id to new id.
+    ///
+    /// # Arguments
+    /// * `other` - Table with the same number of hash tables.
+    /// * `remap` - Translates a hash of `self` to a hash of `other`.
+    pub fn compact_into<F: Fn(Hash, usize) -> Hash>(
+        &self,
+        other: &mut MemoryTable,
+        remap: F,
+    ) -> Result<HashMap<DataId, DataId>> {
+        if self.n_hash_tables != other.n_hash_tables {
+            return Err(Error::Failed(
+                "number of hash tables don't match".to_string(),
+            ));
+        }
+        if self.only_index_storage && !other.only_index_storage {
+            return Err(Error::Failed(
+                "the data points are needed, but only the indexes are stored".to_string(),
+            ));
+        }
+        let mut ids: Vec<DataId> = self
+            .hash_tables
+            .iter()
+            .flat_map(|tbl| tbl.values().flat_map(|bucket| bucket.iter().copied()))
+            .collect::<FnvHashSet<DataId>>()
+            .into_iter()
+            .collect();
+        ids.sort_unstable();
+        let offset = std::cmp::max(other.counter as usize, other.vec_store.map.len()) as DataId;
+        let mapping: HashMap<DataId, DataId> = ids
+            .iter()
+            .enumerate()
+            .map(|(new, &old)| (old, offset + new as DataId))
+            .collect();
+
+        for (i, tbl) in self.hash_tables.iter().enumerate() {
+            for (hash, bucket) in tbl.iter().filter(|(_, bucket)| !bucket.is_empty()) {
+                let hash = remap(hash.clone(), i);
+                for &old in bucket {
+                    // the data points are only stored with table 0.
+                    let d = if i == 0 && !other.only_index_storage {
+                        self.idx_to_datapoint_owned(old)?
+                    } else {
+                        vec![]
+                    };
+                    other.put_with_id(mapping[&old], hash.clone(), &d, i)?;
+                }
+            }
+        }
+        if !self.metadata.is_empty() {
+            for (&old, &new) in &mapping {
+                if let Some(Some(m)) = self.metadata.get(old as usize) {
+                    let new = new as usize;
+                    if new >= other.metadata.len() {
+                        other.metadata.resize(new + 1, None)
+                    }
+                    other.metadata[new] = Some(m.clone());
+                }
+            }
+        }
+        Ok(mapping)
     }
 }
 
@@ -77,11 +930,14 @@ impl HashTables for MemoryTable {
             vec_store: vector_store,
             only_index_storage,
             counter: 0,
+            metadata: vec![],
+            quantized: None,
+            max_bucket_size: None,
         };
         Ok(Box::new(m))
     }
 
-    fn put(&mut self, hash: Hash, d: &DataPointSlice, hash_table: usize) -> Result<u32> {
+    fn put(&mut self, hash: Hash, d: &DataPointSlice, hash_table: usize) -> Result<DataId> {
         // Store hash and id/idx
         let idx = self.counter;
         self.insert_idx(idx, hash, hash_table);
@@ -90,30 +946,134 @@ impl HashTables for MemoryTable {
         // the unique v hash_table 0 and increment the counter (the id)
         // after we've update the last (N) hash_table.
         if (hash_table == 0) && (!self.only_index_storage) {
-            self.vec_store.push(d.to_vec());
-        } else if hash_table == self.n_hash_tables - 1 {
+            match self.quantized.as_mut() {
+                Some(q) => q.push(d),
+                None => {
+                    self.vec_store.push(d.to_vec());
+                }
+            }
+        }
+        if hash_table == self.n_hash_tables - 1 {
             self.counter += 1
         }
         Ok(idx)
     }
 
+    fn put_with_id(
+        &mut self,
+        idx: DataId,
+        hash: Hash,
+        d: &DataPointSlice,
+        hash_table: usize,
+    ) -> Result<()> {
+        self.insert_idx(idx, hash, hash_table);
+        if (hash_table == 0) && (!self.only_index_storage) {
+            match self.quantized.as_mut() {
+                Some(q) => q.insert(idx, d),
+                None => self.vec_store.insert(idx, d.to_vec()),
+            }
+        }
+        // make sure ids assigned by `put` don't collide with the given ids.
+        if hash_table == self.n_hash_tables - 1 {
+            self.counter = std::cmp::max(self.counter, idx + 1)
+        }
+        Ok(())
+    }
+
+    fn supports_put_with_id(&self) -> bool {
+        true
+    }
+
+    fn put_with_metadata(
+        &mut self,
+        hash: Hash,
+        d: &DataPointSlice,
+        hash_table: usize,
+        metadata: Vec<u8>,
+    ) -> Result<DataId> {
+        let idx = self.put(hash, d, hash_table)?;
+        if hash_table == 0 {
+            let i = idx as usize;
+            if i >= self.metadata.len() {
+                self.metadata.resize(i + 1, None)
+            }
+            self.metadata[i] = Some(metadata);
+        }
+        Ok(idx)
+    }
+
+    fn get_metadata(&self, idx: DataId) -> Result<Vec<u8>> {
+        match self.metadata.get(idx as usize) {
+            Some(Some(m)) => Ok(m.clone()),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    fn supports_metadata(&self) -> bool {
+        true
+    }
+
     /// Expensive operation we need to do a linear search over all datapoints
     fn delete(&mut self, hash: &Hash, d: &DataPointSlice, hash_table: usize) -> Result<()> {
         // First find the data point in the VecStore
-        let idx = match self.vec_store.position(d) {
+        let position = match self.quantized.as_ref() {
+            Some(q) => q.position(d),
+            None => self.vec_store.position(d),
+        };
+        let idx = match position {
             None => return Ok(()),
             Some(idx) => idx,
         };
-        // Note: data point remains in VecStore as shrinking the vector would mean we need to
-        // re-hash all datapoints.
-        self.remove_idx(idx, &hash, hash_table)
+        let res = self.remove_idx(idx, hash, hash_table);
+        // Note: the slot remains in VecStore as shrinking the vector would mean we need to
+        // re-hash all datapoints. Use `defragment` to reclaim the slots.
+        if hash_table == self.n_hash_tables - 1 {
+            self.remove_datapoint(idx)
+        }
+        res
+    }
+
+    fn remove_id(&mut self, idx: DataId, hash: &Hash, hash_table: usize) -> Result<()> {
+        let removed = self.hash_tables[hash_table]
+            .get_mut(hash)
+            .is_some_and(|bucket| bucket.remove(&idx));
+        if !removed {
+            return Err(Error::NotFound);
+        }
+        if hash_table == self.n_hash_tables - 1 {
+            self.remove_datapoint(idx)
+        }
+        Ok(())
+    }
+
+    /// Expensive operation, all buckets are scanned.
+    fn delete_ids(&mut self, ids: &[DataId]) -> Result<Vec<DataId>> {
+        let ids: FnvHashSet<DataId> = ids.iter().copied().collect();
+        let mut found = FnvHashSet::default();
+        for tbl in self.hash_tables.iter_mut() {
+            for bucket in tbl.values_mut() {
+                bucket.retain(|idx| {
+                    let delete = ids.contains(idx);
+                    if delete {
+                        found.insert(*idx);
+                    }
+                    !delete
+                })
+            }
+        }
+        found.extend(
+            ids.iter()
+                .filter(|&&idx| self.idx_to_datapoint_owned(idx).is_ok()),
+        );
+        ids.iter().for_each(|&idx| self.remove_datapoint(idx));
+        Ok(found.into_iter().collect())
     }
 
     fn update_by_idx(
         &mut self,
         old_hash: &Hash,
         new_hash: Hash,
-        idx: u32,
+        idx: DataId,
         hash_table: usize,
     ) -> Result<()> {
         self.remove_idx(idx, old_hash, hash_table)?;
@@ -121,6 +1081,14 @@ impl HashTables for MemoryTable {
         Ok(())
     }
 
+    fn clear_table(&mut self, hash_table: usize) -> Result<()> {
+        self.hash_tables
+            .get_mut(hash_table)
+            .ok_or(Error::TableNotExist)?
+            .clear();
+        Ok(())
+    }
+
     /// Query the whole bucket
     fn query_bucket(&self, hash: &Hash, hash_table: usize) -> Result<Bucket> {
         let tbl = &self.hash_tables[hash_table];
@@ -130,13 +1098,49 @@ impl HashTables for MemoryTable {
         }
     }
 
-    fn idx_to_datapoint(&self, idx: u32) -> Result<&DataPoint> {
-        Ok(self.vec_store.get(idx))
+    fn extend_with_bucket(
+        &self,
+        hash: &Hash,
+        hash_table: usize,
+        bucket_union: &mut Bucket,
+    ) -> Result<()> {
+        if let Some(bucket) = self.hash_tables[hash_table].get(hash) {
+            bucket_union.extend(bucket)
+        }
+        Ok(())
+    }
+
+    fn idx_to_datapoint(&self, idx: DataId) -> Result<&DataPoint> {
+        if self.quantized.is_some() {
+            return Err(Error::Failed(
+                "data points are quantized, use idx_to_datapoint_owned".to_string(),
+            ));
+        }
+        self.vec_store.get(idx).ok_or(Error::NotFound)
+    }
+
+    /// Dequantizes on the fly in quantized mode.
+    fn idx_to_datapoint_owned(&self, idx: DataId) -> Result<DataPoint> {
+        match self.quantized.as_ref() {
+            Some(q) => q.get(idx).ok_or(Error::NotFound),
+            None => self.vec_store.get(idx).cloned().ok_or(Error::NotFound),
+        }
     }
 
     fn increase_storage(&mut self, size: usize) {
         increase_capacity(size, &mut self.hash_tables);
-        self.vec_store.increase_storage(size);
+        match self.quantized.as_mut() {
+            Some(q) => increase_capacity(size, &mut q.codes),
+            None => self.vec_store.increase_storage(size),
+        }
+    }
+
+    fn fit_storage(&mut self, vs: &[&DataPointSlice]) {
+        if let Some(q) = self.quantized.as_mut() {
+            if q.quantizer.is_none() && !vs.is_empty() {
+                q.quantizer = Some(ScalarQuantizer::fit(vs))
+            }
+        }
     }
 
     fn describe(&self) -> Result<String> {
@@ -194,13 +1198,86 @@ impl HashTables for MemoryTable {
         }
         hash_numbers
     }
+
+    fn count_empty_buckets(&self) -> Result<(usize, usize)> {
+        Ok(self
+            .hash_tables
+            .iter()
+            .flat_map(|tbl| tbl.values())
+            .fold((0, 0), |(empty, total), bucket| {
+                (empty + bucket.is_empty() as usize, total + 1)
+            }))
+    }
+
+    /// Partial Fisher-Yates shuffle over the ids of the first hash table.
+    fn sample_ids(&self, n: usize, seed: u64) -> Result<Vec<DataId>> {
+        let mut ids: Vec<DataId> = match self.hash_tables.first() {
+            None => return Ok(vec![]),
+            Some(tbl) => tbl.values().flatten().copied().collect(),
+        };
+        let mut rng = create_rng(seed);
+        let n = std::cmp::min(n, ids.len());
+        let (sample, _) = ids.partial_shuffle(&mut rng, n);
+        Ok(sample.to_vec())
+    }
+
+    fn stored_ids(&self) -> Result<Vec<DataId>> {
+        let ids = match self.quantized.as_ref() {
+            Some(q) => q
+                .codes
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| c.is_some())
+                .map(|(i, _)| i as DataId)
+                .collect(),
+            None => self
+                .vec_store
+                .map
+                .iter()
+                .enumerate()
+                .filter(|(_, d)| d.is_some())
+                .map(|(i, _)| i as DataId)
+                .collect(),
+        };
+        Ok(ids)
+    }
+
+    fn ids_not_in(&self, exclude: &Bucket) -> Result<Vec<DataId>> {
+        // an id can be missing in some hash tables, e.g. with a maximum bucket size.
+        let mut ids: Vec<DataId> = self
+            .hash_tables
+            .iter()
+            .flat_map(|tbl| tbl.values().flatten())
+            .filter(|id| !exclude.contains(id))
+            .copied()
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    fn to_bucket_map(&self) -> Result<Vec<HashMap<Hash, Vec<DataId>>>> {
+        Ok(self
+            .hash_tables
+            .iter()
+            .map(|tbl| {
+                tbl.iter()
+                    .map(|(hash, bucket)| {
+                        let mut ids: Vec<DataId> = bucket.iter().copied().collect();
+                        ids.sort_unstable();
+                        (hash.clone(), ids)
+                    })
+                    .collect()
+            })
+            .collect())
+    }
 }
 
 impl std::fmt::Debug for MemoryTable {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "hash_tables:\nhash, \t buckets\n")?;
         for ht in self.hash_tables.iter() {
-            write!(f, "{:?}\n", ht)?;
+            writeln!(f, "{:?}", ht)?;
         }
         Ok(())
     }
@@ -2,9 +2,10 @@ use super::general::Bucket;
 use crate::{
     constants::DESCRIBE_MAX,
     hash::{Hash, HashPrimitive},
-    DataPointSlice, Error, HashTables, Result, VecHash,
+    DataId, DataPointSlice, Error, HashTables, Result, VecHash,
 };
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
+use log::warn;
 use rusqlite::{params, Connection, NO_PARAMS};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
@@ -12,7 +13,7 @@ use std::cell::Cell;
 
 fn vec_to_blob<T>(hash: &[T]) -> &[u8] {
     let data = hash.as_ptr() as *const u8;
-    unsafe { std::slice::from_raw_parts(data, hash.len() * std::mem::size_of::<T>()) }
+    unsafe { std::slice::from_raw_parts(data, std::mem::size_of_val(hash)) }
 }
 
 fn blob_to_vec<T>(blob: &[u8]) -> &[T] {
@@ -20,19 +21,19 @@ fn blob_to_vec<T>(blob: &[u8]) -> &[T] {
     unsafe { std::slice::from_raw_parts(data, blob.len() / std::mem::size_of::<T>()) }
 }
 
-fn query_bucket(blob: &[u8], table_name: &str, connection: &Connection) -> Result<Bucket> {
+fn query_bucket(blob: &[u8], rows: &str, connection: &Connection) -> Result<Bucket> {
     let mut stmt = connection.prepare_cached(&format!(
         "
 SELECT (id) FROM {}
 WHERE hash = ?
         ",
-        table_name
+        rows
     ))?;
     let mut rows = stmt.query(params![blob])?;
 
     let mut bucket = FnvHashSet::default();
     while let Some(row) = rows.next()? {
-        bucket.insert(row.get(0)?);
+        bucket.insert(row.get::<_, i64>(0)? as DataId);
     }
     Ok(bucket)
 }
@@ -49,7 +50,12 @@ fn make_table(table_name: &str, connection: &Connection) -> Result<()> {
     Ok(())
 }
 
-fn insert_table(table_name: &str, hash: &Hash, idx: u32, connection: &Connection) -> Result<usize> {
+fn insert_table(
+    table_name: &str,
+    hash: &Hash,
+    idx: DataId,
+    connection: &Connection,
+) -> Result<usize> {
     let blob = vec_to_blob(hash);
     let mut stmt = connection.prepare_cached(&format!(
         "
@@ -58,15 +64,11 @@ VALUES (?1, ?2)
         ",
         table_name
     ))?;
-    let idx = stmt.execute(params![blob, idx])?;
+    let idx = stmt.execute(params![blob, idx as i64])?;
     Ok(idx)
 }
 
-fn hash_table_stats(
-    table_name: &str,
-    limit: u32,
-    conn: &Connection,
-) -> Result<(f64, f64, u32, u32)> {
+fn hash_table_stats(rows: &str, limit: u32, conn: &Connection) -> Result<(f64, f64, u32, u32)> {
     let mut stmt = conn.prepare_cached(&format!(
         "
 SELECT
@@ -82,7 +84,7 @@ FROM (
 	LIMIT ?
 );
     ",
-        table_name
+        rows
     ))?;
     let out = stmt.query_row(params![limit], |row| {
         let mean: f64 = row.get(0)?;
@@ -99,13 +101,20 @@ FROM (
 ///
 /// State will be save during sessions. The database is automatically
 /// loaded if [LSH](struct.LSH.html) can find the database file (defaults to `./lsh.db3`.
+///
+/// Deleted ids are removed from the hash tables by default. With
+/// [set_tombstone_delete](struct.SqlTable.html#method.set_tombstone_delete) they are only marked as
+/// deleted.
 pub struct SqlTable {
     n_hash_tables: usize,
     only_index_storage: bool, // for now only supported
-    counter: u32,
+    counter: DataId,
     pub conn: Connection,
     table_names: Vec<String>,
     pub committed: Cell<bool>,
+    /// Log a warning if the estimated database size in bytes exceeds this value.
+    pub db_size_warning: Option<u64>,
+    tombstone_delete: bool,
 }
 
 fn fmt_table_name(hash_table: usize) -> String {
@@ -143,8 +152,15 @@ fn get_unique_hash_int(
 
 fn init_table(conn: &Connection, table_names: &[String]) -> Result<()> {
     for table_name in table_names {
-        make_table(&table_name, &conn)?;
+        make_table(table_name, conn)?;
     }
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS tombstones (
+             hash_table INTEGER,
+             id         INTEGER,
+             PRIMARY KEY (hash_table, id)
+            )",
+    )?;
     Ok(())
 }
 
@@ -182,11 +198,90 @@ impl SqlTable {
             conn,
             table_names,
             committed: Cell::new(false),
+            db_size_warning: None,
+            tombstone_delete: false,
         };
         sql.init_transaction()?;
         Ok(sql)
     }
 
+    /// Open an existing database file without modifying its data.
+    /// Returns the table and the stored `(n_projections, dim)`.
+    pub fn open_existing<P: AsRef<std::path::Path>>(path: P) -> Result<(SqlTable, usize, usize)> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Err(Error::Failed(format!("{:?} does not exist", path)));
+        }
+        let conn = Connection::open(path)?;
+        let (n_hash_tables, n_projections, dim): (u32, u32, u32) = conn
+            .query_row(
+                "SELECT n_hash_tables, n_projections, dim FROM params;",
+                NO_PARAMS,
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .map_err(|_| Error::Failed(format!("no LSH parameters stored in {:?}", path)))?;
+        let mut sql = SqlTable::init_from_conn(n_hash_tables as usize, true, conn)?;
+
+        // continue with the ids after the stored ids.
+        let max_id: Option<i64> = sql.conn.query_row(
+            &format!("SELECT max(id) FROM {};", fmt_table_name(0)),
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+        sql.counter = max_id.map_or(0, |id| id as DataId + 1);
+        Ok((sql, n_projections as usize, dim as usize))
+    }
+
+    /// Rows of a hash table that are not deleted.
+    fn rows(&self, hash_table: usize) -> String {
+        let table_name = fmt_table_name(hash_table);
+        if self.tombstone_delete {
+            format!(
+                "(SELECT hash, id FROM {} WHERE id NOT IN \
+                 (SELECT id FROM tombstones WHERE hash_table = {}))",
+                table_name, hash_table
+            )
+        } else {
+            table_name
+        }
+    }
+
+    /// Choose between hard deletion (default) and tombstones.
+    ///
+    /// A hard delete removes the rows from every hash table, which rewrites the pages of all
+    /// tables and holds the write lock for longer. A tombstone is a single row in a small table,
+    /// so deletes are cheap in write heavy workloads. The downside is that every query filters
+    /// the tombstones and the deleted rows keep their space until
+    /// [purge_tombstones](struct.SqlTable.html#method.purge_tombstones) is called.
+    /// Disabling tombstones purges them.
+    pub fn set_tombstone_delete(&mut self, tombstone: bool) -> Result<()> {
+        if self.tombstone_delete && !tombstone {
+            self.purge_tombstones()?;
+        }
+        self.tombstone_delete = tombstone;
+        Ok(())
+    }
+
+    /// Remove the rows of the tombstoned ids from the hash tables. Run
+    /// [vacuum](struct.SqlTable.html#method.vacuum) afterwards to reclaim the disk space.
+    pub fn purge_tombstones(&mut self) -> Result<()> {
+        for (i, table_name) in self.table_names.iter().enumerate() {
+            self.conn.execute(
+                &format!(
+                    "DELETE FROM {} WHERE id IN (SELECT id FROM tombstones WHERE hash_table = ?1);",
+                    table_name
+                ),
+                params![i as i64],
+            )?;
+        }
+        self.conn.execute_batch("DELETE FROM tombstones;")?;
+        Ok(())
+    }
+
+    pub fn n_hash_tables(&self) -> usize {
+        self.n_hash_tables
+    }
+
     pub fn commit(&self) -> Result<()> {
         if !self.committed.replace(true) {
             self.conn.execute_batch("COMMIT TRANSACTION;")?;
@@ -211,6 +306,51 @@ impl SqlTable {
         Ok(())
     }
 
+    /// Rebuild the database file to reclaim unused space and update the query planner statistics.
+    pub fn vacuum(&self) -> Result<()> {
+        // VACUUM cannot run within a transaction.
+        self.commit()?;
+        self.conn.execute_batch("VACUUM; ANALYZE;")?;
+        self.init_transaction()?;
+        Ok(())
+    }
+
+    /// Estimate the size of the database in bytes. (`page_count * page_size`)
+    pub fn estimate_db_size(&self) -> Result<u64> {
+        let page_count: i64 = self
+            .conn
+            .query_row("PRAGMA page_count;", NO_PARAMS, |row| row.get(0))?;
+        let page_size: i64 = self
+            .conn
+            .query_row("PRAGMA page_size;", NO_PARAMS, |row| row.get(0))?;
+        let size = (page_count * page_size) as u64;
+        if let Some(max_size) = self.db_size_warning {
+            if size > max_size {
+                warn!(
+                    "database size of {} bytes exceeds {} bytes, consider calling vacuum",
+                    size, max_size
+                )
+            }
+        }
+        Ok(size)
+    }
+
+    /// Stream the size of every bucket of every hash table. Nothing is materialized.
+    pub(crate) fn for_each_bucket_size<F: FnMut(u32)>(&self, mut f: F) -> Result<()> {
+        self.commit()?;
+        for i in 0..self.n_hash_tables {
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT count(id) FROM {} GROUP BY hash;",
+                self.rows(i)
+            ))?;
+            let mut rows = stmt.query(NO_PARAMS)?;
+            while let Some(row) = rows.next()? {
+                f(row.get(0)?)
+            }
+        }
+        Ok(())
+    }
+
     pub fn index_hash(&self) -> Result<()> {
         self.commit()?;
         for tbl_name in get_table_names(self.n_hash_tables) {
@@ -229,16 +369,16 @@ impl HashTables for SqlTable {
     fn new(n_hash_tables: usize, only_index_storage: bool, db_path: &str) -> Result<Box<Self>> {
         let path = std::path::Path::new(db_path);
         let conn = Connection::open(path)?;
-        SqlTable::init_from_conn(n_hash_tables, only_index_storage, conn).map(|tbl| Box::new(tbl))
+        SqlTable::init_from_conn(n_hash_tables, only_index_storage, conn).map(Box::new)
     }
 
-    fn put(&mut self, hash: Hash, _d: &DataPointSlice, hash_table: usize) -> Result<u32> {
+    fn put(&mut self, hash: Hash, _d: &DataPointSlice, hash_table: usize) -> Result<DataId> {
         // the unique id of the unique vector
         let idx = self.counter;
 
         // Get the table name to store this id
         let table_name = self.get_table_name_put(hash_table)?;
-        let r = insert_table(&table_name, &hash, idx, &self.conn);
+        let r = insert_table(table_name, &hash, idx, &self.conn);
 
         // Once we've traversed the last table we increment the id counter.
         if hash_table == self.n_hash_tables - 1 {
@@ -252,12 +392,97 @@ impl HashTables for SqlTable {
         }
     }
 
+    fn put_with_id(
+        &mut self,
+        idx: DataId,
+        hash: Hash,
+        _d: &DataPointSlice,
+        hash_table: usize,
+    ) -> Result<()> {
+        let table_name = self.get_table_name_put(hash_table)?;
+        if self.tombstone_delete {
+            // a tombstoned id is stored again, remove its old rows.
+            self.conn.execute(
+                &format!(
+                    "DELETE FROM {} WHERE id = ?1 AND id IN \
+                     (SELECT id FROM tombstones WHERE hash_table = ?2);",
+                    table_name
+                ),
+                params![idx as i64, hash_table as i64],
+            )?;
+            self.conn.execute(
+                "DELETE FROM tombstones WHERE hash_table = ?1 AND id = ?2;",
+                params![hash_table as i64, idx as i64],
+            )?;
+        }
+        insert_table(table_name, &hash, idx, &self.conn)?;
+
+        // make sure ids assigned by `put` don't collide with the given ids.
+        if hash_table == self.n_hash_tables - 1 {
+            self.counter = std::cmp::max(self.counter, idx + 1)
+        };
+        Ok(())
+    }
+
+    fn supports_put_with_id(&self) -> bool {
+        true
+    }
+
+    fn delete_ids(&mut self, ids: &[DataId]) -> Result<Vec<DataId>> {
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+        let id_list = ids.iter().map(|idx| idx.to_string()).collect::<Vec<_>>();
+        let mut found = FnvHashSet::default();
+        for hash_table in 0..self.n_hash_tables {
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT DISTINCT id FROM {} WHERE id IN ({});",
+                self.rows(hash_table),
+                id_list.join(", ")
+            ))?;
+            let mut rows = stmt.query(NO_PARAMS)?;
+            while let Some(row) = rows.next()? {
+                found.insert(row.get::<_, i64>(0)? as DataId);
+            }
+        }
+        let found = found.into_iter().collect();
+        if self.tombstone_delete {
+            let mut stmt = self.conn.prepare_cached(
+                "INSERT OR IGNORE INTO tombstones (hash_table, id) VALUES (?1, ?2);",
+            )?;
+            for hash_table in 0..self.n_hash_tables {
+                for &idx in ids {
+                    stmt.execute(params![hash_table as i64, idx as i64])?;
+                }
+            }
+            return Ok(found);
+        }
+        for table_name in &self.table_names {
+            self.conn.execute_batch(&format!(
+                "DELETE FROM {} WHERE id IN ({});",
+                table_name,
+                id_list.join(", ")
+            ))?;
+        }
+        Ok(found)
+    }
+
+    fn clear_table(&mut self, hash_table: usize) -> Result<()> {
+        let table_name = self.get_table_name_put(hash_table)?;
+        self.conn
+            .execute_batch(&format!("DELETE FROM {};", table_name))?;
+        self.conn.execute(
+            "DELETE FROM tombstones WHERE hash_table = ?1;",
+            params![hash_table as i64],
+        )?;
+        Ok(())
+    }
+
     /// Query the whole bucket
     fn query_bucket(&self, hash: &Hash, hash_table: usize) -> Result<Bucket> {
         self.commit()?;
-        let table_name = fmt_table_name(hash_table);
         let blob = vec_to_blob(hash);
-        let res = query_bucket(blob, &table_name, &self.conn);
+        let res = query_bucket(blob, &self.rows(hash_table), &self.conn);
 
         match res {
             Ok(bucket) => Ok(bucket),
@@ -275,13 +500,12 @@ WHERE type='table' AND type LIKE '%hash%';"#,
             let i: i64 = row.get_unwrap(0);
             Ok(i.to_string())
         })?;
-        let mut out = String::from(format!("No. of tables: {}\n", row));
+        let mut out = format!("No. of tables: {}\n", row);
 
         out.push_str("Unique hash values:\n");
         let hv = get_unique_hash_int(self.n_hash_tables, &self.conn).unwrap();
         out.push_str(&format!("{:?}", hv));
 
-        let tables = get_table_names(self.n_hash_tables);
         let mut avg = Vec::with_capacity(self.n_hash_tables);
         let mut std_dev = Vec::with_capacity(self.n_hash_tables);
         let mut min = Vec::with_capacity(self.n_hash_tables);
@@ -289,8 +513,8 @@ WHERE type='table' AND type LIKE '%hash%';"#,
 
         // maximum 3 tables will be used in stats
         let i = std::cmp::min(3, self.n_hash_tables);
-        for table_name in &tables[..i] {
-            let stats = hash_table_stats(&table_name, DESCRIBE_MAX, &self.conn)?;
+        for hash_table in 0..i {
+            let stats = hash_table_stats(&self.rows(hash_table), DESCRIBE_MAX, &self.conn)?;
             avg.push(stats.0);
             std_dev.push(stats.1);
             min.push(stats.2);
@@ -324,6 +548,20 @@ WHERE type='table' AND type LIKE '%hash%';"#,
         Ok(())
     }
 
+    fn store_params(&mut self, n_projections: usize, dim: usize) -> Result<()> {
+        self.conn.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS params (
+            n_hash_tables   INTEGER,
+            n_projections   INTEGER,
+            dim             INTEGER
+        );
+        DELETE FROM params;
+        INSERT INTO params VALUES ({}, {}, {});",
+            self.n_hash_tables, n_projections, dim
+        ))?;
+        Ok(())
+    }
+
     fn load_hashers<H: VecHash + DeserializeOwned>(&self) -> Result<Vec<H>> {
         let mut stmt = self.conn.prepare("SELECT * FROM state;")?;
         let buf: Vec<u8> = stmt.query_row(NO_PARAMS, |row| {
@@ -337,6 +575,82 @@ WHERE type='table' AND type LIKE '%hash%';"#,
     fn get_unique_hash_int(&self) -> FnvHashSet<HashPrimitive> {
         get_unique_hash_int(self.n_hash_tables, &self.conn).unwrap()
     }
+
+    /// A bucket is a group of rows with the same hash, so buckets are never empty.
+    fn count_empty_buckets(&self) -> Result<(usize, usize)> {
+        self.commit()?;
+        let mut total = 0;
+        for i in 0..self.n_hash_tables {
+            let n: i64 = self.conn.query_row(
+                &format!(
+                    "SELECT count(*) FROM (SELECT hash FROM {} GROUP BY hash);",
+                    self.rows(i)
+                ),
+                NO_PARAMS,
+                |row| row.get(0),
+            )?;
+            total += n as usize;
+        }
+        Ok((0, total))
+    }
+
+    /// Sample with `ORDER BY RANDOM()`. Sqlite's RNG can't be seeded, so `seed` is ignored.
+    fn sample_ids(&self, n: usize, _seed: u64) -> Result<Vec<DataId>> {
+        self.commit()?;
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT DISTINCT id FROM {} ORDER BY RANDOM() LIMIT ?1;",
+            self.rows(0)
+        ))?;
+        let ids = stmt
+            .query_map(params![n as i64], |row| row.get::<_, i64>(0))?
+            .map(|id| Ok(id? as DataId))
+            .collect();
+        ids
+    }
+
+    fn ids_not_in(&self, exclude: &Bucket) -> Result<Vec<DataId>> {
+        self.commit()?;
+        let exclude = exclude
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let union = (0..self.n_hash_tables)
+            .map(|i| format!("SELECT id FROM {}", self.rows(i)))
+            .collect::<Vec<_>>()
+            .join(" UNION ");
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT id FROM ({}) WHERE id NOT IN ({}) ORDER BY id;",
+            union, exclude
+        ))?;
+        let ids = stmt
+            .query_map(NO_PARAMS, |row| row.get::<_, i64>(0))?
+            .map(|id| Ok(id? as DataId))
+            .collect();
+        ids
+    }
+
+    fn to_bucket_map(&self) -> Result<Vec<FnvHashMap<Hash, Vec<DataId>>>> {
+        self.commit()?;
+        let mut out = Vec::with_capacity(self.n_hash_tables);
+        for i in 0..self.n_hash_tables {
+            let mut stmt = self.conn.prepare(&format!(
+                "SELECT hash, id FROM {} ORDER BY id;",
+                self.rows(i)
+            ))?;
+            let mut rows = stmt.query(NO_PARAMS)?;
+            let mut map: FnvHashMap<Hash, Vec<DataId>> = FnvHashMap::default();
+            while let Some(row) = rows.next()? {
+                let blob: Vec<u8> = row.get(0)?;
+                let hash: Hash = blob_to_vec(&blob).to_vec();
+                map.entry(hash)
+                    .or_default()
+                    .push(row.get::<_, i64>(1)? as DataId);
+            }
+            out.push(map)
+        }
+        Ok(out)
+    }
 }
 
 #[cfg(test)]
@@ -368,13 +682,26 @@ mod test {
         println!("{:?}", &bucket);
         match bucket {
             Ok(b) => assert!(b.contains(&0)),
-            _ => assert!(false),
+            _ => panic!("bucket not found"),
         }
     }
 
+    #[test]
+    fn test_vacuum() {
+        let mut sql = *SqlTableMem::new(1, true, ".").unwrap();
+        for i in 0..100 {
+            sql.put(vec![i, 2], &[], 0).unwrap();
+        }
+        sql.vacuum().unwrap();
+        assert!(sql.estimate_db_size().unwrap() > 0);
+        // transaction is restored.
+        sql.put(vec![1, 2], &[], 0).unwrap();
+        assert!(sql.query_bucket(&vec![1, 2], 0).unwrap().contains(&100));
+    }
+
     #[test]
     fn test_blob_hash_casting() {
-        for hash in vec![
+        for hash in [
             &vec![2, 3, 4],
             &vec![-124, 32, 89],
             &vec![1, 2, 3, 4, 5, 6],
@@ -387,6 +714,61 @@ mod test {
         }
     }
 
+    fn n_rows(sql: &SqlTable) -> i64 {
+        sql.conn
+            .query_row("SELECT count(*) FROM hash_table_0;", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    fn page_count(sql: &SqlTable) -> i64 {
+        sql.conn
+            .query_row("PRAGMA page_count;", NO_PARAMS, |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_hard_and_tombstone_delete() {
+        let dir = tempfile::tempdir().unwrap();
+        for tombstone in [false, true] {
+            let path = dir.path().join(format!("lsh_sql_delete_{}.db3", tombstone));
+            let mut sql = *SqlTable::new(2, true, path.to_str().unwrap()).unwrap();
+            sql.set_tombstone_delete(tombstone).unwrap();
+            for i in 0..2000 {
+                let hash = vec![(i % 7) as i8, 1, 2, 3, 4, 5, 6, 7];
+                sql.put(hash.clone(), &[], 0).unwrap();
+                sql.put(hash, &[], 1).unwrap();
+            }
+            sql.commit().unwrap();
+            let (rows, pages) = (n_rows(&sql), page_count(&sql));
+            assert_eq!(rows, 2000);
+
+            let ids: Vec<DataId> = (0..1500).collect();
+            assert_eq!(sql.delete_ids(&ids).unwrap().len(), 1500);
+            // deleted ids aren't found again.
+            assert!(sql.delete_ids(&ids[..10]).unwrap().is_empty());
+            let hash = vec![0, 1, 2, 3, 4, 5, 6, 7];
+            assert!(sql
+                .query_bucket(&hash, 1)
+                .unwrap()
+                .iter()
+                .all(|&id| id >= 1500));
+            assert_eq!(
+                sql.to_bucket_map().unwrap()[0].values().flatten().count(),
+                500
+            );
+            if tombstone {
+                // rows are only marked as deleted.
+                assert_eq!(n_rows(&sql), rows);
+                sql.purge_tombstones().unwrap();
+            }
+            assert_eq!(n_rows(&sql), 500);
+            sql.vacuum().unwrap();
+            assert!(page_count(&sql) < pages);
+        }
+    }
+
     #[test]
     fn test_in_mem_to_disk() {
         let mut sql = *SqlTableMem::new(1, true, ".").unwrap();
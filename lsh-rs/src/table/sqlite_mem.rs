@@ -2,9 +2,10 @@ use super::sqlite::SqlTable;
 use crate::{
     hash::{Hash, HashPrimitive},
     table::general::Bucket,
-    DataPoint, DataPointSlice, HashTables, Result,
+    DataId, DataPoint, DataPointSlice, HashTables, Result, VecHash,
 };
-use fnv::FnvHashSet;
+use fnv::{FnvHashMap, FnvHashSet};
+use serde::{de::DeserializeOwned, Serialize};
 use std::ops::{Deref, DerefMut};
 use std::path::Path;
 
@@ -52,20 +53,42 @@ impl HashTables for SqlTableMem {
     /// * `hash` - hashed vector.
     /// * `d` - Vector to store in the buckets.
     /// * `hash_table` - Number of the hash_table to store the vector. Ranging from 0 to L.
-    fn put(&mut self, hash: Hash, d: &DataPointSlice, hash_table: usize) -> Result<u32> {
+    fn put(&mut self, hash: Hash, d: &DataPointSlice, hash_table: usize) -> Result<DataId> {
         self.sql_table.put(hash, d, hash_table)
     }
 
+    fn put_with_id(
+        &mut self,
+        idx: DataId,
+        hash: Hash,
+        d: &DataPointSlice,
+        hash_table: usize,
+    ) -> Result<()> {
+        self.sql_table.put_with_id(idx, hash, d, hash_table)
+    }
+
+    fn supports_put_with_id(&self) -> bool {
+        true
+    }
+
     fn delete(&mut self, hash: &Hash, d: &DataPointSlice, hash_table: usize) -> Result<()> {
         self.sql_table.delete(hash, d, hash_table)
     }
 
+    fn delete_ids(&mut self, ids: &[DataId]) -> Result<Vec<DataId>> {
+        self.sql_table.delete_ids(ids)
+    }
+
+    fn clear_table(&mut self, hash_table: usize) -> Result<()> {
+        self.sql_table.clear_table(hash_table)
+    }
+
     /// Query the whole bucket
     fn query_bucket(&self, hash: &Hash, hash_table: usize) -> Result<Bucket> {
         self.sql_table.query_bucket(hash, hash_table)
     }
 
-    fn idx_to_datapoint(&self, idx: u32) -> Result<&DataPoint> {
+    fn idx_to_datapoint(&self, idx: DataId) -> Result<&DataPoint> {
         self.sql_table.idx_to_datapoint(idx)
     }
 
@@ -73,7 +96,35 @@ impl HashTables for SqlTableMem {
         self.sql_table.describe()
     }
 
+    fn store_hashers<H: VecHash + Serialize>(&mut self, hashers: &[H]) -> Result<()> {
+        self.sql_table.store_hashers(hashers)
+    }
+
+    fn store_params(&mut self, n_projections: usize, dim: usize) -> Result<()> {
+        self.sql_table.store_params(n_projections, dim)
+    }
+
+    fn load_hashers<H: VecHash + DeserializeOwned>(&self) -> Result<Vec<H>> {
+        self.sql_table.load_hashers()
+    }
+
     fn get_unique_hash_int(&self) -> FnvHashSet<HashPrimitive> {
         self.sql_table.get_unique_hash_int()
     }
+
+    fn count_empty_buckets(&self) -> Result<(usize, usize)> {
+        self.sql_table.count_empty_buckets()
+    }
+
+    fn sample_ids(&self, n: usize, seed: u64) -> Result<Vec<DataId>> {
+        self.sql_table.sample_ids(n, seed)
+    }
+
+    fn ids_not_in(&self, exclude: &Bucket) -> Result<Vec<DataId>> {
+        self.sql_table.ids_not_in(exclude)
+    }
+
+    fn to_bucket_map(&self) -> Result<Vec<FnvHashMap<Hash, Vec<DataId>>>> {
+        self.sql_table.to_bucket_map()
+    }
 }
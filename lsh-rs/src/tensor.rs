@@ -0,0 +1,125 @@
+//! LSH for pairs of vectors, e.g. for entity relationship retrieval.
+use crate::{
+    hash::TensorSketch, DataId, DataPoint, DataPointSlice, Error, Hash, HashTables, Result,
+    VecHash, LSH,
+};
+use serde::{Deserialize, Serialize};
+
+/// Hasher for a pair `(u, v)` given as the concatenation `[u, v]`. The tensor product `u ⊗ v`
+/// is sketched with a [TensorSketch](../struct.TensorSketch.html) of degree 2 and hashed with
+/// sign random projections. Pairs collide with high probability if
+/// `(u_1^T u_2)(v_1^T v_2)` is large relative to their norms.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PairHash {
+    sketch: TensorSketch,
+}
+
+impl PairHash {
+    ///
+    /// # Arguments
+    ///
+    /// * `dim` - Dimension of `u` and of `v`.
+    /// * `sketch_dim` - Dimension of the sketch of `u ⊗ v`.
+    /// * `n_projections` - Hash length.
+    pub fn new(dim: usize, sketch_dim: usize, n_projections: usize, seed: u64) -> PairHash {
+        PairHash {
+            sketch: TensorSketch::new(dim, 2, sketch_dim, 0., n_projections, seed),
+        }
+    }
+}
+
+impl VecHash for PairHash {
+    fn hash_vec_query(&self, v: &[f32]) -> Hash {
+        let (u, v) = v.split_at(v.len() / 2);
+        self.sketch
+            .hash_tuple(&[u, v])
+            .expect("the sketch has degree 2")
+    }
+
+    fn hash_vec_put(&self, v: &[f32]) -> Hash {
+        self.hash_vec_query(v)
+    }
+
+    fn n_projections(&self) -> usize {
+        self.sketch.n_projections()
+    }
+
+    /// Dimension of the concatenation `[u, v]`.
+    fn dim(&self) -> usize {
+        2 * self.sketch.dim()
+    }
+}
+
+/// LSH for pairs of vectors. Created with [tensor_srp](../struct.LSH.html#method.tensor_srp).
+///
+/// # Example
+///
+/// ```
+/// use lsh_rs::LshMem;
+/// let mut lsh = LshMem::new(8, 10, 3).seed(1).tensor_srp(64).unwrap();
+/// let id = lsh.store_pair(&[1., 0., 2.], &[0., 1., 1.]).unwrap();
+/// assert!(lsh
+///     .query_pair(&[1., 0., 2.], &[0., 1., 1.])
+///     .unwrap()
+///     .contains(&id));
+/// ```
+pub struct TensorLSH<T: HashTables> {
+    /// Underlying LSH. The stored data points are the concatenated pairs `[u, v]`.
+    pub lsh: LSH<T, PairHash>,
+}
+
+impl<T: HashTables> TensorLSH<T> {
+    fn concat(&self, u: &DataPointSlice, v: &DataPointSlice) -> Result<DataPoint> {
+        if u.len() != v.len() {
+            return Err(Error::Failed(
+                "u and v should have the same dimension".to_string(),
+            ));
+        }
+        Ok([u, v].concat())
+    }
+
+    /// Store a pair of vectors. Returns id.
+    ///
+    /// # Arguments
+    /// * `u` - First vector.
+    /// * `v` - Second vector.
+    pub fn store_pair(&mut self, u: &DataPointSlice, v: &DataPointSlice) -> Result<DataId> {
+        let uv = self.concat(u, v)?;
+        self.lsh.store_vec(&uv)
+    }
+
+    /// Query the ids of the pairs that collide with `(u, v)`.
+    ///
+    /// # Arguments
+    /// * `u` - First vector.
+    /// * `v` - Second vector.
+    pub fn query_pair(&self, u: &DataPointSlice, v: &DataPointSlice) -> Result<Vec<DataId>> {
+        let uv = self.concat(u, v)?;
+        self.lsh.query_bucket_ids(&uv)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::LshMem;
+
+    #[test]
+    fn test_tensor_lsh() {
+        let mut lsh = LshMem::new(10, 8, 4).seed(1).tensor_srp(128).unwrap();
+        let u = [1., 0.5, -0.2, 0.];
+        let v = [0., 1., 1., -1.];
+        let id = lsh.store_pair(&u, &v).unwrap();
+        lsh.store_pair(&v, &u).unwrap();
+
+        assert!(lsh
+            .query_pair(&[1., 0.52, -0.2, 0.], &v)
+            .unwrap()
+            .contains(&id));
+        // u ⊗ -v = -(u ⊗ v), so all signs of the sketch flip.
+        assert!(!lsh
+            .query_pair(&u, &[0., -1., -1., 1.])
+            .unwrap()
+            .contains(&id));
+        assert!(lsh.query_pair(&u, &v[..3]).is_err());
+    }
+}
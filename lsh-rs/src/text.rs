@@ -0,0 +1,111 @@
+use fnv::FnvHasher;
+use std::hash::Hasher;
+
+/// Convert a string to a set of k-character shingles. Every shingle is hashed with FNV and the
+/// unique hashes are returned sorted. Strings shorter than `k` characters result in a single shingle.
+///
+/// # Arguments
+/// * `text` - Text to shingle.
+/// * `k` - Number of characters per shingle.
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::text::shingle;
+/// let shingles = shingle("the quick brown fox", 3);
+/// ```
+pub fn shingle(text: &str, k: usize) -> Vec<u32> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty() || k == 0 {
+        return vec![];
+    }
+    let k = std::cmp::min(k, chars.len());
+
+    let mut shingles: Vec<u32> = chars
+        .windows(k)
+        .map(|w| {
+            let mut hasher = FnvHasher::default();
+            for c in w {
+                hasher.write_u32(*c as u32);
+            }
+            hasher.finish() as u32
+        })
+        .collect();
+    shingles.sort_unstable();
+    shingles.dedup();
+    shingles
+}
+
+/// Exact Jaccard similarity between two sorted sets, such as the output of [shingle](fn.shingle.html).
+///
+/// # Examples
+///
+/// ```
+/// use lsh_rs::text::jaccard_exact;
+/// let sim = jaccard_exact(&[1, 2, 3], &[2, 3, 4]);
+/// assert_eq!(sim, 0.5);
+/// ```
+pub fn jaccard_exact(a: &[u32], b: &[u32]) -> f32 {
+    if a.is_empty() && b.is_empty() {
+        return 1.;
+    }
+    let mut i = 0;
+    let mut j = 0;
+    let mut intersection = 0;
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                intersection += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    let union = a.len() + b.len() - intersection;
+    intersection as f32 / union as f32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shingle() {
+        assert_eq!(shingle("abcabc", 3).len(), 3);
+        assert_eq!(shingle("ab", 3), shingle("ab", 2));
+        assert!(shingle("", 3).is_empty());
+        let s = shingle("the quick brown fox", 4);
+        let mut sorted = s.clone();
+        sorted.sort_unstable();
+        assert_eq!(s, sorted);
+    }
+
+    #[test]
+    fn test_near_duplicates() {
+        let corpus = [
+            "Locality sensitive hashing reduces the dimensionality of high dimensional data.",
+            "Locality sensitive hashing reduces the dimensionality of high-dimensional data!",
+            "The quick brown fox jumps over the lazy dog near the river bank.",
+            "The quick brown fox jumped over the lazy dog near the river bank.",
+            "Rust is a multi-paradigm programming language focused on performance and safety.",
+            "Rust is a multi paradigm programming language focused on performance and safety.",
+        ];
+        let sets: Vec<_> = corpus.iter().map(|t| shingle(t, 5)).collect();
+
+        // every paragraph should find its near duplicate as most similar paragraph.
+        for (i, a) in sets.iter().enumerate() {
+            let best = sets
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .max_by(|(_, x), (_, y)| jaccard_exact(a, x).total_cmp(&jaccard_exact(a, y)))
+                .map(|(j, _)| j)
+                .unwrap();
+            assert_eq!(best, i ^ 1);
+            assert!(jaccard_exact(a, &sets[i ^ 1]) > 0.7);
+        }
+        assert_eq!(jaccard_exact(&sets[0], &sets[0]), 1.);
+    }
+}
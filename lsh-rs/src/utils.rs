@@ -1,6 +1,7 @@
 use rand::rngs::SmallRng;
 use rand::{thread_rng, Rng, SeedableRng};
 use rand_distr::StandardNormal;
+use serde::{Deserialize, Serialize};
 
 pub fn increase_capacity<T>(size: usize, container: &mut Vec<T>) {
     if container.capacity() < size {
@@ -21,6 +22,42 @@ pub fn create_rng(seed: u64) -> SmallRng {
     }
 }
 
+/// Deterministic sequence of seeds derived from a root seed with
+/// [SplitMix64](https://prng.di.unimi.it/splitmix64.c). The seeds are unique for the first
+/// `2^64` calls and never 0, as 0 means a randomly seeded RNG.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeedSchedule {
+    state: u64,
+}
+
+impl SeedSchedule {
+    /// # Arguments
+    /// * `root_seed` - Seed of the schedule. If 0, the schedule is seeded randomly.
+    pub fn new(root_seed: u64) -> Self {
+        let state = if root_seed == 0 {
+            create_rng(0).gen()
+        } else {
+            root_seed
+        };
+        SeedSchedule { state }
+    }
+
+    /// Next seed of the schedule.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> u64 {
+        loop {
+            self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = self.state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            if z != 0 {
+                return z;
+            }
+        }
+    }
+}
+
 pub fn rand_unit_vec<RNG: Rng>(size: usize, rng: RNG) -> Vec<f32> {
     rng.sample_iter(StandardNormal).take(size).collect()
 }
@@ -43,7 +80,6 @@ where
 #[cfg(test)]
 mod test {
     use super::*;
-    use rand::SeedableRng;
 
     #[test]
     fn test_all_eq() {
@@ -51,4 +87,19 @@ mod test {
         assert!(all_eq(&[1., 2.], &[1., 2.]));
         assert!(!all_eq(&[1.1, -1.], &[1., 2.]));
     }
+
+    #[test]
+    fn test_seed_schedule() {
+        let seeds: Vec<u64> = {
+            let mut s = SeedSchedule::new(42);
+            (0..1000).map(|_| s.next()).collect()
+        };
+        let mut s = SeedSchedule::new(42);
+        assert!(seeds.iter().all(|&seed| seed == s.next()));
+        let mut unique = seeds.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), seeds.len());
+        assert_ne!(SeedSchedule::new(43).next(), seeds[0]);
+    }
 }
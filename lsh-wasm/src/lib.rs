@@ -0,0 +1,60 @@
+//! WebAssembly bindings of `lsh-rs` for browser based similarity search.
+//!
+//! Build with `wasm-pack build --target nodejs` (or `--target web` for the browser).
+//! Data points are passed as flat `Float32Array`s in row major order, ids are returned as
+//! `Uint32Array`s.
+use lsh_rs::{LshMem, SignRandomProjections};
+use wasm_bindgen::prelude::*;
+
+fn to_js_err<E: std::fmt::Display>(e: E) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+/// In memory Signed Random Projections LSH.
+#[wasm_bindgen]
+pub struct WasmLsh {
+    lsh: LshMem<SignRandomProjections>,
+}
+
+#[wasm_bindgen]
+impl WasmLsh {
+    /// Create an empty index.
+    ///
+    /// # Arguments
+    ///
+    /// * `n_proj` - Hash length.
+    /// * `n_tables` - Number of hash tables.
+    /// * `dim` - Dimension of the data points.
+    /// * `seed` - Seed of the hash functions. If 0, randomness is seeded from the os.
+    #[wasm_bindgen(constructor)]
+    pub fn new(n_proj: usize, n_tables: usize, dim: usize, seed: u32) -> Result<WasmLsh, JsValue> {
+        let lsh = LshMem::new(n_proj, n_tables, dim)
+            .seed(seed as u64)
+            .srp()
+            .map_err(to_js_err)?;
+        Ok(WasmLsh { lsh })
+    }
+
+    /// Store a flat array of `n * dim` values. Returns the ids of the `n` data points.
+    #[wasm_bindgen(js_name = storeVecs)]
+    pub fn store_vecs(&mut self, vs: &[f32]) -> Result<Vec<u32>, JsValue> {
+        let dim = self.lsh.dim;
+        if vs.is_empty() || vs.len() % dim != 0 {
+            return Err(JsValue::from_str(&format!(
+                "length should be a positive multiple of {}",
+                dim
+            )));
+        }
+        vs.chunks(dim)
+            .map(|v| self.lsh.store_vec(v).map_err(to_js_err))
+            .collect()
+    }
+
+    /// Ids of the data points that collide with `v` in any hash table.
+    #[wasm_bindgen(js_name = queryBucketIds)]
+    pub fn query_bucket_ids(&self, v: &[f32]) -> Result<Vec<u32>, JsValue> {
+        let mut ids = self.lsh.query_bucket_ids(v).map_err(to_js_err)?;
+        ids.sort_unstable();
+        Ok(ids)
+    }
+}
@@ -0,0 +1,17 @@
+const { WasmLsh } = require("../pkg/lsh_wasm");
+
+test("store and query round trip", () => {
+  const lsh = new WasmLsh(5, 10, 3, 1);
+  const ids = lsh.storeVecs(new Float32Array([2, 3, 4, -1, -1, 1]));
+  expect(Array.from(ids)).toEqual([0, 1]);
+  expect(ids).toBeInstanceOf(Uint32Array);
+
+  const result = lsh.queryBucketIds(new Float32Array([2, 3, 4]));
+  expect(result).toContain(0);
+});
+
+test("wrong dimension throws", () => {
+  const lsh = new WasmLsh(5, 10, 3, 1);
+  expect(() => lsh.storeVecs(new Float32Array([1, 2]))).toThrow();
+  expect(() => lsh.queryBucketIds(new Float32Array([1, 2]))).toThrow();
+});